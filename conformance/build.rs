@@ -1,4 +1,9 @@
 fn main() {
+    // The C++ shim is only needed for the full harness (the default `cpp`
+    // feature), not for the offline mode.
+    if std::env::var_os("CARGO_FEATURE_CPP").is_none() {
+        return;
+    }
     // wuff-capi exposes its C++ header directory via DEP_WUFF_INCLUDE_DIR
     // (thanks to `links = "wuff"` in its Cargo.toml).
     let include_dir = std::env::var("DEP_WUFF_INCLUDE_DIR").unwrap();
@@ -0,0 +1,64 @@
+#!/usr/bin/env python3
+"""Record the glyf and loca tables which fontTools reconstructs from the wpt fonts.
+
+For each font which ../wpt/manifest.txt marks as valid (other than collections),
+the transformed glyf and loca tables are reconstructed with fontTools'
+WOFF2 reader, and the SHA-256 hashes of the two tables are written to
+expected.txt. `cargo run -p conformance -- --fonttools` checks them against the
+output of wuff's `OutputProfile::FontToolsCompatible` (see
+conformance/src/fonttools.rs).
+
+Usage (from this directory): pip install fonttools brotli && python3 generate.py
+"""
+
+import hashlib
+import io
+from pathlib import Path
+
+import fontTools
+from fontTools.ttLib import TTLibError
+from fontTools.ttLib.woff2 import WOFF2Reader
+
+HERE = Path(__file__).resolve().parent
+WPT_DIR = HERE.parent / "wpt"
+
+
+def valid_fonts():
+    for line in (WPT_DIR / "manifest.txt").read_text().splitlines():
+        line = line.strip()
+        if not line or line.startswith("#"):
+            continue
+        file, expectation, _assertion = line.split()
+        if expectation == "valid":
+            yield file
+
+
+def main():
+    lines = [
+        f"# glyf and loca tables reconstructed by fontTools {fontTools.version}"
+        " (written by generate.py)",
+        "# <wpt file> <sha256 of glyf> <sha256 of loca>, or <wpt file> reject",
+    ]
+    for file in valid_fonts():
+        data = (WPT_DIR / file).read_bytes()
+        if data[4:8] == b"ttcf":
+            continue
+        try:
+            reader = WOFF2Reader(io.BytesIO(data))
+            if "glyf" not in reader.tables:
+                continue
+            # Indexing the reader reconstructs transformed tables
+            glyf, loca = reader["glyf"], reader["loca"]
+        except (TTLibError, AssertionError, ValueError) as e:
+            print(f"{file}: fontTools rejected the font: {e}")
+            lines.append(f"{file} reject")
+            continue
+        glyf_hash = hashlib.sha256(glyf).hexdigest()
+        loca_hash = hashlib.sha256(loca).hexdigest()
+        lines.append(f"{file} {glyf_hash} {loca_hash}")
+    (HERE / "expected.txt").write_text("\n".join(lines) + "\n")
+    print(f"Wrote {HERE / 'expected.txt'}")
+
+
+if __name__ == "__main__":
+    main()
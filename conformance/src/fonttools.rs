@@ -0,0 +1,111 @@
+//! Checking wuff's `OutputProfile::FontToolsCompatible` against fontTools.
+//!
+//! `conformance/fonts/fonttools/generate.py` reconstructs the glyf and loca
+//! tables of the valid wpt fonts with fontTools' WOFF2 reader
+//! (`fontTools.ttLib.woff2`), and records their SHA-256 hashes in
+//! `conformance/fonts/fonttools/expected.txt`. Each line is one of:
+//!
+//! ```text
+//! <wpt file> <sha256 of glyf> <sha256 of loca>
+//! <wpt file> reject
+//! ```
+//!
+//! Here the same fonts are decoded with wuff using the fontTools profile, and
+//! its glyf and loca tables must have the same hashes (or the font must be
+//! rejected). Only glyf and loca are compared: fontTools rewrites other
+//! tables (such as head) when it saves a font, but the profile only governs
+//! how glyf and loca are reconstructed.
+//!
+//! The expectations need Python and fontTools to generate, so this check
+//! isn't part of the offline mode: it runs with `conformance --fonttools`
+//! after `generate.py`, and fails if the expectations are missing.
+
+use std::fs;
+use std::path::Path;
+
+use wuff::{DecodeOptions, OutputProfile};
+
+use crate::offline::sha256_hex;
+use crate::woff1::parse_sfnt;
+
+/// The expectations file, relative to the fonts directory
+const EXPECTED_FILE: &str = "fonttools/expected.txt";
+
+/// The hashes of a font's glyf and loca tables, as decoded by wuff, or `None`
+/// if wuff rejected the font
+fn decode(woff2: &[u8]) -> Result<Option<(String, String)>, String> {
+    let decode = || {
+        let options = DecodeOptions::default().output_profile(OutputProfile::FontToolsCompatible);
+        wuff::decompress_woff2_with_options(woff2, options)
+    };
+    let sfnt = match std::panic::catch_unwind(decode) {
+        Ok(Ok(decoded)) => decoded.font,
+        Ok(Err(_)) => return Ok(None),
+        Err(payload) => return Err(format!("panicked: {}", crate::panic_message(payload))),
+    };
+    let (_, tables) = parse_sfnt(&sfnt)?;
+    let table_hash = |tag: &[u8; 4]| {
+        let table = tables.iter().find(|table| &table.tag == tag);
+        table
+            .map(|table| sha256_hex(table.data))
+            .ok_or_else(|| format!("no {} table in the output", String::from_utf8_lossy(tag)))
+    };
+    Ok(Some((table_hash(b"glyf")?, table_hash(b"loca")?)))
+}
+
+/// Check wuff's fontTools profile against the expectations under
+/// `fonts_dir`, returning a list of failures (empty if everything passed).
+pub fn check(fonts_dir: &Path) -> Vec<String> {
+    let Ok(text) = fs::read_to_string(fonts_dir.join(EXPECTED_FILE)) else {
+        return vec![format!(
+            "FONTTOOLS: {EXPECTED_FILE} hasn't been generated \
+             (run conformance/fonts/fonttools/generate.py)"
+        )];
+    };
+
+    let mut failures = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fail = |msg: String| failures.push(format!("FONTTOOLS: {msg}"));
+        let expected = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [file, "reject"] => (file, None),
+            [file, glyf, loca] => (file, Some((glyf.to_string(), loca.to_string()))),
+            _ => {
+                fail(format!("{EXPECTED_FILE}:{}: malformed line", line_no + 1));
+                continue;
+            }
+        };
+        let (file, expected) = expected;
+        let Ok(woff2) = fs::read(fonts_dir.join("wpt").join(file)) else {
+            fail(format!(
+                "wpt/{file}: listed in {EXPECTED_FILE} but not found"
+            ));
+            continue;
+        };
+        match decode(&woff2) {
+            Ok(actual) if actual == expected => {}
+            Ok(None) => fail(format!("wpt/{file}: rejected, but fontTools decodes it")),
+            Ok(Some(_)) if expected.is_none() => {
+                fail(format!("wpt/{file}: decoded, but fontTools rejects it"))
+            }
+            Ok(Some((glyf, loca))) => {
+                let (expected_glyf, expected_loca) = expected.unwrap_or_default();
+                if glyf != expected_glyf {
+                    fail(format!(
+                        "wpt/{file}: glyf differs from fontTools' (sha256 {glyf})"
+                    ));
+                }
+                if loca != expected_loca {
+                    fail(format!(
+                        "wpt/{file}: loca differs from fontTools' (sha256 {loca})"
+                    ));
+                }
+            }
+            Err(msg) => fail(format!("wpt/{file}: {msg}")),
+        }
+    }
+    failures
+}
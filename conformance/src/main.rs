@@ -25,8 +25,10 @@
 //! `conformance/fonts/` (such as the wpt (web-platform-tests) WOFF2
 //! conformance suite in `conformance/fonts/wpt/`) are decoded as-is. As
 //! these suites contain deliberately invalid files, consistent rejection by
-//! all three decoders is an acceptable outcome for these inputs (reported as
-//! "pass (wpt reject)").
+//! all three decoders can be an acceptable outcome for these inputs
+//! (reported as "pass (wpt reject)"). For the wpt suite, the expected outcome
+//! of each file is recorded in a manifest (see the `manifest` module), and
+//! decoders that agree on the wrong outcome fail the test.
 //!
 //! Usage:
 //!
@@ -36,22 +38,51 @@
 //!
 //! The harness asserts that all three decoders produce byte-identical output
 //! and exits non-zero if any font fails.
+//!
+//! Pass `--offline` to instead check only wuff against the expected outcomes
+//! and output hashes recorded for the committed font suites (see the
+//! `offline` module). This needs neither the C++ toolchain nor network
+//! access, and also runs under `cargo test`. Building without the default
+//! `cpp` feature (`--no-default-features`) leaves out everything that needs
+//! a C++ compiler, so that only the offline mode is available.
+//!
+//! `conformance --fonttools` checks wuff's fontTools output profile against
+//! the hashes recorded by fontTools (see the `fonttools` module).
+//!
+//! `conformance minimize <font>` shrinks a WOFF2 file on which the decoders
+//! diverge into a small reproducer (see the `minimize` module).
 
 // Force-link the wuff-capi crate: nothing references it from Rust, but the
 // C++ shim (src/capi_shim.cpp) needs its exported wuff_woff2_* C symbols.
+#[cfg(feature = "cpp")]
 use wuff_capi as _;
 
+mod fonttools;
+mod manifest;
+#[cfg(feature = "cpp")]
+mod minimize;
+mod offline;
+#[cfg(feature = "cpp")]
 mod prepare;
+#[cfg(feature = "cpp")]
 mod runner;
+mod woff1;
 
 use std::fs;
 use std::path::{Path, PathBuf};
 
+#[cfg_attr(not(feature = "cpp"), allow(dead_code))]
 struct Config {
     data_dir: PathBuf,
     woff2_dir: PathBuf,
     filters: Vec<String>,
     refresh_fonts: bool,
+    offline: bool,
+    bless: bool,
+    /// Check the fontTools profile against fontTools' output (`--fonttools`)
+    fonttools: bool,
+    /// The font to minimize, for `conformance minimize <font>`
+    minimize: Option<PathBuf>,
 }
 
 fn parse_args() -> Config {
@@ -61,6 +92,10 @@ fn parse_args() -> Config {
         woff2_dir: repo_root.join("woff2"),
         filters: Vec::new(),
         refresh_fonts: false,
+        offline: !cfg!(feature = "cpp"),
+        bless: false,
+        fonttools: false,
+        minimize: None,
     };
     let mut args = std::env::args().skip(1);
     while let Some(arg) = args.next() {
@@ -71,10 +106,25 @@ fn parse_args() -> Config {
         match arg.as_str() {
             "--data-dir" => cfg.data_dir = PathBuf::from(value("--data-dir")),
             "--refresh-fonts" => cfg.refresh_fonts = true,
+            "--offline" => cfg.offline = true,
+            "--bless" => cfg.bless = true,
+            "--fonttools" => cfg.fonttools = true,
+            "minimize" if cfg.filters.is_empty() && cfg.minimize.is_none() => {
+                cfg.minimize = Some(PathBuf::from(value("minimize")));
+            }
             "--help" | "-h" => {
                 println!(
-                    "Usage: conformance [FILTER...] [--data-dir DIR] [--refresh-fonts]\n\n\
-                     FILTER: only test fonts whose path contains the substring"
+                    "Usage: conformance [FILTER...] [--data-dir DIR] [--refresh-fonts]\n       \
+                     conformance --offline [FILTER...] [--bless]\n       \
+                     conformance --fonttools\n       \
+                     conformance minimize FONT [--data-dir DIR]\n\n\
+                     FILTER: only test fonts whose path contains the substring\n\
+                     --offline: check wuff against the recorded outcomes for the committed fonts\n\
+                     --bless: (with --offline) re-record the outcomes from wuff's current output\n\
+                     --fonttools: check the fontTools output profile against the hashes recorded\n\
+                     \x20            by conformance/fonts/fonttools/generate.py\n\
+                     minimize: shrink a WOFF2 file on which the decoders diverge into a\n\
+                     \x20         reproducer in conformance/fonts/custom/"
                 );
                 std::process::exit(0);
             }
@@ -107,9 +157,61 @@ fn discover_files(root: &Path, dir: &Path, extensions: &[&str], out: &mut Vec<Pa
     }
 }
 
+/// Extract a human-readable message from a caught panic payload.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
 fn main() {
     let cfg = parse_args();
+    let fonts_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fonts");
+
+    if cfg.bless && !cfg.offline {
+        fatal("--bless requires --offline");
+    }
+    if cfg.fonttools {
+        if cfg.bless || cfg.minimize.is_some() || !cfg.filters.is_empty() {
+            fatal("--fonttools takes no other arguments");
+        }
+        let failures = fonttools::check(&fonts_dir);
+        for failure in &failures {
+            eprintln!("{failure}");
+        }
+        if !failures.is_empty() {
+            println!("{} failures", failures.len());
+            std::process::exit(1);
+        }
+        println!("The fontTools profile matches fontTools' output");
+    } else if let Some(font) = &cfg.minimize {
+        if cfg.offline || !cfg.filters.is_empty() {
+            fatal("minimize takes a single font and no other arguments than --data-dir");
+        }
+        #[cfg(feature = "cpp")]
+        minimize::run(&cfg, &fonts_dir, font);
+        #[cfg(not(feature = "cpp"))]
+        fatal(&format!(
+            "minimizing {} requires the `cpp` feature",
+            font.display()
+        ));
+    } else if cfg.offline {
+        if offline::run_and_report(&fonts_dir, &cfg.filters, cfg.bless) {
+            std::process::exit(1);
+        }
+    } else {
+        #[cfg(feature = "cpp")]
+        run_full(&cfg, &fonts_dir);
+    }
+}
 
+/// Run the full harness, comparing all three decoders.
+#[cfg(feature = "cpp")]
+fn run_full(cfg: &Config, fonts_dir: &Path) {
     let encoded_dir = cfg.data_dir.join("encoded");
     let scratch_root = cfg.data_dir.join("tmp");
     let _ = fs::remove_dir_all(&scratch_root);
@@ -117,12 +219,11 @@ fn main() {
     // Phase 1 & 2: build the reference tools, then download and encode the
     // corpus into the cache (deleting the sources afterwards).
     let (compress, decompress) = prepare::ensure_woff2_tools(&cfg.woff2_dir);
-    prepare::build_encoded_cache(&cfg, &compress, &encoded_dir, &scratch_root);
+    prepare::build_encoded_cache(cfg, &compress, &encoded_dir, &scratch_root);
 
     // Phase 3: collect the test cases (cache + committed font suites), apply
     // the user's FILTER, and run them.
-    let fonts_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fonts");
-    let mut cases = runner::discover_cases(&encoded_dir, &fonts_dir);
+    let mut cases = runner::discover_cases(&encoded_dir, fonts_dir);
 
     cases.sort_by(|a, b| a.name.cmp(&b.name));
     if !cfg.filters.is_empty() {
@@ -0,0 +1,165 @@
+//! Expected-outcome manifests for the committed test suites (currently only
+//! the wpt WOFF2 suite, in `conformance/fonts/wpt/manifest.txt`).
+//!
+//! These suites deliberately include invalid files, so "all decoders agree"
+//! is not enough to pass: a font that should decode but is rejected by every
+//! decoder would otherwise go unnoticed. A manifest records, for each file in
+//! its directory, whether a conforming decoder must accept it, and which spec
+//! conformance requirement the file exercises (an anchor in
+//! <https://www.w3.org/TR/WOFF2/>, such as `conform-mustRejectLoca`). Each line has the form:
+//!
+//! ```text
+//! <file name> <valid|invalid|metadata-only> <assertion ID, or - if not recorded>
+//! ```
+//!
+//! `metadata-only` files test how user agents display the extended metadata
+//! block. That is outside the scope of a decoder, which must decode the font
+//! regardless of the metadata.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// The name of the manifest file, relative to the suite's directory
+pub const MANIFEST_FILE: &str = "manifest.txt";
+
+/// The directories (relative to `conformance/fonts/`) of the suites that have manifests
+pub const SUITES: &[&str] = &["wpt"];
+
+/// The expected outcome of decoding a font in a suite
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    /// The font must be decoded
+    Valid,
+    /// The font must be rejected
+    Invalid,
+    /// The font tests metadata display; it must be decoded
+    MetadataOnly,
+}
+
+impl Expectation {
+    /// Whether a conforming decoder must accept the font
+    pub fn should_decode(self) -> bool {
+        self != Self::Invalid
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Valid => "valid",
+            Self::Invalid => "invalid",
+            Self::MetadataOnly => "metadata-only",
+        }
+    }
+}
+
+/// A single manifest entry
+pub struct Entry {
+    pub expectation: Expectation,
+    /// The spec conformance requirement the font exercises, if recorded
+    pub assertion: Option<String>,
+}
+
+/// The parsed manifest, keyed by file name
+pub struct Manifest(BTreeMap<String, Entry>);
+
+impl Manifest {
+    /// Load the manifest from a suite's directory.
+    pub fn load(suite_dir: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(suite_dir.join(MANIFEST_FILE))
+            .map_err(|e| format!("failed to read {MANIFEST_FILE}: {e}"))?;
+        let mut entries = BTreeMap::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let malformed = || format!("{MANIFEST_FILE}:{}: malformed line", line_no + 1);
+            let [file, expectation, assertion] = line.split_whitespace().collect::<Vec<_>>()[..]
+            else {
+                return Err(malformed());
+            };
+            let expectation = match expectation {
+                "valid" => Expectation::Valid,
+                "invalid" => Expectation::Invalid,
+                "metadata-only" => Expectation::MetadataOnly,
+                _ => return Err(malformed()),
+            };
+            let assertion = (assertion != "-").then(|| assertion.to_string());
+            let entry = Entry {
+                expectation,
+                assertion,
+            };
+            if entries.insert(file.to_string(), entry).is_some() {
+                return Err(format!(
+                    "{MANIFEST_FILE}:{}: duplicate entry for {file}",
+                    line_no + 1
+                ));
+            }
+        }
+        Ok(Self(entries))
+    }
+
+    /// Look up the entry for a file in the suite's directory
+    pub fn get(&self, file_name: &str) -> Option<&Entry> {
+        self.0.get(file_name)
+    }
+
+    /// The file names listed in the manifest
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+/// Describe a font whose outcome contradicts the manifest
+pub fn describe_wrong_outcome(expectation: Expectation, decoded: bool) -> String {
+    format!(
+        "manifest says {}, but the font was {}",
+        expectation.name(),
+        if decoded { "accepted" } else { "rejected" }
+    )
+}
+
+/// The key under which a font is counted in the coverage report: its spec
+/// assertion, or (if none is recorded) its test group, i.e. its file name
+/// without the extension and numeric suffix (e.g. `[header-length]`).
+/// Keys are prefixed by the suite (e.g. `wpt/conform-mustRejectLoca`).
+pub fn coverage_key(suite: &str, file_name: &str, entry: &Entry) -> String {
+    match &entry.assertion {
+        Some(assertion) => format!("{suite}/{assertion}"),
+        None => {
+            let stem = file_name
+                .rsplit_once('.')
+                .map_or(file_name, |(stem, _)| stem);
+            let group = stem.rsplit_once('-').map_or(stem, |(group, _)| group);
+            format!("{suite}/[{group}]")
+        }
+    }
+}
+
+/// Split a path relative to `conformance/fonts/` into its suite and file
+/// name, if it belongs to a suite with a manifest.
+pub fn split_suite_path(path: &Path) -> Option<(&'static str, String)> {
+    SUITES.iter().find_map(|&suite| {
+        let file = path.strip_prefix(suite).ok()?;
+        Some((suite, file.to_string_lossy().into_owned()))
+    })
+}
+
+/// Summarise test results by spec assertion: for each assertion, how many of
+/// the fonts exercising it passed. `results` yields `(coverage key, passed)` per font.
+pub fn coverage_report(results: impl IntoIterator<Item = (String, bool)>) -> String {
+    let mut coverage: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for (key, passed) in results {
+        let (total, pass) = coverage.entry(key).or_default();
+        *total += 1;
+        *pass += passed as usize;
+    }
+
+    let width = coverage.keys().map(|key| key.len()).max().unwrap_or(0);
+    let mut report = String::new();
+    for (key, (total, pass)) in coverage {
+        writeln!(report, "  {key:width$}  {pass}/{total} pass").unwrap();
+    }
+    report
+}
@@ -0,0 +1,427 @@
+//! `conformance minimize <font>`: shrink a WOFF2 file on which the decoders
+//! diverge into a small reproducer for `conformance/fonts/custom/`.
+//!
+//! The font is decoded to an sfnt (with the C++ reference decoder, or with
+//! wuff if the reference decoder rejects it) and then reduced greedily:
+//! whole tables are dropped, glyphs are emptied (halving the size of the
+//! ranges tried each round, as in delta debugging), and the glyph count is
+//! truncated. After each step the font is re-encoded with the reference
+//! `woff2_compress` encoder and decoded with all three decoders, and the step
+//! is kept only if the decoders still diverge in the same way (see
+//! `Outcome::divergence`). This repeats until no step makes progress.
+//!
+//! The smallest reproducer is written to `custom/<name>-min.woff2`, and its
+//! expected outcome (that of the reference decoder) is added to
+//! `expected.txt`, so that the offline check keeps wuff in line with it.
+//!
+//! Only single fonts (not collections) can be reduced. Glyphs can only be
+//! reduced in TrueType-flavored fonts (those with glyf and loca tables).
+
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use crate::runner::{self, Expected};
+use crate::{Config, fatal, offline, prepare};
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn put_u16(data: &mut [u8], at: usize, value: u16) {
+    data[at..at + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(data: &mut [u8], at: usize, value: u32) {
+    data[at..at + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn round4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// A (non-collection) sfnt, as a list of tables sorted by tag
+#[derive(Clone)]
+struct Font {
+    flavor: [u8; 4],
+    tables: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Font {
+    fn parse(sfnt: &[u8]) -> Result<Self, String> {
+        if sfnt.starts_with(b"ttcf") {
+            return Err("font collections can't be minimized".to_string());
+        }
+        let malformed = || "malformed sfnt".to_string();
+        let num_tables = u16_at(sfnt, 4).ok_or_else(malformed)? as usize;
+        let mut tables = Vec::with_capacity(num_tables);
+        for i in 0..num_tables {
+            let record = 12 + i * 16;
+            let tag = sfnt.get(record..record + 4).ok_or_else(malformed)?;
+            let offset = u32_at(sfnt, record + 8).ok_or_else(malformed)? as usize;
+            let length = u32_at(sfnt, record + 12).ok_or_else(malformed)? as usize;
+            let data = sfnt.get(offset..offset + length).ok_or_else(malformed)?;
+            tables.push((tag.try_into().unwrap(), data.to_vec()));
+        }
+        tables.sort_by_key(|(tag, _)| *tag);
+        Ok(Self {
+            flavor: sfnt[0..4].try_into().unwrap(),
+            tables,
+        })
+    }
+
+    fn table(&self, tag: &[u8; 4]) -> Option<&[u8]> {
+        let (_, data) = self.tables.iter().find(|(t, _)| t == tag)?;
+        Some(data)
+    }
+
+    /// Replace the data of an existing table
+    fn set_table(&mut self, tag: &[u8; 4], data: Vec<u8>) {
+        if let Some((_, table)) = self.tables.iter_mut().find(|(t, _)| t == tag) {
+            *table = data;
+        }
+    }
+
+    fn without_table(&self, tag: &[u8; 4]) -> Self {
+        let mut font = self.clone();
+        font.tables.retain(|(t, _)| t != tag);
+        font
+    }
+
+    /// Serialize the font, with correct checksums and search parameters
+    fn to_sfnt(&self) -> Vec<u8> {
+        let num_tables = self.tables.len();
+        let entry_selector = num_tables.max(1).ilog2();
+        let search_range = 16u16 << entry_selector;
+
+        let mut sfnt = Vec::new();
+        sfnt.extend_from_slice(&self.flavor);
+        sfnt.extend_from_slice(&(num_tables as u16).to_be_bytes());
+        sfnt.extend_from_slice(&search_range.to_be_bytes());
+        sfnt.extend_from_slice(&(entry_selector as u16).to_be_bytes());
+        sfnt.extend_from_slice(
+            &((num_tables as u16 * 16).saturating_sub(search_range)).to_be_bytes(),
+        );
+        let mut offset = 12 + 16 * num_tables;
+        let mut head_offset = None;
+        for (tag, data) in &self.tables {
+            let mut data = data.clone();
+            if tag == b"head" && data.len() >= 12 {
+                // checkSumAdjustment is zero while computing checksums
+                put_u32(&mut data, 8, 0);
+                head_offset = Some(offset);
+            }
+            sfnt.extend_from_slice(tag);
+            sfnt.extend_from_slice(&checksum(&data).to_be_bytes());
+            sfnt.extend_from_slice(&(offset as u32).to_be_bytes());
+            sfnt.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            offset += round4(data.len());
+        }
+        for (tag, data) in &self.tables {
+            let start = sfnt.len();
+            sfnt.extend_from_slice(data);
+            if tag == b"head" && data.len() >= 12 {
+                put_u32(&mut sfnt, start + 8, 0);
+            }
+            sfnt.resize(round4(sfnt.len()), 0);
+        }
+        if let Some(head_offset) = head_offset {
+            let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&sfnt));
+            put_u32(&mut sfnt, head_offset + 8, adjustment);
+        }
+        sfnt
+    }
+
+    /// The data of each glyph, from the glyf and loca tables
+    fn glyphs(&self) -> Option<Vec<Vec<u8>>> {
+        let long_loca = u16_at(self.table(b"head")?, 50)? != 0;
+        let loca = self.table(b"loca")?;
+        let glyf = self.table(b"glyf")?;
+        let offsets: Vec<usize> = if long_loca {
+            loca.chunks_exact(4)
+                .map(|offset| u32::from_be_bytes(offset.try_into().unwrap()) as usize)
+                .collect()
+        } else {
+            loca.chunks_exact(2)
+                .map(|offset| u16::from_be_bytes(offset.try_into().unwrap()) as usize * 2)
+                .collect()
+        };
+        offsets
+            .windows(2)
+            .map(|range| Some(glyf.get(range[0]..range[1])?.to_vec()))
+            .collect()
+    }
+
+    /// Replace the glyf and loca tables (and head.indexToLocFormat), keeping
+    /// the short loca format where the glyph offsets allow it
+    fn with_glyphs(&self, glyphs: &[Vec<u8>]) -> Self {
+        let mut glyf = Vec::new();
+        let mut offsets = vec![0];
+        for glyph in glyphs {
+            glyf.extend_from_slice(glyph);
+            glyf.resize(round4(glyf.len()), 0);
+            offsets.push(glyf.len());
+        }
+        let long_loca = glyf.len() > 2 * u16::MAX as usize;
+        let loca: Vec<u8> = if long_loca {
+            offsets
+                .iter()
+                .flat_map(|&offset| (offset as u32).to_be_bytes())
+                .collect()
+        } else {
+            offsets
+                .iter()
+                .flat_map(|&offset| ((offset / 2) as u16).to_be_bytes())
+                .collect()
+        };
+
+        let mut font = self.clone();
+        let mut head = self.table(b"head").unwrap().to_vec();
+        put_u16(&mut head, 50, long_loca as u16);
+        font.set_table(b"head", head);
+        font.set_table(b"glyf", glyf);
+        font.set_table(b"loca", loca);
+        font
+    }
+
+    /// Keep only the first `num_glyphs` glyphs, updating maxp, hhea and hmtx
+    fn with_num_glyphs(&self, glyphs: &[Vec<u8>], num_glyphs: usize) -> Option<Self> {
+        let mut font = self.with_glyphs(&glyphs[..num_glyphs]);
+        if let Some(maxp) = self.table(b"maxp").filter(|maxp| maxp.len() >= 6) {
+            let mut maxp = maxp.to_vec();
+            put_u16(&mut maxp, 4, num_glyphs as u16);
+            font.set_table(b"maxp", maxp);
+        }
+
+        if let (Some(hhea), Some(hmtx)) = (self.table(b"hhea"), self.table(b"hmtx")) {
+            let num_hmetrics = (u16_at(hhea, 34)? as usize).min(num_glyphs);
+            let mut hhea = hhea.to_vec();
+            put_u16(&mut hhea, 34, num_hmetrics as u16);
+            let hmtx = hmtx.get(..4 * num_hmetrics + 2 * (num_glyphs - num_hmetrics))?;
+            font.set_table(b"hhea", hhea);
+            font.set_table(b"hmtx", hmtx.to_vec());
+        }
+        Some(font)
+    }
+}
+
+/// A reduced font on which the decoders still diverge
+struct Reduced {
+    font: Font,
+    woff2: Vec<u8>,
+}
+
+struct Minimizer {
+    compress: PathBuf,
+    decompress: PathBuf,
+    scratch: PathBuf,
+    /// The divergence to preserve
+    divergence: String,
+    attempts: usize,
+}
+
+impl Minimizer {
+    /// Encode `font` and check whether the decoders still diverge on it in
+    /// the same way, returning the encoded font if so
+    fn reproduces(&mut self, font: &Font) -> Option<Vec<u8>> {
+        self.attempts += 1;
+        let woff2 = prepare::encode_sfnt(&self.compress, &font.to_sfnt(), &self.scratch).ok()?;
+        let outcome = runner::check_font(
+            &self.decompress,
+            &woff2,
+            Expected::AcceptOrReject,
+            &self.scratch,
+        );
+        (outcome.divergence().as_ref() == Some(&self.divergence)).then_some(woff2)
+    }
+
+    /// Keep `font` in place of `best` if it still reproduces the divergence
+    fn try_reduce(&mut self, best: &mut Reduced, font: Font) -> bool {
+        let reproduced = match self.reproduces(&font) {
+            Some(woff2) => {
+                *best = Reduced { font, woff2 };
+                true
+            }
+            None => false,
+        };
+        eprint!(
+            "\r{} attempts, smallest reproducer {} bytes",
+            self.attempts,
+            best.woff2.len()
+        );
+        let _ = std::io::stderr().flush();
+        reproduced
+    }
+
+    fn drop_tables(&mut self, best: &mut Reduced) -> bool {
+        let mut progress = false;
+        let tags: Vec<[u8; 4]> = best.font.tables.iter().map(|(tag, _)| *tag).collect();
+        for tag in tags {
+            let font = best.font.without_table(&tag);
+            progress |= self.try_reduce(best, font);
+        }
+        progress
+    }
+
+    /// Empty ranges of glyphs, halving the range size each round
+    fn empty_glyphs(&mut self, best: &mut Reduced) -> bool {
+        let Some(mut glyphs) = best.font.glyphs() else {
+            return false;
+        };
+        let mut progress = false;
+        let mut chunk = glyphs.len();
+        while chunk > 0 {
+            for start in (0..glyphs.len()).step_by(chunk) {
+                let range = start..(start + chunk).min(glyphs.len());
+                if glyphs[range.clone()].iter().all(|glyph| glyph.is_empty()) {
+                    continue;
+                }
+                let mut candidate = glyphs.clone();
+                candidate[range].iter_mut().for_each(Vec::clear);
+                if self.try_reduce(best, best.font.with_glyphs(&candidate)) {
+                    glyphs = candidate;
+                    progress = true;
+                }
+            }
+            chunk /= 2;
+        }
+        progress
+    }
+
+    /// Truncate the glyph count to the smallest power of two which still
+    /// reproduces the divergence
+    fn truncate_glyphs(&mut self, best: &mut Reduced) -> bool {
+        let Some(glyphs) = best.font.glyphs() else {
+            return false;
+        };
+        let candidates = std::iter::successors(Some(1), |n| Some(n * 2));
+        for num_glyphs in candidates.take_while(|&n| n < glyphs.len()) {
+            if let Some(font) = best.font.with_num_glyphs(&glyphs, num_glyphs) {
+                if self.try_reduce(best, font) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Decode a WOFF2 file for reduction: with the reference decoder if it
+/// accepts the file, otherwise with wuff
+fn decode(decompress: &Path, woff2: &[u8], scratch: &Path) -> Option<Vec<u8>> {
+    runner::cpp_decode(decompress, woff2, scratch)
+        .ok()
+        .or_else(|| {
+            std::panic::catch_unwind(|| wuff::decompress_woff2(woff2))
+                .ok()?
+                .ok()
+        })
+}
+
+/// The name of the reproducer for `font`: its file name up to the first
+/// '.', restricted to characters which are safe in file names
+fn reproducer_name(font: &Path) -> String {
+    let name = font.file_name().unwrap_or_default().to_string_lossy();
+    let stem: String = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("{stem}-min.woff2")
+}
+
+/// Minimize `font`, writing the reproducer to `custom/` in `fonts_dir`
+pub fn run(cfg: &Config, fonts_dir: &Path, font: &Path) {
+    let (compress, decompress) = prepare::ensure_woff2_tools(&cfg.woff2_dir);
+    let scratch = cfg.data_dir.join("tmp").join("minimize");
+    let original = fs::read(font)
+        .unwrap_or_else(|e| fatal(&format!("failed to read {}: {e}", font.display())));
+
+    let outcome = runner::check_font(&decompress, &original, Expected::AcceptOrReject, &scratch);
+    let Some(divergence) = outcome.divergence() else {
+        let (category, _) = runner::describe_outcome(&outcome);
+        fatal(&format!(
+            "the decoders don't diverge on {} ({category})",
+            font.display()
+        ));
+    };
+    let (category, details) = runner::describe_outcome(&outcome);
+    eprintln!("{category}: {}: {details}", font.display());
+
+    let sfnt = decode(&decompress, &original, &scratch).unwrap_or_else(|| {
+        fatal("neither the reference decoder nor wuff can decode the font, so it can't be reduced")
+    });
+    let parsed = Font::parse(&sfnt).unwrap_or_else(|e| fatal(&e));
+    let mut minimizer = Minimizer {
+        compress,
+        decompress,
+        scratch,
+        divergence,
+        attempts: 0,
+    };
+    let woff2 = minimizer.reproduces(&parsed).unwrap_or_else(|| {
+        fatal(&format!(
+            "the {} doesn't reproduce after re-encoding the decoded font with woff2_compress",
+            minimizer.divergence
+        ))
+    });
+    let mut best = Reduced {
+        font: parsed,
+        woff2,
+    };
+    loop {
+        let mut progress = minimizer.drop_tables(&mut best);
+        progress |= minimizer.empty_glyphs(&mut best);
+        progress |= minimizer.truncate_glyphs(&mut best);
+        if !progress {
+            break;
+        }
+    }
+    eprintln!();
+
+    let name = Path::new("custom").join(reproducer_name(font));
+    let path = fonts_dir.join(&name);
+    fs::write(&path, &best.woff2)
+        .unwrap_or_else(|e| fatal(&format!("failed to write {}: {e}", path.display())));
+    let expected = runner::cpp_decode(&minimizer.decompress, &best.woff2, &minimizer.scratch).ok();
+    offline::record_expected(fonts_dir, &name, expected.as_deref()).unwrap_or_else(|e| fatal(&e));
+    let _ = fs::remove_dir_all(&minimizer.scratch);
+
+    println!(
+        "Wrote {} ({} bytes, down from {}; {} tables: {})",
+        path.display(),
+        best.woff2.len(),
+        original.len(),
+        best.font.tables.len(),
+        best.font
+            .tables
+            .iter()
+            .map(|(tag, _)| String::from_utf8_lossy(tag).into_owned())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!(
+        "Recorded the reference decoder's outcome for it in expected.txt, which the offline \
+         check (`cargo test -p conformance`) compares wuff's output against"
+    );
+}
@@ -0,0 +1,304 @@
+//! Offline conformance mode: decode the committed font suites under
+//! `conformance/fonts/` with wuff alone and check the results against the
+//! expected outcomes recorded in `conformance/fonts/expected.txt`.
+//!
+//! Unlike the full harness, this needs neither the C++ reference tools nor
+//! network access. Each line of `expected.txt` is one of:
+//!
+//! ```text
+//! <path> accept <sha256 of the decoded font>
+//! <path> reject
+//! ```
+//!
+//! where `<path>` is relative to `conformance/fonts/`. Blank lines and lines
+//! starting with `#` are ignored. The expectations were recorded from the
+//! output of the C++ reference decoder, so a hash mismatch means that wuff
+//! has diverged from it. After an intended change in wuff's output, the file
+//! can be regenerated from wuff's current output with `--bless` (or
+//! `WUFF_CONFORMANCE_BLESS=1 cargo test -p conformance`); check the result
+//! against the reference decoder with the full harness before committing it.
+//!
+//! Fonts in the wpt suite are also checked against its manifest (see the
+//! `manifest` module), and the results are summarised by spec assertion.
+//! Finally, the WOFF1 decoder is tested differentially (see the `woff1`
+//! module).
+//!
+//! This mode also runs as part of `cargo test`.
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{manifest, woff1};
+
+/// The name of the expectations file, relative to the fonts directory
+const EXPECTED_FILE: &str = "expected.txt";
+
+/// The expected (or actual) result of decoding a font
+#[derive(Clone, PartialEq, Eq)]
+enum Expected {
+    /// The font decodes to output with the given SHA-256 hash (lowercase hex)
+    Accept(String),
+    /// The font is rejected
+    Reject,
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Accept(hash) => write!(f, "accept {hash}"),
+            Self::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        })
+}
+
+/// Decode a font with wuff (as WOFF1 or WOFF2 depending on its extension),
+/// returning its outcome, or an error message if wuff panicked.
+fn decode(name: &Path, data: &[u8]) -> Result<Expected, String> {
+    let decompress = match name.extension() {
+        Some(ext) if ext == "woff" => wuff::decompress_woff1,
+        _ => wuff::decompress_woff2,
+    };
+    match std::panic::catch_unwind(|| decompress(data)) {
+        Ok(Ok(sfnt)) => Ok(Expected::Accept(sha256_hex(&sfnt))),
+        Ok(Err(_)) => Ok(Expected::Reject),
+        Err(payload) => Err(crate::panic_message(payload)),
+    }
+}
+
+/// Parse `expected.txt`, keyed by font path.
+fn parse_expected(text: &str) -> Result<BTreeMap<PathBuf, Expected>, String> {
+    let mut expected = BTreeMap::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let outcome = match fields[1..] {
+            ["accept", hash] if hash.len() == 64 => Expected::Accept(hash.to_string()),
+            ["reject"] => Expected::Reject,
+            _ => return Err(format!("{EXPECTED_FILE}:{}: malformed line", line_no + 1)),
+        };
+        if expected.insert(PathBuf::from(fields[0]), outcome).is_some() {
+            return Err(format!(
+                "{EXPECTED_FILE}:{}: duplicate entry for {}",
+                line_no + 1,
+                fields[0]
+            ));
+        }
+    }
+    Ok(expected)
+}
+
+fn write_expected(path: &Path, outcomes: &BTreeMap<PathBuf, Expected>) {
+    let mut text = String::from(
+        "# Expected outcomes of decoding the fonts in this directory (see conformance/src/offline.rs).\n\
+         # Regenerate with `cargo run -p conformance -- --offline --bless`.\n",
+    );
+    for (name, outcome) in outcomes {
+        writeln!(text, "{} {outcome}", name.display()).unwrap();
+    }
+    fs::write(path, text).expect("failed to write expected outcomes");
+}
+
+/// Add (or replace) the entry for `name` (relative to `fonts_dir`) in
+/// `expected.txt`: `Some(sfnt)` for a font which decodes to `sfnt`, `None`
+/// for one which is rejected.
+#[cfg(feature = "cpp")]
+pub fn record_expected(
+    fonts_dir: &Path,
+    name: &Path,
+    decoded: Option<&[u8]>,
+) -> Result<(), String> {
+    let path = fonts_dir.join(EXPECTED_FILE);
+    let mut outcomes = parse_expected(&fs::read_to_string(&path).unwrap_or_default())?;
+    let outcome = match decoded {
+        Some(sfnt) => Expected::Accept(sha256_hex(sfnt)),
+        None => Expected::Reject,
+    };
+    outcomes.insert(name.to_path_buf(), outcome);
+    write_expected(&path, &outcomes);
+    Ok(())
+}
+
+/// The results of an offline run
+pub struct Report {
+    /// One line per failing font; empty if everything matched
+    pub failures: Vec<String>,
+    /// `(coverage key, passed)` for each font in a suite with a manifest that was checked
+    pub coverage: Vec<(String, bool)>,
+}
+
+/// Decode every committed font under `fonts_dir` whose path contains one of
+/// `filters` (or all of them if there are no filters), and compare the
+/// outcomes with `expected.txt` and the suite manifests. Without filters, the
+/// WOFF1 differential test and the fontTools profile check are run too. With `bless`, `expected.txt` is
+/// rewritten from the current outcomes instead (filters are ignored),
+/// provided that they are consistent with the manifests.
+pub fn check(fonts_dir: &Path, filters: &[String], bless: bool) -> Report {
+    let mut report = Report {
+        failures: Vec::new(),
+        coverage: Vec::new(),
+    };
+    let mut fonts = Vec::new();
+    crate::discover_files(fonts_dir, fonts_dir, &["woff", "woff2"], &mut fonts);
+    fonts.sort();
+
+    let expected_path = fonts_dir.join(EXPECTED_FILE);
+    let expected = if bless {
+        BTreeMap::new()
+    } else {
+        let text = fs::read_to_string(&expected_path).unwrap_or_default();
+        match parse_expected(&text) {
+            Ok(expected) => expected,
+            Err(msg) => {
+                report.failures.push(msg);
+                return report;
+            }
+        }
+    };
+    let mut manifests = BTreeMap::new();
+    for &suite in manifest::SUITES {
+        match manifest::Manifest::load(&fonts_dir.join(suite)) {
+            Ok(manifest) => {
+                manifests.insert(suite, manifest);
+            }
+            Err(msg) => {
+                report.failures.push(format!("{suite}/{msg}"));
+                return report;
+            }
+        }
+    }
+
+    let failures = &mut report.failures;
+    let mut outcomes = BTreeMap::new();
+    for name in &fonts {
+        let path = name.to_string_lossy();
+        if !bless && !filters.is_empty() && !filters.iter().any(|f| path.contains(f.as_str())) {
+            continue;
+        }
+        let data = fs::read(fonts_dir.join(name)).expect("failed to read font");
+        let actual = match decode(name, &data) {
+            Ok(actual) => actual,
+            Err(msg) => {
+                failures.push(format!("PANIC: {path}: wuff panicked: {msg}"));
+                continue;
+            }
+        };
+        let mut passed = true;
+        match expected.get(name) {
+            _ if bless => {}
+            None => {
+                failures.push(format!("MISSING: {path}: no entry in {EXPECTED_FILE}"));
+                passed = false;
+            }
+            Some(expected) if *expected != actual => {
+                failures.push(format!("FAIL: {path}: expected {expected}, got {actual}"));
+                passed = false;
+            }
+            Some(_) => {}
+        }
+        if let Some((suite, file)) = manifest::split_suite_path(name) {
+            match manifests[suite].get(&file) {
+                None => failures.push(format!(
+                    "MISSING: {path}: no entry in {suite}/{}",
+                    manifest::MANIFEST_FILE
+                )),
+                Some(entry) => {
+                    let decoded = matches!(actual, Expected::Accept(_));
+                    if entry.expectation.should_decode() != decoded {
+                        failures.push(format!(
+                            "WRONG OUTCOME: {path}: {}",
+                            manifest::describe_wrong_outcome(entry.expectation, decoded)
+                        ));
+                        passed = false;
+                    }
+                    report
+                        .coverage
+                        .push((manifest::coverage_key(suite, &file, entry), passed));
+                }
+            }
+        }
+        outcomes.insert(name.clone(), actual);
+    }
+
+    if bless {
+        if failures.is_empty() {
+            write_expected(&expected_path, &outcomes);
+        }
+    } else if filters.is_empty() {
+        for name in expected.keys().filter(|name| !fonts.contains(name)) {
+            failures.push(format!(
+                "STALE: {}: listed in {EXPECTED_FILE} but not found",
+                name.display()
+            ));
+        }
+        for (suite, manifest) in &manifests {
+            let suite_dir = fonts_dir.join(suite);
+            for file in manifest
+                .files()
+                .filter(|file| !suite_dir.join(file).is_file())
+            {
+                failures.push(format!(
+                    "STALE: {suite}/{file}: listed in {suite}/{} but not found",
+                    manifest::MANIFEST_FILE
+                ));
+            }
+        }
+    }
+    if filters.is_empty() {
+        failures.extend(woff1::differential(fonts_dir));
+    }
+    report
+}
+
+/// Run the offline checks from the command line, printing a summary.
+/// Returns true if there were failures.
+pub fn run_and_report(fonts_dir: &Path, filters: &[String], bless: bool) -> bool {
+    let Report { failures, coverage } = check(fonts_dir, filters, bless);
+    for failure in &failures {
+        eprintln!("{failure}");
+    }
+    if !coverage.is_empty() {
+        println!("Coverage by spec assertion\n==========================");
+        print!("{}", manifest::coverage_report(coverage));
+    }
+    if failures.is_empty() {
+        if bless {
+            println!("Wrote {}", fonts_dir.join(EXPECTED_FILE).display());
+        } else {
+            println!("All fonts match {EXPECTED_FILE}");
+        }
+    } else {
+        println!("{} failures", failures.len());
+    }
+    !failures.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn committed_fonts_match_expected_outcomes() {
+        let fonts_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fonts");
+        let bless = std::env::var_os("WUFF_CONFORMANCE_BLESS").is_some();
+        let failures = super::check(&fonts_dir, &[], bless).failures;
+        assert!(
+            failures.is_empty(),
+            "{}\n\nIf the change in output is intended, regenerate the expectations with \
+             `WUFF_CONFORMANCE_BLESS=1 cargo test -p conformance`.",
+            failures.join("\n")
+        );
+    }
+}
@@ -186,28 +186,58 @@ fn encode_font(compress: &Path, src: &Path, dst: &Path, scratch: &Path) -> Resul
         return Err(fs::read_to_string(&fail_marker).unwrap_or_default());
     }
 
-    // woff2_compress writes its output next to its input (replacing the
-    // extension), so encode in a private scratch dir to avoid collisions.
     fs::create_dir_all(scratch).map_err(|e| e.to_string())?;
     let ext = src.extension().and_then(|e| e.to_str()).unwrap_or("ttf");
     let input = scratch.join(format!("input.{ext}"));
     fs::copy(src, &input).map_err(|e| e.to_string())?;
+    match run_compress(compress, &input)? {
+        Ok(output) => {
+            fs::create_dir_all(dst.parent().unwrap()).map_err(|e| e.to_string())?;
+            fs::rename(output, dst).map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        Err(msg) => {
+            if let Some(parent) = fail_marker.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let _ = fs::write(&fail_marker, &msg);
+            Err(msg)
+        }
+    }
+}
+
+/// Run woff2_compress on `input`, returning the path of the encoded file, or
+/// the encoder's error message if it rejected the font. The outer error is
+/// for failures to run the encoder at all.
+///
+/// woff2_compress writes its output next to its input (replacing the
+/// extension), so `input` should be in a private scratch dir to avoid
+/// collisions.
+fn run_compress(compress: &Path, input: &Path) -> Result<Result<PathBuf, String>, String> {
     let output = Command::new(compress)
-        .arg(&input)
+        .arg(input)
         .output()
         .map_err(|e| e.to_string())?;
-    if output.status.success() {
-        fs::create_dir_all(dst.parent().unwrap()).map_err(|e| e.to_string())?;
-        fs::rename(scratch.join("input.woff2"), dst).map_err(|e| e.to_string())?;
-        Ok(())
+    Ok(if output.status.success() {
+        Ok(input.with_extension("woff2"))
     } else {
-        let msg = String::from_utf8_lossy(&output.stderr).trim().to_string();
-        if let Some(parent) = fail_marker.parent() {
-            let _ = fs::create_dir_all(parent);
-        }
-        let _ = fs::write(&fail_marker, &msg);
-        Err(msg)
-    }
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    })
+}
+
+/// Encode an in-memory sfnt to WOFF2 with the C++ reference encoder, using
+/// `scratch` as a private working directory.
+pub fn encode_sfnt(compress: &Path, sfnt: &[u8], scratch: &Path) -> Result<Vec<u8>, String> {
+    fs::create_dir_all(scratch).map_err(|e| e.to_string())?;
+    let ext = if sfnt.starts_with(b"ttcf") {
+        "ttc"
+    } else {
+        "ttf"
+    };
+    let input = scratch.join(format!("input.{ext}"));
+    fs::write(&input, sfnt).map_err(|e| e.to_string())?;
+    let output = run_compress(compress, &input)??;
+    fs::read(output).map_err(|e| e.to_string())
 }
 
 /// Encode every source font under `fonts_dir` (given as `fonts`, paths
@@ -11,6 +11,8 @@ use std::process::Command;
 use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::{fatal, manifest};
+
 /// FFI bindings to the C++ shim (src/capi_shim.cpp) which decodes via the
 /// wuff-capi C++ wrapper headers (`woff2::ConvertWOFF2ToTTF` etc).
 mod capi {
@@ -36,13 +38,13 @@ mod capi {
 }
 
 /// Decode a WOFF2 file with the C++ reference decoder binary.
-fn cpp_decode(decompress: &Path, woff2_path: &Path, scratch: &Path) -> Result<Vec<u8>, String> {
+pub fn cpp_decode(decompress: &Path, woff2: &[u8], scratch: &Path) -> Result<Vec<u8>, String> {
     // woff2_decompress writes its output next to its input (truncating at
     // the last '.' and appending ".ttf"), so decode a copy in a private
     // scratch dir rather than writing alongside the input file.
     fs::create_dir_all(scratch).map_err(|e| e.to_string())?;
     let input = scratch.join("decode_input.woff2");
-    fs::copy(woff2_path, &input).map_err(|e| e.to_string())?;
+    fs::write(&input, woff2).map_err(|e| e.to_string())?;
     let output = Command::new(decompress)
         .arg(&input)
         .output()
@@ -146,20 +148,31 @@ fn describe_mismatch(name_a: &str, a: &[u8], name_b: &str, b: &[u8]) -> String {
     msg
 }
 
+fn mismatch(name_a: &'static str, a: &[u8], name_b: &'static str, b: &[u8]) -> Outcome {
+    Outcome::Mismatch {
+        decoders: match (name_a, name_b) {
+            ("cpp", "wuff") => "cpp/wuff",
+            ("cpp", "capi") => "cpp/capi",
+            _ => "wuff/capi",
+        },
+        details: describe_mismatch(name_a, a, name_b, b),
+    }
+}
+
 /// `woff2::kDefaultMaxSize`: the output cap baked into the woff2_decompress
 /// reference binary. Fonts that decompress to more than this are rejected by
 /// the C++ CLI regardless of validity, so for them only wuff and wuff-capi
 /// outputs can be compared.
 const CPP_CLI_MAX_OUTPUT_SIZE: usize = 128 * 1024 * 1024;
 
-enum Outcome {
+pub enum Outcome {
     Pass,
     /// wuff and wuff-capi agree; the C++ CLI could not decode the font only
     /// because its output exceeds the CLI's hardcoded 128MB cap.
     PassCppSizeCapped,
     /// All three decoders rejected an input for which rejection is an
-    /// acceptable outcome (the committed wpt suite contains deliberately
-    /// invalid WOFF2 files).
+    /// acceptable outcome (a wpt font which the manifest marks as invalid,
+    /// or another committed font).
     PassConsistentReject,
     /// All three decoders rejected the font (still suspicious for
     /// encoder-produced input, but at least consistent).
@@ -171,32 +184,55 @@ enum Outcome {
     EncodeFail(String),
     /// The decoders disagreed about whether the font is valid.
     Disagreement(String),
+    /// All three decoders agreed, but accepted a wpt font which the manifest
+    /// marks as invalid, or rejected one which it marks as valid.
+    ManifestMismatch(String),
     /// All three decoders succeeded but the outputs were not byte-identical.
-    Mismatch(String),
+    Mismatch {
+        /// The two decoders whose outputs differ, e.g. "cpp/wuff"
+        decoders: &'static str,
+        details: String,
+    },
     /// The wuff decoder panicked. wuff must never panic on any input, so this
     /// is always a conformance failure.
     Panic(String),
 }
 
-/// Extract a human-readable message from a caught panic payload.
-fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
-    if let Some(s) = payload.downcast_ref::<&str>() {
-        (*s).to_string()
-    } else if let Some(s) = payload.downcast_ref::<String>() {
-        s.clone()
-    } else {
-        "unknown panic".to_string()
+impl Outcome {
+    /// Identifies the kind of divergence between the decoders, so that
+    /// `minimize` can check that a reduced font still fails in the same way:
+    /// which decoders disagree (and how), which outputs differ, or a wuff
+    /// panic. `None` for passes and for failures which aren't divergences.
+    pub fn divergence(&self) -> Option<String> {
+        match self {
+            Self::Disagreement(msg) => Some(format!(
+                "disagreement ({})",
+                msg.split(';').next().unwrap_or_default()
+            )),
+            Self::Mismatch { decoders, .. } => Some(format!("mismatch ({decoders})")),
+            Self::Panic(_) => Some("wuff panic".to_string()),
+            _ => None,
+        }
     }
 }
 
+/// The expected outcome of decoding a `CaseInput::Decode` input.
+#[derive(Copy, Clone)]
+pub enum Expected {
+    /// The font must decode (encoder-produced input should always decode).
+    Accept,
+    /// Consistent rejection by all three decoders is acceptable (committed
+    /// fonts other than the wpt suite).
+    AcceptOrReject,
+    /// The outcome recorded in the wpt manifest.
+    Wpt(manifest::Expectation),
+}
+
 /// What a `TestCase` decodes (or why it can't).
 enum CaseInput {
     /// A ready-made WOFF2 file to decode: an encoded-cache entry for the
-    /// google/fonts corpus, or a committed file for the wpt suite.
-    /// `reject_ok` is true when consistent rejection by all three decoders
-    /// is an acceptable outcome (the wpt suite contains deliberately invalid
-    /// WOFF2 files; encoder-produced input should always decode).
-    Decode { woff2: PathBuf, reject_ok: bool },
+    /// google/fonts corpus, or a committed file such as the wpt suite.
+    Decode { woff2: PathBuf, expected: Expected },
     /// The C++ reference encoder could not encode the source font (recorded
     /// as a `.fail` marker in the cache); there is nothing to decode.
     EncodeFailed(String),
@@ -207,55 +243,72 @@ pub struct TestCase {
     /// Display name, also used for sorting, filtering and reporting.
     pub name: PathBuf,
     input: CaseInput,
+    /// For wpt fonts, the spec assertion (or test group) the font is counted
+    /// under in the coverage report.
+    coverage_key: Option<String>,
 }
 
 fn test_font(decompress: &Path, case: &TestCase, scratch: &Path) -> Outcome {
-    let (woff2, reject_ok) = match &case.input {
-        CaseInput::Decode { woff2, reject_ok } => (woff2, *reject_ok),
+    let (woff2, expected) = match &case.input {
+        CaseInput::Decode { woff2, expected } => (woff2, *expected),
         CaseInput::EncodeFailed(msg) => return Outcome::EncodeFail(msg.clone()),
     };
-    let woff2_bytes = match fs::read(woff2) {
-        Ok(bytes) => bytes,
-        Err(e) => return Outcome::EncodeFail(format!("failed to read encoded font: {e}")),
-    };
+    match fs::read(woff2) {
+        Ok(woff2_bytes) => check_font(decompress, &woff2_bytes, expected, scratch),
+        Err(e) => Outcome::EncodeFail(format!("failed to read encoded font: {e}")),
+    }
+}
 
-    let cpp = cpp_decode(decompress, woff2, scratch);
+/// Decode `woff2_bytes` with all three decoders and compare the results.
+pub fn check_font(
+    decompress: &Path,
+    woff2_bytes: &[u8],
+    expected: Expected,
+    scratch: &Path,
+) -> Outcome {
+    let cpp = cpp_decode(decompress, woff2_bytes, scratch);
     // wuff must never panic on any input: catch unwinding panics and treat
     // them as an outright failure rather than letting them propagate.
     let wuff = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        wuff::decompress_woff2(&woff2_bytes)
+        wuff::decompress_woff2(woff2_bytes)
     })) {
         Ok(result) => result,
-        Err(payload) => return Outcome::Panic(panic_message(payload)),
+        Err(payload) => return Outcome::Panic(crate::panic_message(payload)),
     };
-    let capi = capi::decode(&woff2_bytes);
+    let capi = capi::decode(woff2_bytes);
 
     match (&cpp, &wuff, &capi) {
         // All decoders succeed
         // Compare output for byte-identity
         (Ok(cpp_out), Ok(wuff_out), Ok(capi_out)) => {
-            if cpp_out != wuff_out {
-                Outcome::Mismatch(describe_mismatch("cpp", cpp_out, "wuff", wuff_out))
+            if let Expected::Wpt(expectation @ manifest::Expectation::Invalid) = expected {
+                Outcome::ManifestMismatch(manifest::describe_wrong_outcome(expectation, true))
+            } else if cpp_out != wuff_out {
+                mismatch("cpp", cpp_out, "wuff", wuff_out)
             } else if cpp_out != capi_out {
-                Outcome::Mismatch(describe_mismatch("cpp", cpp_out, "capi", capi_out))
+                mismatch("cpp", cpp_out, "capi", capi_out)
             } else {
                 Outcome::Pass
             }
         }
 
         // All decoders fail
-        // This is counted as a pass for local test cases which deliberately include invalid inputs
-        // It is counted a fail for encoded ttf files (google fonts), which should always be valid.
-        (Err(cpp_err), Err(wuff_err), Err(_)) => {
-            if reject_ok {
+        // This is counted as a pass for committed test cases which are expected to be invalid.
+        // It is counted a fail for encoded ttf files (google fonts), which should always be valid,
+        // and for wpt fonts that the manifest marks as valid.
+        (Err(cpp_err), Err(wuff_err), Err(_)) => match expected {
+            Expected::AcceptOrReject => Outcome::PassConsistentReject,
+            Expected::Wpt(expectation) if !expectation.should_decode() => {
                 Outcome::PassConsistentReject
-            } else {
-                Outcome::ConsistentReject {
-                    cpp_err: cpp_err.clone(),
-                    wuff_err: wuff_err.to_string(),
-                }
             }
-        }
+            Expected::Wpt(expectation) => {
+                Outcome::ManifestMismatch(manifest::describe_wrong_outcome(expectation, false))
+            }
+            Expected::Accept => Outcome::ConsistentReject {
+                cpp_err: cpp_err.clone(),
+                wuff_err: wuff_err.to_string(),
+            },
+        },
 
         // C++ decoder fails, others both succeed.
         //
@@ -265,7 +318,7 @@ fn test_font(decompress: &Path, case: &TestCase, scratch: &Path) -> Outcome {
             if wuff_out == capi_out {
                 Outcome::PassCppSizeCapped
             } else {
-                Outcome::Mismatch(describe_mismatch("wuff", wuff_out, "capi", capi_out))
+                mismatch("wuff", wuff_out, "capi", capi_out)
             }
         }
 
@@ -309,14 +362,16 @@ fn discover_cached_cases(root: &Path, dir: &Path, out: &mut Vec<TestCase>) {
                 name: PathBuf::from(name),
                 input: CaseInput::Decode {
                     woff2: path.clone(),
-                    reject_ok: false,
+                    expected: Expected::Accept,
                 },
+                coverage_key: None,
             });
         } else if let Some(name) = rel.strip_suffix(".fail") {
             let msg = fs::read_to_string(&path).unwrap_or_default();
             out.push(TestCase {
                 name: PathBuf::from(name),
                 input: CaseInput::EncodeFailed(msg),
+                coverage_key: None,
             });
         }
     }
@@ -330,24 +385,43 @@ pub fn discover_cases(encoded_dir: &Path, fonts_dir: &Path) -> Vec<TestCase> {
 
     // Ready-made WOFF2 files committed to the repository (e.g. the wpt
     // conformance suite). These are decoded as-is, with no encoding step.
+    // The expected outcome of each wpt font is recorded in its manifest.
     if fonts_dir.is_dir() {
+        let wpt_manifest =
+            manifest::Manifest::load(&fonts_dir.join("wpt")).unwrap_or_else(|e| fatal(&e));
         let mut font_files = Vec::new();
         crate::discover_files(fonts_dir, fonts_dir, &["woff2"], &mut font_files);
-        cases.extend(font_files.into_iter().map(|rel| TestCase {
-            name: rel.clone(),
-            input: CaseInput::Decode {
-                woff2: fonts_dir.join(&rel),
-                reject_ok: true,
-            },
+        cases.extend(font_files.into_iter().map(|rel| {
+            let (expected, coverage_key) = match manifest::split_suite_path(&rel) {
+                Some(("wpt", file)) => {
+                    let entry = wpt_manifest.get(&file).unwrap_or_else(|| {
+                        fatal(&format!("{file} is missing from the wpt manifest"))
+                    });
+                    (
+                        Expected::Wpt(entry.expectation),
+                        Some(manifest::coverage_key("wpt", &file, entry)),
+                    )
+                }
+                _ => (Expected::AcceptOrReject, None),
+            };
+            TestCase {
+                name: rel.clone(),
+                input: CaseInput::Decode {
+                    woff2: fonts_dir.join(&rel),
+                    expected,
+                },
+                coverage_key,
+            }
         }));
     }
     cases
 }
 
 /// Run every test case in parallel, print a live progress line, write the
-/// report file to `data_dir`, and print a summary. Returns true if there were
-/// conformance failures (mismatches, disagreements, or consistent rejects of
-/// encoder-produced input), i.e. the process should exit non-zero.
+/// report file to `data_dir`, and print a summary (including wpt coverage by
+/// spec assertion). Returns true if there were conformance failures
+/// (mismatches, disagreements, wrong outcomes for wpt fonts, or consistent
+/// rejects of encoder-produced input), i.e. the process should exit non-zero.
 pub fn run_and_report(
     decompress: &Path,
     cases: &[TestCase],
@@ -361,15 +435,20 @@ pub fn run_and_report(
     let failed = AtomicUsize::new(0);
     let scratch_id = AtomicUsize::new(0);
     let failures: Mutex<Vec<(PathBuf, Outcome)>> = Mutex::new(Vec::new());
+    let coverage: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
 
     cases.par_iter().for_each(|case| {
         let scratch = scratch_root.join(scratch_id.fetch_add(1, Ordering::Relaxed).to_string());
         let outcome = test_font(decompress, case, &scratch);
         let _ = fs::remove_dir_all(&scratch);
-        if !matches!(
+        let passed = matches!(
             outcome,
             Outcome::Pass | Outcome::PassCppSizeCapped | Outcome::PassConsistentReject
-        ) {
+        );
+        if let Some(key) = &case.coverage_key {
+            coverage.lock().unwrap().push((key.clone(), passed));
+        }
+        if !passed {
             failed.fetch_add(1, Ordering::Relaxed);
             let (category, details) = describe_outcome(&outcome);
             eprintln!("\r{}: {}: {}", category, case.name.display(), details);
@@ -388,7 +467,7 @@ pub fn run_and_report(
     failures.sort_by(|a, b| a.0.cmp(&b.0));
 
     // Summarise and write a report file.
-    let mut counts = [0usize; 8];
+    let mut counts = [0usize; 9];
     let mut report = String::new();
     for (rel, outcome) in &failures {
         let (category, details) = describe_outcome(outcome);
@@ -407,6 +486,7 @@ pub fn run_and_report(
         correct_reject,
         panic,
         pass_byte_identical,
+        wrong_outcome,
     ] = counts;
     let fail = failures.len();
     let pass = total - fail;
@@ -418,15 +498,23 @@ pub fn run_and_report(
     println!("fail:                      {fail}");
     println!("  mismatch:                {mismatch}");
     println!("  disagreement:            {disagreement}");
+    println!("  wrong outcome (wpt):     {wrong_outcome}");
     println!("  consistent reject ttf:   {consistent_reject}");
     println!("  panic:                   {panic}");
     println!("  encode fail:             {encode_fail}");
     println!("Report written to {}", report_path.display());
 
+    let coverage = coverage.into_inner().unwrap();
+    if !coverage.is_empty() {
+        println!("\nwpt coverage by spec assertion\n==============================");
+        print!("{}", manifest::coverage_report(coverage));
+    }
+
     // Encoder failures don't reflect on the decoders under test; everything
-    // else (mismatches, disagreements, consistent rejects of encoder output,
-    // and any wuff panic) is a conformance failure.
-    mismatch + disagreement + consistent_reject + panic > 0
+    // else (mismatches, disagreements, wrong outcomes for wpt fonts,
+    // consistent rejects of encoder output, and any wuff panic) is a
+    // conformance failure.
+    mismatch + disagreement + wrong_outcome + consistent_reject + panic > 0
 }
 
 fn category_index(outcome: &Outcome) -> usize {
@@ -434,15 +522,16 @@ fn category_index(outcome: &Outcome) -> usize {
         Outcome::EncodeFail(_) => 0,
         Outcome::ConsistentReject { .. } => 1,
         Outcome::Disagreement(_) => 2,
-        Outcome::Mismatch(_) => 3,
+        Outcome::Mismatch { .. } => 3,
         Outcome::PassCppSizeCapped => 4,
         Outcome::PassConsistentReject => 5,
         Outcome::Panic(_) => 6,
         Outcome::Pass => 7,
+        Outcome::ManifestMismatch(_) => 8,
     }
 }
 
-fn describe_outcome(outcome: &Outcome) -> (&'static str, String) {
+pub fn describe_outcome(outcome: &Outcome) -> (&'static str, String) {
     match outcome {
         Outcome::Pass => ("PASS", String::new()),
         Outcome::PassCppSizeCapped => (
@@ -451,7 +540,7 @@ fn describe_outcome(outcome: &Outcome) -> (&'static str, String) {
         ),
         Outcome::PassConsistentReject => (
             "PASS (WPT REJECT)",
-            "all three decoders reject this (possibly deliberately invalid) input".to_string(),
+            "all three decoders reject this (deliberately invalid) input".to_string(),
         ),
         Outcome::EncodeFail(msg) => ("ENCODE FAIL", msg.clone()),
         Outcome::ConsistentReject { cpp_err, wuff_err } => (
@@ -459,7 +548,8 @@ fn describe_outcome(outcome: &Outcome) -> (&'static str, String) {
             format!("cpp error: {cpp_err}; wuff error: {wuff_err}"),
         ),
         Outcome::Disagreement(msg) => ("DISAGREEMENT", msg.clone()),
-        Outcome::Mismatch(msg) => ("MISMATCH", msg.clone()),
+        Outcome::Mismatch { details, .. } => ("MISMATCH", details.clone()),
+        Outcome::ManifestMismatch(msg) => ("WRONG OUTCOME", msg.clone()),
         Outcome::Panic(msg) => ("PANIC", format!("wuff panicked: {msg}")),
     }
 }
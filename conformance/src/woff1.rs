@@ -0,0 +1,325 @@
+//! Differential testing of wuff's WOFF 1.0 decoder.
+//!
+//! There is no WOFF1 reference decoder in this repository, but WOFF1 is a
+//! simple container: decoding a WOFF1 file must reproduce the tables of the
+//! sfnt it was built from. So each source sfnt is encoded to WOFF1 here, with
+//! wuff's test WOFF1 builder (`wuff::test_support::Woff1Builder`), decoded with
+//! `wuff::decompress_woff1`, and the result is checked against the source:
+//! every table's data and checksum must be equal, and the table directory
+//! must be valid. Invalid variants of the encoded files (each violating one
+//! requirement of the spec) must be rejected.
+//!
+//! The source sfnts are the wpt WOFF2 fonts which the wpt manifest marks as
+//! valid (other than collections), decoded with wuff. Their decoded output is pinned to that of the
+//! C++ reference decoder by the hashes in `expected.txt`.
+//!
+//! This is not the W3C WOFF 1.0 test suite, which isn't committed here. In
+//! particular, the placement rules for the extended metadata and private data
+//! blocks aren't tested: no encoded file has a private data block, and one
+//! variant only adds a well-placed metadata block. (Decoding the font only
+//! checks that the blocks lie within the file, and `wuff::read_metadata`,
+//! which reads the metadata block, is tested separately.)
+
+use std::fs;
+use std::path::Path;
+
+use wuff::test_support::Woff1Builder;
+
+use crate::manifest::{Expectation, Manifest};
+
+/// Size of the WOFF1 header
+const HEADER_SIZE: usize = 44;
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn put_u32(data: &mut [u8], at: usize, value: u32) {
+    data[at..at + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// A table read from an sfnt table directory
+pub struct SfntTable<'a> {
+    pub tag: [u8; 4],
+    pub checksum: u32,
+    pub offset: u32,
+    pub data: &'a [u8],
+}
+
+/// Parse the header and table directory of a (non-collection) sfnt,
+/// returning its flavor and tables in directory order.
+pub fn parse_sfnt(sfnt: &[u8]) -> Result<(u32, Vec<SfntTable<'_>>), String> {
+    let malformed = || "malformed sfnt".to_string();
+    let flavor = u32_at(sfnt, 0).ok_or_else(malformed)?;
+    let num_tables = u16_at(sfnt, 4).ok_or_else(malformed)? as usize;
+    let mut tables = Vec::with_capacity(num_tables);
+    for i in 0..num_tables {
+        let record = 12 + i * 16;
+        let tag = sfnt.get(record..record + 4).ok_or_else(malformed)?;
+        let offset = u32_at(sfnt, record + 8).ok_or_else(malformed)?;
+        let length = u32_at(sfnt, record + 12).ok_or_else(malformed)?;
+        let data = sfnt
+            .get(offset as usize..offset as usize + length as usize)
+            .ok_or_else(|| format!("table {} is out of bounds", String::from_utf8_lossy(tag)))?;
+        tables.push(SfntTable {
+            tag: tag.try_into().unwrap(),
+            checksum: u32_at(sfnt, record + 4).ok_or_else(malformed)?,
+            offset,
+            data,
+        });
+    }
+    Ok((flavor, tables))
+}
+
+/// Options for `encode`
+#[derive(Default)]
+struct EncodeOptions {
+    /// Store every table uncompressed
+    uncompressed: bool,
+    /// An (uncompressed) extended metadata document to append
+    metadata: Option<&'static [u8]>,
+}
+
+/// Encode an sfnt as WOFF1, with its tables in tag order (as in the sfnt).
+/// Unless `uncompressed` is set, tables are zlib-compressed where that makes
+/// them smaller.
+fn encode(sfnt: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, String> {
+    parse_sfnt(sfnt)?;
+    let mut builder = Woff1Builder::from_sfnt(sfnt);
+    if !options.uncompressed {
+        builder = builder.compressed();
+    }
+    if let Some(metadata) = options.metadata {
+        builder = builder.metadata(metadata);
+    }
+    Ok(builder.build())
+}
+
+/// Check that `decoded` is a valid sfnt containing exactly the tables of `source`.
+fn compare_with_source(source: &[u8], decoded: &[u8]) -> Result<(), String> {
+    let (source_flavor, source_tables) = parse_sfnt(source)?;
+    let (flavor, tables) = parse_sfnt(decoded)?;
+
+    if flavor != source_flavor {
+        return Err(format!("flavor {flavor:#x} != {source_flavor:#x}"));
+    }
+    if tables.len() != source_tables.len() {
+        return Err(format!(
+            "{} tables, expected {}",
+            tables.len(),
+            source_tables.len()
+        ));
+    }
+
+    // Binary search parameters of the table directory
+    let num_tables = tables.len() as u16;
+    let entry_selector = num_tables.ilog2() as u16;
+    let search_range = (1u16 << entry_selector) * 16;
+    let range_shift = num_tables * 16 - search_range;
+    if [u16_at(decoded, 6), u16_at(decoded, 8), u16_at(decoded, 10)]
+        != [Some(search_range), Some(entry_selector), Some(range_shift)]
+    {
+        return Err("incorrect searchRange/entrySelector/rangeShift".to_string());
+    }
+
+    // Tables are sorted by tag, 4-byte aligned, after the directory, and don't overlap
+    let mut extents = Vec::new();
+    for (i, table) in tables.iter().enumerate() {
+        let tag = String::from_utf8_lossy(&table.tag);
+        if i > 0 && tables[i - 1].tag >= table.tag {
+            return Err(format!("table directory is not sorted by tag at {tag}"));
+        }
+        if table.offset % 4 != 0 || (table.offset as usize) < 12 + tables.len() * 16 {
+            return Err(format!("bad offset {} for table {tag}", table.offset));
+        }
+        extents.push((
+            table.offset as usize,
+            table.offset as usize + table.data.len(),
+        ));
+
+        let source = &source_tables[i];
+        if source.tag != table.tag {
+            return Err(format!(
+                "table {tag} found where {} was expected",
+                String::from_utf8_lossy(&source.tag)
+            ));
+        }
+        if source.checksum != table.checksum {
+            return Err(format!("checksum of table {tag} differs"));
+        }
+        if source.data != table.data {
+            return Err(format!("data of table {tag} differs"));
+        }
+    }
+    extents.sort();
+    if extents.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+        return Err("tables overlap".to_string());
+    }
+    Ok(())
+}
+
+/// An invalid variant of an encoded WOFF1 file, which must be rejected
+struct Mutation {
+    name: &'static str,
+    apply: fn(&mut Vec<u8>),
+}
+
+/// Offset of the first table directory entry
+const ENTRY: usize = HEADER_SIZE;
+
+const MUTATIONS: &[Mutation] = &[
+    Mutation {
+        name: "bad signature",
+        apply: |woff| woff[0..4].copy_from_slice(b"wOF2"),
+    },
+    Mutation {
+        // <https://www.w3.org/TR/WOFF/#conform-reserved>
+        name: "non-zero reserved field",
+        apply: |woff| woff[14..16].copy_from_slice(&1u16.to_be_bytes()),
+    },
+    Mutation {
+        name: "length field doesn't match the file size",
+        apply: |woff| {
+            let length = woff.len() as u32;
+            put_u32(woff, 8, length + 4);
+        },
+    },
+    Mutation {
+        name: "truncated file",
+        apply: |woff| {
+            woff.truncate(woff.len() - 4);
+            let length = woff.len() as u32;
+            put_u32(woff, 8, length);
+        },
+    },
+    Mutation {
+        name: "zero numTables",
+        apply: |woff| woff[12..14].copy_from_slice(&0u16.to_be_bytes()),
+    },
+    Mutation {
+        name: "table data out of bounds",
+        apply: |woff| {
+            let length = woff.len() as u32;
+            put_u32(woff, ENTRY + 4, length);
+        },
+    },
+    Mutation {
+        name: "compLength greater than origLength",
+        apply: |woff| {
+            let orig_length = u32_at(woff, ENTRY + 12).unwrap();
+            put_u32(woff, ENTRY + 8, orig_length + 1);
+        },
+    },
+    Mutation {
+        name: "compressed table decompresses to the wrong size",
+        apply: |woff| {
+            let orig_length = u32_at(woff, ENTRY + 12).unwrap();
+            put_u32(woff, ENTRY + 12, orig_length + 1);
+        },
+    },
+    Mutation {
+        name: "corrupt zlib stream",
+        apply: |woff| {
+            let offset = u32_at(woff, ENTRY + 4).unwrap() as usize;
+            woff[offset] ^= 0xff;
+        },
+    },
+];
+
+/// Run the differential test against the valid wpt fonts under `fonts_dir`,
+/// returning a list of failures (empty if everything passed).
+pub fn differential(fonts_dir: &Path) -> Vec<String> {
+    let wpt_dir = fonts_dir.join("wpt");
+    let manifest = match Manifest::load(&wpt_dir) {
+        Ok(manifest) => manifest,
+        Err(msg) => return vec![msg],
+    };
+    let sources: Vec<&str> = manifest
+        .files()
+        .filter(|file| manifest.get(file).unwrap().expectation == Expectation::Valid)
+        .collect();
+
+    let mut failures = Vec::new();
+    for file in sources {
+        let woff2 = fs::read(wpt_dir.join(file)).expect("failed to read font");
+        let Ok(source) = wuff::decompress_woff2(&woff2) else {
+            failures.push(format!("WOFF1: wpt/{file}: source font failed to decode"));
+            continue;
+        };
+        // WOFF1 can't contain font collections
+        if source.starts_with(b"ttcf") {
+            continue;
+        }
+        let mut fail = |what: &str, msg: String| {
+            failures.push(format!("WOFF1: wpt/{file} ({what}): {msg}"));
+        };
+
+        let variants = [
+            ("compressed", EncodeOptions::default()),
+            (
+                "uncompressed",
+                EncodeOptions {
+                    uncompressed: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "with metadata",
+                EncodeOptions {
+                    metadata: Some(
+                        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><metadata version=\"1.0\"/>",
+                    ),
+                    ..Default::default()
+                },
+            ),
+        ];
+        let mut compressed_woff = None;
+        for (what, options) in &variants {
+            let woff = match encode(&source, options) {
+                Ok(woff) => woff,
+                Err(msg) => {
+                    fail(what, format!("failed to encode: {msg}"));
+                    continue;
+                }
+            };
+            match std::panic::catch_unwind(|| wuff::decompress_woff1(&woff)) {
+                Ok(Ok(decoded)) => {
+                    if let Err(msg) = compare_with_source(&source, &decoded) {
+                        fail(what, msg);
+                    }
+                }
+                Ok(Err(err)) => fail(what, format!("rejected: {err}")),
+                Err(payload) => fail(what, format!("panicked: {}", crate::panic_message(payload))),
+            }
+            if !options.uncompressed && options.metadata.is_none() {
+                compressed_woff = Some(woff);
+            }
+        }
+
+        let Some(woff) = compressed_woff else {
+            continue;
+        };
+        // The mutations that corrupt the first table need it to be compressed
+        let first_table_compressed = u32_at(&woff, ENTRY + 8) < u32_at(&woff, ENTRY + 12);
+        for mutation in MUTATIONS {
+            if !first_table_compressed && mutation.name.contains("compress") {
+                continue;
+            }
+            let mut mutated = woff.clone();
+            (mutation.apply)(&mut mutated);
+            match std::panic::catch_unwind(|| wuff::decompress_woff1(&mutated)) {
+                Ok(Ok(_)) => fail(mutation.name, "accepted an invalid file".to_string()),
+                Ok(Err(_)) => {}
+                Err(payload) => fail(
+                    mutation.name,
+                    format!("panicked: {}", crate::panic_message(payload)),
+                ),
+            }
+        }
+    }
+    failures
+}
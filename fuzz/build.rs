@@ -0,0 +1,29 @@
+fn main() {
+    // The C++ reference decoder is only needed for the differential targets
+    // (the `cpp` feature). It requires a C++ compiler and the brotli decoder library.
+    if std::env::var_os("CARGO_FEATURE_CPP").is_none() {
+        return;
+    }
+    let woff2_dir = std::path::Path::new("../woff2");
+    println!("cargo::rerun-if-changed=src/woff2_shim.cc");
+    println!("cargo::rerun-if-changed=../woff2/src");
+    cc::Build::new()
+        .cpp(true)
+        .std("c++11")
+        .include(woff2_dir.join("include"))
+        .include(woff2_dir.join("src"))
+        .files(
+            [
+                "table_tags.cc",
+                "variable_length.cc",
+                "woff2_common.cc",
+                "woff2_dec.cc",
+                "woff2_out.cc",
+            ]
+            .map(|file| woff2_dir.join("src").join(file)),
+        )
+        .file("src/woff2_shim.cc")
+        .warnings(false)
+        .compile("woff2_reference");
+    println!("cargo::rustc-link-lib=brotlidec");
+}
@@ -0,0 +1,177 @@
+//! Generate the seed corpora for the fuzz targets from the committed
+//! conformance fonts (`conformance/fonts/`):
+//!
+//! - `decompress_woff2` and `differential`: the WOFF2 files themselves
+//! - `decompress_woff1`: the decoded fonts, re-encoded as WOFF1
+//! - `glyf_transform`, `woff2_structured` and `differential_structured`:
+//!   their transformed glyf tables
+//! - `hmtx_transform`: their transformed hmtx tables, prefixed by numGlyphs and numberOfHMetrics
+//! - `variable_length`: their table directories (UIntBase128 values) and
+//!   transformed glyf nPoints streams (255UInt16 values)
+//!
+//! Usage: `cargo run --example seed_corpus` (from the `fuzz/` directory).
+//! Writes to `corpus/<target>/`.
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn round4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Encode a (non-collection) sfnt as WOFF1, compressing tables where that makes them smaller
+fn encode_woff1(sfnt: &[u8]) -> Option<Vec<u8>> {
+    let num_tables = u16_at(sfnt, 4)? as usize;
+    let mut directory = Vec::new();
+    let mut data = Vec::new();
+    let data_start = 44 + 20 * num_tables;
+    let mut total_sfnt_size = 12 + 16 * num_tables;
+    for i in 0..num_tables {
+        let record = 12 + 16 * i;
+        let offset = u32_at(sfnt, record + 8)? as usize;
+        let length = u32_at(sfnt, record + 12)? as usize;
+        let table = sfnt.get(offset..offset + length)?;
+        total_sfnt_size += round4(length);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(table).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let stored = if compressed.len() < table.len() {
+            &compressed[..]
+        } else {
+            table
+        };
+
+        directory.extend_from_slice(&sfnt[record..record + 4]); // tag
+        directory.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+        directory.extend_from_slice(&(stored.len() as u32).to_be_bytes());
+        directory.extend_from_slice(&(length as u32).to_be_bytes());
+        directory.extend_from_slice(&sfnt[record + 4..record + 8]); // checksum
+        data.extend_from_slice(stored);
+        data.resize(round4(data.len()), 0);
+    }
+
+    let mut woff = Vec::with_capacity(data_start + data.len());
+    woff.extend_from_slice(b"wOFF");
+    woff.extend_from_slice(&sfnt[0..4]); // flavor
+    woff.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+    woff.extend_from_slice(&(num_tables as u16).to_be_bytes());
+    woff.extend_from_slice(&0u16.to_be_bytes()); // reserved
+    woff.extend_from_slice(&(total_sfnt_size as u32).to_be_bytes());
+    woff.extend_from_slice(&1u16.to_be_bytes()); // majorVersion
+    woff.extend_from_slice(&0u16.to_be_bytes()); // minorVersion
+    woff.extend_from_slice(&[0; 20]); // no metadata or private data
+    woff.extend_from_slice(&directory);
+    woff.extend_from_slice(&data);
+    Some(woff)
+}
+
+fn discover_fonts(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).expect("failed to read fonts directory") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            discover_fonts(&path, out);
+        } else if path.extension().is_some_and(|ext| ext == "woff2") {
+            out.push(path);
+        }
+    }
+}
+
+fn main() {
+    let fuzz_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let fonts_dir = fuzz_dir.join("../conformance/fonts");
+    let corpus_dir = fuzz_dir.join("corpus");
+
+    let mut fonts = Vec::new();
+    discover_fonts(&fonts_dir, &mut fonts);
+    fonts.sort();
+
+    let write = |target: &str, name: &str, data: &[u8]| {
+        let dir = corpus_dir.join(target);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(name), data).unwrap();
+    };
+
+    for path in &fonts {
+        let woff2 = fs::read(path).unwrap();
+        let name = path
+            .strip_prefix(&fonts_dir)
+            .unwrap()
+            .with_extension("")
+            .to_string_lossy()
+            .replace(['/', '\\'], "-");
+
+        write("decompress_woff2", &name, &woff2);
+        write("differential", &name, &woff2);
+        if let Some(woff1) = wuff::decompress_woff2(&woff2)
+            .ok()
+            .filter(|sfnt| !sfnt.starts_with(b"ttcf"))
+            .and_then(|sfnt| encode_woff1(&sfnt))
+        {
+            write("decompress_woff1", &name, &woff1);
+        }
+        if let Some(directory) = woff2.get(48..) {
+            write(
+                "variable_length",
+                &format!("{name}-directory"),
+                &[&[1], directory].concat(),
+            );
+        }
+
+        let Ok(tables) = wuff::fuzzing::woff2_tables(&woff2) else {
+            continue;
+        };
+        let find = |tag: &[u8; 4]| tables.iter().find(|table| &table.tag == tag);
+        let Some(glyf) = find(b"glyf").filter(|table| table.transformed) else {
+            continue;
+        };
+        write("glyf_transform", &name, &glyf.data);
+        write("woff2_structured", &name, &glyf.data);
+        write("differential_structured", &name, &glyf.data);
+
+        // The nPoints stream follows the header (36 bytes) and the nContour stream
+        if let Some(n_contour_size) = u32_at(&glyf.data, 8) {
+            let n_points_size = u32_at(&glyf.data, 12).unwrap_or(0) as usize;
+            let start = 36 + n_contour_size as usize;
+            if let Some(n_points) = glyf.data.get(start..start + n_points_size) {
+                write(
+                    "variable_length",
+                    &format!("{name}-npoints"),
+                    &[&[0], n_points].concat(),
+                );
+            }
+        }
+
+        let hhea = find(b"hhea");
+        let hmtx = find(b"hmtx").filter(|table| table.transformed);
+        if let (Some(hhea), Some(hmtx)) = (hhea, hmtx) {
+            let (Some(num_glyphs), Some(num_hmetrics)) =
+                (u16_at(&glyf.data, 4), u16_at(&hhea.data, 34))
+            else {
+                continue;
+            };
+            let mut seed = Vec::new();
+            seed.extend_from_slice(&num_glyphs.to_be_bytes());
+            seed.extend_from_slice(&num_hmetrics.to_be_bytes());
+            seed.extend_from_slice(&hmtx.data);
+            write("hmtx_transform", &name, &seed);
+        }
+    }
+
+    for entry in fs::read_dir(&corpus_dir).unwrap() {
+        let dir = entry.unwrap().path();
+        let count = fs::read_dir(&dir).unwrap().count();
+        println!("{}: {count} seeds", dir.display());
+    }
+}
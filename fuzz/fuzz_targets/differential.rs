@@ -0,0 +1,9 @@
+//! Check that wuff and the C++ reference decoder agree on a WOFF2 file.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    wuff_fuzz::differential::assert_decoders_agree(data);
+});
@@ -0,0 +1,11 @@
+//! Check that wuff and the C++ reference decoder agree on generated WOFF2
+//! files (see `woff2_structured`).
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wuff_fuzz::Woff2Font;
+
+fuzz_target!(|font: Woff2Font| {
+    wuff_fuzz::differential::assert_decoders_agree(&font.build());
+});
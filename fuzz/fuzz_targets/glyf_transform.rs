@@ -0,0 +1,11 @@
+//! Reconstruct glyf/loca from raw transformed glyf table bytes.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((num_glyphs, x_mins)) = wuff::fuzzing::decode_glyf_table(data) {
+        assert_eq!(x_mins.len(), num_glyphs as usize);
+    }
+});
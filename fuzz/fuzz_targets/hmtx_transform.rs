@@ -0,0 +1,26 @@
+//! Reconstruct hmtx from a transformed hmtx table.
+//!
+//! Input: numGlyphs (u16), numberOfHMetrics (u16), then the transformed table.
+//! The glyphs' x_min values (normally taken from the glyf table) are synthesized.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((params, table)) = data.split_first_chunk::<4>() else {
+        return;
+    };
+    let num_glyphs = u16::from_be_bytes([params[0], params[1]]);
+    let num_hmetrics = u16::from_be_bytes([params[2], params[3]]);
+    let x_mins: Vec<i16> = (0..num_glyphs).map(|i| i as i16).collect();
+
+    if let Ok(hmtx) =
+        wuff::fuzzing::decode_hmtx_table_data(table, num_glyphs, num_hmetrics, &x_mins)
+    {
+        assert_eq!(
+            hmtx.len(),
+            2 * num_glyphs as usize + 2 * num_hmetrics as usize
+        );
+    }
+});
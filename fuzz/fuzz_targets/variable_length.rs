@@ -0,0 +1,22 @@
+//! Read a sequence of 255UInt16 (if the first byte is even) or UIntBase128
+//! (if it is odd) values.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, mut data)) = data.split_first() else {
+        return;
+    };
+    let (read, max_value, max_len): (fn(&[u8]) -> _, u32, usize) = if selector % 2 == 0 {
+        (wuff::fuzzing::read_255_u16, u16::MAX as u32, 3)
+    } else {
+        (wuff::fuzzing::read_base128, u32::MAX, 5)
+    };
+    while let Ok((value, len)) = read(data) {
+        assert!(value <= max_value);
+        assert!(len >= 1 && len <= max_len && len <= data.len());
+        data = &data[len..];
+    }
+});
@@ -0,0 +1,12 @@
+//! Decode generated WOFF2 files with a valid container (header, table
+//! directory, brotli stream) around fuzzed table data and transform
+//! substreams, so that inputs get past the header checks.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wuff_fuzz::Woff2Font;
+
+fuzz_target!(|font: Woff2Font| {
+    let _ = wuff::decompress_woff2(&font.build());
+});
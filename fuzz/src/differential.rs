@@ -0,0 +1,117 @@
+//! Differential testing against the vendored C++ reference decoder
+//! (`woff2/src/woff2_dec.cc`), which is linked in-process by `build.rs`.
+//!
+//! Only compiled with the `cpp` feature.
+
+unsafe extern "C" {
+    fn wuff_fuzz_cpp_decode(data: *const u8, length: usize, result_length: *mut usize) -> *mut u8;
+    fn wuff_fuzz_cpp_free(ptr: *mut u8);
+}
+
+/// Decode a WOFF2 file with the C++ reference decoder, returning None if it is rejected.
+pub fn cpp_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut len = 0usize;
+    let ptr = unsafe { wuff_fuzz_cpp_decode(data.as_ptr(), data.len(), &mut len) };
+    if ptr.is_null() {
+        return None;
+    }
+    let out = unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec();
+    unsafe { wuff_fuzz_cpp_free(ptr) };
+    Some(out)
+}
+
+/// Whether the decoders are known to handle `data` differently. Both cases
+/// are malformed fonts that the reference encoder never produces:
+///
+/// - For a single font, the C++ decoder reconstructs tables in table directory
+///   order (and needs glyf and hhea before a transformed hmtx), while wuff
+///   always uses tag order. So their results differ for unsorted directories.
+/// - For a font in a collection that contains the same tag twice, the C++
+///   decoder's table directory keeps only one of them, and stale entries.
+fn known_difference(data: &[u8]) -> bool {
+    let Ok(fonts) = wuff::fuzzing::woff2_font_tags(data) else {
+        return false;
+    };
+    fonts.iter().any(|tags| {
+        let mut sorted = tags.clone();
+        sorted.sort();
+        sorted.dedup();
+        sorted.len() != tags.len() || (fonts.len() == 1 && sorted != *tags)
+    })
+}
+
+/// Panic unless wuff and the C++ reference decoder both reject `data`, or
+/// both accept it and produce byte-identical output.
+pub fn assert_decoders_agree(data: &[u8]) {
+    if known_difference(data) {
+        return;
+    }
+    match (cpp_decode(data), wuff::decompress_woff2(data)) {
+        (None, Err(_)) => {}
+        (Some(_), Err(err)) => {
+            panic!("the C++ decoder accepted the font, but wuff rejected it: {err}")
+        }
+        (None, Ok(_)) => panic!("wuff accepted the font, but the C++ decoder rejected it"),
+        (Some(cpp), Ok(wuff)) => {
+            if let Some(offset) = cpp.iter().zip(&wuff).position(|(a, b)| a != b) {
+                panic!("the decoded fonts differ at offset {offset}");
+            }
+            assert_eq!(
+                cpp.len(),
+                wuff.len(),
+                "the decoded fonts differ in length (C++, wuff)"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Table, TransformedGlyf, Woff2Font};
+
+    /// A font with two empty glyphs, and `tables`
+    fn font(tables: Vec<Table>) -> Vec<u8> {
+        let glyf = TransformedGlyf {
+            option_flags: 0,
+            long_loca: false,
+            n_contours: vec![0, 0],
+            n_points: Vec::new(),
+            flags: Vec::new(),
+            glyphs: Vec::new(),
+            composites: Vec::new(),
+            bbox_bitmap: Vec::new(),
+            bboxes: Vec::new(),
+            instructions: Vec::new(),
+            overlap_bitmap: Vec::new(),
+        };
+        let font = Woff2Font {
+            cff: false,
+            glyf: Some(glyf),
+            hmtx: None,
+            tables,
+        };
+        font.build()
+    }
+
+    /// The reference decoder accepts fonts whose maxp and hhea tables disagree with the
+    /// glyf table about the glyphs, and so must wuff
+    #[test]
+    fn glyph_count_mismatches() {
+        let maxp = |num_glyphs: u16| {
+            let mut data = vec![0, 0, 0x50, 0];
+            data.extend_from_slice(&num_glyphs.to_be_bytes());
+            Table { tag_index: 4, data }
+        };
+        let hhea = |num_hmetrics: u16| {
+            let mut data = vec![0; 36];
+            data[34..36].copy_from_slice(&num_hmetrics.to_be_bytes());
+            Table { tag_index: 2, data }
+        };
+        for tables in [vec![maxp(3)], vec![hhea(0)], vec![hhea(3)]] {
+            let woff2 = font(tables);
+            assert!(cpp_decode(&woff2).is_some());
+            assert_decoders_agree(&woff2);
+        }
+    }
+}
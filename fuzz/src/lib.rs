@@ -0,0 +1,289 @@
+//! Structure-aware WOFF2 generator for the `woff2_structured` fuzz target.
+//!
+//! Random bytes almost never get past the WOFF2 header and table directory
+//! checks, let alone through brotli decompression. [`Woff2Font`] is generated
+//! by `arbitrary` instead, and [`Woff2Font::build`] wraps the generated table
+//! data and glyf/hmtx transform substreams in a valid container: a correct
+//! header, table directory and brotli stream, with consistent lengths. This
+//! way the fuzzer spends its time on the table reconstruction code.
+//!
+//! <https://www.w3.org/TR/WOFF2/>
+
+#[cfg(feature = "cpp")]
+pub mod differential;
+
+use std::collections::BTreeMap;
+use std::io::Write as _;
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+
+/// Known table tag indices used in the table directory flags
+/// <https://www.w3.org/TR/WOFF2/#table_dir_format>
+const HEAD: u8 = 1;
+const HHEA: u8 = 2;
+const HMTX: u8 = 3;
+const GLYF: u8 = 10;
+const LOCA: u8 = 11;
+/// Flags value indicating that an arbitrary tag follows
+const ARBITRARY_TAG: u8 = 63;
+
+/// The tables that can be generated: (known tag index, tag)
+const TAGS: &[(u8, [u8; 4])] = &[
+    (0, *b"cmap"),
+    (HEAD, *b"head"),
+    (HHEA, *b"hhea"),
+    (HMTX, *b"hmtx"),
+    (4, *b"maxp"),
+    (5, *b"name"),
+    (6, *b"OS/2"),
+    (7, *b"post"),
+    (8, *b"cvt "),
+    (9, *b"fpgm"),
+    (GLYF, *b"glyf"),
+    (LOCA, *b"loca"),
+    (12, *b"prep"),
+    (13, *b"CFF "),
+    (17, *b"gasp"),
+    (ARBITRARY_TAG, *b"zzzz"),
+];
+
+fn tag_for(index: u8) -> [u8; 4] {
+    TAGS.iter().find(|(i, _)| *i == index).unwrap().1
+}
+
+/// A WOFF2 font (not a collection)
+#[derive(Arbitrary, Debug)]
+pub struct Woff2Font {
+    /// Whether the flavor is 'OTTO' (rather than TrueType)
+    pub cff: bool,
+    /// A transformed glyf table (and the corresponding loca table)
+    pub glyf: Option<TransformedGlyf>,
+    /// A transformed hmtx table (and the hhea table it depends on)
+    pub hmtx: Option<TransformedHmtx>,
+    /// Other tables, stored with the null transform
+    pub tables: Vec<Table>,
+}
+
+/// A table stored with the null transform
+#[derive(Arbitrary, Debug)]
+pub struct Table {
+    /// Index into `TAGS`, modulo its length
+    pub tag_index: u8,
+    pub data: Vec<u8>,
+}
+
+/// A transformed glyf table
+/// <https://www.w3.org/TR/WOFF2/#glyf_table_format>
+#[derive(Arbitrary, Debug)]
+pub struct TransformedGlyf {
+    pub option_flags: u16,
+    /// Whether loca uses 32-bit offsets
+    pub long_loca: bool,
+    /// The nContour stream. Its length determines numGlyphs.
+    pub n_contours: Vec<i16>,
+    pub n_points: Vec<u8>,
+    pub flags: Vec<u8>,
+    pub glyphs: Vec<u8>,
+    pub composites: Vec<u8>,
+    /// The bounding box bitmap, resized to the length required by numGlyphs
+    pub bbox_bitmap: Vec<u8>,
+    pub bboxes: Vec<u8>,
+    pub instructions: Vec<u8>,
+    /// The overlap bitmap (if enabled by `option_flags`), resized to the length required by numGlyphs
+    pub overlap_bitmap: Vec<u8>,
+}
+
+/// A transformed hmtx table
+/// <https://www.w3.org/TR/WOFF2/#hmtx_table_format>
+#[derive(Arbitrary, Debug)]
+pub struct TransformedHmtx {
+    /// numberOfHMetrics, stored in the generated hhea table
+    pub num_hmetrics: u16,
+    pub flags: u8,
+    pub data: Vec<u8>,
+}
+
+/// A table directory entry and the table's data
+struct Entry {
+    flags: u8,
+    orig_length: u32,
+    /// Only written for transformed tables
+    transform_length: Option<u32>,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    fn null_transform(index: u8, data: Vec<u8>) -> Self {
+        // The null transform is version 3 for glyf and loca, and 0 for everything else
+        let version = if index == GLYF || index == LOCA { 3 } else { 0 };
+        Self {
+            flags: index | (version << 6),
+            orig_length: data.len() as u32,
+            transform_length: None,
+            data,
+        }
+    }
+}
+
+impl TransformedGlyf {
+    fn num_glyphs(&self) -> u16 {
+        self.n_contours.len().min(u16::MAX as usize) as u16
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let num_glyphs = self.num_glyphs() as usize;
+        let n_contours: Vec<u8> = self.n_contours[..num_glyphs]
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect();
+        let mut bboxes = self.bbox_bitmap.clone();
+        bboxes.resize(((num_glyphs + 31) >> 5) << 2, 0);
+        bboxes.extend_from_slice(&self.bboxes);
+        let streams = [
+            &n_contours[..],
+            &self.n_points,
+            &self.flags,
+            &self.glyphs,
+            &self.composites,
+            &bboxes,
+            &self.instructions,
+        ];
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&0u16.to_be_bytes()); // reserved
+        out.extend_from_slice(&self.option_flags.to_be_bytes());
+        out.extend_from_slice(&(num_glyphs as u16).to_be_bytes());
+        out.extend_from_slice(&(self.long_loca as u16).to_be_bytes());
+        for stream in streams {
+            out.extend_from_slice(&(stream.len() as u32).to_be_bytes());
+        }
+        for stream in streams {
+            out.extend_from_slice(stream);
+        }
+        if self.option_flags & 1 != 0 {
+            let mut overlap_bitmap = self.overlap_bitmap.clone();
+            overlap_bitmap.resize((num_glyphs + 7) >> 3, 0);
+            out.extend_from_slice(&overlap_bitmap);
+        }
+        out
+    }
+}
+
+/// Append a UIntBase128
+fn put_base128(out: &mut Vec<u8>, value: u32) {
+    let size = (1..5).find(|&size| value >> (7 * size) == 0).unwrap_or(5);
+    for i in (0..size).rev() {
+        let continuation = if i > 0 { 0x80 } else { 0 };
+        out.push(((value >> (7 * i)) & 0x7f) as u8 | continuation);
+    }
+}
+
+fn round4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+impl Woff2Font {
+    /// Encode the font as a WOFF2 file
+    pub fn build(&self) -> Vec<u8> {
+        // Keyed by tag, so that tags are unique and the table directory is
+        // sorted by tag (as written by the reference encoder)
+        let mut entries: BTreeMap<[u8; 4], Entry> = BTreeMap::new();
+        for table in &self.tables {
+            let (index, tag) = TAGS[table.tag_index as usize % TAGS.len()];
+            entries.insert(tag, Entry::null_transform(index, table.data.clone()));
+        }
+
+        if let Some(glyf) = &self.glyf {
+            let data = glyf.encode();
+            let loca_length = (glyf.num_glyphs() as u32 + 1) * if glyf.long_loca { 4 } else { 2 };
+            entries.insert(
+                tag_for(GLYF),
+                Entry {
+                    flags: GLYF,
+                    orig_length: data.len() as u32,
+                    transform_length: Some(data.len() as u32),
+                    data,
+                },
+            );
+            entries.insert(
+                tag_for(LOCA),
+                Entry {
+                    flags: LOCA,
+                    orig_length: loca_length,
+                    transform_length: Some(0),
+                    data: Vec::new(),
+                },
+            );
+            // head.indexToLocFormat
+            let mut head = vec![0; 54];
+            head[50..52].copy_from_slice(&(glyf.long_loca as u16).to_be_bytes());
+            entries.insert(tag_for(HEAD), Entry::null_transform(HEAD, head));
+        }
+
+        if let Some(hmtx) = &self.hmtx {
+            let mut data = vec![hmtx.flags];
+            data.extend_from_slice(&hmtx.data);
+            entries.insert(
+                tag_for(HMTX),
+                Entry {
+                    flags: HMTX | (1 << 6),
+                    orig_length: data.len() as u32,
+                    transform_length: Some(data.len() as u32),
+                    data,
+                },
+            );
+            // hhea.numberOfHMetrics
+            let mut hhea = vec![0; 36];
+            hhea[34..36].copy_from_slice(&hmtx.num_hmetrics.to_be_bytes());
+            entries.insert(tag_for(HHEA), Entry::null_transform(HHEA, hhea));
+        }
+
+        // A font needs at least one table
+        if entries.is_empty() {
+            entries.insert(tag_for(HEAD), Entry::null_transform(HEAD, vec![0; 54]));
+        }
+
+        // Table directory and the (uncompressed) font data
+        let mut directory = Vec::new();
+        let mut font_data = Vec::new();
+        for (tag, entry) in &entries {
+            directory.push(entry.flags);
+            if entry.flags & 63 == ARBITRARY_TAG {
+                directory.extend_from_slice(tag);
+            }
+            put_base128(&mut directory, entry.orig_length);
+            if let Some(transform_length) = entry.transform_length {
+                put_base128(&mut directory, transform_length);
+            }
+            font_data.extend_from_slice(&entry.data);
+        }
+
+        let mut compressor = brotli::CompressorWriter::new(Vec::new(), 4096, 1, 22);
+        compressor.write_all(&font_data).unwrap();
+        let compressed = compressor.into_inner();
+
+        let total_sfnt_size = 12
+            + 16 * entries.len()
+            + entries
+                .values()
+                .map(|entry| round4(entry.orig_length as usize))
+                .sum::<usize>();
+        let length = round4(48 + directory.len() + compressed.len());
+
+        let mut woff = Vec::with_capacity(length);
+        woff.extend_from_slice(b"wOF2");
+        woff.extend_from_slice(if self.cff { b"OTTO" } else { &[0, 1, 0, 0] });
+        woff.extend_from_slice(&(length as u32).to_be_bytes());
+        woff.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        woff.extend_from_slice(&0u16.to_be_bytes()); // reserved
+        woff.extend_from_slice(&(total_sfnt_size as u32).to_be_bytes());
+        woff.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        woff.extend_from_slice(&1u16.to_be_bytes()); // majorVersion
+        woff.extend_from_slice(&0u16.to_be_bytes()); // minorVersion
+        woff.extend_from_slice(&[0; 20]); // no metadata or private data
+        woff.extend_from_slice(&directory);
+        woff.extend_from_slice(&compressed);
+        woff.resize(length, 0);
+        woff
+    }
+}
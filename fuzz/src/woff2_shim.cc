@@ -0,0 +1,42 @@
+// Shim exposing the vendored C++ reference decoder (woff2/src/woff2_dec.cc)
+// to the differential fuzz targets through a small extern "C" interface.
+// The decoder is driven the same way as the conformance harness's shim: via
+// woff2::ComputeWOFF2FinalSize, woff2::WOFF2StringOut and
+// woff2::ConvertWOFF2ToTTF.
+
+#include <cstdlib>
+#include <cstring>
+#include <string>
+
+#include <woff2/decode.h>
+
+extern "C" {
+
+// Decode `data` with the C++ reference decoder. On success, returns a
+// malloc'd buffer (free with wuff_fuzz_cpp_free) and stores its length in
+// *result_length. On failure, returns null and stores 0.
+uint8_t* wuff_fuzz_cpp_decode(const uint8_t* data, size_t length,
+                              size_t* result_length) {
+  *result_length = 0;
+  size_t final_size = woff2::ComputeWOFF2FinalSize(data, length);
+  std::string output;
+  woff2::WOFF2StringOut out(&output);
+  if (final_size > out.MaxSize()) {
+    out.SetMaxSize(final_size);
+  }
+  if (!woff2::ConvertWOFF2ToTTF(data, length, &out)) {
+    return nullptr;
+  }
+  // malloc(0) may return null, which would read as a failure
+  uint8_t* result = static_cast<uint8_t*>(malloc(out.Size() + 1));
+  if (result == nullptr) {
+    return nullptr;
+  }
+  memcpy(result, output.data(), out.Size());
+  *result_length = out.Size();
+  return result;
+}
+
+void wuff_fuzz_cpp_free(uint8_t* ptr) { free(ptr); }
+
+}  // extern "C"
@@ -1,6 +1,41 @@
+use std::path::PathBuf;
+
 fn main() {
     // Expose the C/C++ headers to dependent crates as DEP_WUFF_INCLUDE_DIR
     // (the `links = "wuff"` key in Cargo.toml determines the WUFF part).
     let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
-    println!("cargo::metadata=include_dir={manifest_dir}/include");
+    let include_dir = format!("{manifest_dir}/include");
+    println!("cargo::metadata=include_dir={include_dir}");
+
+    // Write a `wuff.pc` pkg-config file for the in-tree libraries to OUT_DIR,
+    // which the C API test builds against. Packages installing the library are
+    // built with cargo-c instead (see `[package.metadata.capi]` in Cargo.toml),
+    // which writes its own pkg-config file with the installed paths.
+    //
+    // OUT_DIR is `target/<profile>/build/wuff-capi-<hash>/out`. The libraries
+    // are always written to `target/<profile>/deps` (`cargo build` also copies
+    // them up to `target/<profile>`, but `cargo test` does not), so that is the
+    // `libdir` recorded in the pkg-config file.
+    println!("cargo::rerun-if-changed=wuff.pc.in");
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let Some(profile_dir) = out_dir.ancestors().nth(3) else {
+        return;
+    };
+    let lib_dir = profile_dir.join("deps");
+    let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
+    // The system libraries that Rust's standard library links against, as
+    // reported by `rustc --print native-static-libs`. Only needed when
+    // linking the static library.
+    let libs_private = match target_os.as_str() {
+        "linux" => "-lgcc_s -lutil -lrt -lpthread -lm -ldl -lc",
+        "macos" | "ios" => "-lSystem -lc -lm",
+        _ => "",
+    };
+    let template = std::fs::read_to_string(format!("{manifest_dir}/wuff.pc.in")).unwrap();
+    let pc = template
+        .replace("@LIBDIR@", &lib_dir.display().to_string())
+        .replace("@INCLUDEDIR@", &include_dir)
+        .replace("@VERSION@", &std::env::var("CARGO_PKG_VERSION").unwrap())
+        .replace("@LIBS_PRIVATE@", libs_private);
+    std::fs::write(out_dir.join("wuff.pc"), pc).unwrap();
 }
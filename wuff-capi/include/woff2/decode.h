@@ -9,8 +9,8 @@
    This is a header-only reimplementation of the decoding API from the woff2
    C++ library (https://github.com/google/woff2), provided by the wuff-capi
    Rust crate. The `woff2` namespace functions below are implemented on top
-   of C symbols exported by the wuff-capi Rust library, which must be linked
-   into the final binary.
+   of the C symbols declared in wuff.h, which are exported by the wuff-capi
+   Rust library and must be linked into the final binary.
 */
 
 #ifndef WOFF2_WOFF2_DEC_H_
@@ -20,23 +20,8 @@
 #include <inttypes.h>
 #include <woff2/output.h>
 
-// Import public API from Rust library
-extern "C" {
-   // Compute the size of the final uncompressed font by reading the
-   // totalSfntSize field of the WOFF2 header, or 0 on error.
-   size_t wuff_woff2_compute_final_size(const uint8_t *data, size_t length);
-
-   // Decompress a WOFF2 font into a newly-allocated buffer.
-   //
-   // On success, returns  a pointer to the decompressed font and stores its length in *result_length.
-   // On failure, returns NULL and stores 0 in *result_length.
-   // The buffer must be freed with wuff_woff2_free.
-   uint8_t *wuff_woff2_decode(const uint8_t *data, size_t length, size_t *result_length);
-
-   // Free a buffer previously returned by wuff_woff2_decode.
-   // `length` must be the value stored in *result_length by that call.
-   void wuff_woff2_free(uint8_t *ptr, size_t length);
-}
+// The C API exported by the wuff-capi Rust library
+#include <wuff.h>
 
 namespace woff2 {
 
@@ -0,0 +1,73 @@
+/* C API for the wuff pure-Rust WOFF2 decoder (https://github.com/nicoburns/wuff).
+
+   Distributed under MIT license.
+   See file LICENSE for detail or copy at https://opensource.org/licenses/MIT
+*/
+
+#ifndef WUFF_H
+#define WUFF_H
+
+/* This file is generated from wuff-capi/src/lib.rs by cbindgen. Do not edit it manually. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Compute the size of the final uncompressed font, or 0 on error.
+//
+// This reads the `totalSfntSize` field of the WOFF2 header. It is the
+// C equivalent of the woff2 library's `woff2::ComputeWOFF2FinalSize`.
+//
+// # Safety
+//
+// `data` must either be null (in which case 0 is returned) or point to
+// `length` bytes of readable memory.
+size_t wuff_woff2_compute_final_size(const uint8_t *data, size_t length);
+
+// Compute the size of the decoded font from the font's table directory, or 0
+// on error.
+//
+// Unlike [`wuff_woff2_compute_final_size`], this doesn't trust the
+// `totalSfntSize` header field. The result is exact unless the font has a
+// transformed `glyf` table, in which case it is a tight upper bound. Either
+// way, a buffer of this size is large enough for the decoded font.
+//
+// # Safety
+//
+// `data` must either be null (in which case 0 is returned) or point to
+// `length` bytes of readable memory.
+size_t wuff_woff2_compute_decoded_size(const uint8_t *data, size_t length);
+
+// Decompress a WOFF2 font into a newly-allocated buffer.
+//
+// On success, returns a pointer to the decompressed font and stores its
+// length in `*result_length`. The buffer must be freed by passing the
+// returned pointer and length to [`wuff_woff2_free`].
+//
+// On failure, returns null and stores 0 in `*result_length`.
+//
+// # Safety
+//
+// - `data` must either be null (in which case null is returned) or point to
+//   `length` bytes of readable memory.
+// - `result_length` must be a valid pointer to a writable `size_t`.
+uint8_t *wuff_woff2_decode(const uint8_t *data, size_t length, size_t *result_length);
+
+// Free a buffer previously returned by [`wuff_woff2_decode`].
+//
+// # Safety
+//
+// - `ptr` must either be null (in which case this is a no-op) or a pointer
+//   previously returned by [`wuff_woff2_decode`], with `length` being the
+//   value stored in `*result_length` by that call.
+// - The buffer must not have already been freed.
+void wuff_woff2_free(uint8_t *ptr, size_t length);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WUFF_H */
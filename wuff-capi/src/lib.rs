@@ -1,16 +1,21 @@
 //! C API for the [wuff](https://docs.rs/wuff) WOFF2 decoder.
 //!
-//! This crate exposes `extern "C"` symbols wrapping wuff's WOFF2 decoder, plus
-//! (in its `include` directory) C++ headers (`woff2/decode.h` and
-//! `woff2/output.h`) which reimplement the decoding API of the
+//! This crate exposes `extern "C"` symbols wrapping wuff's WOFF2 decoder. Its
+//! `include` directory contains a pure C header declaring them (`wuff.h`,
+//! generated from this file by cbindgen), plus C++ headers (`woff2/decode.h`
+//! and `woff2/output.h`) which reimplement the decoding API of the
 //! [woff2](https://github.com/google/woff2) C++ library on top of those
 //! symbols. This allows the crate to be used as a drop-in replacement for the
 //! woff2 library by C/C++ code (such as the `ots` sanitiser) that consumes
 //! only its decoding API.
 //!
 //! Build scripts of dependent crates can locate the headers via the
-//! `DEP_WUFF_INCLUDE_DIR` environment variable.
+//! `DEP_WUFF_INCLUDE_DIR` environment variable. The crate is also built as a
+//! static and dynamic library, with a `wuff.pc` pkg-config file, for projects
+//! not built with Cargo.
 
+// The exported functions which call into wuff do so within `catch_unwind`: wuff doesn't panic
+// while decoding, but unwinding across an `extern "C"` boundary would abort the process.
 use std::panic::catch_unwind;
 
 /// Compute the size of the final uncompressed font, or 0 on error.
@@ -31,6 +36,32 @@ pub unsafe extern "C" fn wuff_woff2_compute_final_size(data: *const u8, length:
     u32::from_be_bytes(bytes[16..20].try_into().unwrap()) as usize
 }
 
+/// Compute the size of the decoded font from the font's table directory, or 0
+/// on error.
+///
+/// Unlike [`wuff_woff2_compute_final_size`], this doesn't trust the
+/// `totalSfntSize` header field. The result is exact unless the font has a
+/// transformed `glyf` table, in which case it is a tight upper bound. Either
+/// way, a buffer of this size is large enough for the decoded font.
+///
+/// # Safety
+///
+/// `data` must either be null (in which case 0 is returned) or point to
+/// `length` bytes of readable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wuff_woff2_compute_decoded_size(data: *const u8, length: usize) -> usize {
+    if data.is_null() {
+        return 0;
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(data, length) };
+
+    // Catch panics (see the `catch_unwind` import)
+    match catch_unwind(|| wuff::compute_decoded_size(bytes)) {
+        Ok(Ok(size)) => size.size,
+        Ok(Err(_)) | Err(_) => 0,
+    }
+}
+
 /// Decompress a WOFF2 font into a newly-allocated buffer.
 ///
 /// On success, returns a pointer to the decompressed font and stores its
@@ -56,7 +87,7 @@ pub unsafe extern "C" fn wuff_woff2_decode(
     }
     let bytes = unsafe { std::slice::from_raw_parts(data, length) };
 
-    // Catch panics: unwinding across an `extern "C"` boundary would abort.
+    // Catch panics (see the `catch_unwind` import)
     let result = catch_unwind(|| wuff::decompress_woff2(bytes));
     match result {
         Ok(Ok(decompressed)) => {
@@ -115,6 +146,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn compute_decoded_size_ignores_total_sfnt_size() {
+        let font = include_bytes!("../../conformance/fonts/wpt/header-totalsfntsize-001.woff2");
+        let decoded = wuff::decompress_woff2(font).unwrap();
+        unsafe {
+            assert_eq!(wuff_woff2_compute_decoded_size(std::ptr::null(), 0), 0);
+            let size = wuff_woff2_compute_decoded_size(font.as_ptr(), font.len());
+            assert!(size >= decoded.len());
+            assert_ne!(
+                size,
+                wuff_woff2_compute_final_size(font.as_ptr(), font.len())
+            );
+        }
+    }
+
     #[test]
     fn decode_invalid_data_returns_null() {
         let garbage = [0u8; 64];
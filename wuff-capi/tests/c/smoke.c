@@ -0,0 +1,78 @@
+/* Smoke test for the pure-C wuff.h header: compiled as strict C99 and linked
+   against the wuff-capi static library by tests/c_api.rs.
+
+   Usage: smoke <font.woff2>
+   Exits 0 if the font decodes to a non-empty sfnt and invalid input is
+   rejected, and non-zero otherwise. */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include <wuff.h>
+
+static uint8_t *read_file(const char *path, size_t *length) {
+  FILE *file = fopen(path, "rb");
+  uint8_t *data = NULL;
+  long size;
+  if (file == NULL) {
+    return NULL;
+  }
+  if (fseek(file, 0, SEEK_END) == 0 && (size = ftell(file)) > 0 &&
+      fseek(file, 0, SEEK_SET) == 0) {
+    data = malloc((size_t)size);
+    if (data != NULL && fread(data, 1, (size_t)size, file) != (size_t)size) {
+      free(data);
+      data = NULL;
+    }
+    *length = (size_t)size;
+  }
+  fclose(file);
+  return data;
+}
+
+int main(int argc, char **argv) {
+  uint8_t garbage[64] = {0};
+  uint8_t *data;
+  uint8_t *result;
+  size_t length = 0;
+  size_t result_length = 1;
+  size_t size_bound;
+
+  if (argc != 2) {
+    fprintf(stderr, "usage: %s <font.woff2>\n", argv[0]);
+    return 2;
+  }
+
+  /* Invalid input is rejected. */
+  if (wuff_woff2_decode(garbage, sizeof(garbage), &result_length) != NULL ||
+      result_length != 0) {
+    fprintf(stderr, "garbage input was not rejected\n");
+    return 1;
+  }
+
+  data = read_file(argv[1], &length);
+  if (data == NULL) {
+    fprintf(stderr, "failed to read %s\n", argv[1]);
+    return 1;
+  }
+  if (wuff_woff2_compute_final_size(data, length) == 0) {
+    fprintf(stderr, "wuff_woff2_compute_final_size returned 0\n");
+    return 1;
+  }
+  size_bound = wuff_woff2_compute_decoded_size(data, length);
+  result = wuff_woff2_decode(data, length, &result_length);
+  free(data);
+  if (result == NULL || result_length == 0) {
+    fprintf(stderr, "failed to decode %s\n", argv[1]);
+    return 1;
+  }
+  if (result_length > size_bound) {
+    fprintf(stderr, "wuff_woff2_compute_decoded_size returned %lu for a %lu byte font\n",
+            (unsigned long)size_bound, (unsigned long)result_length);
+    return 1;
+  }
+  wuff_woff2_free(result, result_length);
+
+  printf("ok\n");
+  return 0;
+}
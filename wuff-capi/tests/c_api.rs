@@ -0,0 +1,68 @@
+//! Compiles `tests/c/smoke.c` as strict C99 against `include/wuff.h` and the
+//! generated `wuff.pc`, links it against the wuff-capi library, and runs it.
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory containing the compiled wuff-capi libraries
+/// (`target/<profile>/deps`), which is also where integration test binaries
+/// are written.
+fn lib_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+fn pkg_config(args: &[&str]) -> Vec<String> {
+    // The build script writes `wuff.pc` (for the libraries in `lib_dir`) to OUT_DIR
+    let output = Command::new("pkg-config")
+        .env("PKG_CONFIG_PATH", env!("OUT_DIR"))
+        .args(args)
+        .arg("wuff")
+        .output()
+        .expect("failed to run pkg-config");
+    assert!(
+        output.status.success(),
+        "pkg-config failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn c99_program_links_and_decodes() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib_dir = lib_dir();
+    let out_dir = std::env::temp_dir().join(format!("wuff-capi-c-api-{}", std::process::id()));
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let exe = out_dir.join("smoke");
+
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .args(["-std=c99", "-pedantic", "-Wall", "-Wextra", "-Werror"])
+        .arg(crate_dir.join("tests/c/smoke.c"))
+        .arg("-o")
+        .arg(&exe)
+        .args(pkg_config(&["--cflags", "--libs", "--static"]))
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "failed to compile tests/c/smoke.c");
+
+    let font = crate_dir.join("../conformance/fonts/wpt/valid-001.woff2");
+    let output = Command::new(&exe)
+        .arg(font)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&out_dir);
+    assert!(
+        output.status.success(),
+        "smoke test failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
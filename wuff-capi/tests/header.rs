@@ -0,0 +1,32 @@
+//! Checks that the checked-in C header (`include/wuff.h`) matches the
+//! `extern "C"` declarations in `src/lib.rs`.
+
+use std::path::Path;
+
+#[test]
+fn header_is_up_to_date() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let header_path = crate_dir.join("include/wuff.h");
+
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("failed to read cbindgen.toml");
+    let bindings = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(crate_dir.join("src/lib.rs"))
+        .generate()
+        .expect("failed to generate C bindings");
+    let mut generated = Vec::new();
+    bindings.write(&mut generated);
+
+    if std::env::var_os("WUFF_CAPI_BLESS").is_some() {
+        std::fs::write(&header_path, &generated).expect("failed to write include/wuff.h");
+        return;
+    }
+
+    let committed = std::fs::read(&header_path).unwrap_or_default();
+    assert!(
+        committed == generated,
+        "include/wuff.h is out of date with src/lib.rs. \
+         Regenerate it with `WUFF_CAPI_BLESS=1 cargo test -p wuff-capi --test header`."
+    );
+}
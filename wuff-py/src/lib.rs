@@ -0,0 +1,377 @@
+//! Python bindings for the [wuff](https://docs.rs/wuff) WOFF and WOFF2 decoder.
+//!
+//! This crate is built into a Python extension module named `wuff` by
+//! [maturin](https://www.maturin.rs) (see `pyproject.toml`):
+//!
+//! ```python
+//! import wuff
+//!
+//! with open("font.woff2", "rb") as f:
+//!     ttf = wuff.decompress_woff2(f.read())
+//! ```
+//!
+//! Every function accepts any object supporting the buffer protocol with
+//! byte-sized items (`bytes`, `bytearray`, `memoryview`, `mmap`, ...). The
+//! input is read in place without being copied. Decoding errors are raised as
+//! `wuff.WuffError` (a subclass of `ValueError`) whose message names the
+//! `WuffErr` variant, or `MemoryError` if the font doesn't fit in memory.
+
+use pyo3::buffer::PyBuffer;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyBufferError, PyMemoryError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use wuff::{DecodeOptions, OutputProfile, WoffVersion, WuffErr};
+
+create_exception!(
+    wuff,
+    WuffError,
+    PyValueError,
+    "Raised when a font cannot be decoded."
+);
+
+fn to_py_err(err: WuffErr) -> PyErr {
+    match err {
+        WuffErr::OutOfMemory => PyMemoryError::new_err("out of memory while decoding font"),
+        // Name the variant, so that errors which aren't about the data itself are told apart
+        _ => WuffError::new_err(format!("invalid or unsupported font data ({err})")),
+    }
+}
+
+/// Call `f` with the contents of a Python buffer, without copying them.
+fn with_buffer<R>(buffer: &PyBuffer<u8>, f: impl FnOnce(&[u8]) -> R) -> PyResult<R> {
+    if !buffer.is_c_contiguous() {
+        return Err(PyBufferError::new_err(
+            "font data must be a contiguous buffer",
+        ));
+    }
+    let data = match buffer.len_bytes() {
+        0 => &[][..],
+        // SAFETY: the buffer is contiguous and `len_bytes` long, and `PyBuffer` keeps it alive
+        // until it is dropped. The GIL is held until `f` returns, so Python code cannot
+        // modify the buffer (e.g. a `bytearray`) while it is borrowed.
+        len => unsafe { std::slice::from_raw_parts(buffer.buf_ptr() as *const u8, len) },
+    };
+    Ok(f(data))
+}
+
+/// Decode a WOFF (version 1) font to an OpenType/TrueType font.
+#[pyfunction]
+fn decompress_woff<'py>(py: Python<'py>, data: PyBuffer<u8>) -> PyResult<Bound<'py, PyBytes>> {
+    let sfnt = with_buffer(&data, wuff::decompress_woff1)?.map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &sfnt))
+}
+
+/// Decode a WOFF2 font to an OpenType/TrueType font (or font collection).
+///
+/// `profile` chooses how a transformed glyf table is reconstructed: `"reference"`
+/// (like the reference decoder), `"fonttools"` (following fontTools) or `"compact"`.
+#[pyfunction]
+#[pyo3(signature = (data, profile = "reference"))]
+fn decompress_woff2<'py>(
+    py: Python<'py>,
+    data: PyBuffer<u8>,
+    profile: &str,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let output_profile = match profile {
+        "reference" => OutputProfile::ReferenceCompatible,
+        "fonttools" => OutputProfile::FontToolsCompatible,
+        "compact" => OutputProfile::Compact,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown output profile {profile:?}"
+            )));
+        }
+    };
+    let options = DecodeOptions::default().output_profile(output_profile);
+    let decoded = with_buffer(&data, |data| {
+        wuff::decompress_woff2_with_options(data, options)
+    })?;
+    Ok(PyBytes::new(py, &decoded.map_err(to_py_err)?.font))
+}
+
+/// Read and decompress the extended metadata (an XML document) of a WOFF or
+/// WOFF2 font. Returns `None` if the font has no metadata block.
+#[pyfunction]
+fn read_metadata<'py>(
+    py: Python<'py>,
+    data: PyBuffer<u8>,
+) -> PyResult<Option<Bound<'py, PyBytes>>> {
+    let metadata = with_buffer(&data, wuff::read_metadata)?.map_err(to_py_err)?;
+    Ok(metadata.map(|metadata| PyBytes::new(py, &metadata)))
+}
+
+/// Read the header and table directory of a WOFF or WOFF2 font without decoding it.
+#[pyfunction]
+fn inspect(data: PyBuffer<u8>) -> PyResult<WoffInfo> {
+    let info = with_buffer(&data, wuff::inspect)?.map_err(to_py_err)?;
+    Ok(WoffInfo::from(info))
+}
+
+fn tag_to_string(tag: [u8; 4]) -> String {
+    String::from_utf8_lossy(&tag).into_owned()
+}
+
+/// Information about a WOFF or WOFF2 font, as returned by `inspect`.
+#[pyclass(module = "wuff", frozen, get_all)]
+struct WoffInfo {
+    /// `"woff"` or `"woff2"`
+    format: &'static str,
+    /// The "sfnt version" of the font (e.g. `"OTTO"`, or `"ttcf"` for a collection)
+    flavor: String,
+    /// Total size of the file in bytes
+    length: u32,
+    /// The (untrusted) `totalSfntSize` header field
+    total_sfnt_size: u32,
+    /// (WOFF2 only) Total length of the compressed data block
+    total_compressed_size: u32,
+    major_version: u16,
+    minor_version: u16,
+    /// Length of the compressed metadata block (0 if there is no metadata)
+    meta_length: u32,
+    /// Uncompressed size of the metadata block
+    meta_orig_length: u32,
+    /// Length of the private data block (0 if there is no private data)
+    priv_length: u32,
+    /// The number of fonts in the file (1 unless the file is a collection)
+    num_fonts: usize,
+    /// The entries of the table directory, in the order they are stored in the file
+    tables: Vec<TableInfo>,
+}
+
+impl From<wuff::WoffInfo> for WoffInfo {
+    fn from(info: wuff::WoffInfo) -> Self {
+        Self {
+            format: match info.version {
+                WoffVersion::Woff1 => "woff",
+                WoffVersion::Woff2 => "woff2",
+            },
+            flavor: tag_to_string(info.flavor),
+            length: info.length,
+            total_sfnt_size: info.total_sfnt_size,
+            total_compressed_size: info.total_compressed_size,
+            major_version: info.major_version,
+            minor_version: info.minor_version,
+            meta_length: info.meta_length,
+            meta_orig_length: info.meta_orig_length,
+            priv_length: info.priv_length,
+            num_fonts: info.num_fonts,
+            tables: info
+                .tables
+                .into_iter()
+                .map(|table| TableInfo {
+                    tag: tag_to_string(table.tag),
+                    orig_length: table.orig_length,
+                    length: table.length,
+                    transformed: table.transformed,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[pymethods]
+impl WoffInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "WoffInfo(format={:?}, flavor={:?}, num_fonts={}, num_tables={})",
+            self.format,
+            self.flavor,
+            self.num_fonts,
+            self.tables.len()
+        )
+    }
+}
+
+/// A single entry in the table directory of a WOFF or WOFF2 font.
+#[pyclass(module = "wuff", frozen, get_all, skip_from_py_object)]
+#[derive(Clone)]
+struct TableInfo {
+    /// The table's tag
+    tag: String,
+    /// Length of the original (decoded) table
+    orig_length: u32,
+    /// Length of the table as stored in the file
+    length: u32,
+    /// Whether the table is transformed (WOFF2) or compressed (WOFF1)
+    transformed: bool,
+}
+
+#[pymethods]
+impl TableInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "TableInfo(tag={:?}, orig_length={}, length={}, transformed={})",
+            self.tag,
+            self.orig_length,
+            self.length,
+            if self.transformed { "True" } else { "False" }
+        )
+    }
+}
+
+/// Pure-Rust WOFF and WOFF2 font decoding
+#[pymodule]
+#[pyo3(name = "wuff")]
+fn wuff_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(decompress_woff, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress_woff2, m)?)?;
+    m.add_function(wrap_pyfunction!(read_metadata, m)?)?;
+    m.add_function(wrap_pyfunction!(inspect, m)?)?;
+    m.add_class::<WoffInfo>()?;
+    m.add_class::<TableInfo>()?;
+    m.add("WuffError", m.py().get_type::<WuffError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use wuff::test_support::Woff1Builder;
+
+    const VALID_WOFF2: &[u8] = include_bytes!("../../conformance/fonts/wpt/valid-001.woff2");
+    const WOFF2_WITH_METADATA: &[u8] =
+        include_bytes!("../../conformance/fonts/wpt/metadata-noeffect-002.woff2");
+    const METADATA_XML: &[u8] =
+        b"<?xml version='1.0' encoding='UTF-8'?><metadata version='1.0'></metadata>";
+
+    /// A WOFF1 font with the tables of `VALID_WOFF2` and `METADATA_XML` as its metadata
+    fn woff1_with_metadata() -> Vec<u8> {
+        let sfnt = wuff::decompress_woff2(VALID_WOFF2).unwrap();
+        Woff1Builder::from_sfnt(&sfnt)
+            .compressed()
+            .metadata(METADATA_XML)
+            .build()
+    }
+
+    /// Run a Python test script with the `wuff` module and the test fonts in scope.
+    fn run_python(script: &std::ffi::CStr) {
+        Python::attach(|py| {
+            let locals = PyDict::new(py);
+            locals
+                .set_item("wuff", pyo3::wrap_pymodule!(wuff_py)(py))
+                .unwrap();
+            locals
+                .set_item("VALID_WOFF2", PyBytes::new(py, VALID_WOFF2))
+                .unwrap();
+            locals
+                .set_item("WOFF2_WITH_METADATA", PyBytes::new(py, WOFF2_WITH_METADATA))
+                .unwrap();
+            locals
+                .set_item(
+                    "WOFF1_WITH_METADATA",
+                    PyBytes::new(py, &woff1_with_metadata()),
+                )
+                .unwrap();
+            locals
+                .set_item("METADATA_XML", PyBytes::new(py, METADATA_XML))
+                .unwrap();
+            if let Err(err) = py.run(script, None, Some(&locals)) {
+                err.display(py);
+                panic!("Python test failed: {err}");
+            }
+        });
+    }
+
+    #[test]
+    fn decompress_woff2_accepts_buffers() {
+        run_python(
+            cr#"
+sfnt = wuff.decompress_woff2(VALID_WOFF2)
+assert sfnt[:4] == b"OTTO"
+assert wuff.decompress_woff2(bytearray(VALID_WOFF2)) == sfnt
+assert wuff.decompress_woff2(memoryview(VALID_WOFF2)) == sfnt
+"#,
+        );
+    }
+
+    #[test]
+    fn decompress_woff2_profiles() {
+        run_python(
+            cr#"
+sfnt = wuff.decompress_woff2(VALID_WOFF2)
+assert wuff.decompress_woff2(VALID_WOFF2, profile="reference") == sfnt
+for profile in ["fonttools", "compact"]:
+    assert wuff.decompress_woff2(VALID_WOFF2, profile=profile)[:4] == b"OTTO"
+try:
+    wuff.decompress_woff2(VALID_WOFF2, profile="smallest")
+except ValueError:
+    pass
+else:
+    raise AssertionError("expected ValueError")
+"#,
+        );
+    }
+
+    #[test]
+    fn errors_are_wuff_errors() {
+        run_python(
+            cr#"
+for bad in [b"", b"\0" * 64, VALID_WOFF2[:-8]]:
+    try:
+        wuff.decompress_woff2(bad)
+    except wuff.WuffError as err:
+        assert str(err) == "invalid or unsupported font data (WuffErr::GenericError)", err
+    else:
+        raise AssertionError("expected WuffError")
+assert issubclass(wuff.WuffError, ValueError)
+"#,
+        );
+    }
+
+    #[test]
+    fn inspect_woff2() {
+        run_python(
+            cr#"
+info = wuff.inspect(VALID_WOFF2)
+assert info.format == "woff2"
+assert info.flavor == "OTTO"
+assert info.length == len(VALID_WOFF2)
+assert info.num_fonts == 1
+assert "head" in [table.tag for table in info.tables]
+"#,
+        );
+    }
+
+    #[test]
+    fn read_woff2_metadata() {
+        run_python(
+            cr#"
+assert wuff.read_metadata(VALID_WOFF2) is None
+metadata = wuff.read_metadata(WOFF2_WITH_METADATA)
+assert metadata.startswith(b"<?xml")
+assert len(metadata) == wuff.inspect(WOFF2_WITH_METADATA).meta_orig_length
+"#,
+        );
+    }
+
+    /// Check that a WOFF1 font (with a metadata block) built from the tables of a decoded
+    /// WOFF2 font decodes back to the same tables.
+    #[test]
+    fn woff1_round_trip() {
+        run_python(
+            cr#"
+import struct
+
+sfnt = wuff.decompress_woff2(VALID_WOFF2)
+flavor, num_tables = struct.unpack(">4sH", sfnt[:6])
+tables = []
+for i in range(num_tables):
+    tag, checksum, offset, length = struct.unpack(">4sIII", sfnt[12 + 16 * i:28 + 16 * i])
+    tables.append((tag, checksum, sfnt[offset:offset + length]))
+
+woff = WOFF1_WITH_METADATA
+decoded = wuff.decompress_woff(woff)
+assert decoded[:4] == flavor
+for i, (tag, checksum, table) in enumerate(tables):
+    entry = struct.unpack(">4sIII", decoded[12 + 16 * i:28 + 16 * i])
+    assert entry[0] == tag and entry[1] == checksum
+    assert decoded[entry[2]:entry[2] + entry[3]] == table
+assert wuff.read_metadata(memoryview(woff)) == METADATA_XML
+info = wuff.inspect(woff)
+assert info.format == "woff" and len(info.tables) == num_tables
+"#,
+        );
+    }
+}
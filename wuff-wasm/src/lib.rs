@@ -0,0 +1,274 @@
+//! WebAssembly bindings for the [wuff](https://docs.rs/wuff) WOFF and WOFF2 decoder.
+//!
+//! This crate exposes wuff's WOFF and WOFF2 decoders to JavaScript via
+//! [wasm-bindgen](https://docs.rs/wasm-bindgen). Font data is passed in and
+//! returned as `Uint8Array`s:
+//!
+//! ```js
+//! import { decompress, detect_format, inspect } from "wuff-wasm";
+//!
+//! const woff = new Uint8Array(await (await fetch("font.woff2")).arrayBuffer());
+//! if (["woff", "woff2"].includes(detect_format(woff))) {
+//!   const ttf = decompress(woff); // Uint8Array
+//! }
+//! ```
+//!
+//! WOFF1 files are decompressed with wuff's pure-Rust `inflate` backend
+//! (miniz_oxide) rather than flate2.
+//!
+//! Each exported function is a thin wrapper around a plain Rust function
+//! (which converts errors into JavaScript `Error`s), so that the bindings can
+//! be tested without a JavaScript engine.
+
+use core::fmt;
+
+use wasm_bindgen::prelude::*;
+use wuff::{FontFormat, WoffVersion, WuffErr};
+
+/// Errors returned to JavaScript (as a thrown `Error`) by the functions in this crate
+#[derive(Debug)]
+enum Error {
+    /// The input is not a WOFF or WOFF2 file
+    UnsupportedFormat(Option<FontFormat>),
+    /// The input could not be decoded
+    Invalid(WuffErr),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedFormat(Some(format)) => write!(
+                f,
+                "unsupported font format: {} (only WOFF and WOFF2 can be decoded)",
+                format_name(*format)
+            ),
+            Self::UnsupportedFormat(None) => write!(f, "unrecognised font format"),
+            Self::Invalid(err) => write!(f, "invalid font: {err}"),
+        }
+    }
+}
+
+impl From<WuffErr> for Error {
+    fn from(err: WuffErr) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+impl From<Error> for JsError {
+    fn from(err: Error) -> Self {
+        JsError::new(&err.to_string())
+    }
+}
+
+fn format_name(format: FontFormat) -> &'static str {
+    match format {
+        FontFormat::Woff1 => "woff",
+        FontFormat::Woff2 => "woff2",
+        FontFormat::Sfnt => "sfnt",
+        FontFormat::Collection => "collection",
+    }
+}
+
+fn decompress_bytes(data: &[u8]) -> Result<Vec<u8>, Error> {
+    match wuff::detect_format(data) {
+        Some(FontFormat::Woff1) => Ok(wuff::decompress_woff1(data)?),
+        Some(FontFormat::Woff2) => Ok(wuff::decompress_woff2(data)?),
+        format => Err(Error::UnsupportedFormat(format)),
+    }
+}
+
+/// Decode a WOFF or WOFF2 file to an OpenType/TrueType font.
+///
+/// Throws an `Error` if the input is not a valid WOFF or WOFF2 file.
+#[wasm_bindgen]
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, JsError> {
+    Ok(decompress_bytes(data)?)
+}
+
+/// Detect the format of a font file from its signature.
+///
+/// Returns one of `"woff"`, `"woff2"`, `"sfnt"` (an uncompressed font) or
+/// `"collection"` (an uncompressed font collection), or `undefined` if the
+/// signature is not recognised. This does not validate the rest of the file.
+#[wasm_bindgen]
+pub fn detect_format(data: &[u8]) -> Option<String> {
+    wuff::detect_format(data).map(|format| format_name(format).to_string())
+}
+
+fn inspect_bytes(data: &[u8]) -> Result<FontInfo, Error> {
+    Ok(FontInfo(wuff::inspect(data)?))
+}
+
+/// Read the header and table directory of a WOFF or WOFF2 file without decoding it.
+///
+/// Throws an `Error` if the header or table directory is invalid.
+#[wasm_bindgen]
+pub fn inspect(data: &[u8]) -> Result<FontInfo, JsError> {
+    Ok(inspect_bytes(data)?)
+}
+
+fn tag_to_string(tag: [u8; 4]) -> String {
+    String::from_utf8_lossy(&tag).into_owned()
+}
+
+/// Information about a WOFF or WOFF2 file, as returned by `inspect`
+#[wasm_bindgen]
+pub struct FontInfo(wuff::WoffInfo);
+
+#[wasm_bindgen]
+impl FontInfo {
+    /// `"woff"` or `"woff2"`
+    #[wasm_bindgen(getter)]
+    pub fn format(&self) -> String {
+        match self.0.version {
+            WoffVersion::Woff1 => "woff",
+            WoffVersion::Woff2 => "woff2",
+        }
+        .to_string()
+    }
+
+    /// The "sfnt version" of the font (e.g. `"OTTO"`, or `"ttcf"` for a collection)
+    #[wasm_bindgen(getter)]
+    pub fn flavor(&self) -> String {
+        tag_to_string(self.0.flavor)
+    }
+
+    /// Total size of the file in bytes
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> u32 {
+        self.0.length
+    }
+
+    /// The (untrusted) `totalSfntSize` header field
+    #[wasm_bindgen(getter, js_name = totalSfntSize)]
+    pub fn total_sfnt_size(&self) -> u32 {
+        self.0.total_sfnt_size
+    }
+
+    /// Major version of the WOFF file
+    #[wasm_bindgen(getter, js_name = majorVersion)]
+    pub fn major_version(&self) -> u16 {
+        self.0.major_version
+    }
+
+    /// Minor version of the WOFF file
+    #[wasm_bindgen(getter, js_name = minorVersion)]
+    pub fn minor_version(&self) -> u16 {
+        self.0.minor_version
+    }
+
+    /// Whether the file contains an extended metadata block
+    #[wasm_bindgen(getter, js_name = hasMetadata)]
+    pub fn has_metadata(&self) -> bool {
+        self.0.meta_length != 0
+    }
+
+    /// Whether the file contains a private data block
+    #[wasm_bindgen(getter, js_name = hasPrivateData)]
+    pub fn has_private_data(&self) -> bool {
+        self.0.priv_length != 0
+    }
+
+    /// The number of fonts in the file (1 unless the file is a collection)
+    #[wasm_bindgen(getter, js_name = numFonts)]
+    pub fn num_fonts(&self) -> usize {
+        self.0.num_fonts
+    }
+
+    /// The entries of the table directory, in the order they are stored in the file
+    #[wasm_bindgen(getter)]
+    pub fn tables(&self) -> Vec<TableInfo> {
+        self.0.tables.iter().cloned().map(TableInfo).collect()
+    }
+}
+
+/// A single entry in the table directory of a WOFF or WOFF2 file
+#[wasm_bindgen]
+pub struct TableInfo(wuff::TableInfo);
+
+#[wasm_bindgen]
+impl TableInfo {
+    /// The table's tag
+    #[wasm_bindgen(getter)]
+    pub fn tag(&self) -> String {
+        tag_to_string(self.0.tag)
+    }
+
+    /// Length of the original (decoded) table
+    #[wasm_bindgen(getter, js_name = origLength)]
+    pub fn orig_length(&self) -> u32 {
+        self.0.orig_length
+    }
+
+    /// Length of the table as stored in the file
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> u32 {
+        self.0.length
+    }
+
+    /// Whether the table is transformed (WOFF2) or compressed (WOFF1)
+    #[wasm_bindgen(getter)]
+    pub fn transformed(&self) -> bool {
+        self.0.transformed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wuff::test_support::Woff1Builder;
+
+    const VALID_WOFF2: &[u8] = include_bytes!("../../conformance/fonts/wpt/valid-001.woff2");
+
+    #[test]
+    fn decompress_valid_woff2() {
+        let sfnt = decompress_bytes(VALID_WOFF2).unwrap();
+        assert_eq!(detect_format(&sfnt).as_deref(), Some("sfnt"));
+    }
+
+    #[test]
+    fn decompress_valid_woff1() {
+        let sfnt = decompress_bytes(VALID_WOFF2).unwrap();
+        let woff = Woff1Builder::from_sfnt(&sfnt).compressed().build();
+        assert_eq!(detect_format(&woff).as_deref(), Some("woff"));
+        assert_eq!(decompress_bytes(&woff).unwrap(), sfnt);
+
+        let mut truncated = woff;
+        truncated.truncate(truncated.len() - 4);
+        assert!(matches!(
+            decompress_bytes(&truncated),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn decompress_rejects_other_formats() {
+        assert!(matches!(
+            decompress_bytes(b"true\0\0\0\0"),
+            Err(Error::UnsupportedFormat(Some(FontFormat::Sfnt)))
+        ));
+        assert!(matches!(
+            decompress_bytes(&[0u8; 64]),
+            Err(Error::UnsupportedFormat(None))
+        ));
+    }
+
+    #[test]
+    fn detect_format_names() {
+        assert_eq!(detect_format(VALID_WOFF2).as_deref(), Some("woff2"));
+        assert_eq!(detect_format(b"wOFF").as_deref(), Some("woff"));
+        assert_eq!(detect_format(b"ttcf").as_deref(), Some("collection"));
+        assert_eq!(detect_format(b"wOF"), None);
+    }
+
+    #[test]
+    fn inspect_valid_woff2() {
+        let info = inspect_bytes(VALID_WOFF2).unwrap();
+        assert_eq!(info.format(), "woff2");
+        assert_eq!(info.length() as usize, VALID_WOFF2.len());
+        assert_eq!(info.num_fonts(), 1);
+        let tables = info.tables();
+        assert!(!tables.is_empty());
+        assert!(tables.iter().any(|table| table.tag() == "head"));
+    }
+}
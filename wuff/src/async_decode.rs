@@ -0,0 +1,174 @@
+//! Decoding WOFF and WOFF2 fonts from asynchronous (tokio) readers.
+//!
+//! This module is only compiled when the `async` feature is enabled. The input is read
+//! into memory (the decoders need random access to it), but only after the header has been
+//! read and the declared file length checked against a caller-supplied limit, so oversize
+//! inputs are rejected without being read. Decoding itself is CPU-bound, so it is run on
+//! tokio's blocking thread pool (with [`tokio::task::spawn_blocking`]) rather than on the
+//! executor. These functions must therefore be called from within a tokio runtime.
+//!
+//! Readers implementing the `futures` crate's `AsyncRead` can be adapted with
+//! `tokio_util::compat::FuturesAsyncReadCompatExt::compat`.
+
+use alloc::{vec, vec::Vec};
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::WuffErr;
+use crate::woff::headers::WoffVersion;
+
+/// Size of the fixed-size WOFF1 header
+const WOFF1_HEADER_SIZE: usize = 44;
+/// Size of the fixed-size WOFF2 header
+const WOFF2_HEADER_SIZE: usize = 48;
+
+/// Read a complete WOFF or WOFF2 file of the given version from `reader`.
+///
+/// The header is read first, and the rest of the file is only read if the `length` field of
+/// the header is no larger than `max_size`. Exactly `length` bytes are read from `reader`.
+async fn read_woff<R: AsyncRead + Unpin>(
+    mut reader: R,
+    version: WoffVersion,
+    max_size: usize,
+) -> io::Result<Vec<u8>> {
+    let header_size = match version {
+        WoffVersion::Woff1 => WOFF1_HEADER_SIZE,
+        WoffVersion::Woff2 => WOFF2_HEADER_SIZE,
+    };
+
+    let mut header = vec![0u8; header_size];
+    let (signature, rest_of_header) = header
+        .split_at_mut_checked(4)
+        .ok_or_else(|| invalid_data(WuffErr::GenericError))?;
+    reader.read_exact(signature).await?;
+    let expected_signature = match version {
+        WoffVersion::Woff1 => b"wOFF",
+        WoffVersion::Woff2 => b"wOF2",
+    };
+    if *signature != *expected_signature {
+        return Err(invalid_data(WuffErr::GenericError));
+    }
+    reader.read_exact(rest_of_header).await?;
+
+    let length = match header.get(8..12).and_then(|bytes| bytes.try_into().ok()) {
+        Some(bytes) => u32::from_be_bytes(bytes) as usize,
+        None => return Err(invalid_data(WuffErr::GenericError)),
+    };
+    if length > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            std::format!("font is {length} bytes, which exceeds the limit of {max_size} bytes"),
+        ));
+    }
+    let Some(remaining) = length.checked_sub(header_size) else {
+        return Err(invalid_data(WuffErr::GenericError));
+    };
+
+    // `length` has been checked against `max_size`, so allocate up front (fallibly, like the
+    // decoders, since `max_size` may be more than is available)
+    let mut data = header;
+    data.try_reserve_exact(remaining)
+        .map_err(|err| invalid_data(err.into()))?;
+    let read = (&mut reader)
+        .take(remaining as u64)
+        .read_to_end(&mut data)
+        .await?;
+    if read != remaining {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(data)
+}
+
+fn invalid_data(err: WuffErr) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Run a decoder on the blocking thread pool
+async fn decode_blocking(
+    data: Vec<u8>,
+    decode: fn(&[u8]) -> Result<Vec<u8>, WuffErr>,
+) -> io::Result<Vec<u8>> {
+    match tokio::task::spawn_blocking(move || decode(&data)).await {
+        Ok(result) => result.map_err(invalid_data),
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(io::Error::other(err)),
+    }
+}
+
+/// Read a WOFF2 file from an asynchronous reader and decode it.
+///
+/// Files whose header declares a length greater than `max_size` bytes are rejected (with
+/// [`io::ErrorKind::InvalidData`]) before the rest of the file is read. Decoding errors, and
+/// failing to allocate the buffer for the file, are also reported as
+/// [`io::ErrorKind::InvalidData`], with the [`WuffErr`] as the source.
+///
+/// Decoding is run with [`tokio::task::spawn_blocking`], so this must be called from within
+/// a tokio runtime.
+#[cfg(feature = "brotli")]
+pub async fn decompress_woff2_async<R: AsyncRead + Unpin>(
+    reader: R,
+    max_size: usize,
+) -> io::Result<Vec<u8>> {
+    let data = read_woff(reader, WoffVersion::Woff2, max_size).await?;
+    decode_blocking(data, crate::decompress_woff2).await
+}
+
+/// Read a WOFF1 file from an asynchronous reader and decode it.
+///
+/// Files whose header declares a length greater than `max_size` bytes are rejected (with
+/// [`io::ErrorKind::InvalidData`]) before the rest of the file is read. Decoding errors, and
+/// failing to allocate the buffer for the file, are also reported as
+/// [`io::ErrorKind::InvalidData`], with the [`WuffErr`] as the source.
+///
+/// Decoding is run with [`tokio::task::spawn_blocking`], so this must be called from within
+/// a tokio runtime.
+#[cfg(any(feature = "z", feature = "inflate"))]
+pub async fn decompress_woff1_async<R: AsyncRead + Unpin>(
+    reader: R,
+    max_size: usize,
+) -> io::Result<Vec<u8>> {
+    let data = read_woff(reader, WoffVersion::Woff1, max_size).await?;
+    decode_blocking(data, crate::decompress_woff1).await
+}
+
+#[cfg(all(test, feature = "brotli"))]
+mod tests {
+    use super::*;
+
+    const VALID_WOFF2: &[u8] = include_bytes!("../../conformance/fonts/wpt/valid-001.woff2");
+
+    #[tokio::test]
+    async fn decompress_from_reader() {
+        let sfnt = decompress_woff2_async(VALID_WOFF2, VALID_WOFF2.len())
+            .await
+            .unwrap();
+        assert_eq!(sfnt, crate::decompress_woff2(VALID_WOFF2).unwrap());
+    }
+
+    #[tokio::test]
+    async fn oversize_input_is_not_read() {
+        let mut reader = VALID_WOFF2;
+        let err = decompress_woff2_async(&mut reader, VALID_WOFF2.len() - 1)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // Only the header has been consumed
+        assert_eq!(reader.len(), VALID_WOFF2.len() - WOFF2_HEADER_SIZE);
+    }
+
+    #[tokio::test]
+    async fn truncated_input() {
+        let truncated = &VALID_WOFF2[..VALID_WOFF2.len() - 1];
+        let err = decompress_woff2_async(truncated, usize::MAX)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let err = decompress_woff2_async(&b"wOFF"[..], usize::MAX)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,24 +1,31 @@
 //! Built-in WOFF2 Brotli decompression, backed by `brotli-decompressor`.
 //!
 //! This module is only compiled when the `brotli` feature is enabled. It plugs the
-//! `brotli-decompressor` crate into [`decompress_woff2_with_custom_brotli`](crate::decompress_woff2_with_custom_brotli)
+//! `brotli-decompressor` crate into the decoder as a [`BrotliDecompressor`]
 //! using an `alloc`-backed allocator, so it works on `no_std` targets (with a global allocator).
 
-use alloc::{boxed::Box, vec, vec::Vec};
-use core::error::Error;
+use alloc::vec::Vec;
+use core::cell::Cell;
 
-use crate::WuffErr;
-use crate::decompress_woff2_with_custom_brotli;
+use brotli_decompressor::HuffmanCode;
 
-/// A `Box<[T]>` wrapper implementing the allocation traits that `brotli-decompressor`
+use crate::{BrotliDecompressor, DecodeOptions, Decoded, DecompressError, WuffErr};
+use crate::{
+    decompress_woff2_font_with_decompressor, decompress_woff2_with_decompressor,
+    decompress_woff2_with_decompressor_and_options,
+};
+
+/// A `Vec<T>` wrapper implementing the allocation traits that `brotli-decompressor`
 /// requires, so the decoder can allocate through the global allocator (`alloc`) rather
 /// than depending on `std`. This is the no_std equivalent of the crate's built-in
 /// `StandardAlloc` (which is only available behind its `std` feature).
-struct Rebox<T>(Box<[T]>);
+///
+/// A cell from the pool may have more capacity than its length.
+struct Rebox<T>(Vec<T>);
 
 impl<T> Default for Rebox<T> {
     fn default() -> Self {
-        Rebox(Vec::new().into_boxed_slice())
+        Rebox(Vec::new())
     }
 }
 
@@ -34,59 +41,163 @@ impl<T> brotli_decompressor::SliceWrapperMut<T> for Rebox<T> {
     }
 }
 
-/// Zero-sized allocator handing out `Rebox` cells backed by the global allocator.
-struct HeapAlloc;
+/// Allocator handing out `Rebox` cells. Cells freed by the decoder are kept in a pool and
+/// reused by later allocations, falling back to the global allocator when no pooled cell is
+/// large enough.
+///
+/// If an allocation fails, an empty cell is returned (which `brotli-decompressor` reports as
+/// a failure) and `out_of_memory` is set.
+struct PoolAlloc<'a, T> {
+    pool: &'a mut Vec<Vec<T>>,
+    out_of_memory: &'a Cell<bool>,
+}
 
-impl<T: Clone + Default> brotli_decompressor::Allocator<T> for HeapAlloc {
+impl<T: Clone + Default> brotli_decompressor::Allocator<T> for PoolAlloc<'_, T> {
     type AllocatedMemory = Rebox<T>;
     fn alloc_cell(&mut self, len: usize) -> Rebox<T> {
-        Rebox(vec![T::default(); len].into_boxed_slice())
+        if len == 0 {
+            return Rebox::default();
+        }
+        // Take the smallest pooled cell which is large enough
+        let best_fit = (self.pool.iter().enumerate())
+            .filter(|(_, data)| data.capacity() >= len)
+            .min_by_key(|(_, data)| data.capacity())
+            .map(|(idx, _)| idx);
+        let mut data = match best_fit {
+            Some(idx) => self.pool.swap_remove(idx),
+            None => {
+                let mut data = Vec::new();
+                if data.try_reserve_exact(len).is_err() {
+                    self.out_of_memory.set(true);
+                    return Rebox::default();
+                }
+                data
+            }
+        };
+        // (within the cell's capacity)
+        data.clear();
+        data.resize(len, T::default());
+        Rebox(data)
     }
-    fn free_cell(&mut self, _data: Rebox<T>) {}
+    fn free_cell(&mut self, cell: Rebox<T>) {
+        // If the pool can't grow, the cell is simply deallocated
+        if cell.0.capacity() > 0 && self.pool.try_reserve(1).is_ok() {
+            self.pool.push(cell.0);
+        }
+    }
+}
+
+/// `brotli_decompressor::huffman::BROTLI_HUFFMAN_MAX_TABLE_SIZE`
+const HUFFMAN_MAX_TABLE_SIZE: usize = 1080;
+
+/// The built-in Brotli decompressor. Keeps the memory used by the decoder's state between
+/// calls, so that decompressing similar streams again doesn't allocate.
+#[derive(Default)]
+pub(crate) struct Brotli {
+    u8_pool: Vec<Vec<u8>>,
+    u32_pool: Vec<Vec<u32>>,
+    huffman_code_pool: Vec<Vec<HuffmanCode>>,
 }
 
-fn decompress_brotli(
-    compressed_data: &[u8],
-    expected_size: usize,
-) -> Result<Vec<u8>, Box<dyn Error>> {
-    use brotli_decompressor::{BrotliDecompressStream, BrotliResult, BrotliState};
-
-    // Allocate the output buffer once, up front, at exactly the (trusted) expected size and
-    // decompress directly into it. `BrotliDecompressStream` never writes past the end of the
-    // slice, so `expected_size` is a HARD upper bound on the output: a stream that would expand
-    // further stops with `NeedsMoreOutput` rather than driving an unbounded allocation. This
-    // mirrors the reference C++ decoder, which decompresses into a fixed-size buffer and rejects
-    // the result unless the decoded size matches exactly.
-    let mut output = vec![0u8; expected_size];
-    let mut available_in = compressed_data.len();
-    let mut input_offset = 0usize;
-    let mut available_out = output.len();
-    let mut output_offset = 0usize;
-    let mut total_out = 0usize;
-    let mut state = BrotliState::new(HeapAlloc, HeapAlloc, HeapAlloc);
-
-    let result = BrotliDecompressStream(
-        &mut available_in,
-        &mut input_offset,
-        compressed_data,
-        &mut available_out,
-        &mut output_offset,
-        &mut output,
-        &mut total_out,
-        &mut state,
-    );
-
-    // Require a clean end-of-stream producing exactly `expected_size` bytes. Any trailing WOFF2
-    // padding bytes (up to 3, counted in `totalCompressedSize`) are harmless: the decoder reports
-    // success at end-of-stream and simply leaves them unconsumed in the input.
-    if !matches!(result, BrotliResult::ResultSuccess) || output_offset != expected_size {
-        return Err(Box::new(WuffErr::GenericError));
+impl Brotli {
+    /// `brotli-decompressor` doesn't check whether its first three cells of Huffman codes
+    /// (`HUFFMAN_MAX_TABLE_SIZE` codes for the context map table, then `3 *
+    /// HUFFMAN_MAX_TABLE_SIZE` for each of the block type and block length trees) were
+    /// allocated. Make sure that they can be taken from the pool.
+    fn reserve_unchecked_cells(&mut self) -> Result<(), DecompressError> {
+        const LEN: usize = 3 * HUFFMAN_MAX_TABLE_SIZE;
+        let pool = &mut self.huffman_code_pool;
+        let available = pool.iter().filter(|data| data.capacity() >= LEN).count();
+        for _ in available..3 {
+            let mut data = Vec::new();
+            data.try_reserve_exact(LEN)
+                .map_err(|_| DecompressError::OutOfMemory)?;
+            pool.try_reserve(1)
+                .map_err(|_| DecompressError::OutOfMemory)?;
+            pool.push(data);
+        }
+        Ok(())
     }
+}
+
+impl BrotliDecompressor for Brotli {
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError> {
+        use brotli_decompressor::{BrotliDecompressStream, BrotliResult, BrotliState};
 
-    Ok(output)
+        self.reserve_unchecked_cells()?;
+
+        // Decompress directly into the caller's buffer, which is exactly the (trusted) expected
+        // size. `BrotliDecompressStream` never writes past the end of the slice, so its length
+        // is a HARD upper bound on the output: a stream that would expand further stops with
+        // `NeedsMoreOutput` rather than driving an unbounded allocation. This mirrors the
+        // reference C++ decoder, which decompresses into a fixed-size buffer and rejects the
+        // result unless the decoded size matches exactly.
+        let mut available_in = input.len();
+        let mut input_offset = 0usize;
+        let mut available_out = output.len();
+        let mut output_offset = 0usize;
+        let mut total_out = 0usize;
+        let out_of_memory = Cell::new(false);
+        // Dropping the state frees its cells back into the pools
+        let mut state = BrotliState::new(
+            PoolAlloc {
+                pool: &mut self.u8_pool,
+                out_of_memory: &out_of_memory,
+            },
+            PoolAlloc {
+                pool: &mut self.u32_pool,
+                out_of_memory: &out_of_memory,
+            },
+            PoolAlloc {
+                pool: &mut self.huffman_code_pool,
+                out_of_memory: &out_of_memory,
+            },
+        );
+
+        let result = BrotliDecompressStream(
+            &mut available_in,
+            &mut input_offset,
+            input,
+            &mut available_out,
+            &mut output_offset,
+            output,
+            &mut total_out,
+            &mut state,
+        );
+
+        // Require a clean end-of-stream producing exactly `output.len()` bytes. Any trailing WOFF2
+        // padding bytes (up to 3, counted in `totalCompressedSize`) are harmless: the decoder
+        // reports success at end-of-stream and simply leaves them unconsumed in the input.
+        match result {
+            BrotliResult::ResultSuccess if output_offset == output.len() => Ok(()),
+            BrotliResult::ResultSuccess | BrotliResult::NeedsMoreInput => {
+                Err(DecompressError::TooShort)
+            }
+            BrotliResult::NeedsMoreOutput => Err(DecompressError::TooLong),
+            BrotliResult::ResultFailure if out_of_memory.get() => Err(DecompressError::OutOfMemory),
+            BrotliResult::ResultFailure => Err(DecompressError::InvalidData),
+        }
+    }
 }
 
 /// Decompress a WOFF2 file using the built-in brotli decompressor
 pub fn decompress_woff2(raw_woff_data: &[u8]) -> Result<Vec<u8>, WuffErr> {
-    decompress_woff2_with_custom_brotli(raw_woff_data, &mut decompress_brotli)
+    decompress_woff2_with_decompressor(raw_woff_data, &mut Brotli::default())
+}
+
+/// Decompress a single font from a WOFF2 file using the built-in brotli decompressor
+///
+/// If the file is a font collection, the font at `index` is emitted as a standalone sfnt
+/// containing just that font's tables (tables which only other fonts use are never
+/// reconstructed). A file containing a single font is treated as a collection of one font.
+pub fn decompress_woff2_font(raw_woff_data: &[u8], index: usize) -> Result<Vec<u8>, WuffErr> {
+    decompress_woff2_font_with_decompressor(raw_woff_data, index, &mut Brotli::default())
+}
+
+/// Decompress a WOFF2 file using the built-in brotli decompressor, with the given options
+pub fn decompress_woff2_with_options(
+    raw_woff_data: &[u8],
+    options: DecodeOptions,
+) -> Result<Decoded, WuffErr> {
+    decompress_woff2_with_decompressor_and_options(raw_woff_data, &mut Brotli::default(), options)
 }
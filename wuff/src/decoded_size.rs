@@ -0,0 +1,252 @@
+//! Computing the size of a decoded WOFF2 font without decoding it.
+
+use alloc::vec::Vec;
+
+use crate::decompress_woff2::{K_MAX_PLAUSIBLE_COMPRESSION_RATIO, read_num_hmetrics};
+use crate::error::{WuffErr, bail, bail_if};
+use crate::fallible::FallibleVec;
+use crate::woff::glyf_decoder::GlyfHeader;
+use crate::woff::headers::{
+    CollectionDirectory, CollectionDirectoryEntry, TableDirectory, TableDirectoryEntry, WoffHeader,
+    WoffVersion,
+};
+use crate::{BrotliDecompressor, DecompressError, GLYF, HMTX, LOCA, Round4};
+
+/// The number of bytes of the hhea table needed to read numberOfHMetrics
+const HHEA_NUM_HMETRICS_END: usize = 36;
+
+/// The size of the font that decoding a WOFF2 file produces
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DecodedSize {
+    /// The size of the decoded font in bytes, or an upper bound on it if `is_exact` is false
+    pub size: usize,
+    /// Whether `size` is exact. The size of a reconstructed (transformed) glyf table depends
+    /// on the outlines of its glyphs, so for fonts with one `size` is a tight upper bound.
+    pub is_exact: bool,
+}
+
+/// Compute the size of the font that decoding a WOFF2 file produces, using the built-in
+/// brotli decompressor
+///
+/// Unlike the untrusted `totalSfntSize` header field, the size is computed from the table
+/// directory and the headers of the transformed glyf and hmtx tables (only the start of the
+/// compressed data is decompressed to read these). Files which are rejected before decoding
+/// any tables (such as those with an implausible compression ratio) are rejected here too, but
+/// decoding may still fail for a file whose size can be computed.
+#[cfg(feature = "brotli")]
+#[cfg_attr(docsrs, doc(cfg(feature = "brotli")))]
+pub fn compute_decoded_size(raw_woff_data: &[u8]) -> Result<DecodedSize, WuffErr> {
+    compute_decoded_size_with_decompressor(raw_woff_data, &mut crate::brotli::Brotli::default())
+}
+
+/// Compute the size of the font that decoding a WOFF2 file produces, using a custom
+/// [`BrotliDecompressor`]
+///
+/// See [`compute_decoded_size`](crate::compute_decoded_size).
+pub fn compute_decoded_size_with_decompressor(
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn BrotliDecompressor,
+) -> Result<DecodedSize, WuffErr> {
+    let mut input = raw_woff_data;
+    let header = WoffHeader::parse(&mut input)?;
+    bail_if!(header.woff_version != WoffVersion::Woff2);
+    let tables = TableDirectory::parse_woff2(&mut input, header.num_tables as usize)?;
+    let collection_directory = if header.is_collection() {
+        CollectionDirectory::parse(&mut input, &tables)?
+    } else {
+        CollectionDirectory::generate_for_single_font(header.flavor, &tables)?
+    };
+
+    // Make the same checks as the decoder does before decompressing
+    bail_if!(tables.uncompressed_size < 1);
+    let compression_ratio = (tables.uncompressed_size as f32) / (raw_woff_data.len() as f32);
+    bail_if!(compression_ratio > K_MAX_PLAUSIBLE_COMPRESSION_RATIO);
+    let compressed_data = input
+        .get(..header.total_compressed_size as usize)
+        .ok_or(WuffErr::GenericError)?;
+
+    // Decompress as much of the data as is needed to read the headers of the transformed tables
+    let mut needed_length = 0;
+    for font in &collection_directory.fonts {
+        if let Some(glyf) = transformed_table(font, &tables, GLYF) {
+            let glyf_header_end = (glyf.woff_offset as usize).checked_add(GlyfHeader::SIZE);
+            needed_length = needed_length.max(glyf_header_end.ok_or(WuffErr::GenericError)?);
+        }
+        if transformed_table(font, &tables, HMTX).is_some() {
+            let hhea = font.hhea_idx.ok_or(WuffErr::GenericError)?;
+            let hhea_offset = tables.entry(hhea)?.woff_offset as usize;
+            let hhea_end = hhea_offset.checked_add(HHEA_NUM_HMETRICS_END);
+            needed_length = needed_length.max(hhea_end.ok_or(WuffErr::GenericError)?);
+        }
+    }
+    let mut data = Vec::new();
+    if needed_length > 0 {
+        bail_if!(needed_length > tables.uncompressed_size);
+        data.try_resize(needed_length, 0)?;
+        match decompressor.decompress(compressed_data, &mut data) {
+            Ok(()) => {}
+            // Only the start of the data was decompressed
+            Err(DecompressError::TooLong) if needed_length < tables.uncompressed_size => {}
+            Err(DecompressError::OutOfMemory) => return Err(WuffErr::OutOfMemory),
+            Err(_) => bail!(),
+        }
+    }
+
+    // A TTC header (for collections), then a table directory for each font
+    // (the sizes are of at most 2^16 fonts and tables, each less than 2^36 bytes, so these
+    // u64 sums can't wrap)
+    let mut size = (collection_directory.fonts.iter())
+        .map(|font| font.table_directory_size() as u64)
+        .fold(0u64, u64::wrapping_add);
+    if header.is_collection() {
+        let collection_header_size = collection_directory.collection_header_required_size();
+        size = size.wrapping_add(collection_header_size as u64);
+    }
+    let mut is_exact = true;
+    // Tables shared between the fonts of a collection are only written once
+    let mut counted: Vec<bool> = Vec::new();
+    counted.try_resize(tables.len(), false)?;
+    for font in &collection_directory.fonts {
+        let glyf_header = match transformed_table(font, &tables, GLYF) {
+            Some(glyf) => Some(GlyfHeader::parse(&mut table_start(
+                &data,
+                glyf,
+                GlyfHeader::SIZE,
+            )?)?),
+            None => None,
+        };
+
+        for &table_idx in &font.table_indices {
+            let is_counted = counted.get_mut(table_idx as usize);
+            if core::mem::replace(is_counted.ok_or(WuffErr::GenericError)?, true) {
+                continue;
+            }
+            let table = tables.entry(table_idx)?;
+            let length = match (table.is_transformed(), table.tag, &glyf_header) {
+                (false, _, _) => table.orig_length as u64,
+                (true, GLYF, Some(glyf)) => {
+                    is_exact = false;
+                    glyf.max_glyf_length()
+                }
+                (true, LOCA, Some(glyf)) => glyf.loca_length(),
+                (true, HMTX, Some(glyf)) => {
+                    let hhea = font.hhea_idx.ok_or(WuffErr::GenericError)?;
+                    let hhea = tables.entry(hhea)?;
+                    let num_hmetrics =
+                        read_num_hmetrics(table_start(&data, hhea, HHEA_NUM_HMETRICS_END)?)?;
+                    bail_if!(num_hmetrics < 1 || num_hmetrics > glyf.num_glyphs);
+                    // (u16s, so this can't wrap)
+                    (glyf.num_glyphs as u64)
+                        .wrapping_add(num_hmetrics as u64)
+                        .wrapping_mul(2)
+                }
+                // Tables with transforms which can't be reversed, and transformed loca and
+                // hmtx tables without a transformed glyf table, are rejected by the decoder
+                (true, _, _) => bail!(),
+            };
+            size = size.wrapping_add(Round4!(length));
+        }
+    }
+
+    Ok(DecodedSize {
+        size: usize::try_from(size).map_err(|_| WuffErr::GenericError)?,
+        is_exact,
+    })
+}
+
+/// The font's table with the given tag, if it is transformed
+fn transformed_table<'a>(
+    font: &CollectionDirectoryEntry,
+    tables: &'a TableDirectory,
+    tag: crate::Tag,
+) -> Option<&'a TableDirectoryEntry> {
+    (font.table_indices.iter())
+        .filter_map(|&idx| tables.get(idx as usize))
+        .find(|table| table.tag == tag && table.is_transformed())
+}
+
+/// The first `length` bytes of a table
+fn table_start<'a>(
+    data: &'a [u8],
+    table: &TableDirectoryEntry,
+    length: usize,
+) -> Result<&'a [u8], WuffErr> {
+    bail_if!((table.woff_length as usize) < length);
+    let start = table.woff_offset as usize;
+    let end = start.checked_add(length).ok_or(WuffErr::GenericError)?;
+    data.get(start..end).ok_or(WuffErr::GenericError)
+}
+
+#[cfg(all(test, feature = "brotli"))]
+mod tests {
+    use super::*;
+    use crate::decompress_woff2;
+    use crate::test_support::*;
+    use alloc::vec;
+
+    fn assert_size(woff: &[u8], is_exact: bool) -> DecodedSize {
+        let size = compute_decoded_size(woff).unwrap();
+        let decoded = decompress_woff2(woff).unwrap();
+        assert_eq!(size.is_exact, is_exact);
+        if is_exact {
+            assert_eq!(size.size, decoded.len());
+        } else {
+            assert!(size.size >= decoded.len());
+        }
+        size
+    }
+
+    #[test]
+    fn untransformed_tables_are_exact() {
+        let woff = Woff2Builder::new(TRUETYPE)
+            .table(Table::new(b"head", head_table(0)))
+            .table(Table::new(b"name", [1; 5]))
+            // The untrusted totalSfntSize isn't used
+            .header(HeaderField::TotalSfntSize, 12345)
+            .build();
+        assert_size(&woff, true);
+
+        let collection = Woff2Builder::collection(0x00020000)
+            .table(Table::new(b"head", head_table(0)))
+            .table(Table::new(b"cmap", [0; 4]))
+            .table(Table::new(b"name", [1; 6]))
+            .font(TRUETYPE, &[0, 1])
+            .font(TRUETYPE, &[0, 2])
+            .build();
+        assert_size(&collection, true);
+    }
+
+    #[test]
+    fn transformed_glyf_is_bounded() {
+        let glyphs = GlyfTransform::new(vec![
+            Glyph::Empty,
+            Glyph::simple(&[&[(10, 0, true), (100, 0, true), (50, 80, false)]]),
+            Glyph::simple(&[&[(-20, -10, true), (3000, 40, true), (0, 90, true)]]),
+        ]);
+        let hmtx = HmtxTransform {
+            advance_widths: vec![400, 500],
+            lsbs: None,
+            left_side_bearings: None,
+            reserved_flags: 0,
+        };
+        let woff = Woff2Builder::new(TRUETYPE)
+            .table(Table::new(b"head", head_table(0)))
+            .table(Table::new(b"hhea", hhea_table(2)))
+            .table(Table::with_transform(b"hmtx", 1, hmtx.encode()).orig_length(2 * 3 + 2 * 2))
+            .glyf(&glyphs)
+            .build();
+        let size = assert_size(&woff, false);
+        // The bound is within a few bytes per glyph and point of the real size
+        let decoded = decompress_woff2(&woff).unwrap();
+        assert!(size.size - decoded.len() <= 3 * 15 + 6 * 5);
+    }
+
+    #[test]
+    fn rejects_fonts_the_decoder_rejects_early() {
+        let woff = Woff2Builder::new(TRUETYPE)
+            .table(Table::with_transform(b"name", 1, [1; 5]))
+            .build();
+        assert!(compute_decoded_size(&woff).is_err());
+        assert!(compute_decoded_size(b"wOF2").is_err());
+    }
+}
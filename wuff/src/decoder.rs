@@ -0,0 +1,122 @@
+//! Reusable decoders for decoding many fonts.
+//!
+//! The free functions allocate their working memory (decompression state, buffers for
+//! reconstructing tables, the output font, ...) on every call. A decoder keeps it between
+//! calls instead, so once it has decoded a font, decoding fonts of a similar size doesn't
+//! allocate.
+
+use core::fmt;
+
+use crate::decompress_woff1::{Woff1Scratch, decode_woff1_into};
+use crate::decompress_woff2::{Woff2Scratch, decode_woff2_into};
+use crate::diagnostics::Diagnostics;
+use crate::{BrotliDecompressor, OutputProfile, WuffErr, ZlibDecompressor};
+
+/// A WOFF2 decoder which reuses its allocations between fonts
+///
+/// Produces the same output as [`decompress_woff2`](crate::decompress_woff2).
+#[derive(Default)]
+pub struct Woff2Decoder {
+    scratch: Woff2Scratch,
+    #[cfg(feature = "brotli")]
+    brotli: crate::brotli::Brotli,
+}
+
+impl Woff2Decoder {
+    /// Create a new decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decompress a WOFF2 file using the built-in brotli decompressor. The decoded font is
+    /// borrowed from the decoder until the next call.
+    #[cfg(feature = "brotli")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "brotli")))]
+    pub fn decode(&mut self, raw_woff_data: &[u8]) -> Result<&[u8], WuffErr> {
+        let Self { scratch, brotli } = self;
+        decode_woff2_into(
+            raw_woff_data,
+            None,
+            brotli,
+            &mut Diagnostics::strict(),
+            OutputProfile::default(),
+            scratch,
+        )?;
+        Ok(&scratch.out)
+    }
+
+    /// Decompress a WOFF2 file using a custom [`BrotliDecompressor`]. The decoded font is
+    /// borrowed from the decoder until the next call.
+    pub fn decode_with_decompressor(
+        &mut self,
+        raw_woff_data: &[u8],
+        decompressor: &mut dyn BrotliDecompressor,
+    ) -> Result<&[u8], WuffErr> {
+        let scratch = &mut self.scratch;
+        decode_woff2_into(
+            raw_woff_data,
+            None,
+            decompressor,
+            &mut Diagnostics::strict(),
+            OutputProfile::default(),
+            scratch,
+        )?;
+        Ok(&scratch.out)
+    }
+}
+
+impl fmt::Debug for Woff2Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Woff2Decoder").finish_non_exhaustive()
+    }
+}
+
+/// A WOFF1 decoder which reuses its allocations between fonts
+///
+/// Produces the same output as [`decompress_woff1`](crate::decompress_woff1).
+#[derive(Default)]
+pub struct Woff1Decoder {
+    scratch: Woff1Scratch,
+    #[cfg(any(feature = "z", feature = "inflate"))]
+    zlib: crate::zlib::Zlib,
+}
+
+impl Woff1Decoder {
+    /// Create a new decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decompress a WOFF1 file using the built-in zlib decompressor. The decoded font is
+    /// borrowed from the decoder until the next call.
+    #[cfg(any(feature = "z", feature = "inflate"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "z", feature = "inflate"))))]
+    pub fn decode(&mut self, raw_woff_data: &[u8]) -> Result<&[u8], WuffErr> {
+        let Self { scratch, zlib } = self;
+        decode_woff1_into(raw_woff_data, zlib, &mut Diagnostics::strict(), scratch)?;
+        Ok(&scratch.out)
+    }
+
+    /// Decompress a WOFF1 file using a custom [`ZlibDecompressor`]. The decoded font is
+    /// borrowed from the decoder until the next call.
+    pub fn decode_with_decompressor(
+        &mut self,
+        raw_woff_data: &[u8],
+        decompressor: &mut dyn ZlibDecompressor,
+    ) -> Result<&[u8], WuffErr> {
+        let scratch = &mut self.scratch;
+        decode_woff1_into(
+            raw_woff_data,
+            decompressor,
+            &mut Diagnostics::strict(),
+            scratch,
+        )?;
+        Ok(&scratch.out)
+    }
+}
+
+impl fmt::Debug for Woff1Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Woff1Decoder").finish_non_exhaustive()
+    }
+}
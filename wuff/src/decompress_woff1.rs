@@ -1,35 +1,90 @@
 use alloc::{boxed::Box, vec::Vec};
 
 use crate::{
-    Round4, compute_checksum,
-    error::{WuffErr, bail_if},
+    DecodeOptions, Decoded, HEAD, Round4, Tag, ZlibDecompressor, compute_checksum,
+    diagnostics::{Diagnostics, WarningKind},
+    error::{WuffErr, bail, bail_if},
+    fallible::{FallibleVec, try_sort_by_key},
+    recovery::{Repair, is_essential_table},
     woff::headers::{TableDirectory, TableDirectoryEntry, WoffHeader, WoffVersion},
     write_table_directory_header,
 };
 use bytes::BufMut as _;
 use core::error::Error;
 
-#[cfg(feature = "z")]
-fn decompress_z(compressed_data: &[u8], size_hint: usize) -> Result<Vec<u8>, Box<dyn Error>> {
-    use flate2::{Decompress, FlushDecompress};
-    let mut output: Vec<u8> = Vec::with_capacity(size_hint);
-    let mut decompressor = Decompress::new(true);
-    decompressor.decompress_vec(compressed_data, &mut output, FlushDecompress::Finish)?;
-    Ok(output)
-}
-
-#[cfg(feature = "z")]
-/// Decompress a WOFF1 file using the built-in gzip decompressor
-pub fn decompress_woff1(raw_woff_data: &[u8]) -> Result<Vec<u8>, WuffErr> {
-    decompress_woff1_with_custom_z(raw_woff_data, &mut decompress_z)
-}
+/// The maximum compression ratio of zlib (deflate): each 258-byte match takes at least two
+/// bits to code, so no stream inflates to more than 1032 times its size.
+/// <https://zlib.net/zlib_tech.html> ("Maximum compression factor")
+pub(crate) const MAX_ZLIB_COMPRESSION_RATIO: u64 = 1032;
 
 #[allow(clippy::type_complexity)]
 /// Decompress a WOFF1 file using a custom gzip decompressor passed as a closure
 pub fn decompress_woff1_with_custom_z(
     raw_woff_data: &[u8],
-    decompress_z: &mut dyn FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>>,
+    mut decompress_z: &mut dyn FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>>,
+) -> Result<Vec<u8>, WuffErr> {
+    decode_woff1(raw_woff_data, &mut decompress_z, &mut Diagnostics::strict())
+}
+
+/// Decompress a WOFF1 file using a custom [`ZlibDecompressor`]
+pub fn decompress_woff1_with_decompressor(
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn ZlibDecompressor,
+) -> Result<Vec<u8>, WuffErr> {
+    decode_woff1(raw_woff_data, decompressor, &mut Diagnostics::strict())
+}
+
+/// Decompress a WOFF1 file using a custom [`ZlibDecompressor`], with the given options
+pub fn decompress_woff1_with_decompressor_and_options(
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn ZlibDecompressor,
+    options: DecodeOptions,
+) -> Result<Decoded, WuffErr> {
+    let mut diagnostics = Diagnostics::new(options);
+    let font = decode_woff1(raw_woff_data, decompressor, &mut diagnostics)?;
+    Ok(diagnostics.into_decoded(font))
+}
+
+fn decode_woff1(
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn ZlibDecompressor,
+    diagnostics: &mut Diagnostics,
 ) -> Result<Vec<u8>, WuffErr> {
+    let mut scratch = Woff1Scratch::default();
+    decode_woff1_into(raw_woff_data, decompressor, diagnostics, &mut scratch)?;
+    Ok(scratch.out)
+}
+
+/// Working memory for decoding WOFF1 files, which can be reused between decodes to avoid
+/// allocating (see [`Woff1Decoder`](crate::Woff1Decoder))
+#[derive(Default)]
+pub(crate) struct Woff1Scratch {
+    table_directory: TableDirectory,
+    sort_scratch: Vec<(usize, TableDirectoryEntry)>,
+    /// Indices into the table directory, sorted by table offset
+    tables_by_offset: Vec<usize>,
+    /// Tables left out of the output in recovery mode, by tag index
+    skipped: Vec<bool>,
+    /// The decoded font
+    pub(crate) out: Vec<u8>,
+}
+
+/// Decode a WOFF1 file into `scratch.out`
+pub(crate) fn decode_woff1_into(
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn ZlibDecompressor,
+    diagnostics: &mut Diagnostics,
+    scratch: &mut Woff1Scratch,
+) -> Result<(), WuffErr> {
+    let Woff1Scratch {
+        table_directory,
+        sort_scratch,
+        tables_by_offset,
+        skipped,
+        out,
+    } = scratch;
+    out.clear();
+
     // Here we create a new view over the `raw_woff_data`. Because we pass `&mut input` to parsing functons,
     // they will actually mutate the slice (not the data it points to) such that it only includes unparsed data.
     //
@@ -37,79 +92,196 @@ pub fn decompress_woff1_with_custom_z(
     let mut input = raw_woff_data;
 
     // Parse header and table directory
-    let header = WoffHeader::parse(&mut input)?;
+    let header = WoffHeader::parse_with_diagnostics(&mut input, diagnostics)?;
     bail_if!(header.woff_version != WoffVersion::Woff1);
-    let mut table_directory = TableDirectory::parse_woff1(&mut input, header.num_tables as usize)?;
+    table_directory.parse_woff1_into(&mut input, header.num_tables as usize)?;
 
-    table_directory.tables.sort_by_key(|t| t.tag);
+    try_sort_by_key(&mut table_directory.tables, sort_scratch, |t| t.tag)?;
 
-    // Create output buffer
-    let mut out: Vec<u8> = Vec::with_capacity(0 /* TODO */);
     let mut checksum: u32 = 0;
 
     // Write table directory header
-    write_table_directory_header(&mut out, header.flavor, table_directory.len() as u16);
-    checksum = checksum.wrapping_add(compute_checksum(&out));
+    out.try_reserve(12)?;
+    write_table_directory_header(out, header.flavor, table_directory.len() as u16);
+    checksum = checksum.wrapping_add(compute_checksum(out));
 
     // Reserve space for the rest of the table directory
-    let table_directory_size = table_directory.len() * 16;
+    let table_directory_size = table_directory.len().checked_mul(16);
     let table_directory_start = out.len();
-    out.resize(out.len() + table_directory_size, 0);
+    let table_directory_end = table_directory_size.and_then(|size| size.checked_add(out.len()));
+    out.try_resize(table_directory_end.ok_or(WuffErr::GenericError)?, 0)?;
 
     // Sort tables by offset, while keeping track of their order by tag
     // Table directory entries are stored in tag order
     // Tables themselves are stored in woff_offset order
-    struct TableWithTagIdx<'a> {
-        table: &'a TableDirectoryEntry,
-        tag_index: usize,
-    }
-    let mut tables_by_offset: Vec<TableWithTagIdx> = table_directory
-        .iter()
-        .enumerate()
-        .map(|(tag_index, table)| TableWithTagIdx { table, tag_index })
-        .collect();
-    tables_by_offset.sort_by_key(|t| t.table.woff_offset);
+    tables_by_offset.clear();
+    tables_by_offset.try_reserve(table_directory.len())?;
+    tables_by_offset.extend(0..table_directory.len());
+    // Tables with the same offset stay in tag order (the indices start out in that order)
+    tables_by_offset.sort_unstable_by_key(|&tag_index| {
+        let woff_offset = table_directory
+            .get(tag_index)
+            .map(|table| table.woff_offset);
+        (woff_offset, tag_index)
+    });
+
+    // Tables left out of the output in recovery mode, by tag index
+    skipped.clear();
+    skipped.try_resize(table_directory.len(), false)?;
 
     // let mut head_table_offset = None;
-    for TableWithTagIdx { table, tag_index } in tables_by_offset.into_iter() {
+    for &tag_index in tables_by_offset.iter() {
+        let table = table_directory.entry(tag_index)?;
         let table_offset = out.len();
-        let table_end = table_offset + (table.orig_length as usize);
+        let table_end = table_offset
+            .checked_add(table.orig_length as usize)
+            .ok_or(WuffErr::GenericError)?;
 
         // Store HEAD offset for later in order to write checksum
         // if table.tag == HEAD {
         //     head_table_offset = Some(table_offset);
         // }
 
+        // Write table data. A table which claims to inflate to more than zlib can produce is
+        // damaged: check it before allocating an output buffer of its origLength.
+        let is_implausible = table.woff_length < table.orig_length
+            && table.orig_length as u64
+                > (table.woff_length as u64).saturating_mul(MAX_ZLIB_COMPRESSION_RATIO);
+        let written = if is_implausible {
+            Err(WuffErr::GenericError)
+        } else {
+            write_table_data(out, table, raw_woff_data, decompressor)
+        };
+        // Running out of memory isn't a problem with the font, so is never repaired
+        let is_damaged = matches!(written, Err(WuffErr::GenericError));
+        if is_damaged && !is_essential_table(table.tag) {
+            out.truncate(table_offset);
+            let tag = table.tag.to_be_bytes();
+            let repair = if is_implausible {
+                Repair::ImplausibleCompressionRatio {
+                    tag,
+                    comp_length: table.woff_length,
+                    orig_length: table.orig_length,
+                }
+            } else {
+                Repair::SkippedTable { tag }
+            };
+            diagnostics.repair(repair)?;
+            *skipped.get_mut(tag_index).ok_or(WuffErr::GenericError)? = true;
+            continue;
+        }
+        written?;
+
+        // Like the reference implementation, the head table's checksum is taken with
+        // checkSumAdjustment set to 0
+        let table_data = out.get(table_offset..).ok_or(WuffErr::GenericError)?;
+        let mut actual_checksum = compute_checksum(table_data);
+        if let (HEAD, Some(checksum_adjustment)) = (table.tag, table_data.get(8..12)) {
+            actual_checksum = actual_checksum.wrapping_sub(compute_checksum(checksum_adjustment));
+        }
+        if actual_checksum != table.orig_checksum {
+            let kind = WarningKind::ChecksumMismatch {
+                declared: table.orig_checksum,
+                actual: actual_checksum,
+            };
+            diagnostics.warn(kind, Some(table.tag), table.woff_offset);
+        }
+
+        // Pad output to 4 bytes
+        out.try_resize(Round4!(out.len()), 0)?;
+
         // Write table directory entry for table
-        let dir_entry_start = table_directory_start + (tag_index * 16);
-        let dir_entry_end = dir_entry_start + 16;
-        let mut dir_entry_writer = &mut out[dir_entry_start..dir_entry_end];
+        let dir_entry_start = (tag_index.checked_mul(16))
+            .and_then(|offset| offset.checked_add(table_directory_start))
+            .ok_or(WuffErr::GenericError)?;
+        let dir_entry = (out
+            .get_mut(dir_entry_start..)
+            .and_then(|out| out.get_mut(..16)))
+        .ok_or(WuffErr::GenericError)?;
+        let mut dir_entry_writer = &mut *dir_entry;
+        // (the entry is 16 bytes long, so these fit)
         dir_entry_writer.put_u32(u32::from_be_bytes(table.tag.to_be_bytes()));
         dir_entry_writer.put_u32(table.orig_checksum);
         dir_entry_writer.put_u32(table_offset as u32);
         dir_entry_writer.put_u32(table.orig_length);
 
-        // Write table data
-        let is_compressed = table.woff_length < table.orig_length;
-        if is_compressed {
-            let compressed_data = table.data_as_slice(raw_woff_data)?;
-            let decompressed_data = decompress_z(compressed_data, table.orig_length as usize)
-                .map_err(|_| WuffErr::GenericError)?;
-            bail_if!(decompressed_data.len() != table.orig_length as usize);
-            out.extend_from_slice(&decompressed_data);
-        } else {
-            out.extend_from_slice(table.data_as_slice(raw_woff_data)?);
-        };
-
-        // Pad output to 4 bytes
-        out.resize(Round4!(out.len()), 0);
-
         // Update checksum
-        checksum = checksum.wrapping_add(compute_checksum(&out[dir_entry_start..dir_entry_end]));
-        checksum = checksum.wrapping_add(compute_checksum(&out[table_offset..table_end]));
+        checksum = checksum.wrapping_add(compute_checksum(dir_entry));
+        let table_data = out.get(table_offset..table_end);
+        checksum =
+            checksum.wrapping_add(compute_checksum(table_data.ok_or(WuffErr::GenericError)?));
     }
 
     // TODO: Checksum adjustment
 
+    if skipped.contains(&true) {
+        *out = remove_skipped_tables(out, header.flavor, skipped)?;
+    }
+
+    if header.total_sfnt_size as usize != out.len() {
+        let kind = WarningKind::TotalSfntSizeMismatch {
+            declared: header.total_sfnt_size,
+            actual: out.len() as u32,
+        };
+        diagnostics.warn(kind, None, 16);
+    }
+
+    Ok(())
+}
+
+/// Append the (decompressed) data of a table to `out`
+fn write_table_data(
+    out: &mut Vec<u8>,
+    table: &TableDirectoryEntry,
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn ZlibDecompressor,
+) -> Result<(), WuffErr> {
+    // A compressed table must be smaller than the uncompressed data
+    bail_if!(table.woff_length > table.orig_length);
+    let is_compressed = table.woff_length < table.orig_length;
+    if is_compressed {
+        let compressed_data = table.data_as_slice(raw_woff_data)?;
+        let table_offset = out.len();
+        let table_end = table_offset.checked_add(table.orig_length as usize);
+        out.try_resize(table_end.ok_or(WuffErr::GenericError)?, 0)?;
+        let output = out.get_mut(table_offset..).ok_or(WuffErr::GenericError)?;
+        decompressor.decompress(compressed_data, output)?;
+    } else {
+        out.try_extend_from_slice(table.data_as_slice(raw_woff_data)?)?;
+    };
+    Ok(())
+}
+
+/// Rewrite a decoded font without the (zeroed) table directory entries of skipped tables
+fn remove_skipped_tables(font: &[u8], flavor: Tag, skipped: &[bool]) -> Result<Vec<u8>, WuffErr> {
+    let num_kept = skipped.iter().filter(|&&skipped| !skipped).count();
+    // (the table directory was written to `font`, so its size doesn't overflow)
+    let removed_size = skipped.len().wrapping_sub(num_kept).wrapping_mul(16);
+    let data_start = skipped.len().wrapping_mul(16).wrapping_add(12);
+    let out_len = font.len().checked_sub(removed_size);
+
+    // Everything written to `out` below fits in this
+    let mut out = Vec::new();
+    out.try_reserve_exact(out_len.ok_or(WuffErr::GenericError)?)?;
+    write_table_directory_header(&mut out, flavor, num_kept as u16);
+    let table_directory = font.get(12..data_start).ok_or(WuffErr::GenericError)?;
+    for (entry, _) in table_directory
+        .chunks_exact(16)
+        .zip(skipped)
+        .filter(|(_, skipped)| !**skipped)
+    {
+        // The tag and checksum are copied as-is, then the offset is moved back
+        let Some((tag_and_checksum, rest)) = entry.split_first_chunk::<8>() else {
+            bail!()
+        };
+        let Some((offset, length)) = rest.split_first_chunk::<4>() else {
+            bail!()
+        };
+        out.extend_from_slice(tag_and_checksum);
+        let offset = u32::from_be_bytes(*offset).checked_sub(removed_size as u32);
+        out.put_u32(offset.ok_or(WuffErr::GenericError)?);
+        out.extend_from_slice(length);
+    }
+    out.extend_from_slice(font.get(data_start..).ok_or(WuffErr::GenericError)?);
     Ok(out)
 }
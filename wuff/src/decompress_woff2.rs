@@ -1,33 +1,160 @@
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::{boxed::Box, vec::Vec};
 use core::error::Error;
 
 use crate::Tag;
 use bytes::{Buf as _, BufMut};
 
 use crate::{
-    GLYF, HEAD, HMTX, LOCA, Round4, compute_checksum,
+    BrotliDecompressor, DecodeOptions, DecompressError, GLYF, HEAD, HHEA, HMTX, LOCA, MAXP,
+    OutputProfile, Round4, compute_checksum,
+    diagnostics::{Diagnostics, WarningKind},
     error::{WuffErr, bail, bail_if, bail_with_msg_if},
+    fallible::FallibleVec,
+    read_u16_at,
+    recovery::{Decoded, Repair, is_essential_table},
+    variable_length::BufVariableExt as _,
     woff::{
-        glyf_decoder::tranform_glyf_table,
+        composite_graph::{ComponentLimits, check_composite_glyphs},
+        glyf_decoder::{GlyfAndLocaData, tranform_glyf_table_into},
         headers::{
             CollectionDirectory, CollectionDirectoryEntry, TableDirectory, TableDirectoryEntry,
             WOFF2FontInfo, WoffHeader, WoffVersion,
         },
-        hmtx_decoder::{decode_hmtx_table, generate_hmtx_table},
+        hmtx_decoder::{HmtxData, decode_hmtx_table_into, write_hmtx_table},
     },
-    write_table_directory_header,
+    write_table_directory_header, write_u16_at, write_u32_at,
 };
 
+/// The offset of indexToLocFormat in the head table
+const HEAD_INDEX_TO_LOC_FORMAT_OFFSET: usize = 50;
+/// The offset of numGlyphs in the maxp table
+const MAXP_NUM_GLYPHS_OFFSET: usize = 4;
+/// The offsets of maxComponentElements and maxComponentDepth in a version 1.0 maxp table
+const MAXP_MAX_COMPONENT_ELEMENTS_OFFSET: usize = 28;
+const MAXP_MAX_COMPONENT_DEPTH_OFFSET: usize = 30;
+
 // Over 14k test fonts the max compression ratio seen to date was ~20.
 // >100 suggests you wrote a bad uncompressed size.
-const K_MAX_PLAUSIBLE_COMPRESSION_RATIO: f32 = 100.0;
+pub(crate) const K_MAX_PLAUSIBLE_COMPRESSION_RATIO: f32 = 100.0;
 
 #[allow(clippy::type_complexity)]
 /// Decompress a WOFF2 file using a custom brotli decompressor passed as a closure
 pub fn decompress_woff2_with_custom_brotli(
     raw_woff_data: &[u8],
-    decompress_brotli: &mut dyn FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>>,
+    mut decompress_brotli: &mut dyn FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>>,
+) -> Result<Vec<u8>, WuffErr> {
+    decode_woff2(
+        raw_woff_data,
+        None,
+        &mut decompress_brotli,
+        &mut Diagnostics::strict(),
+        OutputProfile::default(),
+    )
+}
+
+/// Decompress a WOFF2 file using a custom [`BrotliDecompressor`]
+pub fn decompress_woff2_with_decompressor(
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn BrotliDecompressor,
+) -> Result<Vec<u8>, WuffErr> {
+    decode_woff2(
+        raw_woff_data,
+        None,
+        decompressor,
+        &mut Diagnostics::strict(),
+        OutputProfile::default(),
+    )
+}
+
+/// Decompress the font at `index` within a WOFF2 file using a custom [`BrotliDecompressor`]
+///
+/// See [`decompress_woff2_font`](crate::decompress_woff2_font).
+pub fn decompress_woff2_font_with_decompressor(
+    raw_woff_data: &[u8],
+    index: usize,
+    decompressor: &mut dyn BrotliDecompressor,
+) -> Result<Vec<u8>, WuffErr> {
+    decode_woff2(
+        raw_woff_data,
+        Some(index),
+        decompressor,
+        &mut Diagnostics::strict(),
+        OutputProfile::default(),
+    )
+}
+
+/// Decompress a WOFF2 file using a custom [`BrotliDecompressor`], with the given options
+pub fn decompress_woff2_with_decompressor_and_options(
+    raw_woff_data: &[u8],
+    decompressor: &mut dyn BrotliDecompressor,
+    options: DecodeOptions,
+) -> Result<Decoded, WuffErr> {
+    let profile = options.output_profile;
+    let mut diagnostics = Diagnostics::new(options);
+    let font = decode_woff2(raw_woff_data, None, decompressor, &mut diagnostics, profile)?;
+    Ok(diagnostics.into_decoded(font))
+}
+
+fn decode_woff2(
+    raw_woff_data: &[u8],
+    font_index: Option<usize>,
+    decompressor: &mut dyn BrotliDecompressor,
+    diagnostics: &mut Diagnostics,
+    profile: OutputProfile,
 ) -> Result<Vec<u8>, WuffErr> {
+    let mut scratch = Woff2Scratch::default();
+    decode_woff2_into(
+        raw_woff_data,
+        font_index,
+        decompressor,
+        diagnostics,
+        profile,
+        &mut scratch,
+    )?;
+    Ok(scratch.out)
+}
+
+/// Working memory for decoding WOFF2 files, which can be reused between decodes to avoid
+/// allocating (see [`Woff2Decoder`](crate::Woff2Decoder))
+#[derive(Default)]
+pub(crate) struct Woff2Scratch {
+    table_directory: TableDirectory,
+    collection_directory: CollectionDirectory,
+    sort_scratch: Vec<(usize, u16)>,
+    decompressed_data: Vec<u8>,
+    out_header: HeaderData,
+    table_metadata: Vec<Option<TableMetadata>>,
+    glyf: GlyfAndLocaData,
+    hmtx: HmtxData,
+    hmtx_table: Vec<u8>,
+    /// The decoded font
+    pub(crate) out: Vec<u8>,
+}
+
+/// Decode a WOFF2 file into `scratch.out`. If `font_index` is given, only that font is decoded
+/// (as a standalone sfnt, even if the file is a collection).
+pub(crate) fn decode_woff2_into(
+    raw_woff_data: &[u8],
+    font_index: Option<usize>,
+    decompressor: &mut dyn BrotliDecompressor,
+    diagnostics: &mut Diagnostics,
+    profile: OutputProfile,
+    scratch: &mut Woff2Scratch,
+) -> Result<(), WuffErr> {
+    let Woff2Scratch {
+        table_directory,
+        collection_directory,
+        sort_scratch,
+        decompressed_data,
+        out_header,
+        table_metadata,
+        glyf,
+        hmtx,
+        hmtx_table,
+        out,
+    } = scratch;
+    out.clear();
+
     // Here we create a new view over the `raw_woff_data`. Because we pass `&mut input` to parsing functons,
     // they will actually mutate the slice (not the data it points to) such that it only includes unparsed data.
     //
@@ -36,41 +163,52 @@ pub fn decompress_woff2_with_custom_brotli(
     let full_input_len = input.len();
 
     // Parse header, table directory and collection directory
-    let header = WoffHeader::parse(&mut input)?;
+    let header = WoffHeader::parse_with_diagnostics(&mut input, diagnostics)?;
     bail_if!(header.woff_version != WoffVersion::Woff2);
 
-    let table_directory = TableDirectory::parse_woff2(&mut input, header.num_tables as usize)?;
-    let mut collection_directory = if header.is_collection() {
-        CollectionDirectory::parse(&mut input, &table_directory)?
-    } else {
-        CollectionDirectory::generate_for_single_font(header.flavor, &table_directory)
-    };
+    table_directory.parse_woff2_into(&mut input, header.num_tables as usize)?;
+    if header.is_collection() {
+        collection_directory.parse_into(&mut input, table_directory)?;
+    }
 
     // Validate header (blocks do not overlap, and have at most 3 bytes padding between them)
 
-    let compressed_offset = full_input_len - input.len();
+    // (parsing only shrinks `input`)
+    let compressed_offset = full_input_len.wrapping_sub(input.len());
     bail_if!(compressed_offset > u32::MAX as usize);
 
-    let mut src_offset = Round4!(compressed_offset + header.total_compressed_size as usize);
-    bail_if!(src_offset > full_input_len);
+    let compressed_end = compressed_offset.checked_add(header.total_compressed_size as usize);
+    let compressed_end = compressed_end.ok_or(WuffErr::GenericError)?;
+    let mut src_offset = Round4!(compressed_end);
+    if src_offset > full_input_len {
+        // Only the padding may be missing, not the compressed data itself
+        bail_if!(compressed_end > full_input_len);
+        diagnostics.repair(Repair::MissingPadding)?;
+    }
 
     if header.meta_offset != 0 {
-        bail_if!(src_offset != header.meta_offset as usize);
-        src_offset = Round4!(header.meta_offset as usize + header.meta_length as usize);
+        check_block_offset(src_offset, header.meta_offset, diagnostics)?;
+        let meta_end = (header.meta_offset as usize).checked_add(header.meta_length as usize);
+        src_offset = Round4!(meta_end.ok_or(WuffErr::GenericError)?);
         bail_if!(src_offset > u32::MAX as usize);
     }
 
     if header.priv_offset != 0 {
-        bail_if!(src_offset != header.priv_offset as usize);
-        src_offset = Round4!(header.priv_offset as usize + header.priv_length as usize);
+        check_block_offset(src_offset, header.priv_offset, diagnostics)?;
+        let priv_end = (header.priv_offset as usize).checked_add(header.priv_length as usize);
+        src_offset = Round4!(priv_end.ok_or(WuffErr::GenericError)?);
         bail_if!(src_offset > u32::MAX as usize);
     }
 
-    bail_if!(src_offset != Round4!(full_input_len));
-
-    // Re-order tables in output (OTSpec) order
-    collection_directory.sort_tables_within_each_font(&table_directory);
-    let num_fonts = collection_directory.fonts.len();
+    let file_end = Round4!(full_input_len);
+    if src_offset != file_end {
+        bail_if!(src_offset > file_end);
+        diagnostics.repair(Repair::ExtraneousData {
+            offset: src_offset as u32,
+            // (`src_offset` is a multiple of 4 less than `file_end`, so within the input)
+            length: full_input_len.wrapping_sub(src_offset) as u32,
+        })?;
+    }
 
     // Compute compression ratio using the trusted, table-directory-derived uncompressed size
     // (not the untrusted `totalSfntSize` from the file header). Perform the plausibility check
@@ -79,48 +217,233 @@ pub fn decompress_woff2_with_custom_brotli(
         (table_directory.uncompressed_size as f32) / (raw_woff_data.len() as f32);
 
     // Validate header (and compression ratio)
-    bail_if!(header.total_sfnt_size < 1);
+    if header.total_sfnt_size < 1 {
+        diagnostics.repair(Repair::WrongTotalSfntSize {
+            declared: header.total_sfnt_size,
+        })?;
+    }
+    // There's nothing to decompress if all the tables are empty. The reference decoder
+    // rejects such fonts too (see ConvertWOFF2ToTTF in woff2/src/woff2_dec.cc).
+    bail_if!(table_directory.uncompressed_size < 1);
     bail_with_msg_if!(
         compression_ratio > K_MAX_PLAUSIBLE_COMPRESSION_RATIO,
         "Implausible compression ratio {:.1}",
         compression_ratio
     );
 
-    // Decompress data with brotli decoder. We pass the trusted `uncompressed_size` as the hard
-    // upper bound on the size of the decompressed data.
-    let compressed_data = &input[0..(header.total_compressed_size as usize)];
-    let decompressed_data = decompress_brotli(compressed_data, table_directory.uncompressed_size)
-        .map_err(|_| WuffErr::GenericError)?;
+    // Decompress data with brotli decoder. The output buffer is the trusted `uncompressed_size`,
+    // which is the hard upper bound on the size of the decompressed data.
+    let compressed_data = input
+        .get(..header.total_compressed_size as usize)
+        .ok_or(WuffErr::GenericError)?;
+    decompressed_data.clear();
+    decompressed_data.try_resize(table_directory.uncompressed_size, 0)?;
+    match decompressor.decompress(compressed_data, decompressed_data) {
+        Ok(()) => {}
+        // The decompressed data block must be exactly the size of the tables it contains
+        // (tables are stored consecutively with no padding or extraneous data).
+        // <https://www.w3.org/TR/WOFF2/#conform-mustRejectExtraData>
+        Err(DecompressError::TooLong) => diagnostics.repair(Repair::ExtraneousFontData)?,
+        Err(DecompressError::OutOfMemory) => return Err(WuffErr::OutOfMemory),
+        Err(_) => bail!(),
+    }
 
-    // The decompressed data block must be exactly the size of the tables it contains
-    // (tables are stored consecutively with no padding or extraneous data).
-    // <https://www.w3.org/TR/WOFF2/#conform-mustRejectExtraData>
-    bail_if!(decompressed_data.len() != table_directory.uncompressed_size);
+    if !header.is_collection() {
+        if diagnostics.is_recovering() {
+            add_missing_loca(table_directory, decompressed_data, diagnostics)?;
+        }
+        collection_directory.generate_for_single_font_into(header.flavor, table_directory)?;
+    }
+    // Drop the other fonts, so that tables only they use are never reconstructed
+    let is_collection = match font_index {
+        Some(index) => {
+            let fonts = &mut collection_directory.fonts;
+            let num_fonts = fonts.len();
+            bail_with_msg_if!(
+                index >= num_fonts,
+                "Font index {} out of range ({} fonts)",
+                index,
+                num_fonts
+            );
+            fonts.truncate(index.saturating_add(1));
+            fonts.drain(..index);
+            false
+        }
+        None => header.is_collection(),
+    };
+    if diagnostics.is_recovering() {
+        rebuild_mismatched_loca(table_directory, collection_directory, diagnostics)?;
+    }
+    skip_unsupported_tables(table_directory, collection_directory, diagnostics)?;
 
-    let mut out: Vec<u8> = Vec::with_capacity(table_directory.uncompressed_size);
+    // Re-order tables in output (OTSpec) order
+    collection_directory.sort_tables_within_each_font(table_directory, sort_scratch)?;
 
-    let mut out_header = generate_header(&header, &table_directory, &collection_directory);
-    out.extend_from_slice(&out_header.data);
+    out.try_reserve(table_directory.uncompressed_size)?;
+
+    generate_header(
+        &header,
+        is_collection,
+        table_directory,
+        collection_directory,
+        out_header,
+    )?;
+    out.try_extend_from_slice(&out_header.data)?;
 
     // Metadata for tables that have been written. Index corresponds to the table's index within the tables Vec
-    let mut table_metadata: Vec<Option<TableMetadata>> = vec![None; header.num_tables as usize];
-    for i in 0..num_fonts {
+    table_metadata.clear();
+    table_metadata.try_resize(table_directory.len(), None)?;
+    let mut buffers = TableBuffers {
+        glyf,
+        hmtx,
+        hmtx_table,
+    };
+    for (i, font_entry) in collection_directory.fonts.iter().enumerate() {
         reconstruct_font(
-            &decompressed_data,
-            &header,
-            &table_directory,
-            &collection_directory.fonts[i],
-            &mut out_header,
-            &mut table_metadata,
-            &mut out,
+            decompressed_data,
+            table_directory,
+            font_entry,
+            out_header,
+            table_metadata,
+            &mut buffers,
+            out,
             i,
+            diagnostics,
+            profile,
         )?;
     }
 
     // Update header
-    out[0..out_header.data.len()].copy_from_slice(&out_header.data);
+    for (byte, &header_byte) in out.iter_mut().zip(&out_header.data) {
+        *byte = header_byte;
+    }
+
+    // (`totalSfntSize` is the size of the whole collection, not the font decoded from it)
+    if header.total_sfnt_size as usize != out.len() && is_collection == header.is_collection() {
+        let kind = WarningKind::TotalSfntSizeMismatch {
+            declared: header.total_sfnt_size,
+            actual: out.len() as u32,
+        };
+        diagnostics.warn(kind, None, 16);
+    }
+
+    Ok(())
+}
+
+/// Check that a block starts at `offset`, where `expected` is the (padded) end of the
+/// previous block. In recovery mode, a gap between the blocks is skipped.
+fn check_block_offset(
+    expected: usize,
+    offset: u32,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), WuffErr> {
+    let offset = offset as usize;
+    if offset != expected {
+        // Blocks must not overlap
+        bail_if!(offset < expected);
+        diagnostics.repair(Repair::ExtraneousData {
+            offset: expected as u32,
+            // (checked above)
+            length: offset.wrapping_sub(expected) as u32,
+        })?;
+    }
+    Ok(())
+}
+
+/// (Recovery mode) Add an entry for the loca table of a single font which has a transformed
+/// glyf table but no loca table. The loca table is rebuilt from the glyf table anyway.
+fn add_missing_loca(
+    table_directory: &mut TableDirectory,
+    decompressed_data: &[u8],
+    diagnostics: &mut Diagnostics,
+) -> Result<(), WuffErr> {
+    if table_directory.iter().any(|table| table.tag == LOCA) {
+        return Ok(());
+    }
+    let Some(glyf) = table_directory
+        .iter()
+        .find(|table| table.tag == GLYF && table.is_transformed())
+    else {
+        return Ok(());
+    };
+
+    // The loca table's origLength, from numGlyphs and indexFormat in the transformed glyf header
+    let mut glyf_header = glyf.data_as_slice(decompressed_data)?;
+    glyf_header.try_advance(4)?;
+    let num_glyphs = glyf_header.try_get_u16()? as u32;
+    let index_format = glyf_header.try_get_u16()?;
+    #[allow(clippy::arithmetic_side_effects)] // (numGlyphs is a u16)
+    let orig_length = (num_glyphs + 1) * if index_format == 0 { 2 } else { 4 };
+
+    diagnostics.repair(Repair::MissingLoca)?;
+    let woff_offset = table_directory.uncompressed_size as u32;
+    table_directory.try_push(TableDirectoryEntry {
+        version: WoffVersion::Woff2,
+        tag: LOCA,
+        format: 0,
+        woff_offset,
+        woff_length: 0,
+        orig_length,
+        orig_checksum: 0,
+    })?;
+    Ok(())
+}
+
+/// (Recovery mode) Mark untransformed loca tables which go with a transformed glyf table as
+/// transformed, so that they are rebuilt from the glyf table.
+fn rebuild_mismatched_loca(
+    table_directory: &mut TableDirectory,
+    collection_directory: &CollectionDirectory,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), WuffErr> {
+    for font in &collection_directory.fonts {
+        if let (Some(glyf_idx), Some(loca_idx)) = (font.glyf_idx, font.loca_idx) {
+            let loca = table_directory.entry(loca_idx)?;
+            if table_directory.entry(glyf_idx)?.is_transformed() && !loca.is_transformed() {
+                diagnostics.repair(Repair::LocaTransformMismatch)?;
+                table_directory.entry_mut(loca_idx)?.format = 0;
+            }
+        }
+    }
+    Ok(())
+}
 
-    Ok(out)
+/// Reject fonts containing tables with transforms which we can't reverse. In recovery mode,
+/// non-essential tables with such transforms are left out of the decoded font instead.
+fn skip_unsupported_tables(
+    table_directory: &TableDirectory,
+    collection_directory: &mut CollectionDirectory,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), WuffErr> {
+    let mut unsupported: Vec<u16> = Vec::new();
+    let all_indices = collection_directory
+        .fonts
+        .iter()
+        .flat_map(|font| font.table_indices.iter().copied());
+    for idx in all_indices {
+        let table = table_directory.entry(idx)?;
+        if table.is_transformed() && ![GLYF, LOCA, HMTX].contains(&table.tag) {
+            unsupported.try_push(idx)?;
+        }
+    }
+    if unsupported.is_empty() {
+        return Ok(());
+    }
+    unsupported.sort_unstable();
+    unsupported.dedup();
+
+    for &idx in &unsupported {
+        let tag = table_directory.entry(idx)?.tag;
+        bail_if!(is_essential_table(tag));
+        diagnostics.repair(Repair::SkippedTable {
+            tag: tag.to_be_bytes(),
+        })?;
+    }
+    for font in &mut collection_directory.fonts {
+        font.table_indices
+            .retain(|idx| unsupported.binary_search(idx).is_err());
+    }
+    Ok(())
 }
 
 fn iter_tables_for_font<'a>(
@@ -130,7 +453,28 @@ fn iter_tables_for_font<'a>(
     font_entry
         .table_indices
         .iter()
-        .map(|table_idx| (*table_idx as usize, &tables[*table_idx as usize]))
+        // (the indices are checked when the collection directory is parsed)
+        .filter_map(|&table_idx| Some((table_idx as usize, tables.get(table_idx as usize)?)))
+}
+
+/// The offset of the font's table with the given tag in the decompressed data block (0 if
+/// it has no such table), for diagnostics
+fn table_offset(font_entry: &CollectionDirectoryEntry, tables: &TableDirectory, tag: Tag) -> u32 {
+    let table = iter_tables_for_font(font_entry, tables).find(|(_, t)| t.tag == tag);
+    table.map_or(0, |(_, table)| table.woff_offset)
+}
+
+/// Whether `head.indexToLocFormat` is updated to match the reconstructed loca table. The
+/// reference profile leaves it as it is (like the reference decoder), except to repair it.
+fn updates_head(profile: OutputProfile, diagnostics: &Diagnostics) -> bool {
+    profile != OutputProfile::ReferenceCompatible || diagnostics.is_recovering()
+}
+
+/// Buffers used to reconstruct transformed tables
+struct TableBuffers<'a> {
+    glyf: &'a mut GlyfAndLocaData,
+    hmtx: &'a mut HmtxData,
+    hmtx_table: &'a mut Vec<u8>,
 }
 
 // Offset tables assumed to have been written in with 0's initially.
@@ -138,13 +482,15 @@ fn iter_tables_for_font<'a>(
 #[allow(clippy::too_many_arguments)]
 fn reconstruct_font(
     woff_data: &[u8],
-    header: &WoffHeader,
     tables: &TableDirectory,
     font_entry: &CollectionDirectoryEntry,
     out_header: &mut HeaderData,
     table_metadata: &mut [Option<TableMetadata>],
+    buffers: &mut TableBuffers,
     out: &mut Vec<u8>,
     font_idx: usize,
+    diagnostics: &mut Diagnostics,
+    profile: OutputProfile,
 ) -> Result<(), WuffErr> {
     let glyf_idx = font_entry.glyf_idx.map(|idx| idx as usize);
     let loca_idx = font_entry.loca_idx.map(|idx| idx as usize);
@@ -155,7 +501,8 @@ fn reconstruct_font(
     match (glyf_idx, loca_idx) {
         (Some(glyf_idx), Some(loca_idx)) => {
             bail_with_msg_if!(
-                tables[glyf_idx].is_transformed() != tables[loca_idx].is_transformed(),
+                tables.entry(glyf_idx)?.is_transformed()
+                    != tables.entry(loca_idx)?.is_transformed(),
                 "Cannot transform just one of glyf/loca"
             );
         }
@@ -165,8 +512,9 @@ fn reconstruct_font(
         (None, None) => {}
     }
 
-    let mut font_checksum: u32 = if header.is_collection() {
-        out_header.font_infos[font_idx].header_checksum
+    let mut font_checksum: u32 = if out_header.is_collection {
+        let font_info = out_header.font_infos.get(font_idx);
+        font_info.ok_or(WuffErr::GenericError)?.header_checksum
     } else {
         out_header.checksum
     };
@@ -174,29 +522,32 @@ fn reconstruct_font(
     // Read and store "num_hmetrics" from "hhea" table and then used to reconstruct "hmtx"
     let num_hmetrics = match hhea_idx {
         Some(hhea_idx) => {
-            let hhea_table = &tables[hhea_idx];
+            let hhea_table = tables.entry(hhea_idx)?;
             Some(read_num_hmetrics(hhea_table.data_as_slice(woff_data)?)?)
         }
         None => None,
     };
 
-    // These are read from "glyf" and then used to reconstruct "hmtx"
+    // This is read from "glyf" and then used (along with the x_mins in `buffers.glyf`) to
+    // reconstruct "hmtx"
     let mut num_glyphs = None;
-    let mut x_mins = None;
+    // The index format of the loca table reconstructed with "glyf"
+    let mut loca_index_format = None;
 
     // Iterate over the tables for this font.
     // Note: tables within each font (what we are iterating over here) have already been sorted in alphabetical table tag order.
     for (table_idx, table) in iter_tables_for_font(font_entry, tables) {
         // TODO(user) a collection with optimized hmtx that reused glyf/loca
         // would fail. We don't optimize hmtx for collections yet.
-        bail_if!(table.woff_offset as usize + table.woff_length as usize > woff_data.len());
+        let table_end = (table.woff_offset as usize).checked_add(table.woff_length as usize);
+        bail_if!(table_end.is_none_or(|table_end| table_end > woff_data.len()));
 
         // Check to see if we have already processed and saved metadata for this table.
         // If we have then
         // There are two cases when this occurs:
         //   - When a table is reused between fonts in a collection (and this table has already been processed for an earlier font)
         //   - For the "loca" table. This table gets processed as part of processing "glyf"
-        let metadata = if let Some(metadata) = table_metadata[table_idx] {
+        let metadata = if let Some(metadata) = *metadata_for(table_metadata, table_idx)? {
             // Tables shouldn't be reused within a single font (they should be reused between different
             // fonts in a collection). So if we encounter a table we have already computed metadata for in the first
             // font unless the table is a "loca" table because we compute metadata for this table when processing the "glyf"
@@ -207,73 +558,128 @@ fn reconstruct_font(
         }
         // Any table which does not need to be transformed
         else if !table.is_transformed() {
+            let table_data = table.data_as_slice(woff_data)?;
             let check_sum_adjustment = if table.tag == HEAD {
-                bail_if!(table.woff_length < 12);
-                let checksum_slice =
-                    &woff_data[(table.woff_offset as usize + 8)..(table.woff_offset as usize + 12)];
-                let checksum_bytes: [u8; 4] = checksum_slice.try_into().unwrap();
-                u32::from_be_bytes(checksum_bytes)
+                let mut checksum_adjustment = table_data;
+                checksum_adjustment.try_advance(8)?;
+                checksum_adjustment.try_get_u32()?
             } else {
                 0
             };
 
-            let table_data = table.data_as_slice(woff_data)?;
+            let dst_offset = out.len();
+            out.try_extend_from_slice(table_data)?;
+            // head.indexToLocFormat is updated to match the loca table reconstructed with
+            // glyf (which sorts before head, so has already been reconstructed), except by
+            // the reference profile outside recovery mode. Like the reference decoder, that
+            // leaves head as it is (and the mismatch is reported for each font below).
+            let offset = dst_offset.saturating_add(HEAD_INDEX_TO_LOC_FORMAT_OFFSET);
+            if let (HEAD, Some(index_format), Some(head_index_format)) =
+                (table.tag, loca_index_format, read_u16_at(out, offset))
+            {
+                if head_index_format != index_format && updates_head(profile, diagnostics) {
+                    // The compact profile may choose a different index format to the one the
+                    // font declares
+                    let chosen_by_profile = profile == OutputProfile::Compact
+                        && head_index_format == buffers.glyf.declared_index_format;
+                    if !chosen_by_profile {
+                        diagnostics.glyph_table_mismatch(
+                            Repair::IndexToLocFormatMismatch {
+                                head: head_index_format,
+                                loca: index_format,
+                            },
+                            WarningKind::IndexToLocFormatMismatch {
+                                head: head_index_format,
+                                loca: index_format,
+                            },
+                            HEAD,
+                            table.woff_offset,
+                        )?;
+                    }
+                    write_u16_at(out, offset, index_format)?;
+                }
+            }
+
+            let table_data = out.get(dst_offset..).ok_or(WuffErr::GenericError)?;
             let checksum = compute_checksum(table_data).wrapping_sub(check_sum_adjustment);
 
             let metadata = TableMetadata {
-                dst_offset: out.len() as u32,
+                dst_offset: dst_offset as u32,
                 dst_length: table.woff_length,
                 checksum,
             };
-            table_metadata[table_idx] = Some(metadata);
+            *metadata_for(table_metadata, table_idx)? = Some(metadata);
 
-            out.extend_from_slice(table_data);
-            out.resize(Round4!(out.len()), 0);
+            out.try_resize(Round4!(out.len()), 0)?;
 
             metadata
         }
         // glyf table (also process loca table)
         else if table.tag == GLYF {
-            let loca_idx =
-                loca_idx.expect("We already returned an error if glyf is present but loca isn't");
+            // (we already returned an error if glyf is present but loca isn't)
+            let Some(loca_idx) = loca_idx else { bail!() };
 
             // Generate transformed glyf and loca tables
             let raw_glyf_table_data = table.data_as_slice(woff_data)?;
-            let glyf_and_loca_data = tranform_glyf_table(raw_glyf_table_data)?;
+            let glyf_and_loca_data = &mut *buffers.glyf;
+            tranform_glyf_table_into(raw_glyf_table_data, profile, glyf_and_loca_data)?;
 
             // The origLength of the loca table declared in the table directory must exactly
-            // match the size of the reconstructed loca table.
+            // match the size of the reconstructed loca table (in the index format declared
+            // by the glyf table, whatever the output profile).
             // <https://www.w3.org/TR/WOFF2/#conform-mustRejectLoca>
-            bail_with_msg_if!(
-                tables[loca_idx].orig_length as usize != glyf_and_loca_data.loca_table.len(),
-                "loca table origLength does not match reconstructed loca size"
-            );
-
-            // Store num_glyphs and x_mins
+            let offset_size = if glyf_and_loca_data.declared_index_format != 0 {
+                4
+            } else {
+                2
+            };
+            #[allow(clippy::arithmetic_side_effects)] // (numGlyphs is a u16)
+            let loca_length = (glyf_and_loca_data.num_glyphs as usize + 1) * offset_size;
+            let loca = tables.entry(loca_idx)?;
+            if loca.orig_length as usize != loca_length {
+                diagnostics.repair(Repair::LocaLengthMismatch {
+                    declared: loca.orig_length,
+                    actual: loca_length as u32,
+                })?;
+            }
+
+            // (only the reference decoder's glyf table is expected to match origLength)
+            let glyf_length = glyf_and_loca_data.glyf_table.len();
+            if table.orig_length as usize != glyf_length
+                && profile == OutputProfile::ReferenceCompatible
+            {
+                let kind = WarningKind::TableLengthMismatch {
+                    declared: table.orig_length,
+                    reconstructed: glyf_length as u32,
+                };
+                diagnostics.warn(kind, Some(table.tag), table.woff_offset);
+            }
+
+            // Store num_glyphs (x_mins are kept in `buffers.glyf`)
             num_glyphs = Some(glyf_and_loca_data.num_glyphs);
-            x_mins = Some(glyf_and_loca_data.x_mins);
+            loca_index_format = Some(glyf_and_loca_data.index_format);
 
             // Write glyf table
             let glyf_dest_offset = out.len();
-            out.extend_from_slice(&glyf_and_loca_data.glyf_table);
-            out.resize(Round4!(out.len()), 0);
+            out.try_extend_from_slice(&glyf_and_loca_data.glyf_table)?;
+            out.try_resize(Round4!(out.len()), 0)?;
             let glyf_metadata = TableMetadata {
                 checksum: glyf_and_loca_data.glyf_checksum,
                 dst_offset: glyf_dest_offset as u32,
                 dst_length: glyf_and_loca_data.glyf_table.len() as u32,
             };
-            table_metadata[table_idx] = Some(glyf_metadata);
+            *metadata_for(table_metadata, table_idx)? = Some(glyf_metadata);
 
             // Write loca table
             let loca_dest_offset = out.len();
-            out.extend_from_slice(&glyf_and_loca_data.loca_table);
-            out.resize(Round4!(out.len()), 0);
+            out.try_extend_from_slice(&glyf_and_loca_data.loca_table)?;
+            out.try_resize(Round4!(out.len()), 0)?;
             let loca_metdata = TableMetadata {
                 checksum: glyf_and_loca_data.loca_checksum,
                 dst_offset: loca_dest_offset as u32,
                 dst_length: glyf_and_loca_data.loca_table.len() as u32,
             };
-            table_metadata[loca_idx] = Some(loca_metdata);
+            *metadata_for(table_metadata, loca_idx)? = Some(loca_metdata);
 
             // Return glyf metadata
             glyf_metadata
@@ -291,19 +697,33 @@ fn reconstruct_font(
             // TODO: better error_handling
             let num_glyphs = num_glyphs.ok_or(WuffErr::GenericError)?;
             let num_hmetrics = num_hmetrics.ok_or(WuffErr::GenericError)?;
-            let x_mins = x_mins.as_ref().ok_or(WuffErr::GenericError)?;
+            let x_mins = &buffers.glyf.x_mins;
 
             // Generate reconstructed hmtx table
             let mut raw_hmtx_table_data = table.data_as_slice(woff_data)?;
-            let hmtx_data =
-                decode_hmtx_table(&mut raw_hmtx_table_data, num_glyphs, num_hmetrics, x_mins)?;
-            let hmtx_table = generate_hmtx_table(&hmtx_data)?;
-            let checksum = compute_checksum(&hmtx_table);
+            let hmtx_data = &mut *buffers.hmtx;
+            decode_hmtx_table_into(
+                &mut raw_hmtx_table_data,
+                num_glyphs,
+                num_hmetrics,
+                x_mins,
+                hmtx_data,
+            )?;
+            let hmtx_table = &mut *buffers.hmtx_table;
+            write_hmtx_table(hmtx_data, hmtx_table)?;
+            let checksum = compute_checksum(hmtx_table);
+            if table.orig_length as usize != hmtx_table.len() {
+                let kind = WarningKind::TableLengthMismatch {
+                    declared: table.orig_length,
+                    reconstructed: hmtx_table.len() as u32,
+                };
+                diagnostics.warn(kind, Some(table.tag), table.woff_offset);
+            }
 
             // Write table to output buffer
             let dest_offset = out.len();
-            out.extend_from_slice(&hmtx_table);
-            out.resize(Round4!(out.len()), 0);
+            out.try_extend_from_slice(hmtx_table)?;
+            out.try_resize(Round4!(out.len()), 0)?;
             // Note: like the reference implementation, we record the origLength declared in
             // the WOFF2 table directory (rather than the size of the reconstructed table)
             // in the output table directory entry. The two may legitimately differ.
@@ -312,7 +732,7 @@ fn reconstruct_font(
                 dst_offset: dest_offset as u32,
                 dst_length: table.orig_length,
             };
-            table_metadata[table_idx] = Some(hmtx_metadata);
+            *metadata_for(table_metadata, table_idx)? = Some(hmtx_metadata);
 
             hmtx_metadata
         } else {
@@ -323,12 +743,72 @@ fn reconstruct_font(
         font_checksum = font_checksum.wrapping_add(metadata.checksum);
 
         // update the table entry with real values. We replaced 0's, so update  checksum.
-        out_header.update_table_entry(font_idx, table.tag, metadata);
+        out_header.update_table_entry(font_idx, table.tag, metadata)?;
         font_checksum = font_checksum.wrapping_add(metadata.header_checksum_contribution());
 
         // The table (as recorded in the output table directory) must not extend past the end
         // of the data written (including padding) so far.
-        bail_if!(metadata.dst_offset as u64 + metadata.dst_length as u64 > out.len() as u64);
+        let table_end = (metadata.dst_offset as u64).saturating_add(metadata.dst_length as u64);
+        bail_if!(table_end > out.len() as u64);
+    }
+
+    // The tables describing the glyphs must agree with the glyf table on how many there are
+    if let Some(num_glyphs) = num_glyphs {
+        let maxp_num_glyphs = read_table_u16(
+            font_entry,
+            tables,
+            table_metadata,
+            out,
+            MAXP,
+            MAXP_NUM_GLYPHS_OFFSET,
+        );
+        if let Some(maxp_num_glyphs) = maxp_num_glyphs {
+            if maxp_num_glyphs != num_glyphs {
+                diagnostics.glyph_table_mismatch(
+                    Repair::NumGlyphsMismatch {
+                        maxp: maxp_num_glyphs,
+                        glyf: num_glyphs,
+                    },
+                    WarningKind::NumGlyphsMismatch {
+                        maxp: maxp_num_glyphs,
+                        glyf: num_glyphs,
+                    },
+                    MAXP,
+                    table_offset(font_entry, tables, MAXP),
+                )?;
+            }
+        }
+        // (a transformed hmtx table has already been rejected if this doesn't hold)
+        if let Some(num_hmetrics) = num_hmetrics {
+            if !(1..=num_glyphs).contains(&num_hmetrics) {
+                diagnostics.glyph_table_mismatch(
+                    Repair::NumberOfHMetricsMismatch {
+                        num_hmetrics,
+                        num_glyphs,
+                    },
+                    WarningKind::NumberOfHMetricsMismatch {
+                        num_hmetrics,
+                        num_glyphs,
+                    },
+                    HHEA,
+                    table_offset(font_entry, tables, HHEA),
+                )?;
+            }
+        }
+
+        if diagnostics.validates_composites() {
+            let read_maxp =
+                |offset| read_table_u16(font_entry, tables, table_metadata, out, MAXP, offset);
+            // (only version 1.0 maxp tables have the component limits)
+            let limits = match read_maxp(0) {
+                Some(1) => ComponentLimits {
+                    max_component_elements: read_maxp(MAXP_MAX_COMPONENT_ELEMENTS_OFFSET),
+                    max_component_depth: read_maxp(MAXP_MAX_COMPONENT_DEPTH_OFFSET),
+                },
+                _ => ComponentLimits::default(),
+            };
+            check_composite_glyphs(buffers.glyf, limits, diagnostics)?;
+        }
     }
 
     // Update 'head' checkSumAdjustment. We already set it to 0 and summed font.
@@ -349,24 +829,86 @@ fn reconstruct_font(
     // <https://learn.microsoft.com/en-us/typography/opentype/spec/otff#calculating-checksums>
     let checksum_adjustment = 0xB1B0AFBA_u32.wrapping_sub(font_checksum);
     if let Some(head_table_idx) = font_entry.head_idx {
-        let head_table_metadata = &table_metadata[head_table_idx as usize]
-            .expect("Every table in the font should have metadata at this point");
-        let mut writer = &mut out[head_table_metadata.dst_offset as usize + 8..];
-        writer.put_u32(checksum_adjustment);
+        // (every table in the font has metadata at this point)
+        let head_table_metadata = metadata_for(table_metadata, head_table_idx as usize)?;
+        let Some(head_table_metadata) = head_table_metadata else {
+            bail!()
+        };
+        let offset = (head_table_metadata.dst_offset as usize).saturating_add(8);
+        write_u32_at(out, offset, checksum_adjustment)?;
+
+        if let Some(index_format) = loca_index_format {
+            let offset = (head_table_metadata.dst_offset as usize)
+                .saturating_add(HEAD_INDEX_TO_LOC_FORMAT_OFFSET);
+            let head_index_format = read_u16_at(out, offset);
+            if let Some(head_index_format) = head_index_format.filter(|&f| f != index_format) {
+                // A head table shared between the fonts of a collection was checked against
+                // (and updated for) the first font's loca table, so can't be repaired for a
+                // later font's
+                bail_with_msg_if!(
+                    updates_head(profile, diagnostics),
+                    "Fonts sharing a head table need different loca index formats"
+                );
+                diagnostics.glyph_table_mismatch(
+                    Repair::IndexToLocFormatMismatch {
+                        head: head_index_format,
+                        loca: index_format,
+                    },
+                    WarningKind::IndexToLocFormatMismatch {
+                        head: head_index_format,
+                        loca: index_format,
+                    },
+                    HEAD,
+                    table_offset(font_entry, tables, HEAD),
+                )?;
+            }
+        }
     }
 
     Ok(())
 }
 
+/// The big-endian u16 at `offset` in the font's table with the given tag, which has already
+/// been written to `out`. `None` if the font has no such table or it is too short.
+fn read_table_u16(
+    font_entry: &CollectionDirectoryEntry,
+    tables: &TableDirectory,
+    table_metadata: &[Option<TableMetadata>],
+    out: &[u8],
+    tag: Tag,
+    offset: usize,
+) -> Option<u16> {
+    let (table_idx, _) = iter_tables_for_font(font_entry, tables).find(|(_, t)| t.tag == tag)?;
+    let metadata = table_metadata.get(table_idx)?.as_ref()?;
+    let table_start = metadata.dst_offset as usize;
+    let table = out
+        .get(table_start..)?
+        .get(..metadata.dst_length as usize)?;
+    read_u16_at(table, offset)
+}
+
+/// The metadata slot for the table at `table_idx`
+fn metadata_for(
+    table_metadata: &mut [Option<TableMetadata>],
+    table_idx: usize,
+) -> Result<&mut Option<TableMetadata>, WuffErr> {
+    table_metadata
+        .get_mut(table_idx)
+        .ok_or(WuffErr::GenericError)
+}
+
 // Get numberOfHMetrics, https://www.microsoft.com/typography/otspec/hhea.htm
-fn read_num_hmetrics(mut hhea_data: &[u8]) -> Result<u16, WuffErr> {
-    bail_if!(hhea_data.remaining() < 34);
-    hhea_data.advance(34); // Skip 34 to reach 'hhea' numberOfHMetrics
+pub(crate) fn read_num_hmetrics(mut hhea_data: &[u8]) -> Result<u16, WuffErr> {
+    hhea_data.try_advance(34)?; // Skip 34 to reach 'hhea' numberOfHMetrics
     Ok(hhea_data.try_get_u16()?)
 }
 
+#[derive(Default)]
 struct HeaderData {
     data: Vec<u8>,
+    /// Whether the header is for a font collection (a TTC header followed by a table
+    /// directory for each font)
+    is_collection: bool,
     checksum: u32,
     font_infos: Vec<WOFF2FontInfo>,
 }
@@ -392,48 +934,95 @@ impl TableMetadata {
 
 impl HeaderData {
     /// Update the table entry with real values.
-    fn update_table_entry(&mut self, font_idx: usize, tag: Tag, metadata: TableMetadata) {
+    fn update_table_entry(
+        &mut self,
+        font_idx: usize,
+        tag: Tag,
+        metadata: TableMetadata,
+    ) -> Result<(), WuffErr> {
+        let font_info = self.font_infos.get_mut(font_idx);
+        let font_info = font_info.ok_or(WuffErr::GenericError)?;
+
         // Write data
-        let table_entry_offset = self.font_infos[font_idx].table_entry_by_tag[&tag];
+        // Like a map, the last entry for the tag is the one that gets written
+        // (every table in the font has a table directory entry)
+        let Some(&(_, table_entry_offset)) = font_info
+            .table_entries
+            .iter()
+            .rev()
+            .find(|(entry_tag, _)| *entry_tag == tag)
+        else {
+            bail!()
+        };
 
-        let mut out = &mut self.data[(table_entry_offset + 4)..(table_entry_offset + 16)];
-        out.put_u32(metadata.checksum);
-        out.put_u32(metadata.dst_offset);
-        out.put_u32(metadata.dst_length);
+        let data = &mut self.data;
+        write_u32_at(
+            data,
+            table_entry_offset.saturating_add(4),
+            metadata.checksum,
+        )?;
+        write_u32_at(
+            data,
+            table_entry_offset.saturating_add(8),
+            metadata.dst_offset,
+        )?;
+        write_u32_at(
+            data,
+            table_entry_offset.saturating_add(12),
+            metadata.dst_length,
+        )?;
 
         // Update checksum
-        let mut checksum = self.font_infos[font_idx].header_checksum;
+        let mut checksum = font_info.header_checksum;
         checksum = checksum.wrapping_add(metadata.checksum);
         checksum = checksum.wrapping_add(metadata.dst_offset);
         checksum = checksum.wrapping_add(metadata.dst_length);
-        self.font_infos[font_idx].header_checksum = checksum;
+        font_info.header_checksum = checksum;
+        Ok(())
     }
 }
 
 // First table goes after all the headers, table directory, etc
-fn compute_header_size(collection_directory: &CollectionDirectory, is_collection: bool) -> usize {
+fn compute_header_size(
+    collection_directory: &CollectionDirectory,
+    is_collection: bool,
+) -> Result<usize, WuffErr> {
+    let size = collection_directory.table_directories_required_size()?;
     if is_collection {
-        collection_directory.table_directories_required_size()
-            + collection_directory.collection_header_required_size()
+        size.checked_add(collection_directory.collection_header_required_size())
+            .ok_or(WuffErr::GenericError)
     } else {
-        collection_directory.table_directories_required_size()
+        Ok(size)
     }
 }
 
+/// Write the header into `header_data` (reusing its allocations)
 fn generate_header(
     header: &WoffHeader,
+    is_collection: bool,
     tables: &TableDirectory,
     collection_directory: &CollectionDirectory,
-) -> HeaderData {
+    header_data: &mut HeaderData,
+) -> Result<(), WuffErr> {
     let num_fonts = collection_directory.fonts.len();
-    let size_of_header = compute_header_size(collection_directory, header.is_collection());
-    let mut output: Vec<u8> = Vec::with_capacity(size_of_header);
-    let mut font_infos: Vec<WOFF2FontInfo> = vec![WOFF2FontInfo::default(); num_fonts];
+    let size_of_header = compute_header_size(collection_directory, is_collection)?;
+    let output = &mut header_data.data;
+    output.clear();
+    // Everything written to `output` below fits in this
+    output.try_reserve(size_of_header)?;
+    let font_infos = &mut header_data.font_infos;
+    font_infos.truncate(num_fonts);
+    for info in font_infos.iter_mut() {
+        info.table_entries.clear();
+    }
+    // (truncated above)
+    font_infos.try_reserve(num_fonts.wrapping_sub(font_infos.len()))?;
+    font_infos.resize_with(num_fonts, WOFF2FontInfo::default);
 
     let mut checksum: u32 = 0;
 
     // If TTC: write TTC header
-    if header.is_collection() {
+    if is_collection {
         // TTC header
         output.put_u32(u32::from_be_bytes(header.flavor.to_be_bytes())); // TAG TTCTag
         output.put_u32(collection_directory.version); // FIXED Version
@@ -442,15 +1031,19 @@ fn generate_header(
         // let mut offset_table_idx: usize = output.len(); // keep start of offset table for later
 
         // Write tableDirectoryOffsets
+        #[allow(clippy::arithmetic_side_effects)] // (there are at most 2^16 fonts)
         let first_table_directory_offset = match collection_directory.version {
             0x00010000 => 12 + (4 * num_fonts as u32),
             0x00020000 => 12 + 12 + (4 * num_fonts as u32),
-            _ => unreachable!("Only 1.0 and 2.0 are supported versions"),
+            // (only 1.0 and 2.0 are supported versions)
+            _ => bail!(),
         };
         let mut table_directory_offset = first_table_directory_offset;
         for font in collection_directory.fonts.iter() {
             output.put_u32(table_directory_offset);
-            table_directory_offset += font.table_directory_size() as u32;
+            let size = font.table_directory_size() as u32;
+            table_directory_offset =
+                (table_directory_offset.checked_add(size)).ok_or(WuffErr::GenericError)?;
         }
 
         // space for DSIG fields for header v2
@@ -460,7 +1053,7 @@ fn generate_header(
             output.put_u32(0); // ULONG ulDsigOffset
         }
 
-        checksum = checksum.wrapping_add(compute_checksum(&output));
+        checksum = checksum.wrapping_add(compute_checksum(output));
     }
 
     // Write table directory(s)
@@ -469,23 +1062,22 @@ fn generate_header(
         // write the actual offset table so our header doesn't lie
         // font.dst_offset = offset as u32;
         let start_offset = output.len();
-        write_table_directory_header(&mut output, font.flavor, font.table_indices.len() as u16);
+        write_table_directory_header(output, font.flavor, font.table_indices.len() as u16);
 
         for &table_index in &font.table_indices {
-            let tag = tables[table_index as usize].tag;
-            info.table_entry_by_tag.insert(tag, output.len());
-            write_empty_offset_table_entry(&mut output, tag);
+            let tag = tables.entry(table_index)?.tag;
+            info.table_entries.try_push((tag, output.len()))?;
+            write_empty_offset_table_entry(output, tag);
         }
 
-        info.header_checksum = compute_checksum(&output[start_offset..]);
+        let table_directory = output.get(start_offset..).ok_or(WuffErr::GenericError)?;
+        info.header_checksum = compute_checksum(table_directory);
         checksum = checksum.wrapping_add(info.header_checksum);
     }
 
-    HeaderData {
-        data: output,
-        font_infos,
-        checksum,
-    }
+    header_data.is_collection = is_collection;
+    header_data.checksum = checksum;
+    Ok(())
 }
 
 // Writes a single Offset Table entry
@@ -495,3 +1087,501 @@ fn write_empty_offset_table_entry(output: &mut impl BufMut, tag: Tag) {
     output.put_u32(0);
     output.put_u32(0);
 }
+
+#[cfg(all(test, feature = "brotli"))]
+mod tests {
+    use crate::decompress_woff2;
+    use crate::test_support::*;
+    use alloc::{vec, vec::Vec};
+
+    /// A font with only a head table
+    fn minimal() -> Woff2Builder {
+        Woff2Builder::new(TRUETYPE).table(Table::new(b"head", head_table(0)))
+    }
+
+    #[test]
+    fn decodes_minimal_font() {
+        let sfnt = decompress_woff2(&minimal().build()).unwrap();
+        let tables = sfnt_tables(&sfnt, 0);
+        assert_eq!(tables.len(), 1);
+        assert_eq!(&tables[0].tag, b"head");
+        // Everything but checkSumAdjustment is copied as-is
+        let head = head_table(0);
+        assert_eq!(tables[0].data[..8], head[..8]);
+        assert_eq!(tables[0].data[12..], head[12..]);
+    }
+
+    #[test]
+    fn accepts_optional_blocks() {
+        let with_blocks = minimal().metadata(*b"<metadata/>").private_data([1, 2, 3]);
+        assert!(decompress_woff2(&with_blocks.build()).is_ok());
+        // The reserved field is ignored
+        let reserved = minimal().header(HeaderField::Reserved, 1);
+        assert!(decompress_woff2(&reserved.build()).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_block_layout() {
+        let reject = |builder: Woff2Builder| assert!(decompress_woff2(&builder.build()).is_err());
+        // More than 3 bytes between the data block and the end of the file
+        reject(minimal().gap_after_font_data(4));
+        // ... or the metadata block
+        reject(minimal().gap_after_font_data(4).metadata(*b"<metadata/>"));
+        // Data which belongs to no table
+        reject(minimal().extra_font_data([0; 4]));
+        // Metadata block not where the header says it is
+        let metadata = minimal().metadata(*b"<metadata/>");
+        let meta_offset = u32::from_be_bytes(metadata.build()[28..32].try_into().unwrap());
+        reject(metadata.header(HeaderField::MetaOffset, meta_offset + 4));
+        // Wrong file length
+        reject(minimal().header(HeaderField::Length, 4));
+        // Wrong signature
+        reject(minimal().header(HeaderField::Signature, u32::from_be_bytes(*b"wOFF")));
+    }
+
+    #[test]
+    fn rejects_empty_font_data() {
+        use crate::{
+            DecodeMode, DecodeOptions, compute_decoded_size, decompress_woff2_with_options,
+        };
+
+        // All the tables are empty, so there's no font data to decompress
+        let woff = Woff2Builder::new(TRUETYPE)
+            .table(Table::new(b"name", []))
+            .build();
+        assert!(decompress_woff2(&woff).is_err());
+        assert!(compute_decoded_size(&woff).is_err());
+        // This isn't a repair: there's no font to recover
+        let options = DecodeOptions::default().mode(DecodeMode::Recovery);
+        assert!(decompress_woff2_with_options(&woff, options).is_err());
+    }
+
+    #[test]
+    fn decodes_transformed_glyf_and_hmtx() {
+        let glyphs = GlyfTransform::new(vec![
+            Glyph::Empty,
+            Glyph::simple(&[&[(10, 0, true), (100, 0, true), (50, 80, false)]]),
+            Glyph::simple(&[&[(-20, -10, true), (30, 40, true), (0, 90, true)]]),
+        ]);
+        let hmtx = HmtxTransform {
+            advance_widths: vec![400, 500],
+            lsbs: None,
+            left_side_bearings: None,
+            reserved_flags: 0,
+        };
+        let woff = Woff2Builder::new(TRUETYPE)
+            .table(Table::new(b"head", head_table(0)))
+            .table(Table::new(b"hhea", hhea_table(2)))
+            .table(Table::with_transform(b"hmtx", 1, hmtx.encode()).orig_length(2 * 3 + 2 * 2))
+            .glyf(&glyphs)
+            .build();
+
+        let sfnt = decompress_woff2(&woff).unwrap();
+        let tables = sfnt_tables(&sfnt, 0);
+        let tags: Vec<_> = tables.iter().map(|table| &table.tag).collect();
+        assert_eq!(tags, [b"glyf", b"head", b"hhea", b"hmtx", b"loca"]);
+        // The elided lsbs are the x_min of each glyph (0 for empty glyphs)
+        assert_eq!(
+            tables[3].data,
+            [0x01, 0x90, 0, 0, 0x01, 0xF4, 0, 10, 0xFF, 0xEC]
+        );
+        // (head's checksum is taken with checkSumAdjustment set to 0)
+        for table in tables.iter().filter(|table| &table.tag != b"head") {
+            assert_eq!(table.checksum, crate::compute_checksum(table.data));
+        }
+    }
+
+    #[test]
+    fn decodes_collection() {
+        let woff = Woff2Builder::collection(0x00010000)
+            .table(Table::new(b"head", head_table(0)))
+            .table(Table::new(b"cmap", [0; 4]))
+            .table(Table::new(b"name", [1; 6]))
+            .font(TRUETYPE, &[0, 1])
+            .font(TRUETYPE, &[0, 2])
+            .build();
+        let ttc = decompress_woff2(&woff).unwrap();
+        assert_eq!(&ttc[..4], b"ttcf");
+        let offset_at =
+            |at: usize| u32::from_be_bytes(ttc[at..at + 4].try_into().unwrap()) as usize;
+        let fonts = [
+            sfnt_tables(&ttc, offset_at(12)),
+            sfnt_tables(&ttc, offset_at(16)),
+        ];
+        // Tables are sorted by tag, and head is shared between the two fonts
+        let tags = |font: &[SfntTable]| font.iter().map(|table| table.tag).collect::<Vec<_>>();
+        assert_eq!(tags(&fonts[0]), [*b"cmap", *b"head"]);
+        assert_eq!(tags(&fonts[1]), [*b"head", *b"name"]);
+        assert_eq!(fonts[0][1].data.as_ptr(), fonts[1][0].data.as_ptr());
+        assert_eq!(fonts[0][0].data, [0; 4]);
+        assert_eq!(fonts[1][1].data, [1; 6]);
+    }
+
+    #[test]
+    fn decodes_single_font_from_collection() {
+        use crate::decompress_woff2_font;
+
+        let woff = Woff2Builder::collection(0x00020000)
+            .table(Table::new(b"head", head_table(0)))
+            .table(Table::new(b"cmap", [0; 4]))
+            .table(Table::new(b"name", [1; 6]))
+            .font(TRUETYPE, &[0, 1])
+            .font(*b"OTTO", &[0, 2])
+            .build();
+        let sfnt = decompress_woff2_font(&woff, 1).unwrap();
+        assert_eq!(&sfnt[..4], b"OTTO");
+        let tables = sfnt_tables(&sfnt, 0);
+        let tags: Vec<_> = tables.iter().map(|table| table.tag).collect();
+        assert_eq!(tags, [*b"head", *b"name"]);
+        assert_eq!(tables[1].data, [1; 6]);
+        // Only this font's tables are written (the cmap table isn't)
+        // (header and two table records, then the padded head and name tables)
+        assert_eq!(sfnt.len(), 12 + 2 * 16 + 56 + 8);
+        // checkSumAdjustment is computed for the standalone font
+        assert_eq!(crate::compute_checksum(&sfnt), 0xB1B0AFBA);
+
+        assert!(decompress_woff2_font(&woff, 2).is_err());
+        // A file containing a single font is a collection of one
+        let single = minimal().build();
+        assert_eq!(
+            decompress_woff2_font(&single, 0).unwrap(),
+            decompress_woff2(&single).unwrap()
+        );
+        assert!(decompress_woff2_font(&single, 1).is_err());
+    }
+
+    #[test]
+    fn output_profiles() {
+        use crate::{DecodeOptions, OutputProfile, decompress_woff2_with_options};
+
+        let glyphs = GlyfTransform {
+            glyphs: vec![
+                Glyph::simple(&[&[(10, 0, true), (100, 0, true), (50, 80, false)]]),
+                Glyph::simple(&[&[(10, 0, true), (100, 0, true), (50, 80, false)]]),
+            ],
+            index_format: 1,
+        };
+        let woff = Woff2Builder::new(TRUETYPE)
+            .table(Table::new(b"head", head_table(1)))
+            .glyf(&glyphs)
+            .build();
+        let decode = |output_profile| {
+            let options = DecodeOptions::default().output_profile(output_profile);
+            decompress_woff2_with_options(&woff, options).unwrap().font
+        };
+
+        let reference = decode(OutputProfile::ReferenceCompatible);
+        assert_eq!(reference, decompress_woff2(&woff).unwrap());
+        for profile in [OutputProfile::FontToolsCompatible, OutputProfile::Compact] {
+            let sfnt = decode(profile);
+            assert!(sfnt.len() < reference.len());
+            let tables = sfnt_tables(&sfnt, 0);
+            let [glyf, head, loca] = &tables[..] else {
+                panic!("unexpected number of tables: {}", tables.len());
+            };
+            // (the 21 byte glyphs are padded to 22 bytes rather than 24)
+            // Only the compact profile changes the loca index format (and updates head)
+            let index_format = match profile {
+                OutputProfile::Compact => 0,
+                _ => 1,
+            };
+            assert_eq!(head.data[50..52], u16::to_be_bytes(index_format));
+            assert_eq!(loca.data.len(), 3 * (2 + 2 * index_format as usize));
+            let mut head_data = head.data.to_vec();
+            head_data[8..12].fill(0);
+            assert_eq!(head.checksum, crate::compute_checksum(&head_data));
+            for table in [glyf, loca] {
+                assert_eq!(table.checksum, crate::compute_checksum(table.data));
+            }
+            assert_eq!(crate::compute_checksum(&sfnt), 0xB1B0AFBA);
+        }
+    }
+
+    /// Property tests: random TrueType fonts are encoded (the glyf and hmtx
+    /// transforms are applied as the reference encoder does) and must decode
+    /// back to the same tables.
+    mod round_trip {
+        use super::*;
+        use bytes::BufMut;
+        use proptest::collection::vec as vec_of;
+        use proptest::prelude::*;
+
+        #[derive(Debug)]
+        struct RandomFont {
+            glyf: GlyfTransform,
+            hmtx: HmtxTransform,
+        }
+
+        /// A coordinate delta, biased towards the deltas with the more compact
+        /// triplet encodings
+        fn delta() -> impl Strategy<Value = i32> {
+            prop_oneof![-64..=64, -1280..=1280, -4096..=4096, -16000..=16000]
+        }
+
+        fn simple_glyph() -> impl Strategy<Value = Glyph> {
+            let contour = vec_of((delta(), delta(), any::<bool>()), 1..8);
+            (
+                vec_of(contour, 1..4),
+                prop_oneof![vec_of(any::<u8>(), 0..8), vec_of(any::<u8>(), 250..300)],
+                any::<bool>(),
+                proptest::option::of(any::<[i16; 4]>()),
+            )
+                .prop_map(|(contours, instructions, overlap, bbox)| {
+                    let (mut x, mut y) = (0i32, 0i32);
+                    let contours = contours
+                        .into_iter()
+                        .map(|contour| {
+                            contour
+                                .into_iter()
+                                .map(|(dx, dy, on_curve)| {
+                                    x = (x + dx).clamp(-16000, 16000);
+                                    y = (y + dy).clamp(-16000, 16000);
+                                    GlyphPoint {
+                                        x: x as i16,
+                                        y: y as i16,
+                                        on_curve,
+                                    }
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    Glyph::Simple {
+                        contours,
+                        instructions,
+                        overlap,
+                        bbox,
+                    }
+                })
+        }
+
+        /// A composite glyph whose components are glyphs before `index`
+        fn composite_glyph(index: u16) -> impl Strategy<Value = Glyph> {
+            let transform = prop_oneof![
+                Just(0),
+                Just(WE_HAVE_A_SCALE),
+                Just(WE_HAVE_AN_X_AND_Y_SCALE),
+                Just(WE_HAVE_A_TWO_BY_TWO),
+            ];
+            (
+                vec_of((transform, 0..index, any::<i16>(), any::<i16>()), 1..4),
+                proptest::option::of(vec_of(any::<u8>(), 0..16)),
+                any::<[i16; 4]>(),
+            )
+                .prop_map(|(records, instructions, bbox)| {
+                    let last = records.len() - 1;
+                    let components = records
+                        .into_iter()
+                        .enumerate()
+                        .flat_map(|(i, (mut flags, glyph_index, dx, dy))| {
+                            if i < last {
+                                flags |= MORE_COMPONENTS;
+                            } else if instructions.is_some() {
+                                flags |= WE_HAVE_INSTRUCTIONS;
+                            }
+                            component(flags, glyph_index, dx, dy)
+                        })
+                        .collect();
+                    Glyph::Composite {
+                        components,
+                        instructions,
+                        bbox,
+                    }
+                })
+        }
+
+        fn glyph(index: u16) -> BoxedStrategy<Glyph> {
+            match index {
+                0 => prop_oneof![Just(Glyph::Empty), simple_glyph()].boxed(),
+                _ => prop_oneof![
+                    1 => Just(Glyph::Empty),
+                    3 => simple_glyph(),
+                    1 => composite_glyph(index),
+                ]
+                .boxed(),
+            }
+        }
+
+        fn random_font() -> impl Strategy<Value = RandomFont> {
+            (1..24u16)
+                .prop_flat_map(|num_glyphs| {
+                    (
+                        (0..num_glyphs).map(glyph).collect::<Vec<_>>(),
+                        0..=1u16,
+                        1..=num_glyphs as usize,
+                        vec_of(any::<u16>(), num_glyphs as usize),
+                        vec_of(any::<i16>(), num_glyphs as usize),
+                        // Elide the proportional and/or monospace lsbs (not neither)
+                        prop_oneof![Just((true, false)), Just((false, true)), Just((true, true))],
+                    )
+                })
+                .prop_map(
+                    |(glyphs, index_format, num_hmetrics, advance_widths, lsbs, elided)| {
+                        RandomFont {
+                            glyf: GlyfTransform {
+                                glyphs,
+                                index_format,
+                            },
+                            hmtx: HmtxTransform {
+                                advance_widths: advance_widths[..num_hmetrics].to_vec(),
+                                lsbs: (!elided.0).then(|| lsbs[..num_hmetrics].to_vec()),
+                                left_side_bearings: (!elided.1)
+                                    .then(|| lsbs[num_hmetrics..].to_vec()),
+                                reserved_flags: 0,
+                            },
+                        }
+                    },
+                )
+        }
+
+        /// The bbox of a glyph, as written by the decoder
+        fn bbox(glyph: &Glyph) -> [i16; 4] {
+            match glyph {
+                Glyph::Empty => [0; 4],
+                Glyph::Simple {
+                    bbox: Some(bbox), ..
+                }
+                | Glyph::Composite { bbox, .. } => *bbox,
+                Glyph::Simple { contours, .. } => {
+                    let points = contours.iter().flatten();
+                    [
+                        points.clone().map(|p| p.x).min().unwrap(),
+                        points.clone().map(|p| p.y).min().unwrap(),
+                        points.clone().map(|p| p.x).max().unwrap(),
+                        points.clone().map(|p| p.y).max().unwrap(),
+                    ]
+                }
+            }
+        }
+
+        /// The hmtx table the font should decode to: elided lsbs are the
+        /// x_min of each glyph
+        fn expected_hmtx(font: &RandomFont) -> Vec<u8> {
+            let hmtx = &font.hmtx;
+            let num_hmetrics = hmtx.advance_widths.len();
+            let lsbs: Vec<i16> = match (&hmtx.lsbs, &hmtx.left_side_bearings) {
+                (Some(lsbs), _) => lsbs.clone(),
+                (None, _) => font.glyf.glyphs[..num_hmetrics]
+                    .iter()
+                    .map(|g| bbox(g)[0])
+                    .collect(),
+            };
+            let left_side_bearings: Vec<i16> = match &hmtx.left_side_bearings {
+                Some(left_side_bearings) => left_side_bearings.clone(),
+                None => font.glyf.glyphs[num_hmetrics..]
+                    .iter()
+                    .map(|g| bbox(g)[0])
+                    .collect(),
+            };
+            let mut out = Vec::new();
+            for (&advance_width, &lsb) in hmtx.advance_widths.iter().zip(&lsbs) {
+                out.put_u16(advance_width);
+                out.put_i16(lsb);
+            }
+            left_side_bearings.iter().for_each(|&lsb| out.put_i16(lsb));
+            out
+        }
+
+        proptest! {
+            #[test]
+            fn sfnt_woff2_sfnt(font in random_font()) {
+                let num_glyphs = font.glyf.glyphs.len();
+                let num_hmetrics = font.hmtx.advance_widths.len();
+                let index_format = font.glyf.index_format;
+                let hmtx_length = 2 * (num_glyphs + num_hmetrics) as u32;
+                let woff = Woff2Builder::new(TRUETYPE)
+                    .table(Table::new(b"head", head_table(index_format)))
+                    .table(Table::new(b"hhea", hhea_table(num_hmetrics as u16)))
+                    .table(Table::with_transform(b"hmtx", 1, font.hmtx.encode()).orig_length(hmtx_length))
+                    .glyf(&font.glyf)
+                    .build();
+
+                let sfnt = decompress_woff2(&woff).unwrap();
+                let tables = sfnt_tables(&sfnt, 0);
+                let [glyf, head, hhea, hmtx, loca] = &tables[..] else {
+                    panic!("unexpected number of tables: {}", tables.len());
+                };
+                prop_assert_eq!(&head.data[50..52], index_format.to_be_bytes());
+                prop_assert_eq!(hhea.data, hhea_table(num_hmetrics as u16));
+                prop_assert_eq!(hmtx.data, expected_hmtx(&font));
+                for table in [glyf, hhea, hmtx, loca] {
+                    prop_assert_eq!(table.checksum, crate::compute_checksum(table.data));
+                }
+
+                let offset = |i: usize| match index_format {
+                    0 => u16::from_be_bytes(loca.data[2 * i..][..2].try_into().unwrap()) as usize * 2,
+                    _ => u32::from_be_bytes(loca.data[4 * i..][..4].try_into().unwrap()) as usize,
+                };
+                prop_assert_eq!(offset(num_glyphs), glyf.data.len());
+                for (i, expected) in font.glyf.glyphs.iter().enumerate() {
+                    let data = &glyf.data[offset(i)..offset(i + 1)];
+                    match expected {
+                        Glyph::Empty => prop_assert!(data.is_empty()),
+                        Glyph::Simple { contours, instructions, overlap, .. } => {
+                            prop_assert_eq!(
+                                parse_simple_glyph(data),
+                                (bbox(expected), contours.clone(), instructions.clone(), *overlap)
+                            );
+                        }
+                        Glyph::Composite { components, instructions, bbox } => {
+                            let mut glyph = Vec::new();
+                            glyph.put_i16(-1);
+                            bbox.iter().for_each(|&v| glyph.put_i16(v));
+                            glyph.extend_from_slice(components);
+                            if let Some(instructions) = instructions {
+                                glyph.put_u16(instructions.len() as u16);
+                                glyph.extend_from_slice(instructions);
+                            }
+                            prop_assert_eq!(data.len(), crate::Round4!(glyph.len()));
+                            prop_assert_eq!(&data[..glyph.len()], glyph);
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn other_profiles_keep_outlines(font in random_font()) {
+                use crate::{DecodeOptions, OutputProfile, decompress_woff2_with_options};
+
+                let index_format = font.glyf.index_format;
+                let woff = Woff2Builder::new(TRUETYPE)
+                    .table(Table::new(b"head", head_table(index_format)))
+                    .glyf(&font.glyf)
+                    .build();
+                for output_profile in [OutputProfile::FontToolsCompatible, OutputProfile::Compact] {
+                    let options = DecodeOptions::default().output_profile(output_profile);
+                    let sfnt = decompress_woff2_with_options(&woff, options).unwrap().font;
+                    let tables = sfnt_tables(&sfnt, 0);
+                    let [glyf, head, loca] = &tables[..] else {
+                        panic!("unexpected number of tables: {}", tables.len());
+                    };
+                    for table in [glyf, loca] {
+                        prop_assert_eq!(table.checksum, crate::compute_checksum(table.data));
+                    }
+                    // (loca always matches head.indexToLocFormat)
+                    let short_loca = head.data[50..52] == [0, 0];
+                    let offset = |i: usize| match short_loca {
+                        true => u16::from_be_bytes(loca.data[2 * i..][..2].try_into().unwrap()) as usize * 2,
+                        false => u32::from_be_bytes(loca.data[4 * i..][..4].try_into().unwrap()) as usize,
+                    };
+                    for (i, expected) in font.glyf.glyphs.iter().enumerate() {
+                        let data = &glyf.data[offset(i)..offset(i + 1)];
+                        match expected {
+                            Glyph::Empty => prop_assert!(data.is_empty()),
+                            Glyph::Simple { contours, instructions, overlap, .. } => {
+                                prop_assert_eq!(
+                                    parse_simple_glyph(data),
+                                    (bbox(expected), contours.clone(), instructions.clone(), *overlap)
+                                );
+                            }
+                            Glyph::Composite { bbox, .. } => {
+                                prop_assert_eq!(&data[..2], [0xFF, 0xFF]);
+                                let bbox = bbox.map(i16::to_be_bytes);
+                                prop_assert_eq!(&data[2..10], bbox.as_flattened());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
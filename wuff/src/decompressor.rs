@@ -0,0 +1,204 @@
+//! Pluggable Brotli (WOFF2) and zlib (WOFF1) decompression backends.
+//!
+//! The decoder always knows how large each piece of decompressed data must be, so a backend
+//! decompresses into a caller-owned buffer of exactly that size. This keeps the size bound in
+//! the decoder's hands: a backend can't produce more output than the font declares.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::error::Error;
+use core::fmt;
+
+use crate::WuffErr;
+
+/// Why a [`BrotliDecompressor`] or [`ZlibDecompressor`] failed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecompressError {
+    /// The compressed data is malformed
+    InvalidData,
+    /// The data decompresses to fewer bytes than the output buffer holds
+    TooShort,
+    /// The data decompresses to more bytes than the output buffer holds. The output buffer
+    /// holds the first `output.len()` bytes of the decompressed data.
+    TooLong,
+    /// The backend couldn't allocate the memory it needed
+    OutOfMemory,
+    /// The backend failed for some other reason
+    Other,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidData => write!(f, "invalid compressed data"),
+            Self::TooShort => write!(f, "decompressed data is shorter than expected"),
+            Self::TooLong => write!(f, "decompressed data is longer than expected"),
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::Other => write!(f, "decompression failed"),
+        }
+    }
+}
+
+impl Error for DecompressError {}
+
+impl From<DecompressError> for WuffErr {
+    fn from(value: DecompressError) -> Self {
+        match value {
+            DecompressError::OutOfMemory => Self::OutOfMemory,
+            _ => Self::GenericError,
+        }
+    }
+}
+
+/// A Brotli decompressor for the compressed data block of WOFF2 files (and their metadata)
+///
+/// Closures of the form `FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>>` (as taken by
+/// [`decompress_woff2_with_custom_brotli`](crate::decompress_woff2_with_custom_brotli)) implement
+/// this trait. Their output is copied into the buffer, and rejected unless it is exactly the
+/// expected size.
+pub trait BrotliDecompressor {
+    /// Decompress the Brotli stream `input` into `output`, which is exactly the size that the
+    /// stream must decompress to. Must not write more than `output.len()` bytes.
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError>;
+}
+
+/// A zlib decompressor for the compressed tables of WOFF1 files (and their metadata)
+///
+/// Closures of the form `FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>>` (as taken by
+/// [`decompress_woff1_with_custom_z`](crate::decompress_woff1_with_custom_z)) implement this
+/// trait. Their output is copied into the buffer, and rejected unless it is exactly the expected
+/// size.
+pub trait ZlibDecompressor {
+    /// Decompress the zlib stream `input` into `output`, which is exactly the size that the
+    /// stream must decompress to. Must not write more than `output.len()` bytes.
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError>;
+}
+
+/// Adapts a closure returning a `Vec` (with `output.len()` as the size hint) to the
+/// buffer-filling decompressor traits
+fn decompress_with_fn<F>(f: &mut F, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError>
+where
+    F: FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>> + ?Sized,
+{
+    let decompressed = f(input, output.len()).map_err(|_| DecompressError::Other)?;
+    for (byte, &decompressed_byte) in output.iter_mut().zip(&decompressed) {
+        *byte = decompressed_byte;
+    }
+    match decompressed.len().cmp(&output.len()) {
+        core::cmp::Ordering::Less => Err(DecompressError::TooShort),
+        core::cmp::Ordering::Equal => Ok(()),
+        core::cmp::Ordering::Greater => Err(DecompressError::TooLong),
+    }
+}
+
+impl<F> BrotliDecompressor for F
+where
+    F: FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>> + ?Sized,
+{
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError> {
+        decompress_with_fn(self, input, output)
+    }
+}
+
+impl<F> ZlibDecompressor for F
+where
+    F: FnMut(&[u8], usize) -> Result<Vec<u8>, Box<dyn Error>> + ?Sized,
+{
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError> {
+        decompress_with_fn(self, input, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Decompress `input` into a buffer of `len` bytes, returning the buffer
+    fn decompress(
+        decompressor: &mut dyn BrotliDecompressor,
+        input: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, DecompressError> {
+        let mut output = vec![0; len];
+        decompressor.decompress(input, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn closure_adapter() {
+        let mut calls = Vec::new();
+        let mut reverse = |input: &[u8], size_hint: usize| -> Result<Vec<u8>, Box<dyn Error>> {
+            calls.push(size_hint);
+            Ok(input.iter().rev().copied().collect())
+        };
+        assert_eq!(decompress(&mut reverse, b"abc", 3), Ok(b"cba".to_vec()));
+        assert_eq!(
+            decompress(&mut reverse, b"abc", 4),
+            Err(DecompressError::TooShort)
+        );
+        let mut output = [0; 2];
+        let result = BrotliDecompressor::decompress(&mut reverse, b"abc", &mut output);
+        assert_eq!((result, output), (Err(DecompressError::TooLong), *b"cb"));
+        assert_eq!(calls, [3, 4, 2]);
+
+        let mut failing = |_: &[u8], _| -> Result<Vec<u8>, Box<dyn Error>> {
+            Err(Box::new(WuffErr::GenericError))
+        };
+        assert_eq!(
+            decompress(&mut failing, b"", 0),
+            Err(DecompressError::Other)
+        );
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn builtin_brotli() {
+        use crate::brotli::Brotli;
+        use crate::test_support::brotli_stored;
+
+        let data = b"some uncompressed data";
+        let compressed = brotli_stored(data);
+        // The same decompressor is reused between calls (including after errors)
+        let mut brotli = Brotli::default();
+        assert_eq!(
+            decompress(&mut brotli, &compressed, data.len()),
+            Ok(data.to_vec())
+        );
+        let too_short = decompress(&mut brotli, &compressed, data.len() + 1);
+        assert_eq!(too_short, Err(DecompressError::TooShort));
+        let truncated = decompress(&mut brotli, &compressed[..10], data.len());
+        assert_eq!(truncated, Err(DecompressError::TooShort));
+        let too_long = decompress(&mut brotli, &compressed, data.len() - 1);
+        assert_eq!(too_long, Err(DecompressError::TooLong));
+        let invalid = decompress(&mut brotli, &[0xff; 8], data.len());
+        assert_eq!(invalid, Err(DecompressError::InvalidData));
+    }
+
+    #[cfg(any(feature = "z", feature = "inflate"))]
+    #[test]
+    fn builtin_zlib() {
+        use crate::zlib::Zlib;
+
+        let data = b"some uncompressed data, some uncompressed data";
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(data, 6);
+
+        // The same decompressor is reused between calls (including after errors)
+        let mut zlib = Zlib::default();
+        let mut decompress = |input: &[u8], len| {
+            let mut output = vec![0; len];
+            zlib.decompress(input, &mut output).map(|()| output)
+        };
+        assert_eq!(decompress(&compressed, data.len()), Ok(data.to_vec()));
+        let too_short = decompress(&compressed, data.len() + 1);
+        assert_eq!(too_short, Err(DecompressError::TooShort));
+        let truncated = decompress(&compressed[..compressed.len() - 6], data.len());
+        assert_eq!(truncated, Err(DecompressError::TooShort));
+        let missing_checksum = decompress(&compressed[..compressed.len() - 4], data.len());
+        assert_eq!(missing_checksum, Err(DecompressError::InvalidData));
+        let too_long = decompress(&compressed, data.len() - 1);
+        assert_eq!(too_long, Err(DecompressError::TooLong));
+        let invalid = decompress(&[0xff; 8], data.len());
+        assert_eq!(invalid, Err(DecompressError::InvalidData));
+    }
+}
@@ -0,0 +1,277 @@
+//! Warnings about quirks which the decoder accepts, and the state shared by the checks
+//! made while decoding.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::error::{WuffErr, bail};
+use crate::fallible::FallibleVec;
+use crate::recovery::{Decoded, Repair};
+use crate::{DecodeMode, DecodeOptions, Tag};
+
+/// Something unusual about a font which doesn't affect whether it is accepted or
+/// rejected, reported to [`DecodeOptions::on_warning`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// The table the warning is about, if any
+    pub tag: Option<[u8; 4]>,
+    /// The offset of the data the warning is about. This is an offset in the input file,
+    /// except for the tables of WOFF2 files, where it is the offset of the table in the
+    /// decompressed data block.
+    pub offset: u32,
+}
+
+/// The kinds of [`Warning`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// The (WOFF2) header's reserved field is not zero. Unlike WOFF1, the WOFF2 spec
+    /// requires decoders to ignore it.
+    NonZeroReserved { value: u16 },
+    /// The header's `totalSfntSize` field doesn't match the size of the decoded font
+    TotalSfntSizeMismatch { declared: u32, actual: u32 },
+    /// The `origLength` of a transformed table doesn't match the size of the reconstructed
+    /// table. For glyf, the decoded font's table directory records the reconstructed size
+    /// (and only the reference [`OutputProfile`](crate::OutputProfile) is expected to match).
+    /// For hmtx, it records `origLength` (like the reference decoder), so the recorded
+    /// length doesn't match the table data.
+    TableLengthMismatch { declared: u32, reconstructed: u32 },
+    /// The checksum of a (WOFF1) table doesn't match its `origChecksum`
+    ChecksumMismatch { declared: u32, actual: u32 },
+    /// `head.indexToLocFormat` doesn't match the index format of the loca table
+    /// reconstructed from the (transformed) glyf table. Like the reference decoder, the
+    /// reference [`OutputProfile`](crate::OutputProfile) leaves head as it is.
+    IndexToLocFormatMismatch { head: u16, loca: u16 },
+    /// `maxp.numGlyphs` doesn't match the number of glyphs in the (transformed) glyf table
+    NumGlyphsMismatch { maxp: u16, glyf: u16 },
+    /// `hhea.numberOfHMetrics` is 0, or more than the number of glyphs in the
+    /// (transformed) glyf table
+    NumberOfHMetricsMismatch { num_hmetrics: u16, num_glyphs: u16 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(tag) = &self.tag {
+            let tag = core::str::from_utf8(tag).unwrap_or("????");
+            write!(f, "'{tag}' table at offset {}: ", self.offset)?;
+        } else {
+            write!(f, "offset {}: ", self.offset)?;
+        }
+        match &self.kind {
+            WarningKind::NonZeroReserved { value } => write!(f, "reserved field is {value}"),
+            WarningKind::TotalSfntSizeMismatch { declared, actual } => write!(
+                f,
+                "totalSfntSize is {declared}, but the decoded font is {actual} bytes"
+            ),
+            WarningKind::TableLengthMismatch {
+                declared,
+                reconstructed,
+            } => write!(
+                f,
+                "origLength is {declared}, but the reconstructed table is {reconstructed} bytes"
+            ),
+            WarningKind::ChecksumMismatch { declared, actual } => write!(
+                f,
+                "origChecksum is {declared:#010x}, but the table's checksum is {actual:#010x}"
+            ),
+            WarningKind::IndexToLocFormatMismatch { head, loca } => {
+                write!(f, "indexToLocFormat is {head}, but loca uses format {loca}")
+            }
+            WarningKind::NumGlyphsMismatch { maxp, glyf } => {
+                write!(f, "numGlyphs is {maxp}, but glyf has {glyf} glyphs")
+            }
+            WarningKind::NumberOfHMetricsMismatch {
+                num_hmetrics,
+                num_glyphs,
+            } => write!(
+                f,
+                "numberOfHMetrics is {num_hmetrics}, but glyf has {num_glyphs} glyphs"
+            ),
+        }
+    }
+}
+
+/// Collects the repairs made during a decode, and passes warnings on to the caller
+pub(crate) struct Diagnostics<'a> {
+    mode: DecodeMode,
+    repairs: Vec<Repair>,
+    on_warning: Option<&'a mut dyn FnMut(&Warning)>,
+    validate_composites: bool,
+    validate_glyph_tables: bool,
+}
+
+impl<'a> Diagnostics<'a> {
+    pub fn new(options: DecodeOptions<'a>) -> Self {
+        Self {
+            mode: options.mode,
+            repairs: Vec::new(),
+            on_warning: options.on_warning,
+            validate_composites: options.validate_composites,
+            validate_glyph_tables: options.validate_glyph_tables,
+        }
+    }
+
+    pub fn strict() -> Self {
+        Self::new(DecodeOptions::default())
+    }
+
+    pub fn is_recovering(&self) -> bool {
+        self.mode == DecodeMode::Recovery
+    }
+
+    /// Whether the composite glyphs of reconstructed glyf tables should be checked
+    pub fn validates_composites(&self) -> bool {
+        self.validate_composites
+    }
+
+    /// Reject the font in strict mode, or record `repair` and carry on in recovery mode
+    pub fn repair(&mut self, repair: Repair) -> Result<(), WuffErr> {
+        match self.mode {
+            DecodeMode::Strict => bail!(),
+            DecodeMode::Recovery => self.repairs.try_push(repair),
+        }
+    }
+
+    /// Report a table which disagrees with the glyf table about the glyphs: as `repair` if
+    /// such tables are validated (or in recovery mode), and otherwise as a warning, since
+    /// the reference decoder accepts them
+    pub fn glyph_table_mismatch(
+        &mut self,
+        repair: Repair,
+        kind: WarningKind,
+        tag: Tag,
+        offset: u32,
+    ) -> Result<(), WuffErr> {
+        if self.validate_glyph_tables || self.is_recovering() {
+            self.repair(repair)
+        } else {
+            self.warn(kind, Some(tag), offset);
+            Ok(())
+        }
+    }
+
+    pub fn warn(&mut self, kind: WarningKind, tag: Option<Tag>, offset: u32) {
+        if let Some(on_warning) = &mut self.on_warning {
+            on_warning(&Warning {
+                kind,
+                tag: tag.map(Tag::to_be_bytes),
+                offset,
+            });
+        }
+    }
+
+    pub fn into_decoded(self, font: Vec<u8>) -> Decoded {
+        Decoded {
+            font,
+            repairs: self.repairs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress_woff1_with_decompressor_and_options;
+    use crate::test_support::*;
+
+    /// Decode `woff` in strict mode, returning the warnings reported along the way
+    fn warnings(woff: &[u8]) -> Result<Vec<Warning>, WuffErr> {
+        let mut warnings = Vec::new();
+        let mut on_warning = |warning: &Warning| warnings.push(warning.clone());
+        let options = DecodeOptions::default().on_warning(&mut on_warning);
+        if woff.starts_with(b"wOFF") {
+            decompress_woff1_with_decompressor_and_options(woff, &mut NoCompression, options)?;
+        } else {
+            #[cfg(feature = "brotli")]
+            crate::decompress_woff2_with_options(woff, options)?;
+        }
+        Ok(warnings)
+    }
+
+    #[test]
+    fn woff1_warnings() {
+        let tables = [
+            Table::new(b"head", head_table(0)),
+            Table::new(b"name", [1; 10]),
+        ];
+        let woff = woff1(TRUETYPE, &tables);
+        assert_eq!(warnings(&woff).unwrap(), []);
+
+        // Corrupt the origChecksum of the name table, and the totalSfntSize
+        let mut woff = woff;
+        let name_checksum = 44 + 20 + 16;
+        woff[name_checksum..name_checksum + 4].copy_from_slice(&[0, 0, 0, 1]);
+        woff[16..20].copy_from_slice(&[0, 0, 0, 99]);
+        let name_offset = 44 + 2 * 20 + 56;
+        assert_eq!(
+            warnings(&woff).unwrap(),
+            [
+                Warning {
+                    kind: WarningKind::ChecksumMismatch {
+                        declared: 1,
+                        actual: 0x01010101 * 2 + 0x01010000,
+                    },
+                    tag: Some(*b"name"),
+                    offset: name_offset,
+                },
+                Warning {
+                    kind: WarningKind::TotalSfntSizeMismatch {
+                        declared: 99,
+                        actual: 12 + 2 * 16 + 56 + 12,
+                    },
+                    tag: None,
+                    offset: 16,
+                }
+            ]
+        );
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn woff2_warnings() {
+        let head = Table::new(b"head", head_table(0));
+        let clean = Woff2Builder::new(TRUETYPE).table(head.clone());
+        assert_eq!(warnings(&clean.build()).unwrap(), []);
+
+        let reserved = clean.clone().header(HeaderField::Reserved, 7).build();
+        let expected = Warning {
+            kind: WarningKind::NonZeroReserved { value: 7 },
+            tag: None,
+            offset: 14,
+        };
+        assert_eq!(warnings(&reserved).unwrap(), [expected]);
+
+        // The hmtx origLength is 2 bytes too long
+        let glyf = GlyfTransform::new(alloc::vec![Glyph::Empty, Glyph::Empty]);
+        let hmtx = HmtxTransform {
+            advance_widths: alloc::vec![500],
+            lsbs: None,
+            left_side_bearings: None,
+            reserved_flags: 0,
+        };
+        let hmtx_table = Table::with_transform(b"hmtx", 1, hmtx.encode()).orig_length(8);
+        let woff = Woff2Builder::new(TRUETYPE)
+            .table(head)
+            .table(Table::new(b"hhea", hhea_table(1)))
+            .table(hmtx_table)
+            .glyf(&glyf)
+            .build();
+        let reported = warnings(&woff).unwrap();
+        let hmtx_warning = Warning {
+            kind: WarningKind::TableLengthMismatch {
+                declared: 8,
+                reconstructed: 6,
+            },
+            tag: Some(*b"hmtx"),
+            offset: 54 + 36,
+        };
+        assert!(reported.contains(&hmtx_warning), "{reported:?}");
+
+        // Warnings don't make the decoder accept fonts it would otherwise reject
+        let reserved_and_gap = clean
+            .header(HeaderField::Reserved, 7)
+            .gap_after_font_data(4);
+        assert!(warnings(&reserved_and_gap.build()).is_err());
+    }
+}
@@ -1,12 +1,16 @@
 #[derive(Copy, Clone, Debug)]
+#[non_exhaustive]
 pub enum WuffErr {
     GenericError,
+    /// An allocation failed while decoding the font
+    OutOfMemory,
 }
 
 impl core::fmt::Display for WuffErr {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
         match self {
             Self::GenericError => write!(f, "WuffErr::GenericError"),
+            Self::OutOfMemory => write!(f, "WuffErr::OutOfMemory"),
         }
     }
 }
@@ -23,10 +27,6 @@ pub(crate) fn usize_will_overflow(a: usize, b: usize) -> bool {
     a.checked_add(b).is_none()
 }
 
-pub(crate) fn u32_will_overflow(a: u32, b: u32) -> bool {
-    a.checked_add(b).is_none()
-}
-
 #[cfg(not(feature = "debug"))]
 mod regular {
     macro_rules! bail {
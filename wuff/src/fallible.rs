@@ -0,0 +1,96 @@
+//! Fallible allocation helpers.
+//!
+//! The decoder allocates through these (or through `try_reserve` followed by writes which
+//! fit in the reserved capacity) so that running out of memory returns
+//! [`WuffErr::OutOfMemory`] rather than aborting the process.
+
+use alloc::collections::TryReserveError;
+use alloc::vec::Vec;
+
+use crate::WuffErr;
+
+impl From<TryReserveError> for WuffErr {
+    fn from(_value: TryReserveError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+pub(crate) trait FallibleVec<T> {
+    fn try_push(&mut self, value: T) -> Result<(), WuffErr>;
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), WuffErr>
+    where
+        T: Clone;
+    fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), WuffErr>
+    where
+        T: Clone;
+}
+
+impl<T> FallibleVec<T> for Vec<T> {
+    fn try_push(&mut self, value: T) -> Result<(), WuffErr> {
+        self.try_reserve(1)?;
+        self.push(value);
+        Ok(())
+    }
+
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), WuffErr>
+    where
+        T: Clone,
+    {
+        self.try_reserve(other.len())?;
+        self.extend_from_slice(other);
+        Ok(())
+    }
+
+    fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), WuffErr>
+    where
+        T: Clone,
+    {
+        self.try_reserve(new_len.saturating_sub(self.len()))?;
+        self.resize(new_len, value);
+        Ok(())
+    }
+}
+
+/// A stable sort by key which reports allocation failure rather than aborting, unlike
+/// `slice::sort_by_key` (which allocates a buffer for larger slices). `scratch` holds a
+/// copy of `items` while they are sorted.
+pub(crate) fn try_sort_by_key<T: Copy, K: Ord>(
+    items: &mut [T],
+    scratch: &mut Vec<(usize, T)>,
+    mut key: impl FnMut(&T) -> K,
+) -> Result<(), WuffErr> {
+    scratch.clear();
+    scratch.try_reserve(items.len())?;
+    scratch.extend(items.iter().copied().enumerate());
+    // Ordering equal keys by their original position makes the (non-allocating) unstable
+    // sort stable
+    scratch.sort_unstable_by_key(|(position, item)| (key(item), *position));
+    for (item, (_, sorted)) in items.iter_mut().zip(scratch.iter()) {
+        *item = *sorted;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn sort_is_stable() {
+        let mut items = [(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (0, 'e')];
+        try_sort_by_key(&mut items, &mut Vec::new(), |(key, _)| *key).unwrap();
+        assert_eq!(items, [(0, 'e'), (1, 'b'), (1, 'd'), (2, 'a'), (2, 'c')]);
+    }
+
+    #[test]
+    fn reports_allocation_failure() {
+        let mut data: Vec<u64> = vec![0];
+        assert!(matches!(
+            data.try_resize(usize::MAX / 2, 0),
+            Err(WuffErr::OutOfMemory)
+        ));
+        assert!(matches!(data.try_push(1), Ok(())));
+        assert_eq!(data, [0, 1]);
+    }
+}
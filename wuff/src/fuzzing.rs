@@ -0,0 +1,115 @@
+//! Entry points into the decoder's internals for the fuzz targets in `fuzz/`.
+//!
+//! This module is only compiled when the `fuzzing` feature is enabled. It is not part of
+//! the public API and may change in any release.
+
+use alloc::vec::Vec;
+
+use crate::{
+    WuffErr,
+    variable_length::{Read255UShort, ReadBase128},
+    woff::{
+        glyf_decoder::GlyfDecoder,
+        hmtx_decoder::{decode_hmtx_table, generate_hmtx_table},
+    },
+};
+
+/// Reconstruct the glyf and loca tables from a transformed glyf table,
+/// returning the number of glyphs and the x_min of each glyph
+pub fn decode_glyf_table(data: &[u8]) -> Result<(u16, Vec<i16>), WuffErr> {
+    let glyf_and_loca_data = GlyfDecoder::new(data)?.transform()?;
+    Ok((glyf_and_loca_data.num_glyphs, glyf_and_loca_data.x_mins))
+}
+
+/// Reconstruct an hmtx table from a transformed hmtx table
+pub fn decode_hmtx_table_data(
+    mut data: &[u8],
+    num_glyphs: u16,
+    num_hmetrics: u16,
+    x_mins: &[i16],
+) -> Result<Vec<u8>, WuffErr> {
+    let hmtx_data = decode_hmtx_table(&mut data, num_glyphs, num_hmetrics, x_mins)?;
+    generate_hmtx_table(&hmtx_data)
+}
+
+/// Read a 255UInt16 from the start of `data`, returning it and the number of bytes read
+pub fn read_255_u16(mut data: &[u8]) -> Result<(u32, usize), WuffErr> {
+    let len = data.len();
+    let value = Read255UShort(&mut data)?;
+    // (reading only shrinks `data`)
+    Ok((value, len.wrapping_sub(data.len())))
+}
+
+/// Read a UIntBase128 from the start of `data`, returning it and the number of bytes read
+pub fn read_base128(mut data: &[u8]) -> Result<(u32, usize), WuffErr> {
+    let len = data.len();
+    let value = ReadBase128(&mut data)?;
+    // (reading only shrinks `data`)
+    Ok((value, len.wrapping_sub(data.len())))
+}
+
+/// The tags of the tables of each font in a WOFF2 file, in table directory
+/// order (or collection directory order, for a collection)
+pub fn woff2_font_tags(raw_woff_data: &[u8]) -> Result<Vec<Vec<[u8; 4]>>, WuffErr> {
+    use crate::woff::headers::{CollectionDirectory, TableDirectory, WoffHeader};
+
+    let mut input = raw_woff_data;
+    let header = WoffHeader::parse(&mut input)?;
+    let table_directory = TableDirectory::parse_woff2(&mut input, header.num_tables as usize)?;
+    let collection_directory = if header.is_collection() {
+        CollectionDirectory::parse(&mut input, &table_directory)?
+    } else {
+        CollectionDirectory::generate_for_single_font(header.flavor, &table_directory)?
+    };
+    collection_directory
+        .fonts
+        .iter()
+        .map(|font| {
+            font.table_indices
+                .iter()
+                .map(|&idx| Ok(table_directory.entry(idx)?.tag.to_be_bytes()))
+                .collect()
+        })
+        .collect()
+}
+
+/// A table from the decompressed data block of a WOFF2 file
+pub struct Woff2Table {
+    pub tag: [u8; 4],
+    pub transformed: bool,
+    pub data: Vec<u8>,
+}
+
+/// Decompress the table data of a WOFF2 file without reconstructing the tables.
+/// Used to extract transformed tables for the seed corpora.
+#[cfg(feature = "brotli")]
+pub fn woff2_tables(raw_woff_data: &[u8]) -> Result<Vec<Woff2Table>, WuffErr> {
+    use crate::BrotliDecompressor as _;
+    use crate::error::bail_if;
+    use crate::woff::headers::{CollectionDirectory, TableDirectory, WoffHeader, WoffVersion};
+
+    let mut input = raw_woff_data;
+    let header = WoffHeader::parse(&mut input)?;
+    bail_if!(header.woff_version != WoffVersion::Woff2);
+    let table_directory = TableDirectory::parse_woff2(&mut input, header.num_tables as usize)?;
+    if header.is_collection() {
+        CollectionDirectory::parse(&mut input, &table_directory)?;
+    }
+
+    let compressed_data = input
+        .get(..header.total_compressed_size as usize)
+        .ok_or(WuffErr::GenericError)?;
+    let mut decompressed_data = alloc::vec![0u8; table_directory.uncompressed_size];
+    crate::brotli::Brotli::default().decompress(compressed_data, &mut decompressed_data)?;
+
+    table_directory
+        .iter()
+        .map(|table| {
+            Ok(Woff2Table {
+                tag: table.tag.to_be_bytes(),
+                transformed: table.is_transformed(),
+                data: table.data_as_slice(&decompressed_data)?.to_vec(),
+            })
+        })
+        .collect()
+}
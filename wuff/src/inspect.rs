@@ -0,0 +1,119 @@
+//! Inspecting font files without decoding them.
+
+use alloc::vec::Vec;
+
+use crate::error::WuffErr;
+use crate::woff::headers::{CollectionDirectory, TableDirectory, WoffHeader, WoffVersion};
+
+/// The container format of a font file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FontFormat {
+    /// A WOFF (version 1) file
+    Woff1,
+    /// A WOFF2 file
+    Woff2,
+    /// An uncompressed OpenType/TrueType (sfnt) font
+    Sfnt,
+    /// An uncompressed OpenType/TrueType font collection
+    Collection,
+}
+
+/// Detect the container format of a font file from its signature.
+///
+/// This only looks at the first four bytes of the file, so a successful result
+/// does not mean that the file is valid. Returns `None` if the signature is not recognised.
+pub fn detect_format(data: &[u8]) -> Option<FontFormat> {
+    match data.get(0..4)? {
+        b"wOFF" => Some(FontFormat::Woff1),
+        b"wOF2" => Some(FontFormat::Woff2),
+        b"\x00\x01\x00\x00" | b"OTTO" | b"true" | b"typ1" => Some(FontFormat::Sfnt),
+        b"ttcf" => Some(FontFormat::Collection),
+        _ => None,
+    }
+}
+
+/// Information about a WOFF or WOFF2 file, read from its header and table directory
+#[derive(Clone, Debug)]
+pub struct WoffInfo {
+    /// Whether the file is a WOFF1 or WOFF2 file
+    pub version: WoffVersion,
+    /// The "sfnt version" of the font (e.g. `b"OTTO"`, or `b"ttcf"` for a collection)
+    pub flavor: [u8; 4],
+    /// Total size of the WOFF file
+    pub length: u32,
+    /// The `totalSfntSize` header field. This is untrusted: it is not used for decoding.
+    pub total_sfnt_size: u32,
+    /// (WOFF2 only) Total length of the compressed data block. 0 for WOFF1 files.
+    pub total_compressed_size: u32,
+    /// Major version of the WOFF file
+    pub major_version: u16,
+    /// Minor version of the WOFF file
+    pub minor_version: u16,
+    /// Length of the compressed metadata block (0 if there is no metadata)
+    pub meta_length: u32,
+    /// Uncompressed size of the metadata block
+    pub meta_orig_length: u32,
+    /// Length of the private data block (0 if there is no private data)
+    pub priv_length: u32,
+    /// The number of fonts in the file (1 unless the file is a collection)
+    pub num_fonts: usize,
+    /// The entries of the table directory, in the order they are stored in the file
+    pub tables: Vec<TableInfo>,
+}
+
+/// Information about a single table, read from the table directory of a WOFF or WOFF2 file
+#[derive(Clone, Debug)]
+pub struct TableInfo {
+    /// The table's tag
+    pub tag: [u8; 4],
+    /// Length of the original (decoded) table
+    pub orig_length: u32,
+    /// Length of the table as stored in the file. For WOFF1 this is the compressed length.
+    /// For WOFF2 this is the length within the decompressed data block (the transformed
+    /// length for transformed tables).
+    pub length: u32,
+    /// Whether the table is transformed (WOFF2) or compressed (WOFF1)
+    pub transformed: bool,
+}
+
+/// Read the header and table directory of a WOFF or WOFF2 file without decoding it
+pub fn inspect(data: &[u8]) -> Result<WoffInfo, WuffErr> {
+    let mut input = data;
+    let header = WoffHeader::parse(&mut input)?;
+    let table_directory = match header.woff_version {
+        WoffVersion::Woff1 => TableDirectory::parse_woff1(&mut input, header.num_tables as usize)?,
+        WoffVersion::Woff2 => TableDirectory::parse_woff2(&mut input, header.num_tables as usize)?,
+    };
+    let num_fonts = if header.woff_version == WoffVersion::Woff2 && header.is_collection() {
+        CollectionDirectory::parse(&mut input, &table_directory)?
+            .fonts
+            .len()
+    } else {
+        1
+    };
+
+    let tables = table_directory
+        .iter()
+        .map(|table| TableInfo {
+            tag: table.tag.to_be_bytes(),
+            orig_length: table.orig_length,
+            length: table.woff_length,
+            transformed: table.is_transformed(),
+        })
+        .collect();
+
+    Ok(WoffInfo {
+        version: header.woff_version,
+        flavor: header.flavor.to_be_bytes(),
+        length: header.length,
+        total_sfnt_size: header.total_sfnt_size,
+        total_compressed_size: header.total_compressed_size,
+        major_version: header.major_version,
+        minor_version: header.minor_version,
+        meta_length: header.meta_length,
+        meta_orig_length: header.meta_orig_length,
+        priv_length: header.priv_length,
+        num_fonts,
+        tables,
+    })
+}
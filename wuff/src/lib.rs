@@ -5,36 +5,113 @@
 #![allow(non_upper_case_globals)]
 #![allow(dead_code)]
 #![allow(clippy::collapsible_if)]
+// Decoding untrusted input must never panic, so constructs which can panic (including
+// indexing and slicing, which panic when out of bounds, and arithmetic, which panics on
+// overflow in debug builds) are denied outside of tests. The `debug` feature deliberately
+// panics where an error would be returned.
+#![cfg_attr(
+    not(any(test, feature = "debug")),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::todo,
+        clippy::unimplemented,
+        clippy::arithmetic_side_effects
+    )
+)]
+// Panicking methods which the lints above don't catch are listed in `clippy.toml`
+#![cfg_attr(test, allow(clippy::disallowed_methods))]
 
 extern crate alloc;
+#[cfg(feature = "async")]
+extern crate std;
 
+#[cfg(feature = "async")]
+mod async_decode;
 #[cfg(feature = "brotli")]
 mod brotli;
+mod decoded_size;
+mod decoder;
 mod decompress_woff1;
 mod decompress_woff2;
+mod decompressor;
+mod diagnostics;
 mod error;
+mod fallible;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod inspect;
+#[cfg(all(feature = "brotli", any(feature = "z", feature = "inflate")))]
+mod metadata;
+mod options;
+mod recovery;
 mod table_tags;
+#[cfg(any(test, feature = "test-support"))]
+#[doc(hidden)]
+pub mod test_support;
 mod variable_length;
 mod woff;
+#[cfg(any(feature = "z", feature = "inflate"))]
+mod zlib;
 
 use bytes::BufMut;
-pub use decompress_woff1::decompress_woff1_with_custom_z;
-pub use decompress_woff2::decompress_woff2_with_custom_brotli;
+#[cfg(feature = "brotli")]
+#[cfg_attr(docsrs, doc(cfg(feature = "brotli")))]
+pub use decoded_size::compute_decoded_size;
+pub use decoded_size::{DecodedSize, compute_decoded_size_with_decompressor};
+pub use decoder::{Woff1Decoder, Woff2Decoder};
+pub use decompress_woff1::{
+    decompress_woff1_with_custom_z, decompress_woff1_with_decompressor,
+    decompress_woff1_with_decompressor_and_options,
+};
+pub use decompress_woff2::{
+    decompress_woff2_font_with_decompressor, decompress_woff2_with_custom_brotli,
+    decompress_woff2_with_decompressor, decompress_woff2_with_decompressor_and_options,
+};
+pub use decompressor::{BrotliDecompressor, DecompressError, ZlibDecompressor};
+pub use diagnostics::{Warning, WarningKind};
 pub use error::WuffErr;
+pub use inspect::{FontFormat, TableInfo, WoffInfo, detect_format, inspect};
+pub use options::{DecodeMode, DecodeOptions, OutputProfile};
+pub use recovery::{Decoded, Repair};
+pub use woff::headers::WoffVersion;
 
-#[cfg(feature = "z")]
-#[cfg_attr(docsrs, doc(cfg(feature = "z")))]
-pub use decompress_woff1::decompress_woff1;
+#[cfg(any(feature = "z", feature = "inflate"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "z", feature = "inflate"))))]
+pub use zlib::{decompress_woff1, decompress_woff1_with_options};
 
 #[cfg(feature = "brotli")]
 #[cfg_attr(docsrs, doc(cfg(feature = "brotli")))]
-pub use brotli::decompress_woff2;
+pub use brotli::{decompress_woff2, decompress_woff2_font, decompress_woff2_with_options};
+
+#[cfg(all(feature = "brotli", any(feature = "z", feature = "inflate")))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "brotli", any(feature = "z", feature = "inflate"))))
+)]
+pub use metadata::read_metadata;
+
+#[cfg(all(feature = "async", any(feature = "z", feature = "inflate")))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "async", any(feature = "z", feature = "inflate"))))
+)]
+pub use async_decode::decompress_woff1_async;
+
+#[cfg(all(feature = "async", feature = "brotli"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "async", feature = "brotli"))))]
+pub use async_decode::decompress_woff2_async;
 
 const HEAD: Tag = Tag::new(b"head");
 const HHEA: Tag = Tag::new(b"hhea");
 const HMTX: Tag = Tag::new(b"hmtx");
 const GLYF: Tag = Tag::new(b"glyf");
 const LOCA: Tag = Tag::new(b"loca");
+const MAXP: Tag = Tag::new(b"maxp");
 
 #[derive(Copy, Clone)]
 pub(crate) struct Point {
@@ -63,33 +140,55 @@ pub(crate) fn compute_checksum(buf: &[u8]) -> u32 {
     let mut checksum: u32 = 0;
     let mut iter = buf.chunks_exact(4);
     for chunk in &mut iter {
-        let bytes: [u8; 4] = chunk.try_into().unwrap();
-        checksum = checksum.wrapping_add(u32::from_be_bytes(bytes));
+        if let &[a, b, c, d] = chunk {
+            checksum = checksum.wrapping_add(u32::from_be_bytes([a, b, c, d]));
+        }
     }
 
     // Treat sizes not aligned on 4 as if it were padded to 4 with 0's.
-    checksum = checksum.wrapping_add(match iter.remainder() {
-        &[a, b, c] => u32::from_be_bytes([a, b, c, 0]),
-        &[a, b] => u32::from_be_bytes([a, b, 0, 0]),
-        &[a] => u32::from_be_bytes([a, 0, 0, 0]),
-        [] => 0,
-        _ => unreachable!("chunk size was 4 so remainder will be a slice of length 3 or smaller"),
-    });
-
-    checksum
+    let mut last_word = [0; 4];
+    for (byte, &remainder_byte) in last_word.iter_mut().zip(iter.remainder()) {
+        *byte = remainder_byte;
+    }
+    checksum.wrapping_add(u32::from_be_bytes(last_word))
+}
+
+/// The big-endian u16 at `offset` in `buf`, if `buf` is long enough
+pub(crate) fn read_u16_at(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Overwrite the big-endian u16 at `offset` in `buf`
+pub(crate) fn write_u16_at(buf: &mut [u8], offset: usize, value: u16) -> Result<(), WuffErr> {
+    let end = offset.checked_add(2).ok_or(WuffErr::GenericError)?;
+    let dst: &mut [u8; 2] = (buf.get_mut(offset..end))
+        .and_then(|dst| dst.try_into().ok())
+        .ok_or(WuffErr::GenericError)?;
+    *dst = value.to_be_bytes();
+    Ok(())
+}
+
+/// Overwrite the big-endian u32 at `offset` in `buf`
+pub(crate) fn write_u32_at(buf: &mut [u8], offset: usize, value: u32) -> Result<(), WuffErr> {
+    let end = offset.checked_add(4).ok_or(WuffErr::GenericError)?;
+    let dst: &mut [u8; 4] = (buf.get_mut(offset..end))
+        .and_then(|dst| dst.try_into().ok())
+        .ok_or(WuffErr::GenericError)?;
+    *dst = value.to_be_bytes();
+    Ok(())
 }
 
 /// Writes an OpenType table directory
 ///
 /// <https://learn.microsoft.com/en-us/typography/opentype/spec/otff#table-directory>
 fn write_table_directory_header(output: &mut impl BufMut, flavor: Tag, num_tables: u16) {
-    let mut max_pow2: u16 = 0;
-    while 1u32 << (max_pow2 + 1) <= (num_tables as u32) {
-        max_pow2 += 1;
-    }
+    // The largest power of 2 which is at most num_tables (taking 1 for 0 tables)
+    let max_pow2 = num_tables.checked_ilog2().unwrap_or(0) as u16;
     let entry_selector = max_pow2;
     let search_range: u16 = (1u16 << max_pow2) << 4;
-    let range_shift = (((num_tables as u32) << 4) - search_range as u32) as u16;
+    // (this can only wrap for 0 tables, which the decoders reject)
+    let range_shift = ((num_tables as u32) << 4).wrapping_sub(search_range as u32) as u16;
 
     output.put_u32(u32::from_be_bytes(flavor.to_be_bytes())); // sfnt version
     output.put_u16(num_tables); // num_tables
@@ -0,0 +1,83 @@
+//! Reading the extended metadata block of WOFF and WOFF2 files.
+
+use alloc::vec::Vec;
+
+use crate::brotli::Brotli;
+use crate::decompress_woff1::MAX_ZLIB_COMPRESSION_RATIO;
+use crate::error::WuffErr;
+use crate::fallible::FallibleVec;
+use crate::woff::headers::{WoffHeader, WoffVersion};
+use crate::zlib::Zlib;
+use crate::{BrotliDecompressor, ZlibDecompressor};
+
+/// Read and decompress the extended metadata block of a WOFF or WOFF2 file.
+///
+/// The metadata block is an XML document describing the font
+/// (<https://www.w3.org/TR/WOFF2/#Metadata>). It is zlib-compressed in WOFF1
+/// files and Brotli-compressed in WOFF2 files. Returns `Ok(None)` if the file
+/// doesn't have a metadata block. The XML is not validated.
+pub fn read_metadata(raw_woff_data: &[u8]) -> Result<Option<Vec<u8>>, WuffErr> {
+    let mut input = raw_woff_data;
+    let header = WoffHeader::parse(&mut input)?;
+    if header.meta_offset == 0 {
+        return Ok(None);
+    }
+
+    // `WoffHeader::parse` has already checked that the block lies within the file
+    let start = header.meta_offset as usize;
+    let compressed_data = (raw_woff_data.get(start..))
+        .and_then(|data| data.get(..header.meta_length as usize))
+        .ok_or(WuffErr::GenericError)?;
+
+    // The declared uncompressed size is the hard upper bound on the size of the output, but
+    // comes from the file: check it's plausible before allocating a buffer of that size. It
+    // can't be more than zlib can inflate the block to, and Brotli-compressed XML doesn't
+    // come close to that ratio either.
+    let max_orig_length = (header.meta_length as u64).saturating_mul(MAX_ZLIB_COMPRESSION_RATIO);
+    if header.meta_orig_length as u64 > max_orig_length {
+        return Err(WuffErr::GenericError);
+    }
+    let mut metadata = Vec::new();
+    metadata.try_resize(header.meta_orig_length as usize, 0)?;
+    match header.woff_version {
+        WoffVersion::Woff1 => Zlib::default().decompress(compressed_data, &mut metadata)?,
+        WoffVersion::Woff2 => Brotli::default().decompress(compressed_data, &mut metadata)?,
+    }
+
+    Ok(Some(metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::WuffErr;
+    use crate::test_support::{HeaderField, TRUETYPE, Table, Woff2Builder};
+
+    fn woff2() -> Woff2Builder {
+        Woff2Builder::new(TRUETYPE)
+            .table(Table::new(b"head", [0; 54]))
+            .metadata(b"<metadata/>".as_slice())
+    }
+
+    #[test]
+    fn reads_metadata() {
+        let metadata = super::read_metadata(&woff2().build());
+        assert_eq!(
+            metadata.ok().flatten().as_deref(),
+            Some(b"<metadata/>".as_slice())
+        );
+        let without = Woff2Builder::new(TRUETYPE).table(Table::new(b"head", [0; 54]));
+        assert!(matches!(super::read_metadata(&without.build()), Ok(None)));
+    }
+
+    #[test]
+    fn rejects_implausible_orig_length() {
+        // Rejected before allocating the 4 GiB it claims to inflate to
+        let woff2 = woff2()
+            .header(HeaderField::MetaOrigLength, u32::MAX)
+            .build();
+        assert!(matches!(
+            super::read_metadata(&woff2),
+            Err(WuffErr::GenericError)
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+//! Options controlling how fonts are decoded.
+
+use core::fmt;
+
+use crate::Warning;
+
+/// How the decoder handles fonts which don't conform to the WOFF or WOFF2 spec
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeMode {
+    /// Reject every font that the specs require decoders to reject. This is what
+    /// browsers (and anything else handling untrusted fonts) should use.
+    #[default]
+    Strict,
+    /// Salvage as much as possible of damaged fonts. Problems which would cause the
+    /// font to be rejected in strict mode are tolerated or repaired where the damage
+    /// can be contained, and each one is reported as a [`Repair`](crate::Repair).
+    Recovery,
+}
+
+/// How the glyf and loca tables of WOFF2 files are reconstructed
+///
+/// A transformed glyf table stores the glyphs' outlines, not the bytes of the original
+/// table, and there are many valid ways to encode the same outlines. The profiles choose
+/// between them. They only affect WOFF2 files with a transformed glyf table: other tables are
+/// always decoded as they were stored.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OutputProfile {
+    /// Byte-for-byte the output of the reference (C++) decoder: each glyph is padded to a
+    /// multiple of 4 bytes, and loca uses the index format recorded in the glyf table
+    #[default]
+    ReferenceCompatible,
+    /// Follows the rules of fontTools' WOFF2 decoder (`fontTools.ttLib.woff2`): glyphs are only
+    /// padded (to a multiple of 2 bytes) when that lets loca use short offsets, flags are
+    /// only run-length encoded in runs of 3 or more, and composite glyph records are
+    /// re-encoded in their shortest form. Fonts which fontTools can't reconstruct (a short
+    /// loca index format with a glyf table too large for it) are rejected. The output hasn't
+    /// yet been checked byte for byte against fontTools'.
+    FontToolsCompatible,
+    /// The smallest output: glyphs are only padded (to a multiple of 2 bytes) when that lets
+    /// loca use short offsets, loca uses short offsets whenever they fit (with
+    /// `head.indexToLocFormat` updated to match), and the coordinates of each simple glyph
+    /// are encoded to give the shortest flag runs and coordinate data overall
+    Compact,
+}
+
+/// Options for the `decompress_*_with_options` functions
+///
+/// Start from the defaults and set the options which differ from them:
+///
+/// ```
+/// use wuff::{DecodeMode, DecodeOptions};
+///
+/// let options = DecodeOptions::default()
+///     .mode(DecodeMode::Recovery)
+///     .validate_composites(true);
+/// ```
+#[derive(Default)]
+#[non_exhaustive]
+pub struct DecodeOptions<'a> {
+    /// Whether to reject or repair non-conforming fonts
+    pub mode: DecodeMode,
+    /// Called with a [`Warning`] for each quirk that the decoder accepts without comment
+    /// (such as a non-zero WOFF2 reserved field). Warnings never change whether a font is
+    /// accepted or rejected.
+    pub on_warning: Option<&'a mut dyn FnMut(&Warning)>,
+    /// How to reconstruct transformed (WOFF2) glyf and loca tables
+    pub output_profile: OutputProfile,
+    /// Whether to check the composite glyphs of transformed (WOFF2) glyf tables: that
+    /// their components exist, that no composite glyph contains itself, and that they are
+    /// within maxp's (and wuff's own) limits on nesting depth and component count. Each
+    /// problem is a [`Repair`](crate::Repair), so rejects the font in strict mode.
+    pub validate_composites: bool,
+    /// Whether to reject fonts whose `head.indexToLocFormat`, `maxp.numGlyphs` or
+    /// `hhea.numberOfHMetrics` don't agree with their transformed (WOFF2) glyf table in
+    /// strict mode. Otherwise, like the reference decoder, strict mode accepts them (with a
+    /// [`Warning`]). Recovery mode always reports them as a [`Repair`](crate::Repair).
+    pub validate_glyph_tables: bool,
+}
+
+impl<'a> DecodeOptions<'a> {
+    /// Set [`mode`](Self::mode)
+    pub fn mode(mut self, mode: DecodeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the [`on_warning`](Self::on_warning) callback
+    pub fn on_warning(mut self, on_warning: &'a mut dyn FnMut(&Warning)) -> Self {
+        self.on_warning = Some(on_warning);
+        self
+    }
+
+    /// Set [`output_profile`](Self::output_profile)
+    pub fn output_profile(mut self, output_profile: OutputProfile) -> Self {
+        self.output_profile = output_profile;
+        self
+    }
+
+    /// Set [`validate_composites`](Self::validate_composites)
+    pub fn validate_composites(mut self, validate_composites: bool) -> Self {
+        self.validate_composites = validate_composites;
+        self
+    }
+
+    /// Set [`validate_glyph_tables`](Self::validate_glyph_tables)
+    pub fn validate_glyph_tables(mut self, validate_glyph_tables: bool) -> Self {
+        self.validate_glyph_tables = validate_glyph_tables;
+        self
+    }
+}
+
+impl fmt::Debug for DecodeOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecodeOptions")
+            .field("mode", &self.mode)
+            .field("on_warning", &self.on_warning.as_ref().map(|_| ".."))
+            .field("output_profile", &self.output_profile)
+            .field("validate_composites", &self.validate_composites)
+            .field("validate_glyph_tables", &self.validate_glyph_tables)
+            .finish()
+    }
+}
@@ -0,0 +1,520 @@
+//! Decoding damaged fonts in [`DecodeMode::Recovery`](crate::DecodeMode::Recovery).
+//!
+//! Each check which the recovery mode relaxes goes through [`Diagnostics::repair`], which
+//! rejects the font in strict mode and records a [`Repair`] in recovery mode.
+//!
+//! [`Diagnostics::repair`]: crate::diagnostics::Diagnostics::repair
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::Tag;
+
+/// A problem with a font which was tolerated or repaired when decoding it in
+/// [`DecodeMode::Recovery`](crate::DecodeMode::Recovery). In strict mode, each of these causes the font to be rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Repair {
+    /// The `length` header field didn't match the size of the file
+    WrongLength { declared: u32, actual: u32 },
+    /// The (WOFF1) header's reserved field was not zero
+    NonZeroReserved { value: u16 },
+    /// The (WOFF2) header's `totalSfntSize` field was invalid. It isn't used for decoding.
+    WrongTotalSfntSize { declared: u32 },
+    /// `length` bytes at `offset` in the file don't belong to any block, and were ignored
+    ExtraneousData { offset: u32, length: u32 },
+    /// The file ended before the padding at the end of the (WOFF2) compressed data block
+    MissingPadding,
+    /// The (WOFF2) decompressed data block was longer than the tables it contains. The
+    /// extra bytes were ignored.
+    ExtraneousFontData,
+    /// The font had a transformed glyf table but no loca table. The loca table was rebuilt
+    /// from the glyf table.
+    MissingLoca,
+    /// The glyf table was transformed but the loca table wasn't. The loca table was rebuilt
+    /// from the glyf table.
+    LocaTransformMismatch,
+    /// The `origLength` of the loca table didn't match the rebuilt loca table
+    LocaLengthMismatch { declared: u32, actual: u32 },
+    /// A table which couldn't be decoded was left out of the decoded font
+    SkippedTable { tag: [u8; 4] },
+    /// A (WOFF1) table's `origLength` was more than zlib can inflate its `compLength` bytes
+    /// of compressed data to, so it can't be valid. The table was left out of the decoded font.
+    ImplausibleCompressionRatio {
+        tag: [u8; 4],
+        comp_length: u32,
+        orig_length: u32,
+    },
+    /// `head.indexToLocFormat` didn't match the index format of the loca table rebuilt from
+    /// the (transformed) glyf table. head was updated to match loca.
+    IndexToLocFormatMismatch { head: u16, loca: u16 },
+    /// `maxp.numGlyphs` didn't match the number of glyphs in the (transformed) glyf table
+    NumGlyphsMismatch { maxp: u16, glyf: u16 },
+    /// `hhea.numberOfHMetrics` was 0, or more than the number of glyphs in the
+    /// (transformed) glyf table
+    NumberOfHMetricsMismatch { num_hmetrics: u16, num_glyphs: u16 },
+    /// A component of composite glyph `glyph` is a glyph which doesn't exist
+    ComponentOutOfRange { glyph: u16, component: u16 },
+    /// Composite glyph `glyph` has a component (`component`) which contains `glyph`
+    CompositeCycle { glyph: u16, component: u16 },
+    /// Composite glyph `glyph` is nested more than `max_depth` levels deep (the lower of
+    /// `maxp.maxComponentDepth` and wuff's own limit)
+    CompositeTooDeep { glyph: u16, max_depth: u16 },
+    /// Composite glyph `glyph` has more components than `maxp.maxComponentElements`
+    TooManyComponents {
+        glyph: u16,
+        num_components: u16,
+        max_elements: u16,
+    },
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WrongLength { declared, actual } => {
+                write!(
+                    f,
+                    "length field is {declared}, but the file is {actual} bytes"
+                )
+            }
+            Self::NonZeroReserved { value } => write!(f, "reserved field is {value}"),
+            Self::WrongTotalSfntSize { declared } => write!(f, "totalSfntSize is {declared}"),
+            Self::ExtraneousData { offset, length } => {
+                write!(f, "ignored {length} extraneous bytes at offset {offset}")
+            }
+            Self::MissingPadding => write!(f, "compressed data block is not padded"),
+            Self::ExtraneousFontData => write!(f, "ignored extraneous bytes after the last table"),
+            Self::MissingLoca => write!(f, "rebuilt missing loca table"),
+            Self::LocaTransformMismatch => write!(f, "rebuilt untransformed loca table"),
+            Self::LocaLengthMismatch { declared, actual } => {
+                write!(
+                    f,
+                    "loca origLength is {declared}, but the rebuilt table is {actual} bytes"
+                )
+            }
+            Self::SkippedTable { tag } => {
+                let tag = core::str::from_utf8(tag).unwrap_or("????");
+                write!(f, "skipped corrupt '{tag}' table")
+            }
+            Self::ImplausibleCompressionRatio {
+                tag,
+                comp_length,
+                orig_length,
+            } => {
+                let tag = core::str::from_utf8(tag).unwrap_or("????");
+                write!(
+                    f,
+                    "skipped '{tag}' table: {comp_length} compressed bytes can't inflate to {orig_length}"
+                )
+            }
+            Self::IndexToLocFormatMismatch { head, loca } => {
+                write!(
+                    f,
+                    "head indexToLocFormat is {head}, but loca uses format {loca}"
+                )
+            }
+            Self::NumGlyphsMismatch { maxp, glyf } => {
+                write!(f, "maxp numGlyphs is {maxp}, but glyf has {glyf} glyphs")
+            }
+            Self::NumberOfHMetricsMismatch {
+                num_hmetrics,
+                num_glyphs,
+            } => {
+                write!(
+                    f,
+                    "hhea numberOfHMetrics is {num_hmetrics}, but glyf has {num_glyphs} glyphs"
+                )
+            }
+            Self::ComponentOutOfRange { glyph, component } => {
+                write!(f, "glyph {glyph} has nonexistent component {component}")
+            }
+            Self::CompositeCycle { glyph, component } => {
+                write!(
+                    f,
+                    "glyph {glyph} has component {component}, which contains it"
+                )
+            }
+            Self::CompositeTooDeep { glyph, max_depth } => {
+                write!(
+                    f,
+                    "glyph {glyph} nests composites more than {max_depth} levels deep"
+                )
+            }
+            Self::TooManyComponents {
+                glyph,
+                num_components,
+                max_elements,
+            } => {
+                write!(
+                    f,
+                    "glyph {glyph} has {num_components} components, but maxp allows {max_elements}"
+                )
+            }
+        }
+    }
+}
+
+/// A decoded font, and the repairs that were made to decode it
+#[derive(Clone, Debug)]
+pub struct Decoded {
+    /// The decoded OpenType/TrueType font (or font collection)
+    pub font: Vec<u8>,
+    /// The repairs made while decoding, in the order they were made.
+    /// Always empty in [`DecodeMode::Strict`](crate::DecodeMode::Strict).
+    pub repairs: Vec<Repair>,
+}
+
+/// Whether a font is unusable without the table, so that it is never skipped in recovery mode
+pub(crate) fn is_essential_table(tag: Tag) -> bool {
+    matches!(
+        tag.as_ref(),
+        b"head" | b"hhea" | b"hmtx" | b"maxp" | b"cmap" | b"glyf" | b"loca" | b"CFF " | b"CFF2"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use crate::{
+        DecodeMode, DecodeOptions, WuffErr, decompress_woff1_with_decompressor_and_options,
+    };
+
+    fn recovery() -> DecodeOptions<'static> {
+        DecodeOptions::default().mode(DecodeMode::Recovery)
+    }
+
+    /// Decode a WOFF1 file whose tables are all stored uncompressed
+    fn decode_woff1(woff: &[u8], options: DecodeOptions<'_>) -> Result<Decoded, WuffErr> {
+        decompress_woff1_with_decompressor_and_options(woff, &mut NoCompression, options)
+    }
+
+    #[test]
+    fn woff1_repairs() {
+        let tables = [
+            Table::new(b"head", head_table(0)),
+            Table::new(b"name", [1; 10]),
+        ];
+        let clean = decode_woff1(&woff1(TRUETYPE, &tables), recovery()).unwrap();
+        assert_eq!(clean.repairs, []);
+
+        let mut reserved = woff1(TRUETYPE, &tables);
+        reserved[14..16].copy_from_slice(&[0, 1]);
+        assert!(decode_woff1(&reserved, DecodeOptions::default()).is_err());
+        let decoded = decode_woff1(&reserved, recovery()).unwrap();
+        assert_eq!(decoded.repairs, [Repair::NonZeroReserved { value: 1 }]);
+        assert_eq!(decoded.font, clean.font);
+
+        // A table which is longer than its origLength
+        let corrupt_name = [tables[0].clone(), tables[1].clone().orig_length(4)];
+        let corrupt_name = woff1(TRUETYPE, &corrupt_name);
+        assert!(decode_woff1(&corrupt_name, DecodeOptions::default()).is_err());
+        let decoded = decode_woff1(&corrupt_name, recovery()).unwrap();
+        assert_eq!(decoded.repairs, [Repair::SkippedTable { tag: *b"name" }]);
+        let without_name = decode_woff1(&woff1(TRUETYPE, &tables[..1]), recovery()).unwrap();
+        assert_eq!(decoded.font, without_name.font);
+
+        // Essential tables can't be skipped
+        let corrupt_head = [tables[0].clone().orig_length(4), tables[1].clone()];
+        assert!(decode_woff1(&woff1(TRUETYPE, &corrupt_head), recovery()).is_err());
+
+        // A table which claims to inflate to more than zlib can produce. (It is never
+        // decompressed: NoCompression would panic.)
+        let implausible_name = [
+            tables[0].clone(),
+            tables[1].clone().orig_length(10 * 1032 + 1),
+        ];
+        let implausible_name = woff1(TRUETYPE, &implausible_name);
+        assert!(decode_woff1(&implausible_name, DecodeOptions::default()).is_err());
+        let decoded = decode_woff1(&implausible_name, recovery()).unwrap();
+        let repair = Repair::ImplausibleCompressionRatio {
+            tag: *b"name",
+            comp_length: 10,
+            orig_length: 10 * 1032 + 1,
+        };
+        assert_eq!(decoded.repairs, [repair]);
+        assert_eq!(decoded.font, without_name.font);
+    }
+
+    #[test]
+    fn out_of_memory_is_not_repaired() {
+        use crate::decompress_woff2_with_decompressor_and_options;
+
+        let head = Table::new(b"head", head_table(0));
+        let compressed_name = Table::new(b"name", [1; 10]).orig_length(20);
+        let woff = woff1(TRUETYPE, &[head.clone(), compressed_name]);
+        let result =
+            decompress_woff1_with_decompressor_and_options(&woff, &mut OutOfMemory, recovery());
+        assert!(matches!(result, Err(WuffErr::OutOfMemory)));
+
+        let woff = Woff2Builder::new(TRUETYPE).table(head).build();
+        let result =
+            decompress_woff2_with_decompressor_and_options(&woff, &mut OutOfMemory, recovery());
+        assert!(matches!(result, Err(WuffErr::OutOfMemory)));
+    }
+
+    #[cfg(feature = "brotli")]
+    mod woff2 {
+        use super::*;
+        use crate::{Warning, WarningKind, decompress_woff2, decompress_woff2_with_options};
+        use alloc::vec;
+
+        fn glyf() -> GlyfTransform {
+            GlyfTransform::new(vec![
+                Glyph::Empty,
+                Glyph::simple(&[&[(0, 0, true), (10, 20, true), (20, 0, false)]]),
+            ])
+        }
+
+        fn font() -> Woff2Builder {
+            Woff2Builder::new(TRUETYPE)
+                .table(Table::new(b"head", head_table(0)))
+                .glyf(&glyf())
+        }
+
+        /// Check that strict mode rejects `woff`, and that it decodes to `expected` with
+        /// `repairs` in recovery mode
+        fn assert_repaired(woff: &[u8], repairs: &[Repair], expected: &Woff2Builder) {
+            assert!(decompress_woff2(woff).is_err());
+            let decoded = decompress_woff2_with_options(woff, recovery()).unwrap();
+            assert_eq!(decoded.repairs, repairs);
+            assert_eq!(decoded.font, decompress_woff2(&expected.build()).unwrap());
+        }
+
+        #[test]
+        fn block_layout_repairs() {
+            let clean = font().build();
+            let gap = font().gap_after_font_data(8).build();
+            let data_end = crate::Round4!(clean.len());
+            let extraneous = [Repair::ExtraneousData {
+                offset: data_end as u32,
+                length: 8,
+            }];
+            assert_repaired(&gap, &extraneous, &font());
+
+            let with_metadata = font().metadata(*b"<metadata/>");
+            let gap = with_metadata.clone().gap_after_font_data(8).build();
+            assert_repaired(&gap, &extraneous, &with_metadata);
+
+            let wrong_length = font().header(HeaderField::Length, 1000).build();
+            let repair = Repair::WrongLength {
+                declared: 1000,
+                actual: clean.len() as u32,
+            };
+            assert_repaired(&wrong_length, &[repair], &font());
+
+            let zero_sfnt_size = font().header(HeaderField::TotalSfntSize, 0).build();
+            let repair = Repair::WrongTotalSfntSize { declared: 0 };
+            assert_repaired(&zero_sfnt_size, &[repair], &font());
+
+            // Blocks still must not overlap
+            let overlapping = with_metadata.clone().build();
+            let meta_offset = u32::from_be_bytes(overlapping[28..32].try_into().unwrap());
+            let overlapping = with_metadata.header(HeaderField::MetaOffset, meta_offset - 4);
+            assert!(decompress_woff2_with_options(&overlapping.build(), recovery()).is_err());
+        }
+
+        #[test]
+        fn loca_repairs() {
+            let head = Table::new(b"head", head_table(0));
+            let glyf_table = Table::with_transform(b"glyf", 0, glyf().encode());
+
+            let missing_loca = Woff2Builder::new(TRUETYPE)
+                .table(head.clone())
+                .table(glyf_table.clone())
+                .build();
+            assert_repaired(&missing_loca, &[Repair::MissingLoca], &font());
+
+            let untransformed_loca = Woff2Builder::new(TRUETYPE)
+                .table(head.clone())
+                .table(glyf_table.clone())
+                .table(Table::new(b"loca", [0; 6]))
+                .build();
+            assert_repaired(
+                &untransformed_loca,
+                &[Repair::LocaTransformMismatch],
+                &font(),
+            );
+
+            let wrong_length = Woff2Builder::new(TRUETYPE)
+                .table(head)
+                .table(glyf_table)
+                .table(Table::with_transform(b"loca", 0, []).orig_length(100))
+                .build();
+            let repair = Repair::LocaLengthMismatch {
+                declared: 100,
+                actual: 6,
+            };
+            assert_repaired(&wrong_length, &[repair], &font());
+        }
+
+        /// Decode `woff` in strict mode, returning the glyph table mismatches reported as
+        /// warnings
+        fn strict_warnings(woff: &[u8]) -> (Vec<u8>, Vec<WarningKind>) {
+            let mut warnings = Vec::new();
+            let mut on_warning = |warning: &Warning| match warning.kind {
+                WarningKind::IndexToLocFormatMismatch { .. }
+                | WarningKind::NumGlyphsMismatch { .. }
+                | WarningKind::NumberOfHMetricsMismatch { .. } => {
+                    warnings.push(warning.kind.clone())
+                }
+                _ => {}
+            };
+            let options = DecodeOptions::default().on_warning(&mut on_warning);
+            let decoded = decompress_woff2_with_options(woff, options).unwrap();
+            (decoded.font, warnings)
+        }
+
+        /// Check that `woff` is rejected when glyph tables are validated in strict mode
+        fn assert_rejected_when_validated(woff: &[u8]) {
+            let validate = DecodeOptions::default().validate_glyph_tables(true);
+            assert!(decompress_woff2_with_options(woff, validate).is_err());
+        }
+
+        #[test]
+        fn glyph_count_repairs() {
+            // head.indexToLocFormat is left as it is in strict mode (like the reference
+            // decoder), and updated to match loca in recovery mode
+            let wrong_format = Woff2Builder::new(TRUETYPE)
+                .table(Table::new(b"head", head_table(1)))
+                .glyf(&glyf())
+                .build();
+            let (decoded, warnings) = strict_warnings(&wrong_format);
+            let mismatch = WarningKind::IndexToLocFormatMismatch { head: 1, loca: 0 };
+            assert_eq!(warnings, [mismatch]);
+            let head = sfnt_tables(&decoded, 0)
+                .into_iter()
+                .find(|t| &t.tag == b"head");
+            assert_eq!(head.unwrap().data[50..52], [0, 1]);
+            assert_rejected_when_validated(&wrong_format);
+            let decoded = decompress_woff2_with_options(&wrong_format, recovery()).unwrap();
+            let repair = Repair::IndexToLocFormatMismatch { head: 1, loca: 0 };
+            assert_eq!(decoded.repairs, [repair]);
+            assert_eq!(decoded.font, decompress_woff2(&font().build()).unwrap());
+
+            // ... unless head is shared with a font whose loca table uses the other format
+            let long_glyf = GlyfTransform {
+                index_format: 1,
+                ..glyf()
+            };
+            let shared_head = Woff2Builder::collection(0x00010000)
+                .table(Table::new(b"head", head_table(0)))
+                .glyf(&glyf())
+                .glyf(&long_glyf)
+                .font(TRUETYPE, &[0, 1, 2])
+                .font(TRUETYPE, &[0, 3, 4])
+                .build();
+            assert!(decompress_woff2_with_options(&shared_head, recovery()).is_err());
+            let (_, warnings) = strict_warnings(&shared_head);
+            let mismatch = WarningKind::IndexToLocFormatMismatch { head: 0, loca: 1 };
+            assert_eq!(warnings, [mismatch]);
+
+            // maxp.numGlyphs and hhea.numberOfHMetrics are left as they are
+            let maxp = |num_glyphs: u16| {
+                let mut maxp = vec![0, 0, 0x50, 0];
+                maxp.extend_from_slice(&num_glyphs.to_be_bytes());
+                Table::new(b"maxp", maxp)
+            };
+            assert_eq!(strict_warnings(&font().table(maxp(2)).build()).1, []);
+            let wrong_count = font().table(maxp(3)).build();
+            let (strict_font, warnings) = strict_warnings(&wrong_count);
+            let mismatch = WarningKind::NumGlyphsMismatch { maxp: 3, glyf: 2 };
+            assert_eq!(warnings, [mismatch]);
+            assert_rejected_when_validated(&wrong_count);
+            let decoded = decompress_woff2_with_options(&wrong_count, recovery()).unwrap();
+            let repair = Repair::NumGlyphsMismatch { maxp: 3, glyf: 2 };
+            assert_eq!(decoded.repairs, [repair]);
+            assert_eq!(decoded.font, strict_font);
+            let maxp_table = sfnt_tables(&decoded.font, 0).pop().unwrap();
+            assert_eq!(maxp_table.data, maxp(3).data);
+
+            let with_hmtx = |num_hmetrics| {
+                font()
+                    .table(Table::new(b"hhea", hhea_table(num_hmetrics)))
+                    .table(Table::new(b"hmtx", [0; 12]))
+                    .build()
+            };
+            assert_eq!(strict_warnings(&with_hmtx(2)).1, []);
+            for num_hmetrics in [0, 3] {
+                let (_, warnings) = strict_warnings(&with_hmtx(num_hmetrics));
+                let mismatch = WarningKind::NumberOfHMetricsMismatch {
+                    num_hmetrics,
+                    num_glyphs: 2,
+                };
+                assert_eq!(warnings, [mismatch]);
+                assert_rejected_when_validated(&with_hmtx(num_hmetrics));
+                let decoded = decompress_woff2_with_options(&with_hmtx(num_hmetrics), recovery());
+                let repair = Repair::NumberOfHMetricsMismatch {
+                    num_hmetrics,
+                    num_glyphs: 2,
+                };
+                assert_eq!(decoded.unwrap().repairs, [repair]);
+            }
+        }
+
+        #[test]
+        fn composite_repairs() {
+            let components = [component(MORE_COMPONENTS, 1, 0, 0), component(0, 9, 0, 0)].concat();
+            let glyf = GlyfTransform::new(vec![
+                Glyph::Composite {
+                    components,
+                    instructions: None,
+                    bbox: [0, 0, 10, 10],
+                },
+                Glyph::simple(&[&[(0, 0, true), (10, 10, true)]]),
+            ]);
+            // A version 1.0 maxp table allowing one component per composite glyph (and
+            // composites of simple glyphs)
+            let mut maxp = vec![0; 32];
+            maxp[0..2].copy_from_slice(&[0, 1]);
+            maxp[4..6].copy_from_slice(&2u16.to_be_bytes());
+            maxp[28..32].copy_from_slice(&[0, 1, 0, 1]);
+            let woff = Woff2Builder::new(TRUETYPE)
+                .table(Table::new(b"head", head_table(0)))
+                .glyf(&glyf)
+                .table(Table::new(b"maxp", maxp))
+                .build();
+
+            // Composite glyphs are only checked when asked to
+            let decoded = decompress_woff2(&woff).unwrap();
+            let validate = |mode| {
+                DecodeOptions::default()
+                    .mode(mode)
+                    .validate_composites(true)
+            };
+            assert!(decompress_woff2_with_options(&woff, validate(DecodeMode::Strict)).is_err());
+            let repaired =
+                decompress_woff2_with_options(&woff, validate(DecodeMode::Recovery)).unwrap();
+            assert_eq!(
+                repaired.repairs,
+                [
+                    Repair::ComponentOutOfRange {
+                        glyph: 0,
+                        component: 9
+                    },
+                    Repair::TooManyComponents {
+                        glyph: 0,
+                        num_components: 2,
+                        max_elements: 1
+                    }
+                ]
+            );
+            assert_eq!(repaired.font, decoded);
+        }
+
+        #[test]
+        fn skips_tables_with_unknown_transforms() {
+            let unknown_transform = font()
+                .table(Table::with_transform(b"name", 1, [0; 8]))
+                .build();
+            let repair = Repair::SkippedTable { tag: *b"name" };
+            assert_repaired(&unknown_transform, &[repair], &font());
+
+            // Essential tables can't be skipped
+            let unknown_transform = font()
+                .table(Table::with_transform(b"cmap", 1, [0; 8]))
+                .build();
+            assert!(decompress_woff2_with_options(&unknown_transform, recovery()).is_err());
+        }
+    }
+}
@@ -6,7 +6,7 @@
 
 //! Font table tags
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct Tag([u8; 4]);
 
 impl Tag {
@@ -0,0 +1,941 @@
+//! Builders for synthetic WOFF and WOFF2 files, for tests.
+//!
+//! This module is compiled for wuff's own unit tests, and exposed (as
+//! `wuff::test_support`) by the `test-support` feature for the tests of the
+//! other crates in the workspace. It isn't part of the public API.
+//!
+//! [`Woff2Builder`] lays out a complete WOFF2 file from a list of [`Table`]s:
+//! the header, table directory, collection directory, compressed data block
+//! and optional metadata and private data blocks. Every computed field can be
+//! overridden afterwards ([`Woff2Builder::header`], [`Table::orig_length`],
+//! [`Woff2Builder::patch`], ...) to produce deliberately invalid files.
+//!
+//! [`GlyfTransform`] and [`HmtxTransform`] produce transformed glyf and hmtx
+//! tables from glyph and metrics descriptions, mirroring the reference
+//! encoder (`woff2/src/transform.cc`).
+//!
+//! The data block is "compressed" as a brotli stream made of uncompressed
+//! meta-blocks, so no brotli encoder is needed.
+//!
+//! [`Woff1Builder`] lays out a WOFF1 file, optionally from the tables of an
+//! existing sfnt ([`Woff1Builder::from_sfnt`]), with the tables stored as they
+//! are or zlib-compressed.
+//!
+//! <https://www.w3.org/TR/WOFF2/>
+//! <https://www.w3.org/TR/WOFF/>
+// Test code: panicking on bad input is fine here
+#![allow(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::unreachable,
+    clippy::arithmetic_side_effects,
+    clippy::disallowed_methods
+)]
+
+use alloc::{vec, vec::Vec};
+
+use bytes::BufMut;
+
+use crate::table_tags::KNOWN_TABLE_TAGS;
+
+/// The null transform version of a table: 3 for glyf and loca, 0 for all others
+pub fn null_transform_version(tag: [u8; 4]) -> u8 {
+    match &tag {
+        b"glyf" | b"loca" => 3,
+        _ => 0,
+    }
+}
+
+/// Append a UIntBase128
+pub fn put_base128(out: &mut Vec<u8>, value: u32) {
+    let size = (1..5).find(|&size| value >> (7 * size) == 0).unwrap_or(5);
+    for i in (0..size).rev() {
+        let continuation = if i > 0 { 0x80 } else { 0 };
+        out.push(((value >> (7 * i)) & 0x7f) as u8 | continuation);
+    }
+}
+
+/// Append a 255UInt16, using the shortest encoding
+pub fn put_255_u16(out: &mut Vec<u8>, value: u16) {
+    match value {
+        0..253 => out.push(value as u8),
+        253..506 => out.extend_from_slice(&[255, (value - 253) as u8]),
+        506..762 => out.extend_from_slice(&[254, (value - 506) as u8]),
+        _ => {
+            out.push(253);
+            out.put_u16(value);
+        }
+    }
+}
+
+/// Encode `data` as a brotli stream of uncompressed meta-blocks
+/// <https://www.rfc-editor.org/rfc/rfc7932#section-9.2>
+pub fn brotli_stored(data: &[u8]) -> Vec<u8> {
+    /// Writes bits least significant first
+    struct BitWriter {
+        out: Vec<u8>,
+        bit: u32,
+    }
+    impl BitWriter {
+        fn put(&mut self, value: u32, num_bits: u32) {
+            for i in 0..num_bits {
+                if self.bit == 0 {
+                    self.out.push(0);
+                }
+                *self.out.last_mut().unwrap() |= (((value >> i) & 1) as u8) << self.bit;
+                self.bit = (self.bit + 1) % 8;
+            }
+        }
+    }
+
+    let mut writer = BitWriter {
+        out: Vec::new(),
+        bit: 0,
+    };
+    writer.put(0, 1); // WBITS = 16
+    for chunk in data.chunks(1 << 16) {
+        writer.put(0, 1); // ISLAST
+        writer.put(0, 2); // MNIBBLES = 4
+        writer.put(chunk.len() as u32 - 1, 16); // MLEN - 1
+        writer.put(1, 1); // ISUNCOMPRESSED
+        writer.bit = 0; // Pad to a byte boundary
+        writer.out.extend_from_slice(chunk);
+    }
+    writer.put(1, 1); // ISLAST
+    writer.put(1, 1); // ISLASTEMPTY
+    writer.out
+}
+
+/// A field of the 48-byte WOFF2 header
+#[derive(Copy, Clone)]
+pub enum HeaderField {
+    Signature,
+    Flavor,
+    Length,
+    NumTables,
+    Reserved,
+    TotalSfntSize,
+    TotalCompressedSize,
+    MajorVersion,
+    MinorVersion,
+    MetaOffset,
+    MetaLength,
+    MetaOrigLength,
+    PrivOffset,
+    PrivLength,
+}
+
+impl HeaderField {
+    /// (offset, size) of the field
+    fn location(self) -> (usize, usize) {
+        match self {
+            Self::Signature => (0, 4),
+            Self::Flavor => (4, 4),
+            Self::Length => (8, 4),
+            Self::NumTables => (12, 2),
+            Self::Reserved => (14, 2),
+            Self::TotalSfntSize => (16, 4),
+            Self::TotalCompressedSize => (20, 4),
+            Self::MajorVersion => (24, 2),
+            Self::MinorVersion => (26, 2),
+            Self::MetaOffset => (28, 4),
+            Self::MetaLength => (32, 4),
+            Self::MetaOrigLength => (36, 4),
+            Self::PrivOffset => (40, 4),
+            Self::PrivLength => (44, 4),
+        }
+    }
+}
+
+/// A table directory entry and the table's data (as stored in the data block)
+#[derive(Clone)]
+pub struct Table {
+    pub tag: [u8; 4],
+    pub transform_version: u8,
+    pub data: Vec<u8>,
+    /// Defaults to the length of `data`
+    pub orig_length: Option<u32>,
+    /// Defaults to the length of `data`. Only written for transformed tables,
+    /// unless set explicitly.
+    pub transform_length: Option<u32>,
+    /// Write the tag explicitly (flags value 63) even if it is a known tag
+    pub explicit_tag: bool,
+    /// Defaults to the checksum of the (uncompressed) data. Only written to
+    /// WOFF1 table directories.
+    pub checksum: Option<u32>,
+}
+
+impl Table {
+    /// A table stored with the null transform
+    pub fn new(tag: &[u8; 4], data: impl Into<Vec<u8>>) -> Self {
+        Self::with_transform(tag, null_transform_version(*tag), data)
+    }
+
+    /// A table with the given transform version
+    pub fn with_transform(tag: &[u8; 4], transform_version: u8, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            tag: *tag,
+            transform_version,
+            data: data.into(),
+            orig_length: None,
+            transform_length: None,
+            explicit_tag: false,
+            checksum: None,
+        }
+    }
+
+    pub fn orig_length(mut self, orig_length: u32) -> Self {
+        self.orig_length = Some(orig_length);
+        self
+    }
+
+    pub fn transform_length(mut self, transform_length: u32) -> Self {
+        self.transform_length = Some(transform_length);
+        self
+    }
+
+    pub fn explicit_tag(mut self) -> Self {
+        self.explicit_tag = true;
+        self
+    }
+
+    pub fn checksum(mut self, checksum: u32) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Whether the directory entry has a transformLength field
+    fn is_transformed(&self) -> bool {
+        self.transform_version != null_transform_version(self.tag)
+    }
+
+    fn write_directory_entry(&self, out: &mut Vec<u8>) {
+        let known_index = KNOWN_TABLE_TAGS
+            .iter()
+            .position(|tag| tag.to_be_bytes() == self.tag)
+            .filter(|_| !self.explicit_tag);
+        out.push(known_index.unwrap_or(63) as u8 | (self.transform_version << 6));
+        if known_index.is_none() {
+            out.extend_from_slice(&self.tag);
+        }
+        let length = self.data.len() as u32;
+        put_base128(out, self.orig_length.unwrap_or(length));
+        if self.is_transformed() || self.transform_length.is_some() {
+            put_base128(out, self.transform_length.unwrap_or(length));
+        }
+    }
+}
+
+/// A font in a collection: its flavor and the indices of its tables in the
+/// table directory
+#[derive(Clone)]
+pub struct CollectionFont {
+    pub flavor: [u8; 4],
+    pub table_indices: Vec<u16>,
+}
+
+/// Builds a WOFF2 file
+#[derive(Clone)]
+pub struct Woff2Builder {
+    flavor: [u8; 4],
+    tables: Vec<Table>,
+    /// (version, fonts) of the collection directory
+    collection: Option<(u32, Vec<CollectionFont>)>,
+    /// Uncompressed extended metadata
+    metadata: Option<Vec<u8>>,
+    private_data: Option<Vec<u8>>,
+    /// Appended to the table data before compression
+    extra_font_data: Vec<u8>,
+    /// Inserted between the data block and the following block
+    gap_after_font_data: usize,
+    header_overrides: Vec<(HeaderField, u32)>,
+    patches: Vec<(usize, Vec<u8>)>,
+}
+
+pub const TRUETYPE: [u8; 4] = [0, 1, 0, 0];
+
+impl Woff2Builder {
+    /// A single font with the given flavor (e.g. [`TRUETYPE`] or `*b"OTTO"`)
+    pub fn new(flavor: [u8; 4]) -> Self {
+        Self {
+            flavor,
+            tables: Vec::new(),
+            collection: None,
+            metadata: None,
+            private_data: None,
+            extra_font_data: Vec::new(),
+            gap_after_font_data: 0,
+            header_overrides: Vec::new(),
+            patches: Vec::new(),
+        }
+    }
+
+    /// A collection with the given collection directory version
+    /// (0x00010000 or 0x00020000). Add fonts with [`Self::font`].
+    pub fn collection(version: u32) -> Self {
+        let mut builder = Self::new(*b"ttcf");
+        builder.collection = Some((version, Vec::new()));
+        builder
+    }
+
+    /// Add a table. Tables are stored in the order they are added.
+    pub fn table(mut self, table: Table) -> Self {
+        self.tables.push(table);
+        self
+    }
+
+    /// Add a transformed glyf table and the (empty) loca table that goes
+    /// with it, with loca's origLength set to match the glyph count
+    pub fn glyf(self, glyf: &GlyfTransform) -> Self {
+        let loca_length =
+            (glyf.glyphs.len() as u32 + 1) * if glyf.index_format == 0 { 2 } else { 4 };
+        self.table(Table::with_transform(b"glyf", 0, glyf.encode()))
+            .table(Table::with_transform(b"loca", 0, Vec::new()).orig_length(loca_length))
+    }
+
+    /// Add a font to a collection
+    pub fn font(mut self, flavor: [u8; 4], table_indices: &[u16]) -> Self {
+        let (_, fonts) = self
+            .collection
+            .as_mut()
+            .expect("fonts can only be added to collections");
+        fonts.push(CollectionFont {
+            flavor,
+            table_indices: table_indices.to_vec(),
+        });
+        self
+    }
+
+    pub fn metadata(mut self, metadata: impl Into<Vec<u8>>) -> Self {
+        self.metadata = Some(metadata.into());
+        self
+    }
+
+    pub fn private_data(mut self, private_data: impl Into<Vec<u8>>) -> Self {
+        self.private_data = Some(private_data.into());
+        self
+    }
+
+    /// Append bytes to the data block which belong to no table
+    pub fn extra_font_data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.extra_font_data = data.into();
+        self
+    }
+
+    /// Leave `gap` bytes (in addition to alignment padding) between the data
+    /// block and the next block (or the end of the file)
+    pub fn gap_after_font_data(mut self, gap: usize) -> Self {
+        self.gap_after_font_data = gap;
+        self
+    }
+
+    /// Override a header field after layout
+    pub fn header(mut self, field: HeaderField, value: u32) -> Self {
+        self.header_overrides.push((field, value));
+        self
+    }
+
+    /// Overwrite bytes of the built file, starting at `offset`
+    pub fn patch(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.patches.push((offset, bytes.to_vec()));
+        self
+    }
+
+    /// Lay out the WOFF2 file
+    pub fn build(&self) -> Vec<u8> {
+        let mut directory = Vec::new();
+        for table in &self.tables {
+            table.write_directory_entry(&mut directory);
+        }
+        if let Some((version, fonts)) = &self.collection {
+            directory.put_u32(*version);
+            put_255_u16(&mut directory, fonts.len() as u16);
+            for font in fonts {
+                put_255_u16(&mut directory, font.table_indices.len() as u16);
+                directory.extend_from_slice(&font.flavor);
+                for &index in &font.table_indices {
+                    put_255_u16(&mut directory, index);
+                }
+            }
+        }
+
+        let mut font_data: Vec<u8> = self.tables.iter().flat_map(|t| t.data.clone()).collect();
+        font_data.extend_from_slice(&self.extra_font_data);
+        let compressed = brotli_stored(&font_data);
+
+        let mut woff = vec![0; 48];
+        woff.extend_from_slice(&directory);
+        woff.extend_from_slice(&compressed);
+        woff.resize(woff.len() + self.gap_after_font_data, 0);
+        let block = |woff: &mut Vec<u8>, data: &[u8]| {
+            woff.resize(crate::Round4!(woff.len()), 0);
+            let offset = woff.len() as u32;
+            woff.extend_from_slice(data);
+            offset
+        };
+        let meta = self.metadata.as_ref().map(|metadata| {
+            let compressed = brotli_stored(metadata);
+            let offset = block(&mut woff, &compressed);
+            (offset, compressed.len() as u32, metadata.len() as u32)
+        });
+        let private = self
+            .private_data
+            .as_ref()
+            .map(|data| (block(&mut woff, data), data.len() as u32));
+        woff.resize(crate::Round4!(woff.len()), 0);
+
+        let total_sfnt_size = match &self.collection {
+            None => 12 + 16 * self.tables.len(),
+            Some((version, fonts)) => {
+                let dsig = if *version == 0x00020000 { 12 } else { 0 };
+                12 + 4 * fonts.len()
+                    + dsig
+                    + fonts
+                        .iter()
+                        .map(|font| 12 + 16 * font.table_indices.len())
+                        .sum::<usize>()
+            }
+        } + self
+            .tables
+            .iter()
+            .map(|table| crate::Round4!(table.orig_length.unwrap_or(table.data.len() as u32)))
+            .sum::<u32>() as usize;
+
+        let length = woff.len() as u32;
+        let mut header = &mut woff[..48];
+        header.put_slice(b"wOF2");
+        header.put_slice(&self.flavor);
+        header.put_u32(length);
+        header.put_u16(self.tables.len() as u16);
+        header.put_u16(0); // reserved
+        header.put_u32(total_sfnt_size as u32);
+        header.put_u32(compressed.len() as u32);
+        header.put_u16(1); // majorVersion
+        header.put_u16(0); // minorVersion
+        let (meta_offset, meta_length, meta_orig_length) = meta.unwrap_or_default();
+        header.put_u32(meta_offset);
+        header.put_u32(meta_length);
+        header.put_u32(meta_orig_length);
+        let (priv_offset, priv_length) = private.unwrap_or_default();
+        header.put_u32(priv_offset);
+        header.put_u32(priv_length);
+
+        for &(field, value) in &self.header_overrides {
+            let (offset, size) = field.location();
+            woff[offset..offset + size].copy_from_slice(&value.to_be_bytes()[4 - size..]);
+        }
+        for (offset, bytes) in &self.patches {
+            woff[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        woff
+    }
+}
+
+/// A point of a simple glyph
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlyphPoint {
+    pub x: i16,
+    pub y: i16,
+    pub on_curve: bool,
+}
+
+/// A glyph, for [`GlyfTransform`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Glyph {
+    Empty,
+    Simple {
+        /// Absolute coordinates
+        contours: Vec<Vec<GlyphPoint>>,
+        instructions: Vec<u8>,
+        /// Whether the OVERLAP_SIMPLE flag is set
+        overlap: bool,
+        /// (x_min, y_min, x_max, y_max). If `None`, it is computed from the
+        /// points and not stored (the decoder computes it).
+        bbox: Option<[i16; 4]>,
+    },
+    Composite {
+        /// The component records (see [`component`])
+        components: Vec<u8>,
+        /// Requires WE_HAVE_INSTRUCTIONS on one of the components
+        instructions: Option<Vec<u8>>,
+        bbox: [i16; 4],
+    },
+}
+
+impl Glyph {
+    /// A simple glyph with no instructions and a computed bbox
+    pub fn simple(contours: &[&[(i16, i16, bool)]]) -> Self {
+        Self::Simple {
+            contours: contours
+                .iter()
+                .map(|contour| {
+                    contour
+                        .iter()
+                        .map(|&(x, y, on_curve)| GlyphPoint { x, y, on_curve })
+                        .collect()
+                })
+                .collect(),
+            instructions: Vec::new(),
+            overlap: false,
+            bbox: None,
+        }
+    }
+}
+
+/// composite glyph flags
+pub const ARG_1_AND_2_ARE_WORDS: u16 = 1 << 0;
+pub const ARGS_ARE_XY_VALUES: u16 = 1 << 1;
+pub const WE_HAVE_A_SCALE: u16 = 1 << 3;
+pub const MORE_COMPONENTS: u16 = 1 << 5;
+pub const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 1 << 6;
+pub const WE_HAVE_A_TWO_BY_TWO: u16 = 1 << 7;
+pub const WE_HAVE_INSTRUCTIONS: u16 = 1 << 8;
+
+/// A composite glyph component record with x/y offsets. `flags` selects the
+/// transform, which is written as that many F2DOT14 values of 1.0.
+pub fn component(flags: u16, glyph_index: u16, dx: i16, dy: i16) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.put_u16(flags | ARG_1_AND_2_ARE_WORDS | ARGS_ARE_XY_VALUES);
+    out.put_u16(glyph_index);
+    out.put_i16(dx);
+    out.put_i16(dy);
+    let num_scales = if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+        4
+    } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+        2
+    } else if flags & WE_HAVE_A_SCALE != 0 {
+        1
+    } else {
+        0
+    };
+    for _ in 0..num_scales {
+        out.put_u16(0x4000);
+    }
+    out
+}
+
+/// A transformed glyf table
+/// <https://www.w3.org/TR/WOFF2/#glyf_table_format>
+#[derive(Clone, Debug)]
+pub struct GlyfTransform {
+    pub glyphs: Vec<Glyph>,
+    /// loca format of the reconstructed table: 0 for short offsets, 1 for long
+    pub index_format: u16,
+}
+
+impl GlyfTransform {
+    pub fn new(glyphs: Vec<Glyph>) -> Self {
+        Self {
+            glyphs,
+            index_format: 0,
+        }
+    }
+
+    /// Encode the table, as the reference encoder does
+    pub fn encode(&self) -> Vec<u8> {
+        let num_glyphs = self.glyphs.len();
+        let mut n_contours = Vec::new();
+        let mut n_points = Vec::new();
+        let mut flags = Vec::new();
+        let mut glyph_stream = Vec::new();
+        let mut composites = Vec::new();
+        let mut bbox_bitmap = vec![0u8; ((num_glyphs + 31) >> 5) << 2];
+        let mut bboxes = Vec::new();
+        let mut instruction_stream = Vec::new();
+        let mut overlap_bitmap = vec![0u8; (num_glyphs + 7) >> 3];
+        let mut has_overlap = false;
+
+        let mut write_bbox = |i: usize, bbox: &[i16; 4]| {
+            bbox_bitmap[i >> 3] |= 0x80 >> (i & 7);
+            bbox.iter().for_each(|&v| bboxes.put_i16(v));
+        };
+        for (i, glyph) in self.glyphs.iter().enumerate() {
+            match glyph {
+                Glyph::Empty => n_contours.put_i16(0),
+                Glyph::Simple {
+                    contours,
+                    instructions,
+                    overlap,
+                    bbox,
+                } => {
+                    n_contours.put_i16(contours.len() as i16);
+                    if let Some(bbox) = bbox {
+                        write_bbox(i, bbox);
+                    }
+                    if *overlap {
+                        overlap_bitmap[i >> 3] |= 0x80 >> (i & 7);
+                        has_overlap = true;
+                    }
+                    let (mut last_x, mut last_y) = (0, 0);
+                    for contour in contours {
+                        put_255_u16(&mut n_points, contour.len() as u16);
+                        for point in contour {
+                            let (x, y) = (point.x as i32, point.y as i32);
+                            put_triplet(
+                                &mut flags,
+                                &mut glyph_stream,
+                                point.on_curve,
+                                x - last_x,
+                                y - last_y,
+                            );
+                            (last_x, last_y) = (x, y);
+                        }
+                    }
+                    put_255_u16(&mut glyph_stream, instructions.len() as u16);
+                    instruction_stream.extend_from_slice(instructions);
+                }
+                Glyph::Composite {
+                    components,
+                    instructions,
+                    bbox,
+                } => {
+                    n_contours.put_i16(-1);
+                    write_bbox(i, bbox);
+                    composites.extend_from_slice(components);
+                    if let Some(instructions) = instructions {
+                        put_255_u16(&mut glyph_stream, instructions.len() as u16);
+                        instruction_stream.extend_from_slice(instructions);
+                    }
+                }
+            }
+        }
+        bbox_bitmap.extend_from_slice(&bboxes);
+
+        let streams = [
+            &n_contours,
+            &n_points,
+            &flags,
+            &glyph_stream,
+            &composites,
+            &bbox_bitmap,
+            &instruction_stream,
+        ];
+        let mut out = Vec::new();
+        out.put_u16(0); // reserved
+        out.put_u16(has_overlap as u16); // optionFlags
+        out.put_u16(num_glyphs as u16);
+        out.put_u16(self.index_format);
+        for stream in streams {
+            out.put_u32(stream.len() as u32);
+        }
+        for stream in streams {
+            out.extend_from_slice(stream);
+        }
+        if has_overlap {
+            out.extend_from_slice(&overlap_bitmap);
+        }
+        out
+    }
+}
+
+/// Append a point as a flag byte and a coordinate triplet, using the smallest
+/// encoding (as `WriteTriplet` in the reference encoder)
+/// <https://www.w3.org/TR/WOFF2/#triplet_decoding>
+fn put_triplet(flags: &mut Vec<u8>, glyphs: &mut Vec<u8>, on_curve: bool, x: i32, y: i32) {
+    let (abs_x, abs_y) = (x.unsigned_abs(), y.unsigned_abs());
+    let on_curve_bit = if on_curve { 0 } else { 128 };
+    let x_sign_bit = (x >= 0) as u32;
+    let y_sign_bit = (y >= 0) as u32;
+    let xy_sign_bits = x_sign_bit + 2 * y_sign_bit;
+    let flag = if x == 0 && abs_y < 1280 {
+        glyphs.push(abs_y as u8);
+        ((abs_y & 0xf00) >> 7) + y_sign_bit
+    } else if y == 0 && abs_x < 1280 {
+        glyphs.push(abs_x as u8);
+        10 + ((abs_x & 0xf00) >> 7) + x_sign_bit
+    } else if abs_x < 65 && abs_y < 65 {
+        glyphs.push(((((abs_x - 1) & 0xf) << 4) | ((abs_y - 1) & 0xf)) as u8);
+        20 + ((abs_x - 1) & 0x30) + (((abs_y - 1) & 0x30) >> 2) + xy_sign_bits
+    } else if abs_x < 769 && abs_y < 769 {
+        glyphs.extend_from_slice(&[(abs_x - 1) as u8, (abs_y - 1) as u8]);
+        84 + 12 * (((abs_x - 1) & 0x300) >> 8) + (((abs_y - 1) & 0x300) >> 6) + xy_sign_bits
+    } else if abs_x < 4096 && abs_y < 4096 {
+        glyphs.extend_from_slice(&[
+            (abs_x >> 4) as u8,
+            (((abs_x & 0xf) << 4) | (abs_y >> 8)) as u8,
+            abs_y as u8,
+        ]);
+        120 + xy_sign_bits
+    } else {
+        glyphs.put_u16(abs_x as u16);
+        glyphs.put_u16(abs_y as u16);
+        124 + xy_sign_bits
+    };
+    flags.push(on_curve_bit + flag as u8);
+}
+
+/// A transformed hmtx table
+/// <https://www.w3.org/TR/WOFF2/#hmtx_table_format>
+#[derive(Clone, Debug)]
+pub struct HmtxTransform {
+    /// One per hMetric (numberOfHMetrics)
+    pub advance_widths: Vec<u16>,
+    /// The lsb of each hMetric, or `None` to elide them (they then equal the x_min of each glyph)
+    pub lsbs: Option<Vec<i16>>,
+    /// The leftSideBearing of each glyph after the hMetrics, or `None` to elide them
+    pub left_side_bearings: Option<Vec<i16>>,
+    /// Reserved flag bits 2-7
+    pub reserved_flags: u8,
+}
+
+impl HmtxTransform {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let flags = self.lsbs.is_none() as u8 | (self.left_side_bearings.is_none() as u8) << 1;
+        out.push(flags | self.reserved_flags << 2);
+        self.advance_widths.iter().for_each(|&v| out.put_u16(v));
+        for lsbs in [&self.lsbs, &self.left_side_bearings].into_iter().flatten() {
+            lsbs.iter().for_each(|&v| out.put_i16(v));
+        }
+        out
+    }
+}
+
+/// Builds a WOFF1 file
+#[derive(Clone)]
+pub struct Woff1Builder {
+    flavor: [u8; 4],
+    tables: Vec<Table>,
+    /// zlib-compress the tables (where that makes them smaller)
+    compress: bool,
+    /// Uncompressed extended metadata
+    metadata: Option<Vec<u8>>,
+}
+
+impl Woff1Builder {
+    /// A font with the given flavor (e.g. [`TRUETYPE`] or `*b"OTTO"`)
+    pub fn new(flavor: [u8; 4]) -> Self {
+        Self {
+            flavor,
+            tables: Vec::new(),
+            compress: false,
+            metadata: None,
+        }
+    }
+
+    /// A font with the flavor and tables of a (non-collection) sfnt, keeping
+    /// the checksums recorded in its table directory
+    pub fn from_sfnt(sfnt: &[u8]) -> Self {
+        let builder = Self::new(sfnt[0..4].try_into().unwrap());
+        sfnt_tables(sfnt, 0)
+            .into_iter()
+            .fold(builder, |builder, table| {
+                builder.table(Table::new(&table.tag, table.data).checksum(table.checksum))
+            })
+    }
+
+    /// Add a table. Tables are stored in the order they are added. Unless
+    /// [`Self::compressed`] is used, the data is stored as it is, and the
+    /// table's `orig_length` overrides the origLength field of its directory
+    /// entry.
+    pub fn table(mut self, table: Table) -> Self {
+        self.tables.push(table);
+        self
+    }
+
+    /// zlib-compress each table, unless that doesn't make it smaller
+    pub fn compressed(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    /// Add an extended metadata block (zlib-compressed)
+    pub fn metadata(mut self, metadata: impl Into<Vec<u8>>) -> Self {
+        self.metadata = Some(metadata.into());
+        self
+    }
+
+    /// Lay out the WOFF1 file
+    pub fn build(&self) -> Vec<u8> {
+        let mut directory = Vec::new();
+        let mut data = Vec::new();
+        let mut total_sfnt_size = 12 + 16 * self.tables.len();
+        let data_start = 44 + 20 * self.tables.len();
+        for table in &self.tables {
+            let compressed = Some(&table.data)
+                .filter(|_| self.compress)
+                .map(|data| miniz_oxide::deflate::compress_to_vec_zlib(data, 9))
+                .filter(|compressed| compressed.len() < table.data.len());
+            let stored = compressed.as_ref().unwrap_or(&table.data);
+            let length = table.data.len() as u32;
+            directory.extend_from_slice(&table.tag);
+            directory.put_u32((data_start + data.len()) as u32);
+            directory.put_u32(stored.len() as u32);
+            directory.put_u32(table.orig_length.unwrap_or(length));
+            directory.put_u32(
+                table
+                    .checksum
+                    .unwrap_or_else(|| crate::compute_checksum(&table.data)),
+            );
+            data.extend_from_slice(stored);
+            data.resize(crate::Round4!(data.len()), 0);
+            total_sfnt_size += crate::Round4!(table.data.len());
+        }
+
+        let mut meta = (0, 0, 0);
+        if let Some(metadata) = &self.metadata {
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(metadata, 9);
+            meta = (data_start + data.len(), compressed.len(), metadata.len());
+            data.extend_from_slice(&compressed);
+        }
+
+        let mut woff = Vec::new();
+        woff.put_slice(b"wOFF");
+        woff.put_slice(&self.flavor);
+        woff.put_u32((data_start + data.len()) as u32);
+        woff.put_u16(self.tables.len() as u16);
+        woff.put_u16(0); // reserved
+        woff.put_u32(total_sfnt_size as u32);
+        woff.put_u16(1); // majorVersion
+        woff.put_u16(0); // minorVersion
+        woff.put_u32(meta.0 as u32);
+        woff.put_u32(meta.1 as u32);
+        woff.put_u32(meta.2 as u32);
+        woff.put_bytes(0, 8); // no private data
+        woff.extend_from_slice(&directory);
+        woff.extend_from_slice(&data);
+        woff
+    }
+}
+
+/// A WOFF1 file with the given tables, stored uncompressed (see
+/// [`Woff1Builder::table`])
+pub fn woff1(flavor: [u8; 4], tables: &[Table]) -> Vec<u8> {
+    let builder = Woff1Builder::new(flavor);
+    tables
+        .iter()
+        .cloned()
+        .fold(builder, Woff1Builder::table)
+        .build()
+}
+
+/// The zlib decompressor for files built by [`woff1`], whose tables are never compressed
+pub struct NoCompression;
+
+impl crate::ZlibDecompressor for NoCompression {
+    fn decompress(&mut self, _: &[u8], _: &mut [u8]) -> Result<(), crate::DecompressError> {
+        unreachable!("no compressed tables")
+    }
+}
+
+/// A decompressor which always runs out of memory
+pub struct OutOfMemory;
+
+impl crate::ZlibDecompressor for OutOfMemory {
+    fn decompress(&mut self, _: &[u8], _: &mut [u8]) -> Result<(), crate::DecompressError> {
+        Err(crate::DecompressError::OutOfMemory)
+    }
+}
+
+impl crate::BrotliDecompressor for OutOfMemory {
+    fn decompress(&mut self, _: &[u8], _: &mut [u8]) -> Result<(), crate::DecompressError> {
+        Err(crate::DecompressError::OutOfMemory)
+    }
+}
+
+/// A minimal head table, with the given indexToLocFormat
+pub fn head_table(index_to_loc_format: u16) -> Vec<u8> {
+    let mut head = vec![0; 54];
+    head[0..4].copy_from_slice(&[0, 1, 0, 0]); // version
+    head[12..16].copy_from_slice(&0x5F0F3CF5u32.to_be_bytes()); // magicNumber
+    head[50..52].copy_from_slice(&index_to_loc_format.to_be_bytes());
+    head
+}
+
+/// A minimal hhea table, with the given numberOfHMetrics
+pub fn hhea_table(num_hmetrics: u16) -> Vec<u8> {
+    let mut hhea = vec![0; 36];
+    hhea[0..4].copy_from_slice(&[0, 1, 0, 0]); // version
+    hhea[34..36].copy_from_slice(&num_hmetrics.to_be_bytes());
+    hhea
+}
+
+/// A table read back from a decoded sfnt (or one font of a collection)
+pub struct SfntTable<'a> {
+    pub tag: [u8; 4],
+    pub checksum: u32,
+    pub data: &'a [u8],
+}
+
+/// The tables of the font whose table directory is at `offset` in `sfnt`
+pub fn sfnt_tables(sfnt: &[u8], offset: usize) -> Vec<SfntTable<'_>> {
+    let u32_at = |at: usize| u32::from_be_bytes(sfnt[at..at + 4].try_into().unwrap());
+    let num_tables = u16::from_be_bytes(sfnt[offset + 4..offset + 6].try_into().unwrap());
+    (0..num_tables as usize)
+        .map(|i| {
+            let record = offset + 12 + 16 * i;
+            let (start, length) = (u32_at(record + 8) as usize, u32_at(record + 12) as usize);
+            SfntTable {
+                tag: sfnt[record..record + 4].try_into().unwrap(),
+                checksum: u32_at(record + 4),
+                data: &sfnt[start..start + length],
+            }
+        })
+        .collect()
+}
+
+/// Parse a reconstructed simple glyph (from the glyf table) into its bbox,
+/// contours and instructions, and whether OVERLAP_SIMPLE is set
+pub fn parse_simple_glyph(glyph: &[u8]) -> ([i16; 4], Vec<Vec<GlyphPoint>>, Vec<u8>, bool) {
+    let i16_at = |at: usize| i16::from_be_bytes(glyph[at..at + 2].try_into().unwrap());
+    let n_contours = i16_at(0) as usize;
+    let bbox = [i16_at(2), i16_at(4), i16_at(6), i16_at(8)];
+    let end_points: Vec<usize> = (0..n_contours)
+        .map(|i| i16_at(10 + 2 * i) as u16 as usize)
+        .collect();
+    let num_points = end_points.last().map_or(0, |&end| end + 1);
+    let instructions_start = 10 + 2 * n_contours;
+    let instruction_length = i16_at(instructions_start) as u16 as usize;
+    let instructions = glyph[instructions_start + 2..][..instruction_length].to_vec();
+
+    let mut pos = instructions_start + 2 + instruction_length;
+    let mut flags = Vec::with_capacity(num_points);
+    while flags.len() < num_points {
+        let flag = glyph[pos];
+        pos += 1;
+        flags.push(flag);
+        if flag & 8 != 0 {
+            let repeat = glyph[pos];
+            pos += 1;
+            flags.extend((0..repeat).map(|_| flag));
+        }
+    }
+    let mut read_coordinates = |short_bit: u8, same_bit: u8| -> Vec<i16> {
+        let mut value = 0i16;
+        flags
+            .iter()
+            .map(|&flag| {
+                if flag & short_bit != 0 {
+                    let delta = glyph[pos] as i16;
+                    pos += 1;
+                    value += if flag & same_bit != 0 { delta } else { -delta };
+                } else if flag & same_bit == 0 {
+                    value += i16_at(pos);
+                    pos += 2;
+                }
+                value
+            })
+            .collect()
+    };
+    let xs = read_coordinates(2, 16);
+    let ys = read_coordinates(4, 32);
+
+    let mut contours = Vec::new();
+    let mut start = 0;
+    for end in end_points {
+        contours.push(
+            (start..=end)
+                .map(|i| GlyphPoint {
+                    x: xs[i],
+                    y: ys[i],
+                    on_curve: flags[i] & 1 != 0,
+                })
+                .collect(),
+        );
+        start = end + 1;
+    }
+    let overlap = flags.first().is_some_and(|flag| flag & 0x40 != 0);
+    (bbox, contours, instructions, overlap)
+}
@@ -11,11 +11,15 @@ use alloc::vec::Vec;
 use bytes::Buf;
 
 use crate::error::{WuffErr, bail, bail_if};
+use crate::fallible::FallibleVec;
 
 pub trait BufVariableExt {
     fn try_get_variable_255_u16(&mut self) -> Result<u16, WuffErr>;
     fn try_get_variable_128_u32(&mut self) -> Result<u32, WuffErr>;
     fn try_read_bytes_into(&mut self, n: usize, buf: &mut Vec<u8>) -> Result<(), WuffErr>;
+    /// Like [`Buf::advance`], but returning an error (rather than panicking) if there are
+    /// fewer than `n` bytes remaining
+    fn try_advance(&mut self, n: usize) -> Result<(), WuffErr>;
 }
 
 impl<T: bytes::Buf> BufVariableExt for T {
@@ -28,55 +32,19 @@ impl<T: bytes::Buf> BufVariableExt for T {
     }
 
     fn try_read_bytes_into(&mut self, n: usize, buf: &mut Vec<u8>) -> Result<(), WuffErr> {
+        bail_if!(self.remaining() < n);
         let orig_len = buf.len();
-        buf.resize(orig_len + n, 0);
-        self.try_copy_to_slice(&mut buf[orig_len..])?;
+        buf.try_resize(orig_len.checked_add(n).ok_or(WuffErr::GenericError)?, 0)?;
+        let dst = buf.get_mut(orig_len..).ok_or(WuffErr::GenericError)?;
+        self.try_copy_to_slice(dst)?;
         Ok(())
     }
-}
-
-fn Size255UShort(value: u16) -> usize {
-    if value < 253 {
-        1
-    } else if value < 762 {
-        2
-    } else {
-        3
-    }
-}
-
-// Last byte is the length
-#[derive(Copy, Clone)]
-struct UShort255([u8; 4]);
-
-impl<'a> IntoIterator for &'a UShort255 {
-    type Item = u8;
-    type IntoIter = core::iter::Copied<core::slice::Iter<'a, u8>>;
-
-    #[inline(always)]
-    fn into_iter(self) -> Self::IntoIter {
-        let len = self.0[3] as usize;
-        self.0[0..len].iter().copied()
-    }
-}
-
-fn Write255UShort(value: i32) -> UShort255 {
-    if value < 253 {
-        UShort255([value as u8, 0, 0, 1])
-    } else if value < 506 {
-        UShort255([255, (value - 253) as u8, 0, 2])
-    } else if value < 762 {
-        UShort255([254, (value - 506) as u8, 0, 2])
-    } else {
-        UShort255([253, (value >> 8) as u8, (value & 0xff) as u8, 3])
-    }
-}
 
-pub(crate) fn Store255UShort(val: i32, offset: &mut usize, dst: &mut [u8]) {
-    let packed = Write255UShort(val);
-    for packed_byte in packed.into_iter() {
-        dst[*offset] = packed_byte;
-        *offset += 1;
+    fn try_advance(&mut self, n: usize) -> Result<(), WuffErr> {
+        bail_if!(self.remaining() < n);
+        #[allow(clippy::disallowed_methods)] // (checked above)
+        self.advance(n);
+        Ok(())
     }
 }
 
@@ -90,8 +58,9 @@ pub(crate) fn Read255UShort(buf: &mut impl Buf) -> Result<u32, WuffErr> {
     let code = buf.try_get_u8()?;
     match code {
         kWordCode => Ok(buf.try_get_u16()? as u32),
-        kOneMoreByteCode1 => Ok(buf.try_get_u8()? as u32 + kLowestUCode),
-        kOneMoreByteCode2 => Ok(buf.try_get_u8()? as u32 + kLowestUCode * 2),
+        // (a u8 plus at most 506, so these can't wrap)
+        kOneMoreByteCode1 => Ok((buf.try_get_u8()? as u32).wrapping_add(kLowestUCode)),
+        kOneMoreByteCode2 => Ok((buf.try_get_u8()? as u32).wrapping_add(kLowestUCode * 2)),
         _ => Ok(code as u32),
     }
 }
@@ -114,24 +83,3 @@ pub(crate) fn ReadBase128(buf: &mut impl Buf) -> Result<u32, WuffErr> {
     // Make sure not to exceed the size bound
     bail!();
 }
-
-fn Base128Size(mut n: usize) -> usize {
-    let mut size: usize = 1;
-    while n < 128 {
-        n >>= 7;
-        size += 1;
-    }
-    size
-}
-
-pub(crate) fn StoreBase128(len: usize, offset: &mut usize, dst: &mut [u8]) {
-    let size: usize = Base128Size(len);
-    for i in 0..size {
-        let mut b: u8 = ((len >> (7 * (size - i - 1))) & 0x7f) as u8;
-        if i < size - 1 {
-            b |= 0x80;
-        }
-        dst[*offset] = b;
-        *offset += 1;
-    }
-}
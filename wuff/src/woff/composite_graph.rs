@@ -0,0 +1,267 @@
+//! Validating the references between the composite glyphs of a reconstructed glyf table.
+//!
+//! Components are copied from the transformed glyf table as they are, so a composite glyph
+//! can reference a glyph which doesn't exist, or (directly or through other composites)
+//! itself. Rasterizers which follow such references may crash or recurse forever.
+
+use alloc::vec::Vec;
+
+use crate::diagnostics::Diagnostics;
+use crate::error::WuffErr;
+use crate::fallible::FallibleVec;
+use crate::recovery::Repair;
+use crate::woff::glyf_decoder::{GlyfAndLocaData, read_component_ids};
+
+/// The deepest nesting of composite glyphs accepted, whatever maxp says. Real fonts rarely
+/// nest composites more than a few levels deep.
+const MAX_COMPONENT_DEPTH: u16 = 32;
+
+/// (depth of a glyph whose components haven't been visited)
+const UNVISITED: u16 = u16::MAX;
+/// (depth of a glyph whose components are being visited)
+const IN_PROGRESS: u16 = u16::MAX - 1;
+
+/// The maxComponentElements and maxComponentDepth fields of a (version 1.0) maxp table
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct ComponentLimits {
+    pub max_component_elements: Option<u16>,
+    pub max_component_depth: Option<u16>,
+}
+
+/// A composite glyph being visited
+struct Visit {
+    glyph: u16,
+    /// The index in `component_ids` of the next component to visit
+    next: usize,
+    /// The depth of the glyph's deepest component visited so far, plus one
+    depth: u16,
+}
+
+/// Check that every component of a composite glyph in `glyf` is a glyph of the font, that no
+/// composite glyph contains itself, and that composites aren't nested more deeply (or with
+/// more components) than `limits` allow. Each problem is a [`Repair`] (which rejects the font
+/// in strict mode), but the glyphs are left as they are.
+pub(crate) fn check_composite_glyphs(
+    glyf: &GlyfAndLocaData,
+    limits: ComponentLimits,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), WuffErr> {
+    let num_glyphs = glyf.num_glyphs;
+
+    // The components of each glyph are component_ids[starts[glyph]..starts[glyph + 1]]
+    let mut component_ids: Vec<u16> = Vec::new();
+    let mut starts: Vec<usize> = Vec::new();
+    starts.try_reserve((num_glyphs as usize).saturating_add(1))?;
+    for glyph in 0..num_glyphs {
+        starts.push(component_ids.len());
+        read_component_ids(glyf.glyph(glyph).unwrap_or_default(), &mut component_ids)?;
+    }
+    starts.push(component_ids.len());
+    let components = |glyph: u16| {
+        let start = starts.get(glyph as usize).copied().unwrap_or_default();
+        let end = starts.get((glyph as usize).saturating_add(1));
+        let end = end.copied().unwrap_or_default();
+        start..end
+    };
+
+    // The maxp limits don't apply beyond the hard limit
+    let max_depth = (limits.max_component_depth).map_or(MAX_COMPONENT_DEPTH, |max_depth| {
+        max_depth.min(MAX_COMPONENT_DEPTH)
+    });
+
+    // Visit the glyphs depth first, recording the depth of each glyph: 0 for simple (and
+    // empty) glyphs, and one more than their deepest component for composite glyphs
+    let mut depths: Vec<u16> = Vec::new();
+    depths.try_resize(num_glyphs as usize, UNVISITED)?;
+    let mut stack: Vec<Visit> = Vec::new();
+    for root in 0..num_glyphs {
+        if depths.get(root as usize) != Some(&UNVISITED) {
+            continue;
+        }
+        stack.try_push(Visit {
+            glyph: root,
+            next: components(root).start,
+            depth: 0,
+        })?;
+        *depths.get_mut(root as usize).ok_or(WuffErr::GenericError)? = IN_PROGRESS;
+
+        while let Some(visit) = stack.last_mut() {
+            let glyph = visit.glyph;
+            if components(glyph).contains(&visit.next) {
+                let component = *component_ids.get(visit.next).ok_or(WuffErr::GenericError)?;
+                visit.next = visit.next.saturating_add(1);
+                match depths.get(component as usize).copied() {
+                    None => diagnostics.repair(Repair::ComponentOutOfRange { glyph, component })?,
+                    Some(IN_PROGRESS) => {
+                        diagnostics.repair(Repair::CompositeCycle { glyph, component })?
+                    }
+                    Some(UNVISITED) => {
+                        if let Some(depth) = depths.get_mut(component as usize) {
+                            *depth = IN_PROGRESS;
+                        }
+                        stack.try_push(Visit {
+                            glyph: component,
+                            next: components(component).start,
+                            depth: 0,
+                        })?;
+                    }
+                    Some(depth) => visit.depth = visit.depth.max(depth.saturating_add(1)),
+                }
+                continue;
+            }
+
+            // All the components of `glyph` have been visited
+            let Visit { depth, .. } = stack.pop().ok_or(WuffErr::GenericError)?;
+            let num_components = components(glyph).len();
+            let depth = if num_components > 0 { depth.max(1) } else { 0 };
+            *depths
+                .get_mut(glyph as usize)
+                .ok_or(WuffErr::GenericError)? = depth;
+            if let Some(parent) = stack.last_mut() {
+                parent.depth = parent.depth.max(depth.saturating_add(1));
+            }
+
+            // (only the shallowest glyphs which are too deep are reported, rather than
+            // every glyph containing them too)
+            if depth == max_depth.saturating_add(1) {
+                diagnostics.repair(Repair::CompositeTooDeep { glyph, max_depth })?;
+            }
+            if let Some(max_elements) = limits.max_component_elements {
+                if num_components > max_elements as usize {
+                    diagnostics.repair(Repair::TooManyComponents {
+                        glyph,
+                        num_components: num_components as u16,
+                        max_elements,
+                    })?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{GlyfTransform, Glyph, MORE_COMPONENTS, WE_HAVE_A_SCALE, component};
+    use crate::woff::glyf_decoder::tranform_glyf_table;
+    use crate::{DecodeMode, DecodeOptions};
+    use alloc::vec;
+
+    /// A composite glyph with the given components
+    fn composite(ids: &[u16]) -> Glyph {
+        let last = ids.len() - 1;
+        let components = (ids.iter().enumerate())
+            .flat_map(|(i, &id)| {
+                let more = if i < last { MORE_COMPONENTS } else { 0 };
+                component(more | WE_HAVE_A_SCALE, id, 0, 0)
+            })
+            .collect();
+        Glyph::Composite {
+            components,
+            instructions: None,
+            bbox: [0, 0, 10, 10],
+        }
+    }
+
+    /// The repairs needed for a font with the given glyphs
+    fn check(glyphs: Vec<Glyph>, limits: ComponentLimits) -> Vec<Repair> {
+        let glyf = tranform_glyf_table(&GlyfTransform::new(glyphs).encode()).unwrap();
+        let mut strict = Diagnostics::strict();
+        let strict_result = check_composite_glyphs(&glyf, limits, &mut strict);
+        let mut diagnostics = Diagnostics::new(DecodeOptions::default().mode(DecodeMode::Recovery));
+        check_composite_glyphs(&glyf, limits, &mut diagnostics).unwrap();
+        let repairs = diagnostics.into_decoded(Vec::new()).repairs;
+        // Strict mode rejects exactly the fonts needing repairs
+        assert_eq!(strict_result.is_ok(), repairs.is_empty());
+        repairs
+    }
+
+    fn simple() -> Glyph {
+        Glyph::simple(&[&[(0, 0, true), (10, 10, true)]])
+    }
+
+    #[test]
+    fn accepts_valid_composites() {
+        let glyphs = vec![
+            Glyph::Empty,
+            simple(),
+            composite(&[1, 1]),
+            composite(&[2, 0]),
+            composite(&[3, 1]),
+        ];
+        assert_eq!(check(glyphs.clone(), ComponentLimits::default()), []);
+        // Glyph 4 is 3 levels deep
+        let limits = ComponentLimits {
+            max_component_elements: Some(2),
+            max_component_depth: Some(3),
+        };
+        assert_eq!(check(glyphs, limits), []);
+    }
+
+    #[test]
+    fn reports_invalid_references() {
+        let glyphs = vec![simple(), composite(&[0, 5]), composite(&[2])];
+        assert_eq!(
+            check(glyphs, ComponentLimits::default()),
+            [
+                Repair::ComponentOutOfRange {
+                    glyph: 1,
+                    component: 5
+                },
+                Repair::CompositeCycle {
+                    glyph: 2,
+                    component: 2
+                },
+            ]
+        );
+
+        // A cycle through several glyphs
+        let glyphs = vec![composite(&[2]), composite(&[0]), composite(&[1])];
+        assert_eq!(
+            check(glyphs, ComponentLimits::default()),
+            [Repair::CompositeCycle {
+                glyph: 1,
+                component: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_exceeded_limits() {
+        // Each glyph contains the one before it
+        let mut glyphs = vec![simple()];
+        glyphs.extend((0..40).map(|i| composite(&[i, i])));
+        let limits = ComponentLimits {
+            max_component_elements: Some(1),
+            max_component_depth: Some(4),
+        };
+        let too_many = (1..=40).map(|glyph| Repair::TooManyComponents {
+            glyph,
+            num_components: 2,
+            max_elements: 1,
+        });
+        let mut expected: Vec<_> = too_many.collect();
+        expected.insert(
+            4,
+            Repair::CompositeTooDeep {
+                glyph: 5,
+                max_depth: 4,
+            },
+        );
+        assert_eq!(check(glyphs.clone(), limits), expected);
+
+        // Nesting deeper than MAX_COMPONENT_DEPTH is reported whatever maxp says
+        let limits = ComponentLimits {
+            max_component_elements: None,
+            max_component_depth: Some(100),
+        };
+        assert_eq!(
+            check(glyphs, limits),
+            [Repair::CompositeTooDeep {
+                glyph: 33,
+                max_depth: MAX_COMPONENT_DEPTH
+            }]
+        );
+    }
+}
@@ -1,10 +1,11 @@
-use alloc::{vec, vec::Vec};
+use alloc::vec::Vec;
 
 use bytes::{Buf, BufMut};
 
 use crate::{
-    Point, Round4, compute_checksum,
-    error::{WuffErr, bail_if, bail_with_msg_if, u32_will_overflow, usize_will_overflow},
+    OutputProfile, Point, Round4, compute_checksum,
+    error::{WuffErr, bail, bail_if, bail_with_msg_if},
+    fallible::FallibleVec,
     variable_length::BufVariableExt as _,
 };
 
@@ -23,20 +24,34 @@ const FLAG_OVERLAP_SIMPLE_BITMAP: u16 = 1 << 0;
 const DEFAULT_GLYPH_BUF_SIZE: usize = 5120;
 
 const FLAG_ARG_1_AND_2_ARE_WORDS: u16 = 1 << 0;
+const FLAG_ARGS_ARE_XY_VALUES: u16 = 1 << 1;
+const FLAG_ROUND_XY_TO_GRID: u16 = 1 << 2;
 const FLAG_WE_HAVE_A_SCALE: u16 = 1 << 3;
+const FLAG_NON_OVERLAPPING: u16 = 1 << 4;
 const FLAG_MORE_COMPONENTS: u16 = 1 << 5;
 const FLAG_WE_HAVE_AN_X_AND_Y_SCALE: u16 = 1 << 6;
 const FLAG_WE_HAVE_A_TWO_BY_TWO: u16 = 1 << 7;
 const FLAG_WE_HAVE_INSTRUCTIONS: u16 = 1 << 8;
+const FLAG_USE_MY_METRICS: u16 = 1 << 9;
+const FLAG_OVERLAP_COMPOUND: u16 = 1 << 10;
+const FLAG_SCALED_COMPONENT_OFFSET: u16 = 1 << 11;
+const FLAG_UNSCALED_COMPONENT_OFFSET: u16 = 1 << 12;
+
+/// Short loca offsets (which are stored divided by 2) must be below this
+const SHORT_LOCA_LIMIT: usize = 0x20000;
 
 const END_PTS_OF_CONTOURS_OFFSET: usize = 10;
 const COMPOSITE_GLYPH_BEGIN: usize = 10;
 
+#[derive(Default)]
 pub struct GlyfAndLocaData {
     /// The number of glyphs in the glyf table
     pub num_glyphs: u16,
-    /// loca index format
+    /// The index format of the reconstructed loca table. This is the index format recorded
+    /// in the transformed glyf table unless the [`OutputProfile`] chose a different one.
     pub index_format: u16,
+    /// The index format recorded in the transformed glyf table
+    pub declared_index_format: u16,
     /// The x_min of the bounding box of each glyph. Used to reconstruct hmtx table
     pub x_mins: Vec<i16>,
     /// Encoded Open Type "glyf" table
@@ -47,6 +62,44 @@ pub struct GlyfAndLocaData {
     pub loca_table: Vec<u8>,
     /// Checksum for "loca" table
     pub loca_checksum: u32,
+    /// Buffers used while decoding, kept for reuse
+    scratch: GlyfScratch,
+}
+
+impl GlyfAndLocaData {
+    /// The reconstructed data of glyph `index` (including any padding)
+    pub fn glyph(&self, index: u16) -> Option<&[u8]> {
+        let index = index as usize;
+        let offsets = self.scratch.loca_values.get(index..)?;
+        let (&start, &end) = (offsets.first()?, offsets.get(1)?);
+        self.glyf_table.get(start as usize..end as usize)
+    }
+}
+
+/// Working memory for decoding a glyf table
+#[derive(Default)]
+struct GlyfScratch {
+    /// The glyph being decoded
+    glyph_buf: Vec<u8>,
+    /// The simple glyph being decoded
+    points: PointBuffers,
+    /// The offset of each glyph in the glyf table
+    loca_values: Vec<u32>,
+    /// The offsets of the bytes added to pad glyphs to an even length
+    padding: Vec<u32>,
+}
+
+/// Working memory for decoding a simple glyph
+#[derive(Default)]
+struct PointBuffers {
+    /// The number of points in each contour
+    n_points: Vec<u16>,
+    /// The points of the glyph
+    points: Vec<Point>,
+    /// The flag of each point
+    flags: Vec<u8>,
+    /// The encodings of each point considered by [`compact_point_flags`]
+    encodings: Vec<PointEncodings>,
 }
 
 /// Decode a WOFF2 transformed glyf table
@@ -56,6 +109,71 @@ pub(crate) fn tranform_glyf_table(data: &[u8]) -> Result<GlyfAndLocaData, WuffEr
     GlyfDecoder::new(data)?.transform()
 }
 
+/// Like [`tranform_glyf_table`], but reusing the allocations of `output`
+/// (and reconstructing the tables as `profile` says)
+pub(crate) fn tranform_glyf_table_into(
+    data: &[u8],
+    profile: OutputProfile,
+    output: &mut GlyfAndLocaData,
+) -> Result<(), WuffErr> {
+    GlyfDecoder::new(data)?
+        .with_profile(profile)
+        .transform_into(output)
+}
+
+/// The header of a transformed glyf table
+///
+/// <https://www.w3.org/TR/WOFF2/#glyf_table_format>
+pub(crate) struct GlyfHeader {
+    pub flags: u16,
+    pub num_glyphs: u16,
+    pub index_format: u16,
+    /// The sizes of the nContour, nPoints, flag, glyph, composite, bbox and instruction
+    /// streams, in that order
+    pub stream_sizes: [u32; NUM_SUB_STREAMS],
+}
+
+impl GlyfHeader {
+    /// The size of the header in bytes
+    pub const SIZE: usize = (2 + NUM_SUB_STREAMS) * 4;
+
+    pub fn parse(input: &mut impl Buf) -> Result<Self, WuffErr> {
+        let _: u16 = input.try_get_u16()?; // first 2 bytes are reserved
+        let flags = input.try_get_u16()?;
+        let num_glyphs = input.try_get_u16()?;
+        let index_format = input.try_get_u16()?;
+        let mut stream_sizes = [0; NUM_SUB_STREAMS];
+        for size in &mut stream_sizes {
+            *size = input.try_get_u32()?;
+        }
+        Ok(Self {
+            flags,
+            num_glyphs,
+            index_format,
+            stream_sizes,
+        })
+    }
+
+    /// An upper bound on the size of the decoded glyf table
+    #[allow(clippy::arithmetic_side_effects)] // (u16s and u32s, which can't overflow a u64 here)
+    pub fn max_glyf_length(&self) -> u64 {
+        let [_, n_points, flags, _, composite, _, instructions] = self.stream_sizes.map(u64::from);
+        // Each glyph has a 10 byte header, a 2 byte instructionLength and up to 3 bytes of
+        // padding. Each contour takes at least a byte of the nPoints stream and adds a 2 byte
+        // endPtsOfContours entry. Each point takes a byte of the flag stream and adds at most
+        // a byte of flags and 2 bytes for each coordinate. Composite glyph records and
+        // instructions are copied as-is.
+        self.num_glyphs as u64 * 15 + 2 * n_points + 5 * flags + composite + instructions
+    }
+
+    /// The size of the decoded loca table
+    #[allow(clippy::arithmetic_side_effects)] // (a u16, which can't overflow a u64 here)
+    pub fn loca_length(&self) -> u64 {
+        let offset_size = if self.index_format != 0 { 4 } else { 2 };
+        (self.num_glyphs as u64 + 1) * offset_size
+    }
+}
+
 pub struct GlyfDecoder<'a> {
     // State
     n_contour_stream: &'a [u8],
@@ -67,58 +185,63 @@ pub struct GlyfDecoder<'a> {
     bbox_stream: &'a [u8],
     instruction_stream: &'a [u8],
     overlap_bitmap: Option<&'a [u8]>,
-    glyph_buf: Vec<u8>,
 
     // Output data
     num_glyphs: u16,
     index_format: u16,
+    profile: OutputProfile,
 }
 
 impl GlyfDecoder<'_> {
     pub fn new<'a>(data: &'a [u8]) -> Result<GlyfDecoder<'a>, WuffErr> {
         let mut input = data;
-        let _: u16 = input.try_get_u16()?; // first 2 bytes are reserved
-        let flags: u16 = input.try_get_u16()?;
-        let has_overlap_bitmap: bool = (flags & FLAG_OVERLAP_SIMPLE_BITMAP) != 0;
-        let num_glyphs = input.try_get_u16()?;
-        let index_format = input.try_get_u16()?;
-
-        let mut offset: usize = (2 + NUM_SUB_STREAMS) * 4;
-        bail_if!(offset > data.len());
-
-        // Invariant from here on: data_size >= offset
-        let mut read_stream = || {
-            let substream_size: usize = input.try_get_u32()? as usize;
-            bail_if!(substream_size > data.len() - offset);
-            let substream_range = offset..(offset + substream_size);
-            offset += substream_size;
-
-            Ok(&data[substream_range])
+        let header = GlyfHeader::parse(&mut input)?;
+        let has_overlap_bitmap: bool = (header.flags & FLAG_OVERLAP_SIMPLE_BITMAP) != 0;
+        let num_glyphs = header.num_glyphs;
+        let index_format = header.index_format;
+
+        // The data after the streams read so far
+        let mut rest = data.get(GlyfHeader::SIZE..).ok_or(WuffErr::GenericError)?;
+        let mut read_stream = |substream_size: u32| {
+            let split = rest.split_at_checked(substream_size as usize);
+            split
+                .ok_or(WuffErr::GenericError)
+                .map(|(substream, after)| {
+                    rest = after;
+                    substream
+                })
         };
 
-        let n_contour_stream = read_stream()?;
-        let n_points_stream = read_stream()?;
-        let flag_stream = read_stream()?;
-        let glyph_stream = read_stream()?;
-        let composite_stream = read_stream()?;
-        let unsplit_bbox_stream = read_stream()?;
-        let instruction_stream = read_stream()?;
-
-        // Safe because num_glyphs is bounded
+        let [
+            n_contour_size,
+            n_points_size,
+            flag_size,
+            glyph_size,
+            composite_size,
+            bbox_size,
+            instruction_size,
+        ] = header.stream_sizes;
+        let n_contour_stream = read_stream(n_contour_size)?;
+        let n_points_stream = read_stream(n_points_size)?;
+        let flag_stream = read_stream(flag_size)?;
+        let glyph_stream = read_stream(glyph_size)?;
+        let composite_stream = read_stream(composite_size)?;
+        let unsplit_bbox_stream = read_stream(bbox_size)?;
+        let instruction_stream = read_stream(instruction_size)?;
+
+        #[allow(clippy::arithmetic_side_effects)] // (num_glyphs is a u16)
         let bitmap_length: usize = ((num_glyphs as usize + 31) >> 5) << 2;
-        bail_if!(bitmap_length > unsplit_bbox_stream.len());
-        let (bbox_bitmap, bbox_stream) = unsplit_bbox_stream.split_at(bitmap_length);
+        let (bbox_bitmap, bbox_stream) =
+            (unsplit_bbox_stream.split_at_checked(bitmap_length)).ok_or(WuffErr::GenericError)?;
 
         let mut overlap_bitmap: Option<&[u8]> = None;
         if has_overlap_bitmap {
+            #[allow(clippy::arithmetic_side_effects)] // (num_glyphs is a u16)
             let overlap_bitmap_length = (num_glyphs as usize + 7) >> 3;
-            bail_if!(overlap_bitmap_length > data.len() - offset);
-            overlap_bitmap = Some(&data[offset..(offset + (overlap_bitmap_length))]);
+            bail_if!(overlap_bitmap_length > rest.len());
+            overlap_bitmap = rest.get(..overlap_bitmap_length);
         }
 
-        // Scratch buffer to decode glyphs int.
-        let glyph_buf: Vec<u8> = Vec::with_capacity(DEFAULT_GLYPH_BUF_SIZE);
-
         Ok(GlyfDecoder {
             n_contour_stream,
             n_points_stream,
@@ -129,78 +252,139 @@ impl GlyfDecoder<'_> {
             bbox_stream,
             instruction_stream,
             overlap_bitmap,
-            glyph_buf,
             num_glyphs,
             index_format,
+            profile: OutputProfile::ReferenceCompatible,
         })
     }
 
-    pub fn transform(mut self) -> Result<GlyfAndLocaData, WuffErr> {
+    /// Reconstruct the tables as `profile` says (rather than as the reference decoder does)
+    pub fn with_profile(mut self, profile: OutputProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn transform(self) -> Result<GlyfAndLocaData, WuffErr> {
+        let mut output = GlyfAndLocaData::default();
+        self.transform_into(&mut output)?;
+        Ok(output)
+    }
+
+    /// Like [`Self::transform`], but reusing the allocations of `output`
+    pub fn transform_into(mut self, output: &mut GlyfAndLocaData) -> Result<(), WuffErr> {
+        let GlyfAndLocaData {
+            x_mins,
+            glyf_table,
+            loca_table,
+            scratch,
+            ..
+        } = output;
+
         // Setup state
-        let mut glyf_checksum: u32 = 0;
-        let mut glyf_table: Vec<u8> = Vec::with_capacity(self.num_glyphs as usize * 12);
-        let mut loca_values: Vec<u32> = Vec::with_capacity(self.num_glyphs as usize + 1);
-        let mut x_mins: Vec<i16> = vec![0; self.num_glyphs as usize];
+        glyf_table.clear();
+        glyf_table.try_reserve((self.num_glyphs as usize).saturating_mul(12))?;
+        let loca_values = &mut scratch.loca_values;
+        loca_values.clear();
+        loca_values.try_reserve((self.num_glyphs as usize).saturating_add(1))?;
+        let padding = &mut scratch.padding;
+        padding.clear();
+        x_mins.clear();
+        x_mins.try_resize(self.num_glyphs as usize, 0)?;
+        // Scratch buffer to decode glyphs into
+        let glyph_buf = &mut scratch.glyph_buf;
+        glyph_buf.clear();
+        glyph_buf.try_reserve(DEFAULT_GLYPH_BUF_SIZE)?;
 
         // Iterate over each glyph
-        for i in 0..(self.num_glyphs as usize) {
-            loca_values.push(glyf_table.len() as u32);
+        for (i, x_min) in x_mins.iter_mut().enumerate() {
+            loca_values.try_push(glyf_table.len() as u32)?;
 
             let n_contours: u16 = self.n_contour_stream.try_get_u16()?;
-            let glyph_has_bbox = (self.bbox_bitmap[i >> 3] & (0x80 >> (i & 7))) != 0;
+            let glyph_has_bbox = is_bit_set(self.bbox_bitmap, i);
 
-            self.glyph_buf.clear();
+            glyph_buf.clear();
             if n_contours == 0xFFFF {
                 // composite glyphs must have an explicit bbox
                 bail_if!(!glyph_has_bbox);
-                self.parse_composite_glyph()?;
+                self.parse_composite_glyph(glyph_buf)?;
             } else if n_contours > 0 {
                 // Note: while this look similar to the glyph_has_bbox code above, it's indexing into a different bitmap
                 let has_overlap_bit: bool = self
                     .overlap_bitmap
-                    .is_some_and(|bitmap| (bitmap[i >> 3] & (0x80 >> (i & 7))) != 0);
-                self.parse_simple_glyph(n_contours, glyph_has_bbox, has_overlap_bit)?;
+                    .is_some_and(|bitmap| is_bit_set(bitmap, i));
+                self.parse_simple_glyph(
+                    n_contours,
+                    glyph_has_bbox,
+                    has_overlap_bit,
+                    glyph_buf,
+                    &mut scratch.points,
+                )?;
             } else {
                 // n_contours == 0; empty glyph. Must NOT have a bbox.
                 bail_with_msg_if!(glyph_has_bbox, "Empty glyph has a bbox")
             }
 
-            glyf_checksum = glyf_checksum.wrapping_add(compute_checksum(&self.glyph_buf));
-
             // Write glyph to output table and pad output
             //
             // TODO(user) Old code aligned glyphs ... but do we actually need to?
             // (definitely useful for loca)
-            glyf_table.extend_from_slice(&self.glyph_buf);
-            glyf_table.resize(Round4!(glyf_table.len()), 0);
+            glyf_table.try_extend_from_slice(glyph_buf)?;
+            if self.profile == OutputProfile::ReferenceCompatible {
+                glyf_table.try_resize(Round4!(glyf_table.len()), 0)?;
+            } else if glyf_table.len() % 2 != 0 {
+                // Glyphs are padded to an even length so that loca can use short offsets.
+                // The padding is removed below if the offsets are too large anyway.
+                padding.try_push(glyf_table.len() as u32)?;
+                glyf_table.try_push(0)?;
+            }
 
             // Read the x_min of the glyph in case we nede it to reconstruct 'hmtx'
             // The x_min value an i16 stored as bytes 2-4 in the glyph header.
-            if n_contours > 0 {
-                let x_min = i16::from_be_bytes(self.glyph_buf[2..4].try_into().unwrap());
-                x_mins[i] = x_min;
+            if let (1.., Some(&[high, low])) = (n_contours, glyph_buf.get(2..4)) {
+                *x_min = i16::from_be_bytes([high, low]);
             }
         }
 
+        if glyf_table.len() >= SHORT_LOCA_LIMIT {
+            remove_padding(glyf_table, loca_values, padding);
+        }
+
         // loca[n] will be equal the length of the glyph data ('glyf') table
-        loca_values.push(glyf_table.len() as u32);
+        loca_values.try_push(glyf_table.len() as u32)?;
+
+        let fits_short_loca = glyf_table.len() < SHORT_LOCA_LIMIT
+            && loca_values.iter().all(|&offset| offset % 2 == 0);
+        let index_format = match self.profile {
+            OutputProfile::ReferenceCompatible => self.index_format,
+            OutputProfile::FontToolsCompatible => {
+                bail_with_msg_if!(
+                    self.index_format == 0 && !fits_short_loca,
+                    "Glyph offsets don't fit in short loca offsets"
+                );
+                // fontTools writes a single zero byte when all the glyphs are empty (the
+                // loca offsets all stay 0)
+                if glyf_table.is_empty() {
+                    glyf_table.try_push(0)?;
+                }
+                self.index_format
+            }
+            OutputProfile::Compact => (!fits_short_loca).into(),
+        };
 
         // Generate loca table
-        let (loca_table, loca_checksum) = generate_loca_table(&loca_values, self.index_format)?;
-
-        Ok(GlyfAndLocaData {
-            num_glyphs: self.num_glyphs,
-            index_format: self.index_format,
-            x_mins,
-            loca_table,
-            loca_checksum,
-            glyf_table,
-            glyf_checksum,
-        })
+        let loca_checksum = generate_loca_table_into(loca_values, index_format, loca_table)?;
+
+        output.num_glyphs = self.num_glyphs;
+        output.index_format = index_format;
+        output.declared_index_format = self.index_format;
+        let glyf_checksum = compute_checksum(glyf_table);
+        output.glyf_checksum = glyf_checksum;
+        output.loca_checksum = loca_checksum;
+        Ok(())
     }
 
-    /// Parse glyph data into `self.glyph_buf`
-    fn parse_composite_glyph(&mut self) -> Result<(), WuffErr> {
+    /// Parse glyph data into `glyph_buf`
+    fn parse_composite_glyph(&mut self, glyph_buf: &mut Vec<u8>) -> Result<(), WuffErr> {
         // Create a new iterator over the composite stream when computing the size so that we
         // we can "rewind" and copy the bytes counted here below.
         let mut ro_composite_stream = self.composite_stream;
@@ -213,232 +397,469 @@ impl GlyfDecoder<'_> {
             0
         };
 
-        let size_needed: usize = 12 + composite_size + (instruction_size as usize);
-        if size_needed > self.glyph_buf.capacity() {
-            self.glyph_buf
-                .reserve(size_needed - self.glyph_buf.capacity());
-        }
+        // `glyph_buf` is empty, and everything written to it below fits in this
+        let size_needed = (composite_size.checked_add(instruction_size as usize))
+            .and_then(|size| size.checked_add(12))
+            .ok_or(WuffErr::GenericError)?;
+        glyph_buf.try_reserve(size_needed)?;
 
         let n_contours: i16 = -1; // All composite glyphs has n_contours = -1
-        self.glyph_buf.put_i16(n_contours);
-
-        self.bbox_stream
-            .try_read_bytes_into(8, &mut self.glyph_buf)?;
-        self.composite_stream
-            .try_read_bytes_into(composite_size, &mut self.glyph_buf)?;
+        glyph_buf.put_i16(n_contours);
+
+        self.bbox_stream.try_read_bytes_into(8, glyph_buf)?;
+        if self.profile == OutputProfile::FontToolsCompatible {
+            let (components, composite_stream) = (self.composite_stream)
+                .split_at_checked(composite_size)
+                .ok_or(WuffErr::GenericError)?;
+            self.composite_stream = composite_stream;
+            write_fonttools_components(components, have_instructions, glyph_buf)?;
+        } else {
+            self.composite_stream
+                .try_read_bytes_into(composite_size, glyph_buf)?;
+        }
 
         if have_instructions {
-            self.glyph_buf.put_u16(instruction_size);
+            glyph_buf.put_u16(instruction_size);
             self.instruction_stream
-                .try_read_bytes_into(instruction_size as usize, &mut self.glyph_buf)?;
+                .try_read_bytes_into(instruction_size as usize, glyph_buf)?;
         }
 
         Ok(())
     }
 
+    /// Parse glyph data into `glyph_buf`, using `buffers` as scratch space
     fn parse_simple_glyph(
         &mut self,
         n_contours: u16,
         glyph_has_bbox: bool,
         has_overlap_bit: bool,
+        glyph_buf: &mut Vec<u8>,
+        buffers: &mut PointBuffers,
     ) -> Result<(), WuffErr> {
+        let PointBuffers {
+            n_points: n_points_vec,
+            points,
+            flags,
+            encodings,
+        } = buffers;
         let n_contours = n_contours as usize;
 
         // simple glyph
-        let mut n_points_vec: Vec<u16> = Vec::with_capacity(n_contours);
+        n_points_vec.clear();
+        n_points_vec.try_reserve(n_contours)?;
         let mut total_n_points: u32 = 0;
         for _ in 0..n_contours {
             let n_points_contour: u16 = self.n_points_stream.try_get_variable_255_u16()?;
-            n_points_vec.push(n_points_contour);
-            bail_if!(u32_will_overflow(total_n_points, n_points_contour as u32));
-            total_n_points += n_points_contour as u32;
+            n_points_vec.try_push(n_points_contour)?;
+            total_n_points = (total_n_points.checked_add(n_points_contour as u32))
+                .ok_or(WuffErr::GenericError)?;
         }
         let flag_size: usize = total_n_points as usize;
-        bail_if!(flag_size > self.flag_stream.len());
+        let (flags_buf, flag_stream) =
+            (self.flag_stream.split_at_checked(flag_size)).ok_or(WuffErr::GenericError)?;
+        self.flag_stream = flag_stream;
 
-        let flags_buf = self.flag_stream;
         let triplet_buf = self.glyph_stream;
 
-        let mut triplet_bytes_consumed: usize = 0;
+        points.clear();
+        points.try_reserve(total_n_points as usize)?;
+        let triplet_bytes_consumed = decode_triplet(flags_buf, triplet_buf, points)?;
 
-        let mut points = Vec::with_capacity(total_n_points as usize);
-        triplet_bytes_consumed +=
-            decode_triplet(&flags_buf[0..flag_size], triplet_buf, &mut points)?;
-
-        self.flag_stream.advance(flag_size);
-        self.glyph_stream.advance(triplet_bytes_consumed); // FIXME: pass glyph_stream directly to decode_triplet instead?
+        self.glyph_stream.try_advance(triplet_bytes_consumed)?; // FIXME: pass glyph_stream directly to decode_triplet instead?
 
         let instruction_size: u16 = self.glyph_stream.try_get_variable_255_u16()?;
         bail_if!(total_n_points >= (1 << 27) || instruction_size as u32 >= (1 << 30));
 
-        // Reserve needed size to reduce allocations
+        // `glyph_buf` is empty, and everything written to it below fits in this
+        #[allow(clippy::arithmetic_side_effects)] // (bounded by the check above)
         let size_needed: usize =
             12 + 2 * n_contours + 5 * (total_n_points as usize) + (instruction_size as usize);
-        if self.glyph_buf.capacity() < size_needed {
-            self.glyph_buf
-                .reserve(size_needed - self.glyph_buf.capacity());
-        }
+        glyph_buf.try_reserve(size_needed)?;
 
-        self.glyph_buf.put_i16(n_contours as i16);
+        glyph_buf.put_i16(n_contours as i16);
 
         if glyph_has_bbox {
-            self.bbox_stream
-                .try_read_bytes_into(8, &mut self.glyph_buf)?;
+            self.bbox_stream.try_read_bytes_into(8, glyph_buf)?;
         } else {
-            write_bbox(points.as_slice(), &mut self.glyph_buf);
+            write_bbox(points.as_slice(), glyph_buf);
         }
 
         // From this point, stop writing to the end of the glyph buffer and write to earlier in the buffer
-        // let mut writer = &mut÷ self.glyph_buf[END_PTS_OF_CONTOURS_OFFSET..];
+        // let mut writer = &mut÷ glyph_buf[END_PTS_OF_CONTOURS_OFFSET..];
 
         let mut end_point: i32 = -1;
-        for countour in n_points_vec {
-            end_point += countour as i32;
+        for &countour in n_points_vec.iter() {
+            // (less than 65536 plus a u16, so this can't wrap)
+            end_point = end_point.wrapping_add(countour as i32);
             bail_if!(end_point >= 65536);
-            self.glyph_buf.put_u16(end_point as u16);
+            glyph_buf.put_u16(end_point as u16);
         }
 
-        self.glyph_buf.put_u16(instruction_size);
+        glyph_buf.put_u16(instruction_size);
         self.instruction_stream
-            .try_read_bytes_into(instruction_size as usize, &mut self.glyph_buf)?;
+            .try_read_bytes_into(instruction_size as usize, glyph_buf)?;
 
-        write_glyph_points(points.as_slice(), has_overlap_bit, &mut self.glyph_buf)?;
+        if self.profile == OutputProfile::Compact {
+            compact_point_flags(points, has_overlap_bit, encodings, flags)?;
+        } else {
+            shortest_point_flags(points, has_overlap_bit, flags)?;
+        }
+        // fontTools writes runs of two flags as they are (which is no longer)
+        let min_repeat_run = match self.profile {
+            OutputProfile::FontToolsCompatible => 3,
+            _ => 2,
+        };
+        write_glyph_points(points, flags, min_repeat_run, glyph_buf);
 
         Ok(())
     }
 }
 
-// This function stores just the point data. On entry, dst points to the
-// beginning of a simple glyph. Returns true on success.
+// This function stores just the point data (flags and coordinates). On entry, dst points to
+// the end of the glyph's instructions. Runs of at least `min_repeat_run` identical flags are
+// stored once with a repeat count.
 fn write_glyph_points(
     points: &[Point],
-    has_overlap_bit: bool,
+    flags: &[u8],
+    min_repeat_run: usize,
     dst: &mut impl BufMut,
-) -> Result<(), WuffErr> {
-    // Write flags
-    let mut last_flag: u8 = u8::MAX; // not a valid flag so next flag will never be equal to it
-    let mut repeat_count: u8 = 0;
-    let mut last_x: i32 = 0;
-    let mut last_y: i32 = 0;
-    for (i, point) in points.iter().enumerate() {
-        // Compute flag value
-        let flag = {
-            let mut flag: u8 = 0;
-
-            if point.on_curve {
-                flag |= GLYF_ON_CURVE;
-            }
-            if has_overlap_bit && i == 0 {
-                flag |= OVERLAP_SIMPLE;
+) {
+    // Write flags. The repeat count is a byte, so a run covers at most 256 points.
+    for run in flags.chunk_by(|a, b| a == b) {
+        for chunk in run.chunks(256) {
+            match chunk {
+                &[flag, ..] if chunk.len() >= min_repeat_run => {
+                    dst.put_u8(flag | GLYF_REPEAT);
+                    // (the chunk isn't empty)
+                    dst.put_u8(chunk.len().wrapping_sub(1) as u8);
+                }
+                _ => chunk.iter().for_each(|&flag| dst.put_u8(flag)),
             }
+        }
+    }
 
-            // Handle x
-            let dx: i32 = point.x - last_x;
-            if dx == 0 {
-                flag |= GLYF_THIS_X_IS_SAME;
-            } else if dx > -256 && dx < 256 {
-                flag |= GLYF_X_SHORT | (if dx > 0 { GLYF_THIS_X_IS_SAME } else { 0 });
-            } else {
-                // Do nothing
-            }
+    // Write x coordinates, then y coordinates, in the encoding chosen by each point's flag
+    let xs = points.iter().map(|point| point.x);
+    write_coordinates(xs, flags, GLYF_X_SHORT, GLYF_THIS_X_IS_SAME, dst);
+    let ys = points.iter().map(|point| point.y);
+    write_coordinates(ys, flags, GLYF_Y_SHORT, GLYF_THIS_Y_IS_SAME, dst);
+}
 
-            // Handle y
-            let dy: i32 = point.y - last_y;
-            if dy == 0 {
-                flag |= GLYF_THIS_Y_IS_SAME;
-            } else if dy > -256 && dy < 256 {
-                flag |= GLYF_Y_SHORT | (if dy > 0 { GLYF_THIS_Y_IS_SAME } else { 0 });
-            } else {
-                // Do nothing
-            }
+/// Write the deltas between coordinates, as the `short_bit` and `same_bit` of each flag say
+fn write_coordinates(
+    values: impl Iterator<Item = i32>,
+    flags: &[u8],
+    short_bit: u8,
+    same_bit: u8,
+    dst: &mut impl BufMut,
+) {
+    let mut last: i32 = 0;
+    for (value, &flag) in values.zip(flags) {
+        let delta = value.wrapping_sub(last);
+        if flag & short_bit != 0 {
+            dst.put_u8(delta.unsigned_abs() as u8);
+        } else if flag & same_bit == 0 {
+            // will always fit for valid input, but overflow is harmless
+            dst.put_i16(delta as i16);
+        }
+        last = value;
+    }
+}
 
-            flag
-        };
+/// The ways of encoding a coordinate delta, as the flag bits and the number of bytes of
+/// coordinate data. The first is the shortest.
+fn coordinate_encodings(delta: i32, short_bit: u8, same_bit: u8) -> impl Iterator<Item = (u8, u8)> {
+    [
+        (delta == 0).then_some((same_bit, 0)),
+        (0..256)
+            .contains(&delta)
+            .then_some((short_bit | same_bit, 1)),
+        (-255..=0).contains(&delta).then_some((short_bit, 1)),
+        Some((0, 2)),
+    ]
+    .into_iter()
+    .flatten()
+}
 
-        // Compare flag value with previous value and write previous value if appropriate.
-        //
-        // To keep writes to the output buffer strictly append-only we don't write flags immediately.
-        // Instead, we keep track of the "last_flag" along with an associated "repeat_count" until we
-        // know that this flag will not be repeated.
+/// The flag bits which don't depend on how the point's coordinates are encoded
+fn base_flag(point: &Point, index: usize, has_overlap_bit: bool) -> u8 {
+    let mut flag: u8 = 0;
+    if point.on_curve {
+        flag |= GLYF_ON_CURVE;
+    }
+    if has_overlap_bit && index == 0 {
+        flag |= OVERLAP_SIMPLE;
+    }
+    flag
+}
 
-        // If the current flag is the same as the previous one and we have not yet reached the repeat count
-        // limit (limit is 255 so it fits in one byte), then we increment the repeat count.
-        if flag == last_flag && repeat_count < 255 {
-            repeat_count += 1;
+/// Set the flag of each point, encoding each coordinate as briefly as possible (as the
+/// reference decoder does)
+fn shortest_point_flags(
+    points: &[Point],
+    has_overlap_bit: bool,
+    flags: &mut Vec<u8>,
+) -> Result<(), WuffErr> {
+    flags.clear();
+    flags.try_reserve(points.len())?;
+    let (mut last_x, mut last_y) = (0, 0);
+    for (i, point) in points.iter().enumerate() {
+        let dx = point.x.wrapping_sub(last_x);
+        let dy = point.y.wrapping_sub(last_y);
+        let mut flag = base_flag(point, i, has_overlap_bit);
+        for (bits, _) in (coordinate_encodings(dx, GLYF_X_SHORT, GLYF_THIS_X_IS_SAME).take(1))
+            .chain(coordinate_encodings(dy, GLYF_Y_SHORT, GLYF_THIS_Y_IS_SAME).take(1))
+        {
+            flag |= bits;
         }
-        // Else if either:
-        //   - The current flag is not the same as the previous one.
-        //   - We have hit the repeat count limit
-        else {
-            // If the previous flag has a repeat count associated with it then
-            // set the GLYF_REPEAT flag on that flag and write 2 bytes:
-            //
-            //   1. The previous flag
-            //   2. The repeat count
-            //
-            // Note: it is important that we delay setting the GLYF_REPEAT until this point
-            // otherwise the "if flag == last_flag" will fail to detect repeats.
-            if last_flag != u8::MAX {
-                if repeat_count > 0 {
-                    dst.put_u8(last_flag | GLYF_REPEAT);
-                    dst.put_u8(repeat_count);
-                }
-                // If the repeat count is 0 then just write the previous flag
-                else {
-                    dst.put_u8(last_flag);
+        flags.push(flag);
+        (last_x, last_y) = (point.x, point.y);
+    }
+    Ok(())
+}
+
+/// One way of encoding a point, and the cheapest way of encoding the points before it to go
+/// with it
+#[derive(Copy, Clone, Default)]
+struct PointEncoding {
+    flag: u8,
+    /// The number of bytes of flags and coordinates needed for the points up to this one
+    cost: u32,
+    /// The length of the run of identical flags ending with this point
+    run: u16,
+    /// The index of the previous point's encoding
+    prev: u8,
+}
+
+/// The ways of encoding a point (there are at most 4 encodings of each coordinate)
+#[derive(Copy, Clone, Default)]
+struct PointEncodings {
+    encodings: [PointEncoding; 16],
+    len: usize,
+}
+
+impl PointEncodings {
+    fn iter(&self) -> impl Iterator<Item = &PointEncoding> {
+        self.encodings.iter().take(self.len)
+    }
+}
+
+/// Set the flag of each point so that the flags and coordinates take as few bytes as
+/// possible. A coordinate is sometimes stored in a longer encoding than it needs, when that
+/// makes its flag the same as the previous point's and so extends a flag run.
+#[allow(clippy::arithmetic_side_effects)] // (at most 5 bytes per point, for under 2^27 points)
+fn compact_point_flags(
+    points: &[Point],
+    has_overlap_bit: bool,
+    encodings: &mut Vec<PointEncodings>,
+    flags: &mut Vec<u8>,
+) -> Result<(), WuffErr> {
+    // Find the cheapest encoding of the points up to each point, for each way of encoding
+    // that point. The cost of extending a flag run depends on its length, but when two
+    // encodings of the earlier points differ in cost they differ by at least the most that
+    // can save, so keeping the cheapest is enough (up to the 256 point limit on runs).
+    encodings.clear();
+    encodings.try_reserve(points.len())?;
+    let (mut last_x, mut last_y) = (0, 0);
+    let mut previous: Option<PointEncodings> = None;
+    for (i, point) in points.iter().enumerate() {
+        let dx = point.x.wrapping_sub(last_x);
+        let dy = point.y.wrapping_sub(last_y);
+        let base_flag = base_flag(point, i, has_overlap_bit);
+        let mut current = PointEncodings::default();
+        for (x_bits, x_bytes) in coordinate_encodings(dx, GLYF_X_SHORT, GLYF_THIS_X_IS_SAME) {
+            for (y_bits, y_bytes) in coordinate_encodings(dy, GLYF_Y_SHORT, GLYF_THIS_Y_IS_SAME) {
+                let flag = base_flag | x_bits | y_bits;
+                let coordinate_cost = u32::from(x_bytes + y_bytes);
+                let encoding = match &previous {
+                    None => Some(PointEncoding {
+                        flag,
+                        cost: 1 + coordinate_cost,
+                        run: 1,
+                        prev: 0,
+                    }),
+                    Some(previous) => (previous.iter().enumerate())
+                        .map(|(prev, encoding)| {
+                            // Extending a run of one flag adds a repeat count
+                            let (flag_cost, run) = if encoding.flag == flag && encoding.run < 256 {
+                                (u32::from(encoding.run == 1), encoding.run + 1)
+                            } else {
+                                (1, 1)
+                            };
+                            PointEncoding {
+                                flag,
+                                cost: encoding.cost + flag_cost + coordinate_cost,
+                                run,
+                                prev: prev as u8,
+                            }
+                        })
+                        // Prefer runs which can be extended for free
+                        .min_by_key(|encoding| (encoding.cost, !(2..256).contains(&encoding.run))),
+                };
+                if let (Some(encoding), Some(slot)) =
+                    (encoding, current.encodings.get_mut(current.len))
+                {
+                    *slot = encoding;
+                    current.len += 1;
                 }
             }
-
-            // Reset the repeat count to 0
-            repeat_count = 0;
         }
-
-        // Store values from this iteration
-        last_x = point.x;
-        last_y = point.y;
-        last_flag = flag;
+        encodings.push(current);
+        previous = Some(current);
+        (last_x, last_y) = (point.x, point.y);
     }
 
-    // Write final flag
-    if last_flag != u8::MAX {
-        if repeat_count > 0 {
-            dst.put_u8(last_flag | GLYF_REPEAT);
-            dst.put_u8(repeat_count);
-        } else {
-            dst.put_u8(last_flag);
+    // Walk back from the cheapest encoding of the last point
+    flags.clear();
+    flags.try_resize(points.len(), 0)?;
+    let mut index = (previous.iter().flat_map(PointEncodings::iter).enumerate())
+        .min_by_key(|(_, encoding)| encoding.cost)
+        .map(|(index, _)| index);
+    for (flag, point_encodings) in flags.iter_mut().zip(encodings.iter()).rev() {
+        let Some(encoding) = index.and_then(|index| point_encodings.encodings.get(index)) else {
+            bail!()
         };
+        *flag = encoding.flag;
+        index = Some(encoding.prev as usize);
     }
+    Ok(())
+}
 
-    // Write x coordinates
-    last_x = 0;
-    for point in points {
-        let dx: i32 = point.x - last_x;
-        if dx == 0 {
-            // do nothing
-        } else if dx > -256 && dx < 256 {
-            dst.put_u8(dx.unsigned_abs() as u8);
+/// Re-encode the component records of a composite glyph as fontTools does: the arguments and
+/// transform are written in their shortest form, the flags describing them are recomputed, and
+/// WE_HAVE_INSTRUCTIONS is only set on the last component
+fn write_fonttools_components(
+    mut components: &[u8],
+    have_instructions: bool,
+    dst: &mut impl BufMut,
+) -> Result<(), WuffErr> {
+    // The flags which fontTools keeps as they are
+    const KEPT_FLAGS: u16 = FLAG_ROUND_XY_TO_GRID
+        | FLAG_USE_MY_METRICS
+        | FLAG_SCALED_COMPONENT_OFFSET
+        | FLAG_UNSCALED_COMPONENT_OFFSET
+        | FLAG_NON_OVERLAPPING
+        | FLAG_OVERLAP_COMPOUND;
+
+    let mut more = true;
+    while more {
+        let flags = components.try_get_u16()?;
+        let glyph_index = components.try_get_u16()?;
+        more = flags & FLAG_MORE_COMPONENTS != 0;
+
+        // The arguments are offsets if ARGS_ARE_XY_VALUES is set, and (unsigned) point
+        // numbers otherwise
+        let are_xy_values = flags & FLAG_ARGS_ARE_XY_VALUES != 0;
+        let args: [i32; 2] = match (flags & FLAG_ARG_1_AND_2_ARE_WORDS != 0, are_xy_values) {
+            (true, true) => [
+                components.try_get_i16()?.into(),
+                components.try_get_i16()?.into(),
+            ],
+            (true, false) => [
+                components.try_get_u16()?.into(),
+                components.try_get_u16()?.into(),
+            ],
+            (false, true) => [
+                components.try_get_i8()?.into(),
+                components.try_get_i8()?.into(),
+            ],
+            (false, false) => [
+                components.try_get_u8()?.into(),
+                components.try_get_u8()?.into(),
+            ],
+        };
+        // [xscale, scale01, scale10, yscale]
+        let transform: Option<[i16; 4]> = if flags & FLAG_WE_HAVE_A_SCALE != 0 {
+            let scale = components.try_get_i16()?;
+            Some([scale, 0, 0, scale])
+        } else if flags & FLAG_WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            let x_scale = components.try_get_i16()?;
+            let y_scale = components.try_get_i16()?;
+            Some([x_scale, 0, 0, y_scale])
+        } else if flags & FLAG_WE_HAVE_A_TWO_BY_TWO != 0 {
+            let mut transform = [0; 4];
+            for value in &mut transform {
+                *value = components.try_get_i16()?;
+            }
+            Some(transform)
         } else {
-            // will always fit for valid input, but overflow is harmless
-            dst.put_i16(dx as i16)
+            None
+        };
+
+        let mut out_flags = flags & KEPT_FLAGS;
+        if more {
+            out_flags |= FLAG_MORE_COMPONENTS;
+        } else if have_instructions {
+            out_flags |= FLAG_WE_HAVE_INSTRUCTIONS;
+        }
+        let byte_range = if are_xy_values { -128..=127 } else { 0..=255 };
+        let args_are_bytes = args.iter().all(|arg| byte_range.contains(arg));
+        if are_xy_values {
+            out_flags |= FLAG_ARGS_ARE_XY_VALUES;
+        }
+        if !args_are_bytes {
+            out_flags |= FLAG_ARG_1_AND_2_ARE_WORDS;
+        }
+        let transform_values: &[i16] = match &transform {
+            Some(transform @ [_, scale01, scale10, _]) if *scale01 != 0 || *scale10 != 0 => {
+                out_flags |= FLAG_WE_HAVE_A_TWO_BY_TWO;
+                transform
+            }
+            Some([x_scale, _, _, y_scale]) if x_scale != y_scale => {
+                out_flags |= FLAG_WE_HAVE_AN_X_AND_Y_SCALE;
+                &[*x_scale, *y_scale]
+            }
+            Some([scale, ..]) => {
+                out_flags |= FLAG_WE_HAVE_A_SCALE;
+                &[*scale]
+            }
+            None => &[],
+        };
+
+        dst.put_u16(out_flags);
+        dst.put_u16(glyph_index);
+        for arg in args {
+            if args_are_bytes {
+                dst.put_u8(arg as u8);
+            } else {
+                dst.put_u16(arg as u16);
+            }
+        }
+        for &value in transform_values {
+            dst.put_i16(value);
         }
-        last_x += dx;
     }
+    Ok(())
+}
 
-    // Write y coordinates
-    last_y = 0;
-    for point in points {
-        let dy: i32 = point.y - last_y;
-        if dy == 0 {
-            // do nothing
-        } else if dy > -256 && dy < 256 {
-            dst.put_u8(dy.unsigned_abs() as u8);
-        } else {
-            dst.put_i16(dy as i16)
+/// Remove the bytes added to pad glyphs (at the offsets in `padding`, in order), moving the
+/// glyphs after them back
+#[allow(clippy::arithmetic_side_effects)] // (offsets in the glyf table are u32s, as in loca)
+fn remove_padding(glyf_table: &mut Vec<u8>, loca_values: &mut [u32], padding: &[u32]) {
+    let mut offset: u32 = 0;
+    let mut remaining = padding.iter().peekable();
+    glyf_table.retain(|_| {
+        let is_padding = remaining.next_if(|&&padding| padding == offset).is_some();
+        offset += 1;
+        !is_padding
+    });
+
+    let mut removed: u32 = 0;
+    let mut remaining = padding.iter().peekable();
+    for value in loca_values {
+        while remaining.next_if(|&&padding| padding < *value).is_some() {
+            removed += 1;
         }
-        last_y += dy;
+        *value -= removed;
     }
+}
 
-    Ok(())
+/// Whether bit `i` of a bitmap (most significant bit first) is set. Bits past the end of the
+/// bitmap are unset.
+fn is_bit_set(bitmap: &[u8], i: usize) -> bool {
+    bitmap
+        .get(i >> 3)
+        .is_some_and(|byte| (byte & (0x80 >> (i & 7))) != 0)
 }
 
 /// Compute the bounding box of the coordinates, and store into a glyf buffer.
@@ -450,11 +871,11 @@ fn write_bbox(points: &[Point], dst: &mut impl BufMut) {
     let mut x_max: i32 = 0;
     let mut y_max: i32 = 0;
 
-    if !points.is_empty() {
-        x_min = points[0].x;
-        x_max = points[0].x;
-        y_min = points[0].y;
-        y_max = points[0].y;
+    if let Some(first) = points.first() {
+        x_min = first.x;
+        x_max = first.x;
+        y_min = first.y;
+        y_max = first.y;
     }
     for &Point { x, y, .. } in points.iter().skip(1) {
         x_min = x.min(x_min);
@@ -469,6 +890,7 @@ fn write_bbox(points: &[Point], dst: &mut impl BufMut) {
     dst.put_i16(y_max as i16);
 }
 
+#[allow(clippy::arithmetic_side_effects)] // (at most the size of the stream)
 fn compute_size_of_composite(composite_stream: &mut impl Buf) -> Result<(usize, bool), WuffErr> {
     let mut bytes_read: usize = 0;
     let mut we_have_instructions: bool = false;
@@ -476,21 +898,8 @@ fn compute_size_of_composite(composite_stream: &mut impl Buf) -> Result<(usize,
     while flags & FLAG_MORE_COMPONENTS != 0 {
         flags = composite_stream.try_get_u16()?;
         we_have_instructions |= (flags & FLAG_WE_HAVE_INSTRUCTIONS) != 0;
-        let mut arg_size: usize = 2; // glyph index
-        if flags & FLAG_ARG_1_AND_2_ARE_WORDS != 0 {
-            arg_size += 4;
-        } else {
-            arg_size += 2;
-        }
-        if flags & FLAG_WE_HAVE_A_SCALE != 0 {
-            arg_size += 2;
-        } else if flags & FLAG_WE_HAVE_AN_X_AND_Y_SCALE != 0 {
-            arg_size += 4;
-        } else if flags & FLAG_WE_HAVE_A_TWO_BY_TWO != 0 {
-            arg_size += 8;
-        }
-        bail_if!(composite_stream.remaining() < arg_size);
-        composite_stream.advance(arg_size);
+        let arg_size = 2 + component_args_size(flags); // glyph index + args
+        composite_stream.try_advance(arg_size)?;
 
         // 2 bytes for the flags + arg_size
         bytes_read += 2 + arg_size
@@ -499,6 +908,41 @@ fn compute_size_of_composite(composite_stream: &mut impl Buf) -> Result<(usize,
     Ok((bytes_read, we_have_instructions))
 }
 
+/// The size of the arguments and transform of a component record with the given flags
+#[allow(clippy::arithmetic_side_effects)] // (at most 12)
+fn component_args_size(flags: u16) -> usize {
+    let mut arg_size = if flags & FLAG_ARG_1_AND_2_ARE_WORDS != 0 {
+        4
+    } else {
+        2
+    };
+    if flags & FLAG_WE_HAVE_A_SCALE != 0 {
+        arg_size += 2;
+    } else if flags & FLAG_WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+        arg_size += 4;
+    } else if flags & FLAG_WE_HAVE_A_TWO_BY_TWO != 0 {
+        arg_size += 8;
+    }
+    arg_size
+}
+
+/// Add the glyph ids of the components of a reconstructed glyph to `ids` (none for a simple
+/// or empty glyph)
+pub(crate) fn read_component_ids(glyph: &[u8], ids: &mut Vec<u16>) -> Result<(), WuffErr> {
+    let Some((&[0xFF, 0xFF], mut records)) = glyph.split_first_chunk() else {
+        return Ok(());
+    };
+    records.try_advance(COMPOSITE_GLYPH_BEGIN - 2)?;
+    let mut flags = FLAG_MORE_COMPONENTS;
+    while flags & FLAG_MORE_COMPONENTS != 0 {
+        flags = records.try_get_u16()?;
+        ids.try_push(records.try_get_u16()?)?;
+        records.try_advance(component_args_size(flags))?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::arithmetic_side_effects)] // (of bytes of the stream, and checked sums)
 fn decode_triplet(flags_in: &[u8], in_: &[u8], result: &mut Vec<Point>) -> Result<usize, WuffErr> {
     #[inline(always)]
     fn with_sign(flag: i32, baseval: i32) -> i32 {
@@ -508,8 +952,7 @@ fn decode_triplet(flags_in: &[u8], in_: &[u8], result: &mut Vec<Point>) -> Resul
 
     #[inline(always)]
     fn safe_add(a: i32, b: i32) -> Result<i32, WuffErr> {
-        bail_if!(((a > 0) && (b > i32::MAX - a)) || ((a < 0) && (b < i32::MIN - a)));
-        Ok(a + b)
+        a.checked_add(b).ok_or(WuffErr::GenericError)
     }
 
     let mut x: i32 = 0;
@@ -533,55 +976,48 @@ fn decode_triplet(flags_in: &[u8], in_: &[u8], result: &mut Vec<Point>) -> Resul
             4
         };
 
-        // Second condition was "triplet_index + n_data_bytes < triplet_index" in C. Clippy detected as checking for overflow
-        // in a way that doesn't work in Rust (because Rust panics rather than wraps in debug mode)
-        bail_if!(
-            usize_will_overflow(triplet_index, n_data_bytes)
-                || (triplet_index + n_data_bytes) > in_.len()
-        );
+        // Was "triplet_index + n_data_bytes < triplet_index" in C, which doesn't work in Rust
+        // (because Rust panics rather than wraps in debug mode)
+        let data_bytes = (triplet_index.checked_add(n_data_bytes))
+            .and_then(|end| in_.get(triplet_index..end))
+            .ok_or(WuffErr::GenericError)?;
+        // (bytes past `n_data_bytes` are unused)
+        let mut data = [0; 4];
+        for (byte, &data_byte) in data.iter_mut().zip(data_bytes) {
+            *byte = data_byte;
+        }
+        let [d0, d1, d2, d3] = data.map(i32::from);
 
         let dx: i32;
         let dy: i32;
         if flag < 10 {
             dx = 0;
-            dy = with_sign(flag, ((flag & 14) << 7) + in_[triplet_index] as i32);
+            dy = with_sign(flag, ((flag & 14) << 7) + d0);
         } else if flag < 20 {
-            dx = with_sign(flag, (((flag - 10) & 14) << 7) + in_[triplet_index] as i32);
+            dx = with_sign(flag, (((flag - 10) & 14) << 7) + d0);
             dy = 0;
         } else if flag < 84 {
             let b0: i32 = flag - 20;
-            let b1: i32 = in_[triplet_index] as i32;
+            let b1: i32 = d0;
             dx = with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4));
             dy = with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f));
         } else if flag < 120 {
             let b0: i32 = flag - 84;
-            dx = with_sign(flag, 1 + ((b0 / 12) << 8) + in_[triplet_index] as i32);
-            dy = with_sign(
-                flag >> 1,
-                1 + (((b0 % 12) >> 2) << 8) + in_[triplet_index + 1] as i32,
-            );
+            dx = with_sign(flag, 1 + ((b0 / 12) << 8) + d0);
+            dy = with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + d1);
         } else if flag < 124 {
-            let b2: i32 = in_[triplet_index + 1] as i32;
-            dx = with_sign(flag, ((in_[triplet_index] as i32) << 4) + (b2 >> 4));
-            dy = with_sign(
-                flag >> 1,
-                ((b2 & 0x0f) << 8) + in_[triplet_index + 2] as i32,
-            );
+            let b2: i32 = d1;
+            dx = with_sign(flag, (d0 << 4) + (b2 >> 4));
+            dy = with_sign(flag >> 1, ((b2 & 0x0f) << 8) + d2);
         } else {
-            dx = with_sign(
-                flag,
-                ((in_[triplet_index] as i32) << 8) + in_[triplet_index + 1] as i32,
-            );
-            dy = with_sign(
-                flag >> 1,
-                ((in_[triplet_index + 2] as i32) << 8) + in_[triplet_index + 3] as i32,
-            );
+            dx = with_sign(flag, (d0 << 8) + d1);
+            dy = with_sign(flag >> 1, (d2 << 8) + d3);
         }
         triplet_index += n_data_bytes;
         x = safe_add(x, dx)?;
         y = safe_add(y, dy)?;
 
-        result.push(Point { x, y, on_curve }); // CHECK: was *result++
+        result.try_push(Point { x, y, on_curve })?; // CHECK: was *result++
     }
 
     Ok(triplet_index)
@@ -594,11 +1030,25 @@ pub(crate) fn generate_loca_table(
     loca_values: &[u32],
     index_format: u16,
 ) -> Result<(Vec<u8>, u32), WuffErr> {
+    let mut loca_content = Vec::new();
+    let checksum = generate_loca_table_into(loca_values, index_format, &mut loca_content)?;
+    Ok((loca_content, checksum))
+}
+
+/// Like [`generate_loca_table`], but writing the table into `loca_content` (reusing its
+/// allocation) and returning only the checksum
+fn generate_loca_table_into(
+    loca_values: &[u32],
+    index_format: u16,
+    loca_content: &mut Vec<u8>,
+) -> Result<u32, WuffErr> {
     let loca_size = loca_values.len();
     let offset_size: usize = if index_format != 0 { 4 } else { 2 };
-    bail_if!((loca_size << 2) >> 2 != loca_size);
+    let loca_length = loca_size.checked_mul(offset_size);
 
-    let mut loca_content: Vec<u8> = Vec::with_capacity(loca_size * offset_size);
+    loca_content.clear();
+    // Everything written to `loca_content` below fits in this
+    loca_content.try_reserve(loca_length.ok_or(WuffErr::GenericError)?)?;
     if index_format != 0 {
         for &value in loca_values {
             // loca long version. The actual local offset is stored.
@@ -612,9 +1062,7 @@ pub(crate) fn generate_loca_table(
         }
     }
 
-    let checksum = compute_checksum(&loca_content);
-
-    Ok((loca_content, checksum))
+    Ok(compute_checksum(loca_content))
 }
 
 // MOVE assert up:
@@ -627,3 +1075,296 @@ pub(crate) fn generate_loca_table(
 // if PREDICT_FALSE(loca_table.dst_length != expected_loca_dst_length) {
 //     return FONT_COMPRESSION_FAILURE();
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        GlyfTransform, Glyph, GlyphPoint, MORE_COMPONENTS, WE_HAVE_A_SCALE,
+        WE_HAVE_AN_X_AND_Y_SCALE, WE_HAVE_INSTRUCTIONS, component, parse_simple_glyph,
+    };
+    use alloc::vec;
+
+    /// The reconstructed data of glyph `index`
+    fn glyph(data: &GlyfAndLocaData, index: usize) -> &[u8] {
+        let offset = |i: usize| match data.index_format {
+            0 => {
+                u16::from_be_bytes(data.loca_table[2 * i..2 * i + 2].try_into().unwrap()) as usize
+                    * 2
+            }
+            _ => u32::from_be_bytes(data.loca_table[4 * i..4 * i + 4].try_into().unwrap()) as usize,
+        };
+        &data.glyf_table[offset(index)..offset(index + 1)]
+    }
+
+    #[test]
+    fn simple_glyphs_round_trip() {
+        // Deltas which exercise each of the triplet encodings
+        let points: &[(i16, i16, bool)] = &[
+            (0, 1000, true),
+            (1200, 1000, false),
+            (1250, 960, true),
+            (1950, 300, true),
+            (-2000, -3000, false),
+            (30000, -30000, true),
+            (30000, -30000, true),
+        ];
+        let mut overlapping = Glyph::simple(&[&[(0, 0, true), (10, 10, true), (20, 0, true)]]);
+        if let Glyph::Simple {
+            instructions,
+            overlap,
+            ..
+        } = &mut overlapping
+        {
+            *instructions = vec![0xb0, 0x01];
+            *overlap = true;
+        }
+        let mut explicit_bbox = Glyph::simple(&[&[(5, 5, true), (15, 25, true)]]);
+        if let Glyph::Simple { bbox, .. } = &mut explicit_bbox {
+            *bbox = Some([-100, -200, 300, 400]);
+        }
+        let glyphs = vec![
+            Glyph::Empty,
+            Glyph::simple(&[points, &[(1, 2, true), (-3, 4, false)]]),
+            overlapping,
+            explicit_bbox,
+        ];
+
+        for index_format in [0, 1] {
+            let transform = GlyfTransform {
+                glyphs: glyphs.clone(),
+                index_format,
+            };
+            let data = tranform_glyf_table(&transform.encode()).unwrap();
+            assert_eq!(data.num_glyphs, 4);
+            assert_eq!(
+                data.loca_table.len(),
+                5 * if index_format == 0 { 2 } else { 4 }
+            );
+            assert!(glyph(&data, 0).is_empty());
+
+            for (i, expected) in glyphs.iter().enumerate().skip(1) {
+                let Glyph::Simple {
+                    contours,
+                    instructions,
+                    overlap,
+                    bbox,
+                } = expected
+                else {
+                    unreachable!()
+                };
+                let all_points = contours.iter().flatten();
+                let computed_bbox = [
+                    all_points.clone().map(|p| p.x).min().unwrap(),
+                    all_points.clone().map(|p| p.y).min().unwrap(),
+                    all_points.clone().map(|p| p.x).max().unwrap(),
+                    all_points.clone().map(|p| p.y).max().unwrap(),
+                ];
+                let expected_bbox = bbox.unwrap_or(computed_bbox);
+                assert_eq!(
+                    parse_simple_glyph(glyph(&data, i)),
+                    (
+                        expected_bbox,
+                        contours.clone(),
+                        instructions.clone(),
+                        *overlap
+                    )
+                );
+                assert_eq!(data.x_mins[i], expected_bbox[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn composite_glyph() {
+        let components = [
+            component(MORE_COMPONENTS | WE_HAVE_A_SCALE, 1, -5, 300),
+            component(WE_HAVE_INSTRUCTIONS, 1, 10, 20),
+        ]
+        .concat();
+        let transform = GlyfTransform::new(vec![
+            Glyph::Composite {
+                components: components.clone(),
+                instructions: Some(vec![1, 2, 3]),
+                bbox: [-5, 0, 100, 320],
+            },
+            Glyph::simple(&[&[(0, 0, true), (10, 20, true)]]),
+        ]);
+        let data = tranform_glyf_table(&transform.encode()).unwrap();
+
+        let mut expected = Vec::new();
+        expected.put_i16(-1);
+        [-5i16, 0, 100, 320]
+            .iter()
+            .for_each(|&v| expected.put_i16(v));
+        expected.extend_from_slice(&components);
+        expected.put_u16(3);
+        expected.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(&glyph(&data, 0)[..expected.len()], expected);
+        // Like the reference decoder, the x_min of composite glyphs is recorded too
+        assert_eq!(data.x_mins, [-5, 0]);
+        assert_eq!(
+            parse_simple_glyph(glyph(&data, 1)).1,
+            [[
+                GlyphPoint {
+                    x: 0,
+                    y: 0,
+                    on_curve: true
+                },
+                GlyphPoint {
+                    x: 10,
+                    y: 20,
+                    on_curve: true
+                }
+            ]]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_glyphs() {
+        // An empty glyph must not have a bbox
+        let empty_with_bbox = Glyph::Simple {
+            contours: Vec::new(),
+            instructions: Vec::new(),
+            overlap: false,
+            bbox: Some([0, 0, 1, 1]),
+        };
+        assert!(tranform_glyf_table(&GlyfTransform::new(vec![empty_with_bbox]).encode()).is_err());
+
+        // Truncating the last substream
+        let transform = GlyfTransform::new(vec![Glyph::simple(&[&[(0, 0, true), (9, 9, true)]])]);
+        let encoded = transform.encode();
+        assert!(tranform_glyf_table(&encoded).is_ok());
+        assert!(tranform_glyf_table(&encoded[..encoded.len() - 1]).is_err());
+
+        // A point count with no corresponding flags
+        let mut encoded = transform.encode();
+        let n_points_offset = 36 + 2;
+        encoded[n_points_offset] = 3;
+        assert!(tranform_glyf_table(&encoded).is_err());
+    }
+
+    /// Reconstruct the tables of an encoded glyf transform with `profile`
+    fn transform_with(encoded: &[u8], profile: OutputProfile) -> Result<GlyfAndLocaData, WuffErr> {
+        GlyfDecoder::new(encoded)?.with_profile(profile).transform()
+    }
+
+    /// A simple glyph with `num_points` points, each 1000 units from the last in x and y.
+    /// (as the deltas don't fit in a byte, each point's coordinates take 4 bytes)
+    fn large_glyph(num_points: usize) -> Glyph {
+        let points: Vec<_> = (0..num_points)
+            .map(|i| ((i % 2) as i16 * 1000, (i % 2) as i16 * 1000, true))
+            .collect();
+        Glyph::simple(&[&points])
+    }
+
+    // (The profile can be checked against fontTools' own output with `conformance
+    // --fonttools`; see conformance/src/fonttools.rs)
+    #[test]
+    fn fonttools_profile() {
+        let components = [
+            component(MORE_COMPONENTS | WE_HAVE_AN_X_AND_Y_SCALE, 1, -5, 100),
+            component(WE_HAVE_INSTRUCTIONS, 1, 200, 0),
+        ]
+        .concat();
+        let transform = GlyfTransform::new(vec![
+            Glyph::simple(&[&[(0, 0, true), (10, 0, true), (20, 0, true)]]),
+            Glyph::Composite {
+                components,
+                instructions: Some(vec![7]),
+                bbox: [-5, 0, 220, 100],
+            },
+        ]);
+        let encoded = transform.encode();
+        let reference = tranform_glyf_table(&encoded).unwrap();
+        let data = transform_with(&encoded, OutputProfile::FontToolsCompatible).unwrap();
+
+        // A run of 2 flags is written out rather than repeated
+        assert_eq!(glyph(&reference, 0)[14..17], [0x31, 0x33 | GLYF_REPEAT, 1]);
+        assert_eq!(glyph(&data, 0)[14..17], [0x31, 0x33, 0x33]);
+        assert_eq!(
+            parse_simple_glyph(glyph(&data, 0)),
+            parse_simple_glyph(glyph(&reference, 0))
+        );
+        // (19 bytes padded to 20, rather than 24)
+        assert_eq!(glyph(&data, 0).len(), 20);
+
+        // Component offsets are written as bytes when they fit, an x and y scale which are
+        // the same is written as a single scale, and only the last component has
+        // WE_HAVE_INSTRUCTIONS
+        let mut expected = Vec::new();
+        expected.put_i16(-1);
+        [-5i16, 0, 220, 100]
+            .iter()
+            .for_each(|&v| expected.put_i16(v));
+        expected.put_u16(FLAG_MORE_COMPONENTS | FLAG_ARGS_ARE_XY_VALUES | FLAG_WE_HAVE_A_SCALE);
+        expected.put_u16(1);
+        expected.extend_from_slice(&[-5i8 as u8, 100]);
+        expected.put_u16(0x4000);
+        expected.put_u16(
+            FLAG_WE_HAVE_INSTRUCTIONS | FLAG_ARGS_ARE_XY_VALUES | FLAG_ARG_1_AND_2_ARE_WORDS,
+        );
+        expected.put_u16(1);
+        expected.put_i16(200);
+        expected.put_i16(0);
+        expected.put_u16(1);
+        expected.push(7);
+        // (29 bytes padded to 30)
+        assert_eq!(glyph(&data, 1), [&expected[..], &[0]].concat());
+        assert_eq!(data.index_format, 0);
+        assert_eq!(data.loca_table, [0, 0, 0, 10, 0, 25]);
+
+        // A glyf table with only empty glyphs is a single zero byte
+        let empty = GlyfTransform::new(vec![Glyph::Empty, Glyph::Empty]).encode();
+        let data = transform_with(&empty, OutputProfile::FontToolsCompatible).unwrap();
+        assert_eq!(data.glyf_table, [0]);
+        assert_eq!(data.loca_table, [0; 6]);
+
+        // Short offsets which can't address the whole glyf table are rejected
+        let large = GlyfTransform::new(vec![large_glyph(34000), Glyph::Empty]);
+        let data = transform_with(&large.encode(), OutputProfile::FontToolsCompatible);
+        assert!(data.is_err());
+    }
+
+    #[test]
+    fn compact_profile() {
+        let glyphs = vec![
+            Glyph::simple(&[&[(0, 0, true), (10, 0, true), (20, 0, true)]]),
+            Glyph::simple(&[
+                &[(-300, 20, false), (-290, 20, true), (-280, 21, true)],
+                &[(1000, 1000, true), (1001, 999, false)],
+            ]),
+            Glyph::Empty,
+        ];
+        let transform = GlyfTransform {
+            glyphs: glyphs.clone(),
+            index_format: 1,
+        };
+        let encoded = transform.encode();
+        let reference = tranform_glyf_table(&encoded).unwrap();
+        let data = transform_with(&encoded, OutputProfile::Compact).unwrap();
+
+        // Short offsets are used, whatever the glyf table says
+        assert_eq!(reference.index_format, 1);
+        assert_eq!((data.index_format, data.declared_index_format), (0, 1));
+        assert_eq!(data.loca_table.len(), 4 * 2);
+        assert!(data.glyf_table.len() < reference.glyf_table.len());
+        for i in 0..glyphs.len() {
+            assert!(glyph(&data, i).len() <= glyph(&reference, i).len());
+            if i < 2 {
+                assert_eq!(
+                    parse_simple_glyph(glyph(&data, i)),
+                    parse_simple_glyph(glyph(&reference, i))
+                );
+            }
+        }
+        assert_eq!(data.x_mins, reference.x_mins);
+        assert_eq!(data.glyf_checksum, compute_checksum(&data.glyf_table));
+
+        // ... unless they can't address the whole glyf table
+        let large = GlyfTransform::new(vec![large_glyph(34000), Glyph::Empty]);
+        let data = transform_with(&large.encode(), OutputProfile::Compact).unwrap();
+        assert_eq!((data.index_format, data.declared_index_format), (1, 0));
+        assert_eq!(data.loca_table.len(), 3 * 4);
+    }
+}
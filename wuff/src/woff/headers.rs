@@ -1,17 +1,20 @@
-use alloc::collections::BTreeMap;
-use alloc::{vec, vec::Vec};
+use alloc::vec::Vec;
 use core::ops::{Deref, DerefMut};
 
 use crate::Tag;
 use bytes::Buf;
 
+use crate::diagnostics::{Diagnostics, WarningKind};
 use crate::error::{WuffErr, bail, bail_if, bail_with_msg_if, usize_will_overflow};
+use crate::fallible::{FallibleVec, try_sort_by_key};
+use crate::recovery::Repair;
 use crate::table_tags::KNOWN_TABLE_TAGS;
 use crate::variable_length::BufVariableExt;
 
 pub const WOFF1_SIG: Tag = Tag::new(b"woFF");
 pub const WOFF2_SIG: Tag = Tag::new(b"woF2");
 
+/// The version of a WOFF file
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum WoffVersion {
     Woff1 = 1,
@@ -44,9 +47,10 @@ pub(crate) struct WOFF2FontInfo {
     /// The minimum x coordinate for each glyph in the font.
     /// Read from the "glyf" table. Used to reconstruct the "hmtx" table.
     pub x_mins: Vec<i16>,
-    /// Map of table tag to the byte offset of that table's entry in the table directory in the output file
+    /// The tag and byte offset of each entry in the font's table directory in the output file.
     /// Allows the checksum, offset and length of the table to be written into the table directory once they are known.
-    pub table_entry_by_tag: BTreeMap<Tag, usize>,
+    /// If a tag appears more than once, the last entry is the one that gets written (like the reference decoder's map).
+    pub table_entries: Vec<(Tag, usize)>,
     /// Checksum of the ouput header
     pub header_checksum: u32,
 }
@@ -89,6 +93,14 @@ pub struct WoffHeader {
 
 impl WoffHeader {
     pub fn parse(input: &mut impl Buf) -> Result<Self, WuffErr> {
+        Self::parse_with_diagnostics(input, &mut Diagnostics::strict())
+    }
+
+    /// Parse the header, tolerating the problems that [`Repair`] covers in recovery mode
+    pub(crate) fn parse_with_diagnostics(
+        input: &mut impl Buf,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Self, WuffErr> {
         let input_len = input.remaining();
         let input_len_u32 = input_len as u32;
 
@@ -124,7 +136,12 @@ impl WoffHeader {
         };
 
         // Validate
-        bail_if!(header.length != input_len_u32);
+        if header.length != input_len_u32 {
+            diagnostics.repair(Repair::WrongLength {
+                declared: header.length,
+                actual: input_len_u32,
+            })?;
+        }
         bail_if!(header.num_tables == 0);
 
         // Both the WOFF1 and WOFF2 specs have a reserved field, but:
@@ -132,18 +149,31 @@ impl WoffHeader {
         //      <https://www.w3.org/TR/WOFF/#conform-reserved>
         //    - The WOFF2 spec requries decoders to accept files with a non-zero reserved field.
         //      <https://www.w3.org/TR/WOFF2/#conform-mustNotUseReservedValue>
-        bail_if!(header.woff_version == WoffVersion::Woff1 && header.reserved != 0);
+        if header.reserved != 0 {
+            match header.woff_version {
+                WoffVersion::Woff1 => diagnostics.repair(Repair::NonZeroReserved {
+                    value: header.reserved,
+                })?,
+                WoffVersion::Woff2 => diagnostics.warn(
+                    WarningKind::NonZeroReserved {
+                        value: header.reserved,
+                    },
+                    None,
+                    14,
+                ),
+            }
+        }
 
         if header.meta_offset != 0 {
             bail_if!(
                 header.meta_offset >= input_len_u32
-                    || input_len_u32 - header.meta_offset < header.meta_length
+                    || input_len_u32.wrapping_sub(header.meta_offset) < header.meta_length
             );
         }
         if header.priv_offset != 0 {
             bail_if!(
                 header.priv_offset >= input_len_u32
-                    || input_len_u32 - header.priv_offset < header.priv_length
+                    || input_len_u32.wrapping_sub(header.priv_offset) < header.priv_length
             );
         }
 
@@ -156,7 +186,7 @@ impl WoffHeader {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TableDirectory {
     pub tables: Vec<TableDirectoryEntry>,
     /// Size of the table directory (in the WOFF) in bytes
@@ -183,13 +213,41 @@ impl TableDirectory {
     pub fn size(&self) -> usize {
         self.size
     }
+
+    /// The entry at `index` in the table directory
+    pub fn entry(&self, index: impl Into<usize>) -> Result<&TableDirectoryEntry, WuffErr> {
+        self.tables.get(index.into()).ok_or(WuffErr::GenericError)
+    }
+
+    /// The entry at `index` in the table directory
+    pub fn entry_mut(
+        &mut self,
+        index: impl Into<usize>,
+    ) -> Result<&mut TableDirectoryEntry, WuffErr> {
+        self.tables
+            .get_mut(index.into())
+            .ok_or(WuffErr::GenericError)
+    }
 }
 
 impl TableDirectory {
     pub fn parse_woff1(input: &mut impl Buf, num_tables: usize) -> Result<Self, WuffErr> {
+        let mut table_directory = Self::default();
+        table_directory.parse_woff1_into(input, num_tables)?;
+        Ok(table_directory)
+    }
+
+    /// Like [`Self::parse_woff1`], but reusing the allocation of `self`
+    pub fn parse_woff1_into(
+        &mut self,
+        input: &mut impl Buf,
+        num_tables: usize,
+    ) -> Result<(), WuffErr> {
         let initial_remaining = input.remaining();
 
-        let mut tables = Vec::with_capacity(num_tables);
+        let tables = &mut self.tables;
+        tables.clear();
+        tables.try_reserve(num_tables)?;
         for _ in 0..num_tables {
             let table = TableDirectoryEntry::parse_woff1(input)?;
             // Check for for overflow
@@ -201,17 +259,27 @@ impl TableDirectory {
             tables.push(table);
         }
 
-        let size_of_directory = initial_remaining - input.remaining();
-        bail_if!(size_of_directory != num_tables * 20);
+        // (reading only shrinks `input`)
+        let size_of_directory = initial_remaining.wrapping_sub(input.remaining());
+        bail_if!(Some(size_of_directory) != num_tables.checked_mul(20));
 
-        Ok(Self {
-            tables,
-            size: size_of_directory,
-            uncompressed_size: 0, // WOFF2 only
-        })
+        self.size = size_of_directory;
+        self.uncompressed_size = 0; // WOFF2 only
+        Ok(())
     }
 
     pub fn parse_woff2(input: &mut impl Buf, num_tables: usize) -> Result<Self, WuffErr> {
+        let mut table_directory = Self::default();
+        table_directory.parse_woff2_into(input, num_tables)?;
+        Ok(table_directory)
+    }
+
+    /// Like [`Self::parse_woff2`], but reusing the allocation of `self`
+    pub fn parse_woff2_into(
+        &mut self,
+        input: &mut impl Buf,
+        num_tables: usize,
+    ) -> Result<(), WuffErr> {
         let initial_remaining = input.remaining();
 
         // Tables in the CompressedFontData field of the WOFF are stored directly after each other
@@ -221,19 +289,16 @@ impl TableDirectory {
         // <https://www.w3.org/TR/WOFF2/#table_format>
         let mut offset_in_woff: usize = 0;
 
-        let mut tables = Vec::with_capacity(num_tables);
+        let tables = &mut self.tables;
+        tables.clear();
+        tables.try_reserve(num_tables)?;
         for _ in 0..num_tables {
             let mut table = TableDirectoryEntry::parse_woff2(input)?;
             table.woff_offset = offset_in_woff as u32;
 
-            // Check for for overflow
-            bail_if!(usize_will_overflow(
-                offset_in_woff,
-                table.woff_length as usize
-            ));
-
             // Add the length of the table to offset_in_woff to determine the offset of the next table
-            offset_in_woff += table.woff_length as usize;
+            offset_in_woff = (offset_in_woff.checked_add(table.woff_length as usize))
+                .ok_or(WuffErr::GenericError)?;
 
             tables.push(table);
         }
@@ -241,15 +306,14 @@ impl TableDirectory {
         // Because the table directory is variable length, we compute it's size (in bytes) by tracking how
         // much data we have processed during processing. This allows us to know the offset that the next
         // section of the file begins at.
-        let size_of_directory = initial_remaining - input.remaining();
-
-        Ok(Self {
-            tables,
-            size: size_of_directory,
-            // Tables are stored consecutively in the decompressed data block, so once all
-            // tables have been processed `offset_in_woff` is the expected size of that block.
-            uncompressed_size: offset_in_woff,
-        })
+        // (reading only shrinks `input`)
+        let size_of_directory = initial_remaining.wrapping_sub(input.remaining());
+
+        self.size = size_of_directory;
+        // Tables are stored consecutively in the decompressed data block, so once all
+        // tables have been processed `offset_in_woff` is the expected size of that block.
+        self.uncompressed_size = offset_in_woff;
+        Ok(())
     }
 
     pub fn sort_tables(&mut self) {
@@ -258,7 +322,7 @@ impl TableDirectory {
 }
 
 /// <https://www.w3.org/TR/WOFF2/#table_dir_format>
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct TableDirectoryEntry {
     /// The version of the WOFF file that the entry is in.
     pub version: WoffVersion,
@@ -293,8 +357,8 @@ impl TableDirectoryEntry {
     }
 
     pub fn data_as_slice<'a>(&self, data: &'a [u8]) -> Result<&'a [u8], WuffErr> {
-        let end = self.woff_offset as usize + self.woff_length as usize;
-        data.get((self.woff_offset as usize)..end)
+        (data.get(self.woff_offset as usize..))
+            .and_then(|data| data.get(..self.woff_length as usize))
             .ok_or(WuffErr::GenericError)
     }
 }
@@ -387,7 +451,7 @@ fn is_transformed(tag: Tag, format: u8) -> bool {
 }
 
 /// <https://www.w3.org/TR/WOFF2/#collection_dir_format>
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CollectionDirectory {
     /// The Version of the TTC Header in the original font.
     pub version: u32,
@@ -397,60 +461,101 @@ pub struct CollectionDirectory {
 
 impl CollectionDirectory {
     pub fn parse(input: &mut impl Buf, table_directory: &TableDirectory) -> Result<Self, WuffErr> {
+        let mut collection_directory = Self::default();
+        collection_directory.parse_into(input, table_directory)?;
+        Ok(collection_directory)
+    }
+
+    /// Like [`Self::parse`], but reusing the allocations of `self`
+    pub fn parse_into(
+        &mut self,
+        input: &mut impl Buf,
+        table_directory: &TableDirectory,
+    ) -> Result<(), WuffErr> {
         let version = input.try_get_u32()?;
-        let num_fonts = input.try_get_variable_255_u16()?;
+        let num_fonts = input.try_get_variable_255_u16()? as usize;
 
         bail_if!(version != 0x00010000 && version != 0x00020000);
         bail_if!(num_fonts == 0);
 
-        let mut fonts = Vec::with_capacity(num_fonts as usize);
-        for _ in 0..num_fonts {
-            fonts.push(CollectionDirectoryEntry::parse(input, table_directory)?);
+        self.version = version;
+        self.fonts.truncate(num_fonts);
+        for i in 0..num_fonts {
+            match self.fonts.get_mut(i) {
+                Some(font) => font.parse_into(input, table_directory)?,
+                None => self
+                    .fonts
+                    .try_push(CollectionDirectoryEntry::parse(input, table_directory)?)?,
+            }
         }
-
-        Ok(Self { version, fonts })
+        Ok(())
     }
 
     /// Generate a fake `CollectionDirectory` for a single font so that we can share
     /// serialization logic between collection and single fonts.
-    pub fn generate_for_single_font(flavor: Tag, table_directory: &TableDirectory) -> Self {
-        let table_indices: Vec<u16> = (0..(table_directory.len() as u16)).collect();
-        let mut head_idx: Option<u16> = None;
-        let mut hhea_idx: Option<u16> = None;
-        let mut glyf_idx: Option<u16> = None;
-        let mut loca_idx: Option<u16> = None;
+    pub fn generate_for_single_font(
+        flavor: Tag,
+        table_directory: &TableDirectory,
+    ) -> Result<Self, WuffErr> {
+        let mut collection_directory = Self::default();
+        collection_directory.generate_for_single_font_into(flavor, table_directory)?;
+        Ok(collection_directory)
+    }
+
+    /// Like [`Self::generate_for_single_font`], but reusing the allocations of `self`
+    pub fn generate_for_single_font_into(
+        &mut self,
+        flavor: Tag,
+        table_directory: &TableDirectory,
+    ) -> Result<(), WuffErr> {
+        self.version = 0x00010000; // Hardcode: will be ignored
+        self.fonts.truncate(1);
+        if self.fonts.is_empty() {
+            self.fonts.try_push(CollectionDirectoryEntry::default())?;
+        }
+
+        let Some(font) = self.fonts.first_mut() else {
+            bail!()
+        };
+        font.flavor = flavor;
+        font.table_indices.clear();
+        font.table_indices.try_reserve(table_directory.len())?;
+        font.table_indices.extend(0..(table_directory.len() as u16));
+        font.head_idx = None;
+        font.hhea_idx = None;
+        font.glyf_idx = None;
+        font.loca_idx = None;
         for (table_index, table) in table_directory.tables.iter().enumerate() {
             match table.tag.as_ref() {
-                b"head" => head_idx = Some(table_index as u16),
-                b"hhea" => hhea_idx = Some(table_index as u16),
-                b"glyf" => glyf_idx = Some(table_index as u16),
-                b"loca" => loca_idx = Some(table_index as u16),
+                b"head" => font.head_idx = Some(table_index as u16),
+                b"hhea" => font.hhea_idx = Some(table_index as u16),
+                b"glyf" => font.glyf_idx = Some(table_index as u16),
+                b"loca" => font.loca_idx = Some(table_index as u16),
                 _ => { /* do nothing */ }
             }
         }
-        Self {
-            version: 0x00010000, // Hardcode: will be ignored
-            fonts: vec![CollectionDirectoryEntry {
-                flavor,
-                table_indices,
-                head_idx,
-                hhea_idx,
-                glyf_idx,
-                loca_idx,
-            }],
-        }
+        Ok(())
     }
 
-    pub fn sort_tables_within_each_font(&mut self, tables: &TableDirectory) {
+    /// Sort the tables of each font by tag, using `scratch` as working memory
+    pub fn sort_tables_within_each_font(
+        &mut self,
+        tables: &TableDirectory,
+        scratch: &mut Vec<(usize, u16)>,
+    ) -> Result<(), WuffErr> {
         for font in &mut self.fonts {
-            font.table_indices
-                .sort_by_cached_key(|idx| tables[*idx as usize].tag);
+            // A stable sort, like the reference decoder
+            try_sort_by_key(&mut font.table_indices, scratch, |idx| {
+                tables.get(*idx as usize).map(|table| table.tag)
+            })?;
         }
+        Ok(())
     }
 
     /// Size of the collection header. 0 if version indicates this isn't a
     /// collection. Ref http://www.microsoft.com/typography/otspec/otff.htm,
     /// True Type Collections
+    #[allow(clippy::arithmetic_side_effects)] // (there are at most 2^16 fonts)
     pub(crate) fn required_size(&self) -> usize {
         let mut size: usize = 0;
         if self.version == 0x00020000 {
@@ -466,6 +571,7 @@ impl CollectionDirectory {
     /// Size of the collection header. 0 if version indicates this isn't a
     /// collection. Ref http://www.microsoft.com/typography/otspec/otff.htm,
     /// True Type Collections
+    #[allow(clippy::arithmetic_side_effects)] // (there are at most 2^16 fonts)
     pub(crate) fn collection_header_required_size(&self) -> usize {
         let mut size: usize = 0;
         if self.version == 0x00020000 {
@@ -478,21 +584,19 @@ impl CollectionDirectory {
         size
     }
 
-    pub(crate) fn table_directories_required_size(&self) -> usize {
-        pub const TABLE_DIRECTORY_HEADER_SIZE: usize = 12;
-        pub const TABLE_DIRECTORY_ENTRY_SIZE: usize = 16;
-        self.collection_header_required_size()
-            + (TABLE_DIRECTORY_HEADER_SIZE * self.fonts.len())
-            + self
-                .fonts
-                .iter()
-                .map(|font| TABLE_DIRECTORY_ENTRY_SIZE * font.table_indices.len())
-                .sum::<usize>()
+    pub(crate) fn table_directories_required_size(&self) -> Result<usize, WuffErr> {
+        // (each font's table directory fits, but all of them together may not)
+        self.fonts
+            .iter()
+            .try_fold(self.collection_header_required_size(), |size, font| {
+                size.checked_add(font.table_directory_size())
+                    .ok_or(WuffErr::GenericError)
+            })
     }
 }
 
 /// <https://www.w3.org/TR/WOFF2/#collection_dir_format>
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CollectionDirectoryEntry {
     /// The "sfnt version" of the font
     pub flavor: Tag,
@@ -509,6 +613,17 @@ pub struct CollectionDirectoryEntry {
 
 impl CollectionDirectoryEntry {
     pub fn parse(input: &mut impl Buf, tables: &TableDirectory) -> Result<Self, WuffErr> {
+        let mut entry = Self::default();
+        entry.parse_into(input, tables)?;
+        Ok(entry)
+    }
+
+    /// Like [`Self::parse`], but reusing the allocation of `self`
+    pub fn parse_into(
+        &mut self,
+        input: &mut impl Buf,
+        tables: &TableDirectory,
+    ) -> Result<(), WuffErr> {
         let num_tables = input.try_get_variable_255_u16()?;
         let flavor = Tag::from_u32(input.try_get_u32()?);
 
@@ -518,12 +633,14 @@ impl CollectionDirectoryEntry {
         let mut hhea_idx: Option<u16> = None;
         let mut glyf_idx: Option<u16> = None;
         let mut loca_idx: Option<u16> = None;
-        let mut table_indices = Vec::with_capacity(num_tables as usize);
+        let table_indices = &mut self.table_indices;
+        table_indices.clear();
+        table_indices.try_reserve(num_tables as usize)?;
         for _ in 0..num_tables {
             let table_index = input.try_get_variable_255_u16()?;
-            bail_if!(table_index as usize >= tables.len());
+            let table = tables.entry(table_index)?;
 
-            match tables[table_index as usize].tag.as_ref() {
+            match table.tag.as_ref() {
                 b"head" => head_idx = Some(table_index),
                 b"hhea" => hhea_idx = Some(table_index),
                 b"glyf" => glyf_idx = Some(table_index),
@@ -539,7 +656,7 @@ impl CollectionDirectoryEntry {
         match (glyf_idx, loca_idx) {
             (Some(glyf_idx), Some(loca_idx)) => {
                 bail_with_msg_if!(
-                    glyf_idx > loca_idx || loca_idx - glyf_idx != 1,
+                    glyf_idx.checked_add(1) != Some(loca_idx),
                     "TTC font has non-consecutive glyf/loca"
                 );
             }
@@ -547,14 +664,12 @@ impl CollectionDirectoryEntry {
             (None, None) => {}
         };
 
-        Ok(Self {
-            flavor,
-            table_indices,
-            head_idx,
-            hhea_idx,
-            glyf_idx,
-            loca_idx,
-        })
+        self.flavor = flavor;
+        self.head_idx = head_idx;
+        self.hhea_idx = hhea_idx;
+        self.glyf_idx = glyf_idx;
+        self.loca_idx = loca_idx;
+        Ok(())
     }
 
     pub fn num_tables(&self) -> usize {
@@ -562,7 +677,79 @@ impl CollectionDirectoryEntry {
     }
 
     /// The size required for a table directory for this font
+    #[allow(clippy::arithmetic_side_effects)] // (there are at most 2^16 tables)
     pub fn table_directory_size(&self) -> usize {
         12 + (16 * self.num_tables())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TRUETYPE, Table, Woff2Builder};
+
+    fn parse_collection(woff: &[u8]) -> Result<CollectionDirectory, WuffErr> {
+        let mut input = woff;
+        let header = WoffHeader::parse(&mut input)?;
+        let table_directory = TableDirectory::parse_woff2(&mut input, header.num_tables as usize)?;
+        CollectionDirectory::parse(&mut input, &table_directory)
+    }
+
+    /// A collection with tables head, hhea, glyf, loca, cmap (in that order)
+    fn collection(version: u32) -> Woff2Builder {
+        Woff2Builder::collection(version)
+            .table(Table::new(b"head", [0; 54]))
+            .table(Table::new(b"hhea", [0; 36]))
+            .table(Table::new(b"glyf", [0; 4]))
+            .table(Table::new(b"loca", [0; 4]))
+            .table(Table::new(b"cmap", [0; 4]))
+    }
+
+    #[test]
+    fn parse_collection_directory() {
+        let woff = collection(0x00020000)
+            .font(TRUETYPE, &[0, 1, 2, 3])
+            .font(*b"OTTO", &[4, 0])
+            .build();
+        let directory = parse_collection(&woff).unwrap();
+        assert_eq!(directory.version, 0x00020000);
+        assert_eq!(directory.fonts.len(), 2);
+
+        let font = &directory.fonts[0];
+        assert_eq!(font.flavor, Tag::new(&TRUETYPE));
+        assert_eq!(font.table_indices, [0, 1, 2, 3]);
+        assert_eq!(
+            (font.head_idx, font.hhea_idx, font.glyf_idx, font.loca_idx),
+            (Some(0), Some(1), Some(2), Some(3))
+        );
+        let font = &directory.fonts[1];
+        assert_eq!(font.flavor, Tag::new(b"OTTO"));
+        assert_eq!(font.table_indices, [4, 0]);
+        assert_eq!((font.head_idx, font.glyf_idx), (Some(0), None));
+    }
+
+    #[test]
+    fn rejects_invalid_collection_directories() {
+        let reject = |builder: Woff2Builder| assert!(parse_collection(&builder.build()).is_err());
+        // Unknown version
+        reject(collection(0x00030000).font(TRUETYPE, &[0]));
+        // No fonts
+        reject(collection(0x00010000));
+        // A font without tables
+        reject(collection(0x00010000).font(TRUETYPE, &[]));
+        // Table index out of range
+        reject(collection(0x00010000).font(TRUETYPE, &[0, 5]));
+        // glyf without loca
+        reject(collection(0x00010000).font(TRUETYPE, &[0, 2]));
+        // glyf and loca must be consecutive, glyf first
+        let swapped = Woff2Builder::collection(0x00010000)
+            .table(Table::new(b"loca", [0; 4]))
+            .table(Table::new(b"glyf", [0; 4]))
+            .font(TRUETYPE, &[0, 1]);
+        reject(swapped);
+        // Truncated
+        let woff = collection(0x00010000).font(TRUETYPE, &[0, 1]).build();
+        let directory_end = woff.len() - 4;
+        assert!(parse_collection(&woff[..directory_end]).is_err());
+    }
+}
@@ -3,9 +3,11 @@ use alloc::vec::Vec;
 use bytes::{Buf, BufMut};
 
 use crate::error::{WuffErr, bail_if, bail_with_msg_if};
+use crate::fallible::FallibleVec;
 
 /// Data decoded from the WOFF2 hmtx table which can be used to reconstruct
 /// an open type hmtx table.
+#[derive(Default)]
 pub struct HmtxData {
     num_glyphs: u16,
     num_hmetrics: u16,
@@ -22,6 +24,19 @@ pub(crate) fn decode_hmtx_table(
     num_hmetrics: u16,
     x_mins: &[i16],
 ) -> Result<HmtxData, WuffErr> {
+    let mut hmtx_data = HmtxData::default();
+    decode_hmtx_table_into(input, num_glyphs, num_hmetrics, x_mins, &mut hmtx_data)?;
+    Ok(hmtx_data)
+}
+
+/// Like [`decode_hmtx_table`], but reusing the allocations of `output`
+pub(crate) fn decode_hmtx_table_into(
+    input: &mut impl Buf,
+    num_glyphs: u16,
+    num_hmetrics: u16,
+    x_mins: &[i16],
+    output: &mut HmtxData,
+) -> Result<(), WuffErr> {
     // Decode flags
     let hmtx_flags: u8 = input.try_get_u8()?;
     let has_proportional_lsbs: bool = (hmtx_flags & 1) == 0;
@@ -48,47 +63,151 @@ pub(crate) fn decode_hmtx_table(
     bail_if!(num_hmetrics < 1);
 
     // Read advance widths
-    let mut advance_widths: Vec<u16> = Vec::with_capacity(num_hmetrics as usize);
+    let advance_widths = &mut output.advance_widths;
+    advance_widths.clear();
+    advance_widths.try_reserve(num_hmetrics as usize)?;
     for _ in 0..num_hmetrics {
-        advance_widths.push(input.try_get_u16()?);
+        advance_widths.try_push(input.try_get_u16()?)?;
     }
 
     // Read lsb (proportional) and leftSideBearing (monospace) values into the same Vec
-    let mut lsbs: Vec<i16> = Vec::with_capacity(num_glyphs as usize);
-    for i in 0..num_hmetrics {
-        lsbs.push(match has_proportional_lsbs {
-            true => input.try_get_i16()?,
-            false => x_mins[i as usize],
-        });
-    }
-    for i in num_hmetrics..num_glyphs {
-        lsbs.push(match has_monospace_lsbs {
+    let lsbs = &mut output.lsbs;
+    lsbs.clear();
+    lsbs.try_reserve(num_glyphs as usize)?;
+    for (i, &x_min) in x_mins.iter().enumerate() {
+        let has_lsbs = match i < num_hmetrics as usize {
+            true => has_proportional_lsbs,
+            false => has_monospace_lsbs,
+        };
+        lsbs.try_push(match has_lsbs {
             true => input.try_get_i16()?,
-            false => x_mins[i as usize],
-        });
+            false => x_min,
+        })?;
     }
 
-    Ok(HmtxData {
-        num_glyphs,
-        num_hmetrics,
-        advance_widths,
-        lsbs,
-    })
+    output.num_glyphs = num_glyphs;
+    output.num_hmetrics = num_hmetrics;
+    Ok(())
 }
 
 /// bake me a shiny new hmtx table
 pub(crate) fn generate_hmtx_table(hmtx_data: &HmtxData) -> Result<Vec<u8>, WuffErr> {
-    let num_glyphs = hmtx_data.num_glyphs as usize;
-    let num_hmetrics = hmtx_data.num_hmetrics as usize;
-
-    let hmtx_output_size: usize = 2 * num_glyphs + 2 * num_hmetrics;
-    let mut hmtx_table: Vec<u8> = Vec::with_capacity(hmtx_output_size);
-    for i in 0..num_glyphs {
-        if i < num_hmetrics {
-            hmtx_table.put_u16(hmtx_data.advance_widths[i]);
+    let mut hmtx_table = Vec::new();
+    write_hmtx_table(hmtx_data, &mut hmtx_table)?;
+    Ok(hmtx_table)
+}
+
+/// Like [`generate_hmtx_table`], but writing the table into `hmtx_table` (reusing its allocation)
+pub(crate) fn write_hmtx_table(
+    hmtx_data: &HmtxData,
+    hmtx_table: &mut Vec<u8>,
+) -> Result<(), WuffErr> {
+    // There is an lsb for each glyph, and an advance width for each of the first
+    // numberOfHMetrics glyphs
+    let HmtxData {
+        advance_widths,
+        lsbs,
+        ..
+    } = hmtx_data;
+
+    let hmtx_output_size = (lsbs.len().checked_add(advance_widths.len()))
+        .and_then(|len| len.checked_mul(2))
+        .ok_or(WuffErr::GenericError)?;
+    hmtx_table.clear();
+    // Everything written to `hmtx_table` below fits in this
+    hmtx_table.try_reserve(hmtx_output_size)?;
+    let mut advance_widths = advance_widths.iter();
+    for &lsb in lsbs {
+        if let Some(&advance_width) = advance_widths.next() {
+            hmtx_table.put_u16(advance_width);
         }
-        hmtx_table.put_i16(hmtx_data.lsbs[i]);
+        hmtx_table.put_i16(lsb);
     }
+    Ok(())
+}
 
-    Ok(hmtx_table)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::HmtxTransform;
+    use alloc::vec;
+
+    fn decode(
+        transform: &HmtxTransform,
+        num_glyphs: u16,
+        x_mins: &[i16],
+    ) -> Result<Vec<u8>, WuffErr> {
+        let encoded = transform.encode();
+        let num_hmetrics = transform.advance_widths.len() as u16;
+        generate_hmtx_table(&decode_hmtx_table(
+            &mut &encoded[..],
+            num_glyphs,
+            num_hmetrics,
+            x_mins,
+        )?)
+    }
+
+    fn hmtx(metrics: &[(Option<u16>, i16)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &(advance, lsb) in metrics {
+            if let Some(advance) = advance {
+                out.put_u16(advance);
+            }
+            out.put_i16(lsb);
+        }
+        out
+    }
+
+    #[test]
+    fn elided_lsbs_are_x_mins() {
+        let x_mins = [-1, -2, -3, -4];
+        let proportional_elided = HmtxTransform {
+            advance_widths: vec![500, 600],
+            lsbs: None,
+            left_side_bearings: Some(vec![30, 40]),
+            reserved_flags: 0,
+        };
+        assert_eq!(
+            decode(&proportional_elided, 4, &x_mins).unwrap(),
+            hmtx(&[(Some(500), -1), (Some(600), -2), (None, 30), (None, 40)])
+        );
+
+        let both_elided = HmtxTransform {
+            lsbs: None,
+            left_side_bearings: None,
+            ..proportional_elided
+        };
+        assert_eq!(
+            decode(&both_elided, 4, &x_mins).unwrap(),
+            hmtx(&[(Some(500), -1), (Some(600), -2), (None, -3), (None, -4)])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        let valid = HmtxTransform {
+            advance_widths: vec![500],
+            lsbs: None,
+            left_side_bearings: Some(vec![7]),
+            reserved_flags: 0,
+        };
+        assert!(decode(&valid, 2, &[0, 0]).is_ok());
+        // Nothing elided: the transform shouldn't have been used
+        let untransformed = HmtxTransform {
+            lsbs: Some(vec![1]),
+            ..valid.clone()
+        };
+        assert!(decode(&untransformed, 2, &[0, 0]).is_err());
+        // Reserved flags must be zero
+        let reserved = HmtxTransform {
+            reserved_flags: 1,
+            ..valid.clone()
+        };
+        assert!(decode(&reserved, 2, &[0, 0]).is_err());
+        // More hMetrics than glyphs
+        assert!(decode(&valid, 0, &[]).is_err());
+        // Truncated
+        let encoded = valid.encode();
+        assert!(decode_hmtx_table(&mut &encoded[..encoded.len() - 1], 2, 1, &[0, 0]).is_err());
+    }
 }
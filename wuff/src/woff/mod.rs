@@ -1,3 +1,4 @@
+pub(crate) mod composite_graph;
 pub(crate) mod glyf_decoder;
 pub(crate) mod headers;
 pub(crate) mod hmtx_decoder;
@@ -0,0 +1,128 @@
+//! Built-in WOFF1 zlib decompression.
+//!
+//! This module is compiled when either the `z` or the `inflate` feature is enabled. It plugs
+//! a zlib decoder into [`decompress_woff1_with_decompressor`] as a [`ZlibDecompressor`]:
+//!
+//! - `z` uses `flate2`, which requires `std`.
+//! - `inflate` uses `miniz_oxide` with only an `alloc`-backed allocator, so it works on
+//!   `no_std` targets (with a global allocator). If both features are enabled, `z` is used.
+
+use alloc::vec::Vec;
+
+use crate::{DecodeOptions, Decoded, DecompressError, WuffErr, ZlibDecompressor};
+use crate::{decompress_woff1_with_decompressor, decompress_woff1_with_decompressor_and_options};
+
+/// The built-in zlib decompressor. Keeps the decoder's state between calls, so that it is
+/// only allocated once.
+#[derive(Default)]
+pub(crate) struct Zlib {
+    #[cfg(feature = "z")]
+    state: Option<flate2::Decompress>,
+    #[cfg(all(feature = "inflate", not(feature = "z")))]
+    state: Option<alloc::boxed::Box<miniz_oxide::inflate::core::DecompressorOxide>>,
+}
+
+#[cfg(feature = "z")]
+impl ZlibDecompressor for Zlib {
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError> {
+        use flate2::{Decompress, FlushDecompress, Status};
+        let decompressor = self.state.get_or_insert_with(|| Decompress::new(true));
+        decompressor.reset(true);
+        let status = decompressor
+            .decompress(input, output, FlushDecompress::None)
+            .map_err(|_| DecompressError::InvalidData)?;
+        let total_out = decompressor.total_out() as usize;
+        if total_out < output.len() {
+            return Err(DecompressError::TooShort);
+        }
+        if status != Status::StreamEnd {
+            // The output buffer is full, but the end of the stream hasn't been reached. Check
+            // whether there is any more data.
+            let consumed = decompressor.total_in() as usize;
+            let rest = input.get(consumed..).ok_or(DecompressError::InvalidData)?;
+            let mut extra = [0u8; 1];
+            let status = decompressor
+                .decompress(rest, &mut extra, FlushDecompress::None)
+                .map_err(|_| DecompressError::InvalidData)?;
+            if decompressor.total_out() as usize > total_out {
+                return Err(DecompressError::TooLong);
+            }
+            if status != Status::StreamEnd {
+                return Err(DecompressError::InvalidData);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "inflate", not(feature = "z")))]
+impl ZlibDecompressor for Zlib {
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), DecompressError> {
+        use miniz_oxide::inflate::TINFLStatus;
+        use miniz_oxide::inflate::core::{decompress, inflate_flags};
+
+        // The decompressor's state is around 11KB, so keep it off the (possibly small) stack.
+        // The whole stream is passed in one call, and the output buffer is the whole of the
+        // (non-wrapping) output, so it never writes past the end of `output`.
+        let state = self.state.get_or_insert_with(Default::default);
+        state.init();
+        let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+            | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+        let (status, _, written) = decompress(state, input, output, 0, flags);
+        match status {
+            TINFLStatus::Done if written == output.len() => Ok(()),
+            TINFLStatus::Done => Err(DecompressError::TooShort),
+            TINFLStatus::HasMoreOutput => Err(DecompressError::TooLong),
+            // The stream ended early
+            TINFLStatus::FailedCannotMakeProgress | TINFLStatus::NeedsMoreInput
+                if written < output.len() =>
+            {
+                Err(DecompressError::TooShort)
+            }
+            _ => Err(DecompressError::InvalidData),
+        }
+    }
+}
+
+/// Decompress a WOFF1 file using the built-in gzip decompressor
+pub fn decompress_woff1(raw_woff_data: &[u8]) -> Result<Vec<u8>, WuffErr> {
+    decompress_woff1_with_decompressor(raw_woff_data, &mut Zlib::default())
+}
+
+/// Decompress a WOFF1 file using the built-in gzip decompressor, with the given options
+pub fn decompress_woff1_with_options(
+    raw_woff_data: &[u8],
+    options: DecodeOptions,
+) -> Result<Decoded, WuffErr> {
+    decompress_woff1_with_decompressor_and_options(raw_woff_data, &mut Zlib::default(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+
+    #[test]
+    fn decodes_compressed_tables() {
+        let name = [7; 100];
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&name, 6);
+        let head = Table::new(b"head", head_table(0));
+        let stored = woff1(TRUETYPE, &[head.clone(), Table::new(b"name", name)]);
+        let compressed_name = Table::new(b"name", compressed).orig_length(name.len() as u32);
+        let compressed = woff1(TRUETYPE, &[head, compressed_name.clone()]);
+        // The directories only differ in the origChecksum, which `woff1` computes over the
+        // compressed data
+        let table_data = 12 + 2 * 16;
+        assert_eq!(
+            decompress_woff1(&compressed).unwrap()[table_data..],
+            decompress_woff1(&stored).unwrap()[table_data..]
+        );
+
+        // The table must decompress to exactly origLength bytes
+        let head = Table::new(b"head", head_table(0));
+        for orig_length in [99, 101] {
+            let wrong_length = compressed_name.clone().orig_length(orig_length);
+            assert!(decompress_woff1(&woff1(TRUETYPE, &[head.clone(), wrong_length])).is_err());
+        }
+    }
+}
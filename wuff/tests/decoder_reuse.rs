@@ -0,0 +1,102 @@
+//! Checks that `Woff2Decoder` and `Woff1Decoder` stop allocating once they have decoded a font.
+//! This is an integration test so that it can install its own global allocator.
+#![cfg(all(feature = "brotli", any(feature = "z", feature = "inflate")))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use wuff::test_support::Woff1Builder;
+use wuff::{Woff1Decoder, Woff2Decoder, decompress_woff1, decompress_woff2};
+
+/// Counts the allocations made by the current thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The number of allocations (and reallocations) made by `f`
+fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+const WOFF2_FONTS: [&[u8]; 4] = [
+    include_bytes!("../../conformance/fonts/wpt/valid-001.woff2"),
+    include_bytes!("../../conformance/fonts/wpt/valid-005.woff2"),
+    include_bytes!("../../conformance/fonts/wpt/tabledata-transform-hmtx-001.woff2"),
+    include_bytes!("../../conformance/fonts/wpt/datatypes-alt-255uint16-001.woff2"),
+];
+
+#[test]
+fn woff2_decoder_reuses_allocations() {
+    // The free functions allocate on every call
+    let (_, allocations) = count_allocations(|| decompress_woff2(WOFF2_FONTS[0]));
+    assert!(allocations > 0);
+
+    let mut decoder = Woff2Decoder::new();
+    for font in WOFF2_FONTS {
+        assert_eq!(
+            decoder.decode(font).unwrap(),
+            decompress_woff2(font).unwrap()
+        );
+    }
+    for font in WOFF2_FONTS {
+        let (decoded, allocations) = count_allocations(|| decoder.decode(font).unwrap().len());
+        assert_eq!(decoded, decompress_woff2(font).unwrap().len());
+        assert_eq!(allocations, 0);
+    }
+
+    // Errors don't leave the decoder in a bad state
+    assert!(decoder.decode(&WOFF2_FONTS[0][..100]).is_err());
+    let (_, allocations) = count_allocations(|| decoder.decode(WOFF2_FONTS[0]).unwrap().len());
+    assert_eq!(allocations, 0);
+    assert_eq!(
+        decoder.decode(WOFF2_FONTS[0]).unwrap(),
+        decompress_woff2(WOFF2_FONTS[0]).unwrap()
+    );
+}
+
+#[test]
+fn woff1_decoder_reuses_allocations() {
+    let fonts: Vec<Vec<u8>> = WOFF2_FONTS
+        .iter()
+        .map(|font| {
+            let sfnt = decompress_woff2(font).unwrap();
+            Woff1Builder::from_sfnt(&sfnt).compressed().build()
+        })
+        .collect();
+
+    let mut decoder = Woff1Decoder::new();
+    for font in &fonts {
+        assert_eq!(
+            decoder.decode(font).unwrap(),
+            decompress_woff1(font).unwrap()
+        );
+    }
+    for font in &fonts {
+        let (decoded, allocations) = count_allocations(|| decoder.decode(font).unwrap().len());
+        assert_eq!(decoded, decompress_woff1(font).unwrap().len());
+        assert_eq!(allocations, 0);
+    }
+}